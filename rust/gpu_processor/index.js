@@ -45,4 +45,48 @@ const native = loadNativeModule();
 export const gpu_processor_init = native.gpu_processor_init;
 export const gpu_processor_get_device_count = native.gpu_processor_get_device_count;
 export const gpu_processor_resize_image = native.gpu_processor_resize_image;
+export const gpu_processor_register_kernel = native.gpu_processor_register_kernel;
+export const gpu_processor_launch_kernel = native.gpu_processor_launch_kernel;
+export const gpu_processor_chroma_key = native.gpu_processor_chroma_key;
+export const gpu_processor_composite = native.gpu_processor_composite;
+export const gpu_processor_vignette = native.gpu_processor_vignette;
+export const gpu_processor_pixelate_regions = native.gpu_processor_pixelate_regions;
+export const gpu_processor_blur_regions = native.gpu_processor_blur_regions;
+export const gpu_processor_upload = native.gpu_processor_upload;
+export const gpu_processor_download = native.gpu_processor_download;
+export const gpu_processor_handle_info = native.gpu_processor_handle_info;
+export const gpu_processor_release = native.gpu_processor_release;
+export const gpu_processor_tone_map = native.gpu_processor_tone_map;
+export const gpu_processor_tone_map_handle = native.gpu_processor_tone_map_handle;
+export const gpu_processor_dither = native.gpu_processor_dither;
+export const gpu_processor_summed_area_table = native.gpu_processor_summed_area_table;
+export const gpu_processor_smart_crop = native.gpu_processor_smart_crop;
+export const gpu_processor_create_resize_stream = native.gpu_processor_create_resize_stream;
+export const gpu_processor_push_frame = native.gpu_processor_push_frame;
+export const gpu_processor_close_resize_stream = native.gpu_processor_close_resize_stream;
+export const gpu_processor_interpolate_frames = native.gpu_processor_interpolate_frames;
+export const gpu_processor_exposure_fusion = native.gpu_processor_exposure_fusion;
+export const gpu_processor_blend_panorama = native.gpu_processor_blend_panorama;
+export const gpu_processor_film_grain = native.gpu_processor_film_grain;
+export const gpu_processor_shadow_border_size = native.gpu_processor_shadow_border_size;
+export const gpu_processor_shadow_border = native.gpu_processor_shadow_border;
+export const gpu_processor_round_corners = native.gpu_processor_round_corners;
+export const gpu_processor_apply_mask = native.gpu_processor_apply_mask;
+export const gpu_processor_convert_primaries = native.gpu_processor_convert_primaries;
+export const gpu_processor_stereo_convert = native.gpu_processor_stereo_convert;
+export const gpu_processor_qr_overlay = native.gpu_processor_qr_overlay;
+export const gpu_processor_handle_device_pointer = native.gpu_processor_handle_device_pointer;
+export const gpu_processor_equalize_histogram = native.gpu_processor_equalize_histogram;
+export const gpu_processor_clahe = native.gpu_processor_clahe;
+export const gpu_processor_smooth_skin = native.gpu_processor_smooth_skin;
+export const gpu_processor_define_preset = native.gpu_processor_define_preset;
+export const gpu_processor_run_preset = native.gpu_processor_run_preset;
+export const gpu_processor_run_pipeline = native.gpu_processor_run_pipeline;
+export const gpu_processor_queue_status = native.gpu_processor_queue_status;
+export const gpu_processor_shutdown = native.gpu_processor_shutdown;
+export const gpu_processor_benchmark = native.gpu_processor_benchmark;
+export const gpu_processor_detect_faces = native.gpu_processor_detect_faces;
+export const gpu_processor_temporal_denoise = native.gpu_processor_temporal_denoise;
+export const gpu_processor_apply_curves = native.gpu_processor_apply_curves;
+export const gpu_processor_refine_matte = native.gpu_processor_refine_matte;
 export default native;
@@ -0,0 +1,237 @@
+//! Throughput measurements on the current device, so a node can be checked
+//! before it is put into rotation.
+
+use crate::filters::{FilmGrain, Vignette};
+use crate::pipeline::{run_operations, Operation};
+use crate::primaries::ColorSpace;
+use crate::validation::{dimension, image_bytes};
+use crate::{download, run_gpu, ResizeMode};
+use cudarc::driver::{sys, CudaDevice, CudaSlice};
+use neon::prelude::*;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Sizes measured when `sizes` is left out: 1080p and 4K.
+const DEFAULT_SIZES: &[(usize, usize)] = &[(1920, 1080), (3840, 2160)];
+
+const DEFAULT_ITERATIONS: usize = 5;
+
+struct Measurement {
+    operation: &'static str,
+    width: usize,
+    height: usize,
+    total_ms: f64,
+    min_ms: f64,
+}
+
+/// The operations measured at each size, with typical settings.
+fn benchmark_operations(width: usize, height: usize) -> Vec<(&'static str, Operation)> {
+    let half = |n: usize| (n / 2).max(1);
+    vec![
+        (
+            "resize",
+            Operation::Resize { width: half(width), height: half(height), mode: ResizeMode::Standard },
+        ),
+        (
+            "resize-linear-light",
+            Operation::Resize { width: half(width), height: half(height), mode: ResizeMode::LinearLight },
+        ),
+        (
+            "resize-half-precision",
+            Operation::Resize { width: half(width), height: half(height), mode: ResizeMode::HalfPrecision },
+        ),
+        ("blur", Operation::Blur { sigma: 8.0 }),
+        ("pixelate", Operation::Pixelate { block_size: 16 }),
+        (
+            "vignette",
+            Operation::Vignette(Vignette {
+                strength: 0.5,
+                radius: 0.5,
+                feather: 0.5,
+                center_x: 0.5,
+                center_y: 0.5,
+                color: [0.0, 0.0, 0.0],
+            }),
+        ),
+        (
+            "film-grain",
+            Operation::FilmGrain(FilmGrain { amount: 16.0, grain_size: 1.0, seed: 0, monochrome: true }),
+        ),
+        ("equalize", Operation::Equalize),
+        ("clahe", Operation::Clahe { tiles_x: 8, tiles_y: 8, clip_limit: 2.0 }),
+        ("smooth-skin", Operation::SmoothSkin { strength: 0.5, radius: 8, threshold: 20.0 }),
+        (
+            "convert-primaries",
+            Operation::ConvertPrimaries { from: ColorSpace::Srgb, to: ColorSpace::DisplayP3 },
+        ),
+    ]
+}
+
+/// A deterministic test card: gradients with some texture, so histogram and
+/// edge-aware operations have real work to do.
+fn test_image(width: usize, height: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let texture = ((x.wrapping_mul(7919) ^ y.wrapping_mul(104_729)) & 31) as u8;
+            data.push(((x * 255) / width.max(1)) as u8 ^ texture);
+            data.push(((y * 255) / height.max(1)) as u8);
+            data.push(128u8.wrapping_add(texture));
+            data.push(255);
+        }
+    }
+    data
+}
+
+fn elapsed_ms(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
+}
+
+/// Times `run` `iterations` times after one untimed warm-up call, which also
+/// compiles any kernels it needs. Each call is synchronized before its time
+/// is taken.
+fn measure(
+    device: &Arc<CudaDevice>,
+    iterations: usize,
+    mut run: impl FnMut() -> Result<(), f64>,
+) -> Result<(f64, f64), f64> {
+    run()?;
+    device.synchronize().map_err(|_| -9.0)?; // Copy back failed
+    let mut total_ms = 0.0;
+    let mut min_ms = f64::INFINITY;
+    for _ in 0..iterations {
+        let start = Instant::now();
+        run()?;
+        device.synchronize().map_err(|_| -9.0)?;
+        let ms = elapsed_ms(start);
+        total_ms += ms;
+        min_ms = min_ms.min(ms);
+    }
+    Ok((total_ms, min_ms))
+}
+
+fn benchmark_size(
+    device: &Arc<CudaDevice>,
+    width: usize,
+    height: usize,
+    iterations: usize,
+    measurements: &mut Vec<Measurement>,
+) -> Result<(), f64> {
+    image_bytes(width, height, 4)?;
+    let host = test_image(width, height);
+    let mut record = |operation, (total_ms, min_ms)| {
+        measurements.push(Measurement { operation, width, height, total_ms, min_ms });
+    };
+
+    let upload = measure(device, iterations, || {
+        device.htod_sync_copy(&host).map(drop).map_err(|_| -3.0) // Memory allocation failed
+    })?;
+    record("upload", upload);
+
+    let image: CudaSlice<u8> = device.htod_sync_copy(&host).map_err(|_| -3.0)?;
+    let download_time = measure(device, iterations, || download(device, &image).map(drop))?;
+    record("download", download_time);
+
+    // Each run gets a fresh copy because several operations work in place.
+    for (name, operation) in benchmark_operations(width, height) {
+        let time = measure(device, iterations, || {
+            let copy = image.try_clone().map_err(|_| -3.0)?; // Memory allocation failed
+            run_operations(device, copy, width, height, &[operation]).map(drop)
+        })?;
+        record(name, time);
+    }
+    Ok(())
+}
+
+/// Reads `[{ width, height }, ...]`. Invalid sizes are kept as 0 x 0 and fail
+/// the whole benchmark with -2, like an invalid image would.
+fn sizes_argument(cx: &mut FunctionContext, index: usize) -> NeonResult<Vec<(usize, usize)>> {
+    let value = match cx.argument_opt(index) {
+        Some(value) if !value.is_a::<JsUndefined, _>(cx) && !value.is_a::<JsNull, _>(cx) => value,
+        _ => return Ok(DEFAULT_SIZES.to_vec()),
+    };
+    let sizes = value.downcast_or_throw::<JsArray, _>(cx)?.to_vec(cx)?;
+    let mut parsed = Vec::with_capacity(sizes.len());
+    for size in sizes {
+        let size = size.downcast_or_throw::<JsObject, _>(cx)?;
+        let width = size.get::<JsNumber, _, _>(cx, "width")?.value(cx);
+        let height = size.get::<JsNumber, _, _>(cx, "height")?.value(cx);
+        parsed.push((dimension(width), dimension(height)));
+    }
+    Ok(parsed)
+}
+
+/// `gpu_processor_benchmark(sizes?, iterations?)`
+///
+/// Measures every operation on a synthetic RGBA image at each `{ width,
+/// height }` in `sizes` (1920x1080 and 3840x2160 by default), `iterations`
+/// times (default 5, at most 100) after a warm-up run that also compiles the
+/// kernels. Apart from the `upload` and `download` entries, timings cover a
+/// device-side copy of the input plus the operation, synchronized, but no
+/// host transfers. Blocks the calling thread for the whole run, so call it
+/// before taking traffic.
+///
+/// Returns `{ deviceOrdinal, computeCapability, results }` with one
+/// `{ operation, width, height, iterations, averageMs, minMs,
+/// megapixelsPerSecond }` per operation and size, where
+/// `megapixelsPerSecond` counts input pixels at the average time. Returns a
+/// negative status code if anything fails.
+pub(crate) fn gpu_processor_benchmark(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let sizes = sizes_argument(&mut cx, 0)?;
+    let iterations = match cx.argument_opt(1) {
+        Some(value) => value.downcast_or_throw::<JsNumber, _>(&mut cx)?.value(&mut cx),
+        None => DEFAULT_ITERATIONS as f64,
+    };
+    if !(1.0..=100.0).contains(&iterations) {
+        return cx.throw_range_error("iterations must be between 1 and 100");
+    }
+    let iterations = iterations as usize;
+
+    let result = run_gpu(|device| {
+        let mut measurements = Vec::new();
+        for &(width, height) in &sizes {
+            benchmark_size(device, width, height, iterations, &mut measurements)?;
+        }
+        let major = device.attribute(sys::CUdevice_attribute::CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MAJOR);
+        let minor = device.attribute(sys::CUdevice_attribute::CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MINOR);
+        let capability = match (major, minor) {
+            (Ok(major), Ok(minor)) => format!("{}.{}", major, minor),
+            _ => String::from("unknown"),
+        };
+        Ok((device.ordinal(), capability, measurements))
+    });
+
+    let (ordinal, capability, measurements) = match result {
+        Ok(output) => output,
+        Err(code) => return Ok(cx.number(code).upcast()),
+    };
+    let results = cx.empty_array();
+    for (i, measurement) in measurements.iter().enumerate() {
+        let average_ms = measurement.total_ms / iterations as f64;
+        let megapixels = (measurement.width * measurement.height) as f64 / 1_000_000.0;
+        let entry = cx.empty_object();
+        let value = cx.string(measurement.operation);
+        entry.set(&mut cx, "operation", value)?;
+        let value = cx.number(measurement.width as f64);
+        entry.set(&mut cx, "width", value)?;
+        let value = cx.number(measurement.height as f64);
+        entry.set(&mut cx, "height", value)?;
+        let value = cx.number(iterations as f64);
+        entry.set(&mut cx, "iterations", value)?;
+        let value = cx.number(average_ms);
+        entry.set(&mut cx, "averageMs", value)?;
+        let value = cx.number(measurement.min_ms);
+        entry.set(&mut cx, "minMs", value)?;
+        let value = cx.number(if average_ms > 0.0 { megapixels * 1000.0 / average_ms } else { 0.0 });
+        entry.set(&mut cx, "megapixelsPerSecond", value)?;
+        results.set(&mut cx, i as u32, entry)?;
+    }
+
+    let obj = cx.empty_object();
+    let value = cx.number(ordinal as f64);
+    obj.set(&mut cx, "deviceOrdinal", value)?;
+    let value = cx.string(capability);
+    obj.set(&mut cx, "computeCapability", value)?;
+    obj.set(&mut cx, "results", results)?;
+    Ok(obj.upcast())
+}
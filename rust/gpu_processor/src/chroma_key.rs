@@ -0,0 +1,103 @@
+//! Chroma keying: turns pixels close to a key color transparent.
+
+use crate::{download, launch_config_2d, load_kernel, run_gpu, upload_rgba, output_argument, write_result};
+use crate::validation::dimension_argument;
+use cudarc::driver::LaunchAsync;
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+
+/// `gpu_processor_chroma_key(input, width, height, key_r, key_g, key_b, tolerance, feather, output?)`
+///
+/// Pixels whose chroma lies within `tolerance` of the key color become fully
+/// transparent; alpha ramps back up over the next `feather` units so edges
+/// stay soft. Distances are measured on the CbCr plane (0–255 scale), which
+/// keeps shadows on a green screen keyed along with the lit areas. Key color
+/// spill is removed from the partially transparent edge pixels.
+pub(crate) fn gpu_processor_chroma_key(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let key_r = cx.argument::<JsNumber>(3)?.value(&mut cx) as f32;
+    let key_g = cx.argument::<JsNumber>(4)?.value(&mut cx) as f32;
+    let key_b = cx.argument::<JsNumber>(5)?.value(&mut cx) as f32;
+    let tolerance = cx.argument::<JsNumber>(6)?.value(&mut cx) as f32;
+    let feather = cx.argument::<JsNumber>(7)?.value(&mut cx) as f32;
+    let output_buffer = output_argument(&mut cx, 8)?;
+
+    let result = run_gpu(|device| {
+        let mut image = upload_rgba(device, input_data.as_slice(&cx), width, height)?;
+        let kernel = load_kernel(device, "chroma_key_module", CHROMA_KEY_KERNEL, &["chroma_key"], "chroma_key")?;
+        let params = (
+            &mut image,
+            width as i32,
+            height as i32,
+            key_r,
+            key_g,
+            key_b,
+            tolerance.max(0.0),
+            feather.max(0.0),
+        );
+        unsafe { kernel.launch(launch_config_2d(width, height), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+        download(device, &image)
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+const CHROMA_KEY_KERNEL: &str = r#"
+__device__ __forceinline__ float2 to_cbcr(float r, float g, float b) {
+    return make_float2(
+        128.0f - 0.168736f * r - 0.331264f * g + 0.5f * b,
+        128.0f + 0.5f * r - 0.418688f * g - 0.081312f * b
+    );
+}
+
+extern "C" __global__ void chroma_key(
+    unsigned char* image,
+    int width,
+    int height,
+    float key_r,
+    float key_g,
+    float key_b,
+    float tolerance,
+    float feather
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+
+    if (x >= width || y >= height) return;
+
+    unsigned char* px = image + (y * width + x) * 4;
+    float r = (float)px[0];
+    float g = (float)px[1];
+    float b = (float)px[2];
+
+    float2 key = to_cbcr(key_r, key_g, key_b);
+    float2 cbcr = to_cbcr(r, g, b);
+    float dist = hypotf(cbcr.x - key.x, cbcr.y - key.y);
+
+    float keep;
+    if (dist <= tolerance) {
+        keep = 0.0f;
+    } else if (feather <= 0.0f || dist >= tolerance + feather) {
+        keep = 1.0f;
+    } else {
+        float t = (dist - tolerance) / feather;
+        keep = t * t * (3.0f - 2.0f * t);
+    }
+
+    // Pull the key color out of semi-transparent edges so they don't glow.
+    if (keep > 0.0f && keep < 1.0f) {
+        float spill = 1.0f - keep;
+        float luma = 0.299f * r + 0.587f * g + 0.114f * b;
+        r = r + (luma - r) * spill;
+        g = g + (luma - g) * spill;
+        b = b + (luma - b) * spill;
+    }
+
+    px[0] = (unsigned char)fminf(fmaxf(r, 0.0f), 255.0f);
+    px[1] = (unsigned char)fminf(fmaxf(g, 0.0f), 255.0f);
+    px[2] = (unsigned char)fminf(fmaxf(b, 0.0f), 255.0f);
+    px[3] = (unsigned char)((float)px[3] * keep + 0.5f);
+}
+"#;
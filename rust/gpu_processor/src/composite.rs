@@ -0,0 +1,204 @@
+//! Batch composition of many images onto one canvas, for grid previews and
+//! sprite sheets.
+
+use crate::{download, launch_config_2d, load_kernel, run_gpu, output_argument, write_result};
+use crate::validation::{dimension, dimension_argument, image_bytes, MAX_DIMENSION};
+use cudarc::driver::LaunchAsync;
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+
+/// Number of `i32` fields describing one layer on the device.
+const LAYER_FIELDS: usize = 6;
+
+/// A layer's `x` or `y`: a whole number within `MAX_DIMENSION` of the
+/// canvas origin either way, so the kernel's `x + drawWidth` cannot overflow.
+fn position(value: f64) -> Option<i32> {
+    (value.fract() == 0.0 && value.abs() <= MAX_DIMENSION as f64).then_some(value as i32)
+}
+
+/// `gpu_processor_composite(canvas_width, canvas_height, background, layers, output?)`
+///
+/// `background` is `[r, g, b, a]`. Each layer is
+/// `{ data, width, height, x, y, drawWidth, drawHeight }`; the source RGBA
+/// image is scaled into the destination rectangle and alpha-blended over the
+/// layers before it. All layers are drawn in a single kernel launch.
+/// Returns -2 if any size or position is not a whole number in range, or if
+/// the layers together hold more pixels than one image may.
+pub(crate) fn gpu_processor_composite(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let canvas_width = dimension_argument(&mut cx, 0)?;
+    let canvas_height = dimension_argument(&mut cx, 1)?;
+    let background = cx.argument::<JsArray>(2)?.to_vec(&mut cx)?;
+    let layers = cx.argument::<JsArray>(3)?.to_vec(&mut cx)?;
+    let output_buffer = output_argument(&mut cx, 4)?;
+
+    let mut background_rgba = [0u8; 4];
+    for (slot, value) in background_rgba.iter_mut().zip(background) {
+        *slot = value.downcast_or_throw::<JsNumber, _>(&mut cx)?.value(&mut cx).clamp(0.0, 255.0) as u8;
+    }
+
+    let mut pixels = Vec::new();
+    let mut layer_pixels = 0usize;
+    let mut descriptors = Vec::with_capacity(layers.len() * LAYER_FIELDS);
+    let mut offsets = Vec::with_capacity(layers.len());
+    for layer in layers {
+        let layer = layer.downcast_or_throw::<JsObject, _>(&mut cx)?;
+        let data = layer.get::<JsBuffer, _, _>(&mut cx, "data")?;
+        let field = |cx: &mut FunctionContext, key: &str| -> NeonResult<f64> {
+            Ok(layer.get::<JsNumber, _, _>(cx, key)?.value(cx))
+        };
+        let width = dimension(field(&mut cx, "width")?);
+        let height = dimension(field(&mut cx, "height")?);
+        let x = position(field(&mut cx, "x")?);
+        let y = position(field(&mut cx, "y")?);
+        let draw_width = dimension(field(&mut cx, "drawWidth")?);
+        let draw_height = dimension(field(&mut cx, "drawHeight")?);
+        let (Some(x), Some(y)) = (x, y) else {
+            return Ok(cx.number(-2.0).upcast()); // Invalid input size
+        };
+        if draw_width == 0 || draw_height == 0 {
+            return Ok(cx.number(-2.0).upcast()); // Invalid input size
+        }
+
+        let data = data.as_slice(&cx);
+        if image_bytes(width, height, 4) != Ok(data.len()) {
+            return Ok(cx.number(-2.0).upcast()); // Invalid input size
+        }
+        // All layers are copied into one upload, so together they get the
+        // pixel budget of a single image.
+        layer_pixels += width * height;
+        if let Err(code) = image_bytes(layer_pixels, 1, 4) {
+            return Ok(cx.number(code).upcast());
+        }
+        offsets.push(pixels.len() as u64);
+        pixels.extend_from_slice(data);
+        descriptors.extend_from_slice(&[width as i32, height as i32, x, y, draw_width as i32, draw_height as i32]);
+    }
+
+    let canvas_size = match image_bytes(canvas_width, canvas_height, 4) {
+        Ok(size) => size,
+        Err(code) => return Ok(cx.number(code).upcast()),
+    };
+
+    let result = run_gpu(|device| {
+        let layer_count = offsets.len();
+        if layer_count == 0 {
+            // Nothing to draw; the canvas is just the background.
+            return Ok(background_rgba.repeat(canvas_size / 4));
+        }
+        let dev_pixels = device.htod_sync_copy(&pixels).map_err(|_| -3.0)?; // Memory allocation failed
+        let dev_descriptors = device.htod_sync_copy(&descriptors).map_err(|_| -3.0)?;
+        let dev_offsets = device.htod_sync_copy(&offsets).map_err(|_| -3.0)?;
+        let mut dev_output = device.alloc_zeros::<u8>(canvas_size).map_err(|_| -4.0)?; // Output allocation failed
+
+        let kernel = load_kernel(device, "composite_module", COMPOSITE_KERNEL, &["composite_layers"], "composite_layers")?;
+        let params = (
+            &dev_pixels,
+            &dev_offsets,
+            &dev_descriptors,
+            layer_count as i32,
+            &mut dev_output,
+            canvas_width as i32,
+            canvas_height as i32,
+            u32::from_le_bytes(background_rgba),
+        );
+        unsafe { kernel.launch(launch_config_2d(canvas_width, canvas_height), params) }
+            .map_err(|_| -8.0)?; // Kernel launch failed
+        download(device, &dev_output)
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+const COMPOSITE_KERNEL: &str = r#"
+extern "C" __global__ void composite_layers(
+    const unsigned char* pixels,
+    const unsigned long long* offsets,
+    const int* layers,
+    int layer_count,
+    unsigned char* output,
+    int canvas_width,
+    int canvas_height,
+    unsigned int background
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+
+    if (x >= canvas_width || y >= canvas_height) return;
+
+    float acc[4];
+    for (int c = 0; c < 4; c++) {
+        acc[c] = (float)((background >> (8 * c)) & 0xff);
+    }
+
+    for (int i = 0; i < layer_count; i++) {
+        const int* layer = layers + i * 6;
+        int src_w = layer[0];
+        int src_h = layer[1];
+        int dst_x = layer[2];
+        int dst_y = layer[3];
+        int dst_w = layer[4];
+        int dst_h = layer[5];
+
+        if (dst_w <= 0 || dst_h <= 0) continue;
+        if (x < dst_x || y < dst_y || x >= dst_x + dst_w || y >= dst_y + dst_h) continue;
+
+        const unsigned char* src = pixels + offsets[i];
+
+        // Sample at the pixel center, mapped into the source image.
+        float src_x = ((float)(x - dst_x) + 0.5f) * (float)src_w / (float)dst_w - 0.5f;
+        float src_y = ((float)(y - dst_y) + 0.5f) * (float)src_h / (float)dst_h - 0.5f;
+        src_x = fminf(fmaxf(src_x, 0.0f), (float)(src_w - 1));
+        src_y = fminf(fmaxf(src_y, 0.0f), (float)(src_h - 1));
+
+        int x1 = (int)src_x;
+        int y1 = (int)src_y;
+        int x2 = min(x1 + 1, src_w - 1);
+        int y2 = min(y1 + 1, src_h - 1);
+        float dx = src_x - (float)x1;
+        float dy = src_y - (float)y1;
+
+        float sample[4];
+        for (int c = 0; c < 4; c++) {
+            float v11 = (float)src[(y1 * src_w + x1) * 4 + c];
+            float v12 = (float)src[(y1 * src_w + x2) * 4 + c];
+            float v21 = (float)src[(y2 * src_w + x1) * 4 + c];
+            float v22 = (float)src[(y2 * src_w + x2) * 4 + c];
+            float v1 = v11 * (1.0f - dx) + v12 * dx;
+            float v2 = v21 * (1.0f - dx) + v22 * dx;
+            sample[c] = v1 * (1.0f - dy) + v2 * dy;
+        }
+
+        // Source-over blending in straight alpha.
+        float sa = sample[3] / 255.0f;
+        float da = acc[3] / 255.0f;
+        float out_a = sa + da * (1.0f - sa);
+        for (int c = 0; c < 3; c++) {
+            acc[c] = out_a > 0.0f
+                ? (sample[c] * sa + acc[c] * da * (1.0f - sa)) / out_a
+                : 0.0f;
+        }
+        acc[3] = out_a * 255.0f;
+    }
+
+    unsigned char* px = output + (y * canvas_width + x) * 4;
+    for (int c = 0; c < 4; c++) {
+        px[c] = (unsigned char)fminf(fmaxf(acc[c] + 0.5f, 0.0f), 255.0f);
+    }
+}
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_stay_within_a_dimension_of_the_origin() {
+        assert_eq!(position(0.0), Some(0));
+        assert_eq!(position(-40.0), Some(-40));
+        assert_eq!(position(MAX_DIMENSION as f64), Some(MAX_DIMENSION as i32));
+        assert_eq!(position(-(MAX_DIMENSION as f64)), Some(-(MAX_DIMENSION as i32)));
+        for value in [0.5, MAX_DIMENSION as f64 + 1.0, 3e9, -3e9, f64::NAN, f64::INFINITY] {
+            assert_eq!(position(value), None, "{}", value);
+        }
+    }
+}
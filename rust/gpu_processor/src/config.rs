@@ -0,0 +1,316 @@
+//! Process-wide settings for the addon.
+//!
+//! Defaults come from the environment (`GPU_PROCESSOR_DEVICE`,
+//! `GPU_PROCESSOR_MEMORY_POOL_BYTES`, `GPU_PROCESSOR_MAX_JOBS`,
+//! `GPU_PROCESSOR_LOG_LEVEL`, `GPU_PROCESSOR_MAX_PIXELS`) and can be
+//! overridden by the options object passed to `gpu_processor_init`.
+
+use crate::logging::LogLevel;
+use crate::validation::MAX_PIXELS;
+use lazy_static::lazy_static;
+use neon::prelude::*;
+use std::sync::{Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct GpuConfig {
+    /// CUDA device the addon opens on first use.
+    pub(crate) device_ordinal: usize,
+    /// Bytes the device's default memory pool keeps reserved instead of
+    /// returning freed memory to the driver. 0 leaves the driver default.
+    pub(crate) memory_pool_bytes: u64,
+    /// GPU calls allowed to run at once across all JS threads; calls beyond
+    /// it return -14 straight away. 0 means no limit.
+    pub(crate) max_concurrent_jobs: usize,
+    pub(crate) log_level: LogLevel,
+    /// Lets `gpu_processor_handle_device_pointer` hand out raw device
+    /// addresses. Off unless the application opts in.
+    pub(crate) allow_raw_pointers: bool,
+    /// Largest image, in pixels, any call may read or allocate. Capped at
+    /// `validation::MAX_PIXELS`; never 0, which would reject every image.
+    pub(crate) max_pixels: usize,
+}
+
+impl GpuConfig {
+    fn from_env() -> GpuConfig {
+        fn var<T: std::str::FromStr>(name: &str) -> Option<T> {
+            std::env::var(name).ok()?.trim().parse().ok()
+        }
+        GpuConfig {
+            device_ordinal: var("GPU_PROCESSOR_DEVICE").unwrap_or(0),
+            memory_pool_bytes: var("GPU_PROCESSOR_MEMORY_POOL_BYTES").unwrap_or(0),
+            max_concurrent_jobs: var("GPU_PROCESSOR_MAX_JOBS").unwrap_or(0),
+            log_level: std::env::var("GPU_PROCESSOR_LOG_LEVEL")
+                .ok()
+                .and_then(|name| LogLevel::parse(name.trim()))
+                .unwrap_or(LogLevel::Error),
+            allow_raw_pointers: false,
+            max_pixels: var("GPU_PROCESSOR_MAX_PIXELS").filter(|&pixels| pixels > 0).unwrap_or(MAX_PIXELS),
+        }
+    }
+}
+
+lazy_static! {
+    static ref CONFIG: RwLock<GpuConfig> = RwLock::new(GpuConfig::from_env());
+    static ref JOBS: (Mutex<JobQueue>, Condvar) = (Mutex::new(JobQueue::new()), Condvar::new());
+}
+
+/// Length of the windows utilization is measured over.
+const UTILIZATION_WINDOW: Duration = Duration::from_secs(1);
+
+/// Bookkeeping for `limited` calls, shared by every JS thread.
+struct JobQueue {
+    running: usize,
+    /// Calls turned away because every slot was taken.
+    rejected: u64,
+    /// Moving average of how long a job holds its slot.
+    average_job_ms: f64,
+    /// Set while at least one job is running.
+    busy_since: Option<Instant>,
+    window_start: Instant,
+    window_busy: Duration,
+    /// Busy time and length of the last closed window, so a fresh window
+    /// still has history.
+    previous_window: (Duration, Duration),
+}
+
+impl JobQueue {
+    fn new() -> JobQueue {
+        JobQueue {
+            running: 0,
+            rejected: 0,
+            average_job_ms: 0.0,
+            busy_since: None,
+            window_start: Instant::now(),
+            window_busy: Duration::ZERO,
+            previous_window: (Duration::ZERO, Duration::ZERO),
+        }
+    }
+
+    /// Moves busy time up to `now` into the current window, closing it once
+    /// it is a full window long.
+    fn account(&mut self, now: Instant) {
+        if let Some(since) = self.busy_since {
+            self.window_busy += now - since;
+            self.busy_since = Some(now);
+        }
+        let length = now - self.window_start;
+        if length >= UTILIZATION_WINDOW {
+            // Scaled to one window so a long idle stretch does not drown out
+            // the load that follows it.
+            let share = self.window_busy.as_secs_f64() / length.as_secs_f64();
+            self.previous_window = (UTILIZATION_WINDOW.mul_f64(share.min(1.0)), UTILIZATION_WINDOW);
+            self.window_start = now;
+            self.window_busy = Duration::ZERO;
+        }
+    }
+
+    fn utilization(&mut self) -> f64 {
+        let now = Instant::now();
+        self.account(now);
+        let busy = self.previous_window.0 + self.window_busy;
+        let length = self.previous_window.1 + (now - self.window_start);
+        if length.is_zero() { 0.0 } else { (busy.as_secs_f64() / length.as_secs_f64()).min(1.0) }
+    }
+
+    fn estimated_wait_ms(&self, limit: usize) -> f64 {
+        if limit == 0 || self.running < limit {
+            return 0.0;
+        }
+        // Nothing queues, so a retry only needs one running job to finish.
+        self.average_job_ms
+    }
+}
+
+pub(crate) fn config() -> GpuConfig {
+    *CONFIG.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+pub(crate) fn set_config(config: GpuConfig) {
+    *CONFIG.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = config;
+}
+
+fn is_non_negative_integer(value: f64) -> bool {
+    value.is_finite() && value >= 0.0 && value.fract() == 0.0
+}
+
+fn non_negative_integer(cx: &mut FunctionContext, obj: Handle<JsObject>, key: &str) -> NeonResult<Option<f64>> {
+    match obj.get_opt::<JsNumber, _, _>(cx, key)? {
+        Some(value) => {
+            let value = value.value(cx);
+            if !is_non_negative_integer(value) {
+                return cx.throw_range_error(format!("{} must be a non-negative integer", key));
+            }
+            Ok(Some(value))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Reads `{ deviceOrdinal, memoryPoolBytes, maxConcurrentJobs, logLevel,
+/// verbose, allowRawPointers, maxPixels }` from argument `index` on top of
+/// the current settings. Missing keys keep their current value; `verbose:
+/// true` is shorthand for `logLevel: "debug"`. `maxPixels: 0` throws, and
+/// `GPU_PROCESSOR_MAX_PIXELS=0` is ignored like any unparsable value.
+pub(crate) fn options_argument(cx: &mut FunctionContext, index: usize) -> NeonResult<GpuConfig> {
+    let mut config = config();
+    let obj = match cx.argument_opt(index) {
+        Some(value) if !value.is_a::<JsUndefined, _>(cx) => value.downcast_or_throw::<JsObject, _>(cx)?,
+        _ => return Ok(config),
+    };
+
+    if let Some(ordinal) = non_negative_integer(cx, obj, "deviceOrdinal")? {
+        config.device_ordinal = ordinal as usize;
+    }
+    if let Some(bytes) = non_negative_integer(cx, obj, "memoryPoolBytes")? {
+        config.memory_pool_bytes = bytes as u64;
+    }
+    if let Some(jobs) = non_negative_integer(cx, obj, "maxConcurrentJobs")? {
+        config.max_concurrent_jobs = jobs as usize;
+    }
+    if let Some(pixels) = non_negative_integer(cx, obj, "maxPixels")? {
+        if pixels == 0.0 {
+            return cx.throw_range_error("maxPixels must be at least 1");
+        }
+        config.max_pixels = pixels as usize;
+    }
+    if let Some(verbose) = obj.get_opt::<JsBoolean, _, _>(cx, "verbose")?
+        && verbose.value(cx)
+    {
+        config.log_level = LogLevel::Debug;
+    }
+    if let Some(allow) = obj.get_opt::<JsBoolean, _, _>(cx, "allowRawPointers")? {
+        config.allow_raw_pointers = allow.value(cx);
+    }
+    if let Some(level) = obj.get_opt::<JsString, _, _>(cx, "logLevel")? {
+        let name = level.value(cx);
+        config.log_level = match LogLevel::parse(&name) {
+            Some(level) => level,
+            None => return cx.throw_type_error(format!("Unknown log level `{}`", name)),
+        };
+    }
+    Ok(config)
+}
+
+struct JobPermit {
+    started: Instant,
+}
+
+impl JobPermit {
+    /// Takes a job slot, or returns `None` when all of them are in use.
+    /// Waiting here would block the JS thread, and with it the event loop
+    /// that the running jobs' callers need to make progress.
+    fn try_acquire() -> Option<JobPermit> {
+        let mut jobs = JOBS.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let limit = config().max_concurrent_jobs;
+        if limit != 0 && jobs.running >= limit {
+            jobs.rejected += 1;
+            return None;
+        }
+        jobs.running += 1;
+        let started = Instant::now();
+        if jobs.busy_since.is_none() {
+            jobs.account(started);
+            jobs.busy_since = Some(started);
+        }
+        Some(JobPermit { started })
+    }
+}
+
+impl Drop for JobPermit {
+    fn drop(&mut self) {
+        let (jobs, available) = &*JOBS;
+        let mut jobs = jobs.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let now = Instant::now();
+        let elapsed_ms = (now - self.started).as_secs_f64() * 1000.0;
+        jobs.average_job_ms = if jobs.average_job_ms == 0.0 {
+            elapsed_ms
+        } else {
+            jobs.average_job_ms * 0.8 + elapsed_ms * 0.2
+        };
+        jobs.running -= 1;
+        if jobs.running == 0 {
+            jobs.account(now);
+            jobs.busy_since = None;
+        }
+        // Wakes `wait_for_idle`.
+        available.notify_all();
+    }
+}
+
+/// Blocks until no `limited` call is running. Calls are synchronous, so the
+/// ones it waits for are on other JS threads.
+pub(crate) fn wait_for_idle() {
+    let (jobs, changed) = &*JOBS;
+    let mut jobs = jobs.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    while jobs.running > 0 {
+        jobs = changed.wait(jobs).unwrap_or_else(|poisoned| poisoned.into_inner());
+    }
+}
+
+/// `gpu_processor_queue_status()`
+///
+/// Returns `{ runningJobs, rejectedJobs, maxConcurrentJobs, averageJobMs,
+/// estimatedWaitMs, deviceUtilization }` for load shedding. `rejectedJobs`
+/// counts calls that returned -14 because every `maxConcurrentJobs` slot was
+/// taken, and `estimatedWaitMs` is how long to wait before retrying one,
+/// from recent job durations (0 while a slot is free). `deviceUtilization`
+/// (0–1) is the share of the last one to two seconds in which at least one
+/// job of this process was running; it does not see other processes using
+/// the GPU.
+pub(crate) fn gpu_processor_queue_status(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let limit = config().max_concurrent_jobs;
+    let (running, rejected, average_job_ms, estimated_wait_ms, utilization) = {
+        let mut jobs = JOBS.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let utilization = jobs.utilization();
+        (jobs.running, jobs.rejected, jobs.average_job_ms, jobs.estimated_wait_ms(limit), utilization)
+    };
+
+    let obj = cx.empty_object();
+    let value = cx.number(running as f64);
+    obj.set(&mut cx, "runningJobs", value)?;
+    let value = cx.number(rejected as f64);
+    obj.set(&mut cx, "rejectedJobs", value)?;
+    let value = cx.number(limit as f64);
+    obj.set(&mut cx, "maxConcurrentJobs", value)?;
+    let value = cx.number(average_job_ms);
+    obj.set(&mut cx, "averageJobMs", value)?;
+    let value = cx.number(estimated_wait_ms);
+    obj.set(&mut cx, "estimatedWaitMs", value)?;
+    let value = cx.number(utilization);
+    obj.set(&mut cx, "deviceUtilization", value)?;
+    Ok(obj)
+}
+
+/// Wraps an exported GPU operation so it returns -14 (queue full) instead
+/// of running when all `maxConcurrentJobs` slots are taken.
+pub(crate) fn limited<V: Value>(
+    f: fn(FunctionContext) -> JsResult<V>,
+) -> impl Fn(FunctionContext) -> JsResult<JsValue> + 'static {
+    move |mut cx| match JobPermit::try_acquire() {
+        Some(_permit) => f(cx).map(|value| value.upcast()),
+        None => Ok(cx.number(-14.0).upcast()), // Queue full
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_take_only_non_negative_integers() {
+        for value in [0.0, 1.0, 4096.0, 9_007_199_254_740_991.0] {
+            assert!(is_non_negative_integer(value), "{}", value);
+        }
+        for value in [-1.0, -0.5, 0.5, 1e-9, f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert!(!is_non_negative_integer(value), "{}", value);
+        }
+    }
+
+    #[test]
+    fn log_levels_parse_by_name() {
+        assert!(matches!(LogLevel::parse("debug"), Some(LogLevel::Debug)));
+        assert!(matches!(LogLevel::parse("error"), Some(LogLevel::Error)));
+        assert!(LogLevel::parse("loud").is_none());
+    }
+}
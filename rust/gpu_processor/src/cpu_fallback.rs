@@ -0,0 +1,60 @@
+//! Host implementation of resize, used when the GPU is missing or keeps
+//! failing. No other operation has one.
+//!
+//! It reproduces the kernel's math so switching paths does not change the
+//! output beyond float rounding.
+
+use crate::validation::image_bytes;
+
+/// Mirrors `bilinear_resize` and `bilinear_resize_linear`.
+pub(crate) fn bilinear_resize(
+    input: &[u8],
+    input_width: usize,
+    input_height: usize,
+    output_width: usize,
+    output_height: usize,
+    linear_light: bool,
+) -> Result<Vec<u8>, f64> {
+    if input.len() != image_bytes(input_width, input_height, 4)? {
+        return Err(-2.0); // Invalid input size
+    }
+    let output_size = image_bytes(output_width, output_height, 4)?;
+
+    let decode = |v: f32| {
+        let v = v / 255.0;
+        if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+    };
+    let encode = |v: f32| {
+        let v = v.clamp(0.0, 1.0);
+        255.0 * if v <= 0.0031308 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 }
+    };
+
+    let mut output = vec![0u8; output_size];
+    for y in 0..output_height {
+        let src_y = y as f32 * input_height as f32 / output_height as f32;
+        let y1 = src_y as usize;
+        let y2 = (y1 + 1).min(input_height - 1);
+        let dy = src_y - y1 as f32;
+        for x in 0..output_width {
+            let src_x = x as f32 * input_width as f32 / output_width as f32;
+            let x1 = src_x as usize;
+            let x2 = (x1 + 1).min(input_width - 1);
+            let dx = src_x - x1 as f32;
+            for c in 0..4 {
+                let tap = |px: usize, py: usize| {
+                    let v = input[(py * input_width + px) * 4 + c] as f32;
+                    if linear_light && c < 3 { decode(v) } else { v }
+                };
+                let val1 = tap(x1, y1) * (1.0 - dx) + tap(x2, y1) * dx;
+                let val2 = tap(x1, y2) * (1.0 - dx) + tap(x2, y2) * dx;
+                let value = val1 * (1.0 - dy) + val2 * dy;
+                output[(y * output_width + x) * 4 + c] = if linear_light && c < 3 {
+                    (encode(value) + 0.5) as u8
+                } else {
+                    value.clamp(0.0, 255.0) as u8
+                };
+            }
+        }
+    }
+    Ok(output)
+}
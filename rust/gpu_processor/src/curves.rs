@@ -0,0 +1,159 @@
+//! Tone curves and color grading through per-channel lookup tables.
+//!
+//! Curves are combined into one table per channel on the host, so any mix of
+//! master, per-channel and parametric curves costs a single lookup per
+//! pixel on the device.
+
+use crate::validation::dimension_argument;
+use crate::{download, launch_config_2d, load_kernel, run_gpu, upload_rgba, output_argument, write_result};
+use cudarc::driver::{CudaDevice, CudaSlice, LaunchAsync};
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+use std::sync::Arc;
+
+type Lut = [u8; 256];
+
+const IDENTITY: Lut = {
+    let mut lut = [0u8; 256];
+    let mut i = 0;
+    while i < 256 {
+        lut[i] = i as u8;
+        i += 1;
+    }
+    lut
+};
+
+/// `((v ^ (1 / gamma)) - 0.5) * contrast + 0.5 + brightness` on the 0–1
+/// scale, rounded and clamped.
+fn parametric_lut(brightness: f64, contrast: f64, gamma: f64) -> Lut {
+    let mut lut = [0u8; 256];
+    for (i, slot) in lut.iter_mut().enumerate() {
+        let v = (i as f64 / 255.0).powf(1.0 / gamma);
+        let v = (v - 0.5) * contrast + 0.5 + brightness / 255.0;
+        *slot = (v * 255.0).round().clamp(0.0, 255.0) as u8;
+    }
+    lut
+}
+
+/// Reads one curve: 256 output levels as an array, Buffer or Uint8Array, or
+/// `{ brightness, contrast, gamma }`.
+fn curve_value(cx: &mut FunctionContext, name: &str, value: Handle<JsValue>) -> NeonResult<Lut> {
+    let mut lut = [0u8; 256];
+    if let Ok(array) = value.downcast::<JsTypedArray<u8>, _>(cx) {
+        let entries = array.as_slice(cx);
+        if entries.len() != 256 {
+            return cx.throw_range_error(format!("curve `{}` must have 256 entries", name));
+        }
+        lut.copy_from_slice(entries);
+    } else if let Ok(array) = value.downcast::<JsArray, _>(cx) {
+        let entries = array.to_vec(cx)?;
+        if entries.len() != 256 {
+            return cx.throw_range_error(format!("curve `{}` must have 256 entries", name));
+        }
+        for (slot, entry) in lut.iter_mut().zip(entries) {
+            let level = entry.downcast_or_throw::<JsNumber, _>(cx)?.value(cx);
+            *slot = level.round().clamp(0.0, 255.0) as u8;
+        }
+    } else {
+        let params = value.downcast_or_throw::<JsObject, _>(cx)?;
+        let mut setting = |key: &str, default: f64| -> NeonResult<f64> {
+            Ok(params.get_opt::<JsNumber, _, _>(cx, key)?.map_or(default, |v| v.value(cx)))
+        };
+        let brightness = setting("brightness", 0.0)?;
+        let contrast = setting("contrast", 1.0)?;
+        let gamma = setting("gamma", 1.0)?;
+        if !(-255.0..=255.0).contains(&brightness) {
+            return cx.throw_range_error(format!("curve `{}`: brightness must be between -255 and 255", name));
+        }
+        if !(0.0..=16.0).contains(&contrast) {
+            return cx.throw_range_error(format!("curve `{}`: contrast must be between 0 and 16", name));
+        }
+        if gamma.is_nan() || gamma <= 0.0 {
+            return cx.throw_range_error(format!("curve `{}`: gamma must be positive", name));
+        }
+        lut = parametric_lut(brightness, contrast, gamma);
+    }
+    Ok(lut)
+}
+
+/// Reads `{ rgb?, r?, g?, b?, a? }` and returns the combined table for each
+/// of the four channels, `rgb` applied before the color channel's own curve.
+fn curves_argument(cx: &mut FunctionContext, index: usize) -> NeonResult<[Lut; 4]> {
+    let curves = cx.argument::<JsObject>(index)?;
+    let mut read = |name: &str| -> NeonResult<Option<Lut>> {
+        match curves.get_opt::<JsValue, _, _>(cx, name)? {
+            Some(value) if !value.is_a::<JsUndefined, _>(cx) && !value.is_a::<JsNull, _>(cx) => {
+                Ok(Some(curve_value(cx, name, value)?))
+            }
+            _ => Ok(None),
+        }
+    };
+    let master = read("rgb")?.unwrap_or(IDENTITY);
+    let mut tables = [IDENTITY; 4];
+    for (c, name) in ["r", "g", "b", "a"].into_iter().enumerate() {
+        let own = read(name)?.unwrap_or(IDENTITY);
+        tables[c] = if c < 3 { std::array::from_fn(|v| own[master[v] as usize]) } else { own };
+    }
+    Ok(tables)
+}
+
+/// Maps every channel of `image` through its table in place.
+pub(crate) fn apply_curves(
+    device: &Arc<CudaDevice>,
+    image: &mut CudaSlice<u8>,
+    width: usize,
+    height: usize,
+    tables: &[Lut; 4],
+) -> Result<(), f64> {
+    let tables = device.htod_sync_copy(tables.as_flattened()).map_err(|_| -3.0)?; // Memory allocation failed
+    let kernel = load_kernel(device, "curves_module", CURVES_KERNEL, &["apply_curves"], "apply_curves")?;
+    let params = (image, width as i32, height as i32, &tables);
+    unsafe { kernel.launch(launch_config_2d(width, height), params) }.map_err(|_| -8.0) // Kernel launch failed
+}
+
+/// `gpu_processor_apply_curves(input, width, height, curves, output?)`
+///
+/// Applies tone curves to an RGBA image in one pass. `curves` has any of
+/// `rgb` (master, applied to red, green and blue first), `r`, `g`, `b` and
+/// `a`; channels without a curve are left as they are. Each curve is either
+/// 256 output levels, indexed by input level, as an array, Buffer or
+/// Uint8Array, or a parametric `{ brightness, contrast, gamma }`: gamma
+/// (default 1) is applied first, then contrast around mid-gray (default 1),
+/// then brightness in levels (-255–255, default 0).
+pub(crate) fn gpu_processor_apply_curves(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let tables = curves_argument(&mut cx, 3)?;
+    let output_buffer = output_argument(&mut cx, 4)?;
+
+    let result = run_gpu(|device| {
+        let mut image = upload_rgba(device, input_data.as_slice(&cx), width, height)?;
+        apply_curves(device, &mut image, width, height, &tables)?;
+        download(device, &image)
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+const CURVES_KERNEL: &str = r#"
+// tables: 256 entries for each of r, g, b, a, staged in shared memory.
+extern "C" __global__ void apply_curves(
+    unsigned char* image,
+    int width,
+    int height,
+    const unsigned char* tables
+) {
+    __shared__ unsigned char lut[1024];
+    int t = threadIdx.y * blockDim.x + threadIdx.x;
+    for (int i = t; i < 1024; i += blockDim.x * blockDim.y) lut[i] = tables[i];
+    __syncthreads();
+
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= width || y >= height) return;
+
+    unsigned char* px = image + (y * width + x) * 4;
+    for (int c = 0; c < 4; c++) px[c] = lut[c * 256 + px[c]];
+}
+"#;
@@ -0,0 +1,234 @@
+//! Runtime registration of user-supplied CUDA kernels.
+//!
+//! Kernels are compiled with NVRTC when they are registered and launched
+//! through a single generic entry point that marshals typed parameters from
+//! JS, so custom effects can ship without changes to this crate.
+
+use crate::gpu_device;
+use cudarc::driver::{result, sys, CudaDevice, CudaSlice, DevicePtr, DeviceSlice};
+use cudarc::nvrtc::compile_ptx;
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{c_void, CString};
+use std::sync::Arc;
+
+struct CustomKernel {
+    /// Keeps the context the module was loaded into alive.
+    device: Arc<CudaDevice>,
+    module: sys::CUmodule,
+    function: sys::CUfunction,
+}
+
+impl Drop for CustomKernel {
+    fn drop(&mut self) {
+        if self.device.bind_to_thread().is_ok() {
+            let _ = unsafe { result::module::unload(self.module) };
+        }
+    }
+}
+
+/// Unloads every kernel registered on this thread.
+pub(crate) fn unregister_all() {
+    CUSTOM_KERNELS.with(|kernels| kernels.borrow_mut().clear());
+}
+
+thread_local! {
+    /// Each JS thread registers its own kernels; names never clash across
+    /// workers and modules are unloaded when the worker exits.
+    static CUSTOM_KERNELS: RefCell<HashMap<String, CustomKernel>> = RefCell::new(HashMap::new());
+}
+
+/// A single launch parameter, kept alive until the kernel has finished.
+enum KernelArg {
+    Buffer(CudaSlice<u8>, sys::CUdeviceptr),
+    I32(i32),
+    U32(u32),
+    I64(i64),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+}
+
+impl KernelArg {
+    fn as_kernel_param(&mut self) -> *mut c_void {
+        match self {
+            KernelArg::Buffer(_, ptr) => ptr as *mut sys::CUdeviceptr as *mut c_void,
+            KernelArg::I32(v) => v as *mut i32 as *mut c_void,
+            KernelArg::U32(v) => v as *mut u32 as *mut c_void,
+            KernelArg::I64(v) => v as *mut i64 as *mut c_void,
+            KernelArg::U64(v) => v as *mut u64 as *mut c_void,
+            KernelArg::F32(v) => v as *mut f32 as *mut c_void,
+            KernelArg::F64(v) => v as *mut f64 as *mut c_void,
+        }
+    }
+}
+
+/// `gpu_processor_register_kernel(name, cuda_source, entry_point)`
+///
+/// Compiles `cuda_source` and registers `entry_point` under `name`.
+/// Registering an existing name replaces the previous kernel.
+pub(crate) fn gpu_processor_register_kernel(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let name = cx.argument::<JsString>(0)?.value(&mut cx);
+    let cuda_source = cx.argument::<JsString>(1)?.value(&mut cx);
+    let entry_point = cx.argument::<JsString>(2)?.value(&mut cx);
+
+    let device = match gpu_device() {
+        Ok(dev) => dev,
+        Err(code) => return Ok(cx.number(code)),
+    };
+
+    if name.is_empty() || entry_point.is_empty() {
+        return Ok(cx.number(-10.0)); // Invalid kernel name
+    }
+    let entry_point = match CString::new(entry_point) {
+        Ok(s) => s,
+        Err(_) => return Ok(cx.number(-10.0)), // Invalid kernel name
+    };
+
+    let ptx = match compile_ptx(cuda_source) {
+        Ok(ptx) => ptx,
+        Err(_) => return Ok(cx.number(-7.0)), // Kernel compilation failed
+    };
+    let image = match CString::new(ptx.to_src()) {
+        Ok(s) => s,
+        Err(_) => return Ok(cx.number(-7.0)), // Kernel compilation failed
+    };
+
+    if device.bind_to_thread().is_err() {
+        return Ok(cx.number(-5.0)); // Module loading failed
+    }
+    let module = match unsafe { result::module::load_data(image.as_ptr() as *const c_void) } {
+        Ok(m) => m,
+        Err(_) => return Ok(cx.number(-5.0)), // Module loading failed
+    };
+    let function = match unsafe { result::module::get_function(module, entry_point) } {
+        Ok(f) => f,
+        Err(_) => {
+            let _ = unsafe { result::module::unload(module) };
+            return Ok(cx.number(-6.0)); // Function loading failed
+        }
+    };
+
+    // Dropping a replaced kernel unloads its module.
+    CUSTOM_KERNELS.with(|kernels| kernels.borrow_mut().insert(name, CustomKernel { device, module, function }));
+
+    Ok(cx.number(0.0)) // Success
+}
+
+/// Reads a launch dimension given either as a number or as `[x, y, z]`.
+fn read_dim3<'a>(
+    cx: &mut FunctionContext<'a>,
+    config: Handle<'a, JsObject>,
+    key: &str,
+) -> NeonResult<(u32, u32, u32)> {
+    let value: Handle<JsValue> = config.get(cx, key)?;
+    if let Ok(n) = value.downcast::<JsNumber, _>(cx) {
+        return Ok((n.value(cx) as u32, 1, 1));
+    }
+    let dims = match value.downcast::<JsArray, _>(cx) {
+        Ok(array) => array.to_vec(cx)?,
+        Err(_) => return cx.throw_type_error(format!("`{}` must be a number or an array", key)),
+    };
+    let mut out = [1u32; 3];
+    for (slot, dim) in out.iter_mut().zip(dims) {
+        *slot = dim.downcast_or_throw::<JsNumber, _>(cx)?.value(cx) as u32;
+    }
+    Ok((out[0], out[1], out[2]))
+}
+
+/// `gpu_processor_launch_kernel(name, { grid, block, sharedMemBytes }, params)`
+///
+/// Launches a registered kernel. Each entry in `params` is an object of the
+/// form `{ type, value, output }` where `type` is one of `i32`, `u32`, `i64`,
+/// `u64`, `f32`, `f64` or `buffer`. Buffers are copied to the device before
+/// the launch and, when `output` is true, copied back afterwards.
+pub(crate) fn gpu_processor_launch_kernel(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let name = cx.argument::<JsString>(0)?.value(&mut cx);
+    let config = cx.argument::<JsObject>(1)?;
+    let params = cx.argument::<JsArray>(2)?.to_vec(&mut cx)?;
+
+    let grid_dim = read_dim3(&mut cx, config, "grid")?;
+    let block_dim = read_dim3(&mut cx, config, "block")?;
+    let shared_mem_bytes = match config.get_opt::<JsNumber, _, _>(&mut cx, "sharedMemBytes")? {
+        Some(n) => n.value(&mut cx) as u32,
+        None => 0,
+    };
+
+    let device = match gpu_device() {
+        Ok(dev) => dev,
+        Err(code) => return Ok(cx.number(code)),
+    };
+
+    let mut args = Vec::with_capacity(params.len());
+    let mut write_backs = Vec::new();
+    for param in params {
+        let param = param.downcast_or_throw::<JsObject, _>(&mut cx)?;
+        let ty = param.get::<JsString, _, _>(&mut cx, "type")?.value(&mut cx);
+        let arg = match ty.as_str() {
+            "buffer" => {
+                let buffer = param.get::<JsBuffer, _, _>(&mut cx, "value")?;
+                let output = match param.get_opt::<JsBoolean, _, _>(&mut cx, "output")? {
+                    Some(b) => b.value(&mut cx),
+                    None => false,
+                };
+                let memory = match device.htod_sync_copy(buffer.as_slice(&cx)) {
+                    Ok(mem) => mem,
+                    Err(_) => return Ok(cx.number(-3.0)), // Memory allocation failed
+                };
+                if output {
+                    write_backs.push((args.len(), buffer));
+                }
+                let ptr = *memory.device_ptr();
+                KernelArg::Buffer(memory, ptr)
+            }
+            scalar => {
+                let value = param.get::<JsNumber, _, _>(&mut cx, "value")?.value(&mut cx);
+                match scalar {
+                    "i32" => KernelArg::I32(value as i32),
+                    "u32" => KernelArg::U32(value as u32),
+                    "i64" => KernelArg::I64(value as i64),
+                    "u64" => KernelArg::U64(value as u64),
+                    "f32" => KernelArg::F32(value as f32),
+                    "f64" => KernelArg::F64(value),
+                    other => return cx.throw_type_error(format!("Unknown parameter type `{}`", other)),
+                }
+            }
+        };
+        args.push(arg);
+    }
+
+    let function = match CUSTOM_KERNELS.with(|kernels| kernels.borrow().get(&name).map(|k| k.function)) {
+        Some(function) => function,
+        None => return Ok(cx.number(-11.0)), // Kernel not registered
+    };
+
+    let mut kernel_params: Vec<*mut c_void> = args.iter_mut().map(KernelArg::as_kernel_param).collect();
+
+    let launched = device.bind_to_thread().and_then(|_| unsafe {
+        result::launch_kernel(
+            function,
+            grid_dim,
+            block_dim,
+            shared_mem_bytes,
+            *device.cu_stream(),
+            &mut kernel_params,
+        )
+    });
+    if launched.is_err() || device.synchronize().is_err() {
+        return Ok(cx.number(-8.0)); // Kernel launch failed
+    }
+
+    for (index, mut buffer) in write_backs {
+        if let KernelArg::Buffer(memory, _) = &args[index] {
+            let mut host = vec![0u8; memory.len()];
+            if device.dtoh_sync_copy_into(memory, &mut host).is_err() {
+                return Ok(cx.number(-9.0)); // Copy back failed
+            }
+            buffer.as_mut_slice(&mut cx).copy_from_slice(&host);
+        }
+    }
+
+    Ok(cx.number(0.0)) // Success
+}
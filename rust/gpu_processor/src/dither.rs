@@ -0,0 +1,169 @@
+//! Palette reduction with dithering, for GIF and indexed-PNG export.
+
+use crate::{download, launch_config_2d, load_kernel, run_gpu, upload_rgba, output_argument, write_result};
+use crate::validation::dimension_argument;
+use cudarc::driver::{LaunchAsync, LaunchConfig};
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+
+/// Rows dithered concurrently per band in Floyd–Steinberg mode.
+const ERROR_DIFFUSION_ROWS: u32 = 1024;
+
+/// `gpu_processor_dither(input, width, height, palette, method, output?)`
+///
+/// Maps every pixel to the nearest entry of `palette` (`[[r, g, b, a?], ...]`,
+/// at most 256 entries) and writes one palette index per pixel into `output`.
+/// `method` is `"ordered"` (8x8 Bayer) or `"floyd-steinberg"`. Error diffusion
+/// is inherently sequential, so rows run as a staggered wavefront where each
+/// row trails the one above it by two pixels.
+pub(crate) fn gpu_processor_dither(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let palette_entries = cx.argument::<JsArray>(3)?.to_vec(&mut cx)?;
+    let method = cx.argument::<JsString>(4)?.value(&mut cx);
+    let output_buffer = output_argument(&mut cx, 5)?;
+
+    if palette_entries.is_empty() || palette_entries.len() > 256 {
+        return cx.throw_range_error("palette must have between 1 and 256 entries");
+    }
+    let mut palette = Vec::with_capacity(palette_entries.len() * 4);
+    for entry in palette_entries {
+        let channels = entry.downcast_or_throw::<JsArray, _>(&mut cx)?.to_vec(&mut cx)?;
+        let mut rgba = [0u8, 0, 0, 255];
+        for (slot, value) in rgba.iter_mut().zip(channels) {
+            *slot = value.downcast_or_throw::<JsNumber, _>(&mut cx)?.value(&mut cx).clamp(0.0, 255.0) as u8;
+        }
+        palette.extend_from_slice(&rgba);
+    }
+    let palette_len = (palette.len() / 4) as i32;
+
+    let function = match method.as_str() {
+        "ordered" => "dither_ordered",
+        "floyd-steinberg" => "dither_floyd_steinberg",
+        _ => return cx.throw_type_error(format!("Unknown dithering method `{}`", method)),
+    };
+
+    let result = run_gpu(|device| {
+        let image = upload_rgba(device, input_data.as_slice(&cx), width, height)?;
+        let dev_palette = device.htod_sync_copy(&palette).map_err(|_| -3.0)?; // Memory allocation failed
+        let mut indices = device.alloc_zeros::<u8>(width * height).map_err(|_| -4.0)?; // Output allocation failed
+        let kernel = load_kernel(device, "dither_module", DITHER_KERNEL, DITHER_FUNCTIONS, function)?;
+
+        if function == "dither_ordered" {
+            let params = (&image, width as i32, height as i32, &dev_palette, palette_len, &mut indices);
+            unsafe { kernel.launch(launch_config_2d(width, height), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+        } else {
+            let mut error = device.alloc_zeros::<f32>(width * height * 4).map_err(|_| -4.0)?; // Output allocation failed
+            let cfg = LaunchConfig {
+                grid_dim: (1, 1, 1),
+                block_dim: (ERROR_DIFFUSION_ROWS.min(height as u32), 1, 1),
+                shared_mem_bytes: 0,
+            };
+            let params = (&image, width as i32, height as i32, &dev_palette, palette_len, &mut error, &mut indices);
+            unsafe { kernel.launch(cfg, params) }.map_err(|_| -8.0)?; // Kernel launch failed
+        }
+        download(device, &indices)
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+const DITHER_FUNCTIONS: &[&str] = &["dither_ordered", "dither_floyd_steinberg"];
+
+const DITHER_KERNEL: &str = r#"
+__device__ int nearest_entry(const unsigned char* palette, int palette_len, float r, float g, float b, float a) {
+    int best = 0;
+    float best_dist = 3.4e38f;
+    for (int i = 0; i < palette_len; i++) {
+        const unsigned char* p = palette + i * 4;
+        float dr = r - (float)p[0];
+        float dg = g - (float)p[1];
+        float db = b - (float)p[2];
+        float da = a - (float)p[3];
+        float dist = dr * dr + dg * dg + db * db + da * da;
+        if (dist < best_dist) {
+            best_dist = dist;
+            best = i;
+        }
+    }
+    return best;
+}
+
+__constant__ unsigned char BAYER_8X8[64] = {
+     0, 32,  8, 40,  2, 34, 10, 42,
+    48, 16, 56, 24, 50, 18, 58, 26,
+    12, 44,  4, 36, 14, 46,  6, 38,
+    60, 28, 52, 20, 62, 30, 54, 22,
+     3, 35, 11, 43,  1, 33,  9, 41,
+    51, 19, 59, 27, 49, 17, 57, 25,
+    15, 47,  7, 39, 13, 45,  5, 37,
+    63, 31, 55, 23, 61, 29, 53, 21
+};
+
+extern "C" __global__ void dither_ordered(
+    const unsigned char* image,
+    int width,
+    int height,
+    const unsigned char* palette,
+    int palette_len,
+    unsigned char* indices
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= width || y >= height) return;
+
+    // Spread the threshold over roughly one palette step per channel.
+    float spread = 255.0f / cbrtf((float)palette_len);
+    float offset = ((float)BAYER_8X8[(y & 7) * 8 + (x & 7)] / 64.0f - 0.5f) * spread;
+
+    const unsigned char* px = image + (y * width + x) * 4;
+    indices[y * width + x] = (unsigned char)nearest_entry(
+        palette, palette_len,
+        (float)px[0] + offset, (float)px[1] + offset, (float)px[2] + offset, (float)px[3]
+    );
+}
+
+extern "C" __global__ void dither_floyd_steinberg(
+    const unsigned char* image,
+    int width,
+    int height,
+    const unsigned char* palette,
+    int palette_len,
+    float* error,
+    unsigned char* indices
+) {
+    int rows = blockDim.x;
+    int r = threadIdx.x;
+
+    for (int band = 0; band < height; band += rows) {
+        int y = band + r;
+        int steps = width + 2 * (rows - 1);
+        for (int s = 0; s < steps; s++) {
+            int x = s - 2 * r;
+            if (y < height && x >= 0 && x < width) {
+                int i = y * width + x;
+                const unsigned char* px = image + i * 4;
+                float v[4];
+                for (int c = 0; c < 4; c++) {
+                    v[c] = fminf(fmaxf((float)px[c] + error[i * 4 + c], 0.0f), 255.0f);
+                }
+
+                int best = nearest_entry(palette, palette_len, v[0], v[1], v[2], v[3]);
+                indices[i] = (unsigned char)best;
+
+                for (int c = 0; c < 4; c++) {
+                    float e = v[c] - (float)palette[best * 4 + c];
+                    if (x + 1 < width) atomicAdd(&error[(i + 1) * 4 + c], e * (7.0f / 16.0f));
+                    if (y + 1 < height) {
+                        if (x > 0) atomicAdd(&error[(i + width - 1) * 4 + c], e * (3.0f / 16.0f));
+                        atomicAdd(&error[(i + width) * 4 + c], e * (5.0f / 16.0f));
+                        if (x + 1 < width) atomicAdd(&error[(i + width + 1) * 4 + c], e * (1.0f / 16.0f));
+                    }
+                }
+            }
+            __syncthreads();
+        }
+    }
+}
+"#;
@@ -0,0 +1,203 @@
+//! Histogram equalization, global and contrast-limited adaptive (CLAHE).
+//!
+//! Both work on luma (full-range YCbCr) so colors keep their hue. Global
+//! equalization is the single-tile, unclipped case of CLAHE.
+
+use crate::{download, launch_config_2d, load_kernel, run_gpu, upload_rgba, output_argument, write_result};
+use crate::validation::dimension_argument;
+use cudarc::driver::{CudaDevice, CudaSlice, LaunchAsync, LaunchConfig};
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+use std::sync::Arc;
+
+/// Equalizes `image` in place over `tiles_x * tiles_y` tiles. A single tile
+/// with `clip_limit` 0 is plain global equalization.
+pub(crate) fn equalize(
+    device: &Arc<CudaDevice>,
+    image: &mut CudaSlice<u8>,
+    width: usize,
+    height: usize,
+    tiles_x: usize,
+    tiles_y: usize,
+    clip_limit: f32,
+) -> Result<(), f64> {
+    // More tiles than pixels would leave empty tiles.
+    let tiles_x = tiles_x.min(width);
+    let tiles_y = tiles_y.min(height);
+    let tiles = tiles_x * tiles_y;
+    let mut histograms = device.alloc_zeros::<u32>(tiles * 256).map_err(|_| -4.0)?; // Output allocation failed
+    let kernel = load_kernel(device, "equalize_module", EQUALIZE_KERNEL, EQUALIZE_FUNCTIONS, "tile_histograms")?;
+    let params = (&*image, width as i32, height as i32, tiles_x as i32, tiles_y as i32, &mut histograms);
+    unsafe { kernel.launch(launch_config_2d(width, height), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+
+    let mut luts = device.alloc_zeros::<f32>(tiles * 256).map_err(|_| -4.0)?; // Output allocation failed
+    let kernel = load_kernel(device, "equalize_module", EQUALIZE_KERNEL, EQUALIZE_FUNCTIONS, "tile_luts")?;
+    let params = (&histograms, tiles as i32, clip_limit, &mut luts);
+    unsafe { kernel.launch(LaunchConfig::for_num_elems(tiles as u32), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+
+    let kernel = load_kernel(device, "equalize_module", EQUALIZE_KERNEL, EQUALIZE_FUNCTIONS, "apply_tile_luts")?;
+    let params = (image, width as i32, height as i32, tiles_x as i32, tiles_y as i32, &luts);
+    unsafe { kernel.launch(launch_config_2d(width, height), params) }.map_err(|_| -8.0) // Kernel launch failed
+}
+
+/// `gpu_processor_equalize_histogram(input, width, height, output?)`
+///
+/// Spreads the luma histogram over the full 0–255 range.
+pub(crate) fn gpu_processor_equalize_histogram(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let output_buffer = output_argument(&mut cx, 3)?;
+
+    let result = run_gpu(|device| {
+        let mut image = upload_rgba(device, input_data.as_slice(&cx), width, height)?;
+        equalize(device, &mut image, width, height, 1, 1, 0.0)?;
+        download(device, &image)
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+/// `gpu_processor_clahe(input, width, height, tiles_x, tiles_y, clip_limit, output?)`
+///
+/// Equalizes each of `tiles_x * tiles_y` tiles separately and blends the
+/// per-tile mappings bilinearly. Histogram bins are capped at `clip_limit`
+/// times the tile's mean bin count (2–4 is typical; higher means stronger
+/// contrast and more noise), with the excess spread evenly over all bins.
+pub(crate) fn gpu_processor_clahe(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let tiles_x = cx.argument::<JsNumber>(3)?.value(&mut cx) as usize;
+    let tiles_y = cx.argument::<JsNumber>(4)?.value(&mut cx) as usize;
+    let clip_limit = cx.argument::<JsNumber>(5)?.value(&mut cx) as f32;
+    let output_buffer = output_argument(&mut cx, 6)?;
+
+    if !(1..=64).contains(&tiles_x) || !(1..=64).contains(&tiles_y) {
+        return cx.throw_range_error("tiles_x and tiles_y must be between 1 and 64");
+    }
+    if clip_limit.is_nan() || clip_limit < 1.0 {
+        return cx.throw_range_error("clip_limit must be at least 1");
+    }
+
+    let result = run_gpu(|device| {
+        let mut image = upload_rgba(device, input_data.as_slice(&cx), width, height)?;
+        equalize(device, &mut image, width, height, tiles_x, tiles_y, clip_limit)?;
+        download(device, &image)
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+const EQUALIZE_FUNCTIONS: &[&str] = &["tile_histograms", "tile_luts", "apply_tile_luts"];
+
+const EQUALIZE_KERNEL: &str = r#"
+__device__ __forceinline__ float luma(const unsigned char* px) {
+    return 0.299f * (float)px[0] + 0.587f * (float)px[1] + 0.114f * (float)px[2];
+}
+
+extern "C" __global__ void tile_histograms(
+    const unsigned char* image,
+    int width,
+    int height,
+    int tiles_x,
+    int tiles_y,
+    unsigned int* histograms
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= width || y >= height) return;
+
+    int tx = x * tiles_x / width;
+    int ty = y * tiles_y / height;
+    int bin = min((int)(luma(image + (y * width + x) * 4) + 0.5f), 255);
+    atomicAdd(&histograms[(ty * tiles_x + tx) * 256 + bin], 1u);
+}
+
+// One thread per tile. clip_limit <= 0 disables clipping.
+extern "C" __global__ void tile_luts(
+    const unsigned int* histograms,
+    int tiles,
+    float clip_limit,
+    float* luts
+) {
+    int tile = blockIdx.x * blockDim.x + threadIdx.x;
+    if (tile >= tiles) return;
+
+    const unsigned int* hist = histograms + tile * 256;
+    float total = 0.0f;
+    for (int v = 0; v < 256; v++) total += (float)hist[v];
+
+    float* lut = luts + tile * 256;
+    if (total <= 0.0f) {
+        for (int v = 0; v < 256; v++) lut[v] = (float)v;
+        return;
+    }
+
+    float clip = clip_limit > 0.0f ? clip_limit * total / 256.0f : 3.4e38f;
+    float excess = 0.0f;
+    for (int v = 0; v < 256; v++) excess += fmaxf((float)hist[v] - clip, 0.0f);
+    float bonus = excess / 256.0f;
+
+    float cdf = 0.0f;
+    float cdf_min = -1.0f;
+    for (int v = 0; v < 256; v++) {
+        cdf += fminf((float)hist[v], clip) + bonus;
+        if (cdf_min < 0.0f && hist[v] > 0) cdf_min = cdf;
+        lut[v] = cdf;
+    }
+    float range = cdf - cdf_min;
+    for (int v = 0; v < 256; v++) {
+        lut[v] = range > 0.0f ? fmaxf(lut[v] - cdf_min, 0.0f) / range * 255.0f : (float)v;
+    }
+}
+
+__device__ __forceinline__ float lookup(const float* luts, int tiles_x, int tx, int ty, float y) {
+    const float* lut = luts + (ty * tiles_x + tx) * 256;
+    int lo = min((int)y, 255);
+    int hi = min(lo + 1, 255);
+    float t = y - (float)lo;
+    return lut[lo] * (1.0f - t) + lut[hi] * t;
+}
+
+extern "C" __global__ void apply_tile_luts(
+    unsigned char* image,
+    int width,
+    int height,
+    int tiles_x,
+    int tiles_y,
+    const float* luts
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= width || y >= height) return;
+
+    unsigned char* px = image + (y * width + x) * 4;
+    float r = (float)px[0];
+    float g = (float)px[1];
+    float b = (float)px[2];
+    float luma_in = luma(px);
+    float cb = -0.168736f * r - 0.331264f * g + 0.5f * b;
+    float cr = 0.5f * r - 0.418688f * g - 0.081312f * b;
+
+    // Interpolate between the mappings of the four nearest tile centers.
+    float fx = fminf(fmaxf(((float)x + 0.5f) * (float)tiles_x / (float)width - 0.5f, 0.0f), (float)(tiles_x - 1));
+    float fy = fminf(fmaxf(((float)y + 0.5f) * (float)tiles_y / (float)height - 0.5f, 0.0f), (float)(tiles_y - 1));
+    int tx1 = (int)fx;
+    int ty1 = (int)fy;
+    int tx2 = min(tx1 + 1, tiles_x - 1);
+    int ty2 = min(ty1 + 1, tiles_y - 1);
+    float wx = fx - (float)tx1;
+    float wy = fy - (float)ty1;
+    float top = lookup(luts, tiles_x, tx1, ty1, luma_in) * (1.0f - wx) + lookup(luts, tiles_x, tx2, ty1, luma_in) * wx;
+    float bottom = lookup(luts, tiles_x, tx1, ty2, luma_in) * (1.0f - wx) + lookup(luts, tiles_x, tx2, ty2, luma_in) * wx;
+    float luma_out = top * (1.0f - wy) + bottom * wy;
+
+    float out[3] = {
+        luma_out + 1.402f * cr,
+        luma_out - 0.344136f * cb - 0.714136f * cr,
+        luma_out + 1.772f * cb
+    };
+    for (int c = 0; c < 3; c++) px[c] = (unsigned char)fminf(fmaxf(out[c] + 0.5f, 0.0f), 255.0f);
+}
+"#;
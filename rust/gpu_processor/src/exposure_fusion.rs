@@ -0,0 +1,95 @@
+//! Exposure fusion of bracketed shots into a single display-ready image.
+
+use crate::pyramid::{blend_multiband, level_count, to_bytes, to_float};
+use crate::{download, launch_config_2d, load_kernel, run_gpu, upload_rgba, output_argument, write_result};
+use crate::validation::dimension_argument;
+use cudarc::driver::LaunchAsync;
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+
+/// Bracketed exposures accepted by one merge.
+const MIN_EXPOSURES: usize = 2;
+const MAX_EXPOSURES: usize = 8;
+
+/// `gpu_processor_exposure_fusion(inputs, width, height, output?)`
+///
+/// Merges bracketed RGBA exposures of the same scene (typically 3–5 shots)
+/// into one RGBA image. Each pixel is weighted by local contrast, saturation
+/// and how well exposed it is, and the weighted shots are blended across a
+/// Laplacian pyramid (Mertens et al.), so the result needs no separate tone
+/// mapping and shows no seams where the chosen exposure changes.
+pub(crate) fn gpu_processor_exposure_fusion(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let inputs = cx.argument::<JsArray>(0)?.to_vec(&mut cx)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let output_buffer = output_argument(&mut cx, 3)?;
+
+    if !(MIN_EXPOSURES..=MAX_EXPOSURES).contains(&inputs.len()) {
+        return cx.throw_range_error(format!("expected {} to {} exposures", MIN_EXPOSURES, MAX_EXPOSURES));
+    }
+    let mut buffers = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        buffers.push(input.downcast_or_throw::<JsBuffer, _>(&mut cx)?);
+    }
+
+    let result = run_gpu(|device| {
+        let kernel = load_kernel(device, "exposure_fusion_module", FUSION_KERNEL, FUSION_FUNCTIONS, "fusion_weight")?;
+        let mut images = Vec::with_capacity(buffers.len());
+        let mut weights = Vec::with_capacity(buffers.len());
+        for buffer in &buffers {
+            let image = upload_rgba(device, buffer.as_slice(&cx), width, height)?;
+            let mut weight = device.alloc_zeros::<f32>(width * height).map_err(|_| -4.0)?; // Output allocation failed
+            let params = (&image, &mut weight, width as i32, height as i32);
+            unsafe { kernel.clone().launch(launch_config_2d(width, height), params) }
+                .map_err(|_| -8.0)?; // Kernel launch failed
+            images.push(to_float(device, &image)?);
+            weights.push(weight);
+        }
+
+        let fused = blend_multiband(device, images, weights, width, height, level_count(width, height))?;
+        download(device, &to_bytes(device, &fused)?)
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+const FUSION_FUNCTIONS: &[&str] = &["fusion_weight"];
+
+const FUSION_KERNEL: &str = r#"
+__device__ __forceinline__ float gray_at(const unsigned char* image, int width, int height, int x, int y) {
+    x = min(max(x, 0), width - 1);
+    y = min(max(y, 0), height - 1);
+    const unsigned char* px = image + (y * width + x) * 4;
+    return (0.299f * (float)px[0] + 0.587f * (float)px[1] + 0.114f * (float)px[2]) / 255.0f;
+}
+
+extern "C" __global__ void fusion_weight(
+    const unsigned char* image,
+    float* weight,
+    int width,
+    int height
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= width || y >= height) return;
+
+    float contrast = fabsf(gray_at(image, width, height, x - 1, y) + gray_at(image, width, height, x + 1, y)
+                           + gray_at(image, width, height, x, y - 1) + gray_at(image, width, height, x, y + 1)
+                           - 4.0f * gray_at(image, width, height, x, y));
+
+    const unsigned char* px = image + (y * width + x) * 4;
+    float rgb[3] = {(float)px[0] / 255.0f, (float)px[1] / 255.0f, (float)px[2] / 255.0f};
+    float mean = (rgb[0] + rgb[1] + rgb[2]) / 3.0f;
+    float saturation = 0.0f;
+    float exposedness = 1.0f;
+    for (int c = 0; c < 3; c++) {
+        saturation += (rgb[c] - mean) * (rgb[c] - mean);
+        // Gaussian around mid-grey, sigma 0.2.
+        exposedness *= __expf(-(rgb[c] - 0.5f) * (rgb[c] - 0.5f) / 0.08f);
+    }
+    saturation = sqrtf(saturation / 3.0f);
+
+    // The small floor keeps pixels that are bad in every shot from dividing by zero.
+    weight[y * width + x] = (contrast + 1e-3f) * (saturation + 1e-3f) * exposedness + 1e-12f;
+}
+"#;
@@ -0,0 +1,161 @@
+//! Face detection for smart cropping and redaction.
+//!
+//! Faces are found by the SeetaFace frontal detector, ported to Rust as
+//! rustface: a funnel-structured cascade of boosted LAB feature classifiers
+//! followed by SURF-feature MLPs, trained on frontal and near-frontal faces.
+//! It works on luma, so grayscale photos and every skin tone are handled
+//! alike. The model (`models/seeta_fd_frontal_v1.0.bin`, BSD 2-Clause, see
+//! `models/LICENSE-seetaface`) is compiled into the addon. Detection runs on
+//! the host, so it does not need a device.
+
+use crate::smart_crop::{crop_rect_object, CropRect};
+use crate::validation::{dimension_argument, image_bytes};
+use lazy_static::lazy_static;
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+use rustface::{ImageData, Model};
+
+/// Smallest face the model's 40-pixel window can be scaled down to.
+const MIN_DETECTOR_FACE: usize = 20;
+
+/// Longest side images are reduced to before detection, unless that would
+/// shrink faces of `minSize` below `MIN_DETECTOR_FACE`.
+const DETECT_SIDE: usize = 1024;
+
+static MODEL_DATA: &[u8] = include_bytes!("../models/seeta_fd_frontal_v1.0.bin");
+
+lazy_static! {
+    static ref MODEL: Option<Model> = rustface::read_model(MODEL_DATA).ok();
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct FaceOptions {
+    /// Smallest face side, in pixels.
+    pub(crate) min_size: usize,
+    pub(crate) max_faces: usize,
+    /// Minimum classifier score.
+    pub(crate) min_score: f64,
+}
+
+impl Default for FaceOptions {
+    fn default() -> Self {
+        FaceOptions { min_size: 24, max_faces: 16, min_score: 2.0 }
+    }
+}
+
+/// Luma of an RGBA image, box-averaged down to `out_width` x `out_height`.
+fn scaled_luma(rgba: &[u8], width: usize, height: usize, out_width: usize, out_height: usize) -> Vec<u8> {
+    let mut luma = Vec::with_capacity(out_width * out_height);
+    for oy in 0..out_height {
+        let (y0, y1) = (oy * height / out_height, ((oy + 1) * height / out_height).max(oy * height / out_height + 1));
+        for ox in 0..out_width {
+            let (x0, x1) = (ox * width / out_width, ((ox + 1) * width / out_width).max(ox * width / out_width + 1));
+            let mut sum = 0u32;
+            for y in y0..y1 {
+                for px in rgba[(y * width + x0) * 4..(y * width + x1) * 4].chunks_exact(4) {
+                    sum += 299 * px[0] as u32 + 587 * px[1] as u32 + 114 * px[2] as u32;
+                }
+            }
+            let count = ((y1 - y0) * (x1 - x0)) as u32 * 1000;
+            luma.push(((sum + count / 2) / count) as u8);
+        }
+    }
+    luma
+}
+
+/// Detects faces in a tightly packed RGBA image, best first.
+pub(crate) fn detect_faces(rgba: &[u8], width: usize, height: usize, options: &FaceOptions) -> Result<Vec<CropRect>, f64> {
+    if rgba.len() != image_bytes(width, height, 4)? {
+        return Err(-2.0); // Invalid input size
+    }
+    let model = MODEL.as_ref().ok_or(-5.0)?; // Module loading failed
+    let min_size = options.min_size.max(MIN_DETECTOR_FACE);
+    if options.max_faces == 0 || width.min(height) < min_size {
+        return Ok(Vec::new());
+    }
+
+    // Large photos are searched at a reduced size, which finds the same faces
+    // far faster as long as the smallest one asked for stays detectable.
+    let scale = (DETECT_SIDE as f64 / width.max(height) as f64).max(MIN_DETECTOR_FACE as f64 / min_size as f64).min(1.0);
+    let out_width = ((width as f64 * scale).round() as usize).max(1);
+    let out_height = ((height as f64 * scale).round() as usize).max(1);
+    let luma = scaled_luma(rgba, width, height, out_width, out_height);
+
+    let mut detector = rustface::create_detector_with_model(model.clone());
+    detector.set_min_face_size(((min_size as f64 * scale).round() as u32).max(MIN_DETECTOR_FACE as u32));
+    detector.set_score_thresh(options.min_score);
+    detector.set_pyramid_scale_factor(0.8);
+    detector.set_slide_window_step(4, 4);
+    let found = detector.detect(&ImageData::new(&luma, out_width as u32, out_height as u32));
+
+    let mut faces: Vec<CropRect> = found
+        .iter()
+        .filter_map(|face| {
+            let bbox = face.bbox();
+            let to_image = |v: f64, limit: usize| ((v / scale).round().max(0.0) as usize).min(limit);
+            let x0 = to_image(bbox.x() as f64, width);
+            let y0 = to_image(bbox.y() as f64, height);
+            let x1 = to_image(bbox.x() as f64 + bbox.width() as f64, width);
+            let y1 = to_image(bbox.y() as f64 + bbox.height() as f64, height);
+            (x1 > x0 && y1 > y0).then_some(CropRect { x: x0, y: y0, width: x1 - x0, height: y1 - y0, score: face.score() })
+        })
+        .collect();
+    faces.sort_by(|a, b| b.score.total_cmp(&a.score));
+    faces.truncate(options.max_faces);
+    Ok(faces)
+}
+
+/// Reads `{ minSize, maxFaces, minScore }`, each optional.
+fn options_argument(cx: &mut FunctionContext, index: usize) -> NeonResult<FaceOptions> {
+    let mut options = FaceOptions::default();
+    let object = match cx.argument_opt(index) {
+        Some(value) if value.is_a::<JsObject, _>(cx) => value.downcast_or_throw::<JsObject, _>(cx)?,
+        _ => return Ok(options),
+    };
+    if let Some(value) = object.get_opt::<JsNumber, _, _>(cx, "minSize")? {
+        options.min_size = value.value(cx).max(1.0) as usize;
+    }
+    if let Some(value) = object.get_opt::<JsNumber, _, _>(cx, "maxFaces")? {
+        options.max_faces = value.value(cx).max(0.0) as usize;
+    }
+    if let Some(value) = object.get_opt::<JsNumber, _, _>(cx, "minScore")? {
+        let min_score = value.value(cx);
+        if !(min_score.is_finite() && min_score > 0.0) {
+            return cx.throw_range_error("minScore must be above 0");
+        }
+        options.min_score = min_score;
+    }
+    Ok(options)
+}
+
+/// `gpu_processor_detect_faces(input, width, height, options?)`
+///
+/// Returns an array of `{ x, y, width, height, score }` face boxes, best
+/// first, or a negative status code. `score` is the detector's classifier
+/// score: detections start at `minScore` and clear faces typically score 5 to
+/// 30. Options: `minSize` (smallest face side in pixels, default 24, at least
+/// 20), `maxFaces` (default 16) and `minScore` (default 2; lower finds more
+/// faces and more false positives). The result can be passed straight to
+/// `gpu_processor_blur_regions` or `gpu_processor_pixelate_regions`.
+///
+/// The model is trained on frontal and near-frontal faces; profiles and
+/// strongly tilted heads are often missed. Pad the boxes or lower `minScore`
+/// when a miss is worse than an extra blur. Detection runs on the CPU, so it
+/// works without a GPU.
+pub(crate) fn gpu_processor_detect_faces(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let options = options_argument(&mut cx, 3)?;
+
+    let faces = match detect_faces(input_data.as_slice(&cx), width, height, &options) {
+        Ok(faces) => faces,
+        Err(code) => return Ok(cx.number(code).upcast()),
+    };
+    let array = cx.empty_array();
+    for (i, face) in faces.into_iter().enumerate() {
+        let obj = crop_rect_object(&mut cx, face)?;
+        array.set(&mut cx, i as u32, obj)?;
+    }
+    Ok(array.upcast())
+}
@@ -0,0 +1,240 @@
+//! Per-pixel effects applied in place on an RGBA image.
+
+use crate::{download, launch_config_2d, load_kernel, run_gpu, upload_rgba, output_argument, write_result};
+use crate::validation::dimension_argument;
+use cudarc::driver::{CudaDevice, CudaSlice, LaunchAsync};
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+use std::sync::Arc;
+
+/// Reads a `[r, g, b]` array argument into floats on the 0–255 scale.
+pub(crate) fn color_argument(cx: &mut FunctionContext, index: usize) -> NeonResult<[f32; 3]> {
+    let values = cx.argument::<JsArray>(index)?.to_vec(cx)?;
+    let mut color = [0.0f32; 3];
+    for (slot, value) in color.iter_mut().zip(values) {
+        *slot = value.downcast_or_throw::<JsNumber, _>(cx)?.value(cx).clamp(0.0, 255.0) as f32;
+    }
+    Ok(color)
+}
+
+/// Vignette settings; see `gpu_processor_vignette`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Vignette {
+    pub(crate) strength: f32,
+    pub(crate) radius: f32,
+    pub(crate) feather: f32,
+    pub(crate) center_x: f32,
+    pub(crate) center_y: f32,
+    pub(crate) color: [f32; 3],
+}
+
+impl Vignette {
+    pub(crate) fn apply(&self, device: &Arc<CudaDevice>, image: &mut CudaSlice<u8>, width: usize, height: usize) -> Result<(), f64> {
+        let kernel = load_kernel(device, "filters_module", FILTERS_KERNEL, FILTER_FUNCTIONS, "vignette")?;
+        let params = (
+            image,
+            width as i32,
+            height as i32,
+            self.strength.clamp(0.0, 1.0),
+            self.radius.max(0.0),
+            self.feather.max(0.0),
+            self.center_x,
+            self.center_y,
+            self.color[0],
+            self.color[1],
+            self.color[2],
+        );
+        unsafe { kernel.launch(launch_config_2d(width, height), params) }.map_err(|_| -8.0) // Kernel launch failed
+    }
+}
+
+/// Film grain settings; see `gpu_processor_film_grain`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct FilmGrain {
+    pub(crate) amount: f32,
+    pub(crate) grain_size: f32,
+    pub(crate) seed: u32,
+    pub(crate) monochrome: bool,
+}
+
+impl FilmGrain {
+    pub(crate) fn apply(&self, device: &Arc<CudaDevice>, image: &mut CudaSlice<u8>, width: usize, height: usize) -> Result<(), f64> {
+        let kernel = load_kernel(device, "filters_module", FILTERS_KERNEL, FILTER_FUNCTIONS, "film_grain")?;
+        let params = (
+            image,
+            width as i32,
+            height as i32,
+            self.amount.max(0.0),
+            self.grain_size.max(1.0),
+            self.seed,
+            self.monochrome as i32,
+        );
+        unsafe { kernel.launch(launch_config_2d(width, height), params) }.map_err(|_| -8.0) // Kernel launch failed
+    }
+}
+
+/// `gpu_processor_vignette(input, width, height, strength, radius, feather, center_x, center_y, color, output?)`
+///
+/// Blends pixels toward `color` (`[r, g, b]`, usually black) with a radial
+/// falloff. `center_x`/`center_y` are in 0–1 image coordinates, `radius` and
+/// `feather` are fractions of the distance from the center to the farthest
+/// corner, and `strength` (0–1) is the blend amount at the edge.
+pub(crate) fn gpu_processor_vignette(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let strength = cx.argument::<JsNumber>(3)?.value(&mut cx) as f32;
+    let radius = cx.argument::<JsNumber>(4)?.value(&mut cx) as f32;
+    let feather = cx.argument::<JsNumber>(5)?.value(&mut cx) as f32;
+    let center_x = cx.argument::<JsNumber>(6)?.value(&mut cx) as f32;
+    let center_y = cx.argument::<JsNumber>(7)?.value(&mut cx) as f32;
+    let color = color_argument(&mut cx, 8)?;
+    let output_buffer = output_argument(&mut cx, 9)?;
+
+    let vignette = Vignette { strength, radius, feather, center_x, center_y, color };
+    let result = run_gpu(|device| {
+        let mut image = upload_rgba(device, input_data.as_slice(&cx), width, height)?;
+        vignette.apply(device, &mut image, width, height)?;
+        download(device, &image)
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+/// `gpu_processor_film_grain(input, width, height, amount, grain_size, seed, monochrome, output?)`
+///
+/// Overlays film grain whose standard deviation is `amount` on the 0–255
+/// scale, strongest in the midtones. `grain_size` is the grain pitch in
+/// pixels (1 is per-pixel noise). The pattern depends only on `seed` and the
+/// pixel position, so the same inputs always render the same grain.
+/// `monochrome` uses one value for all three channels.
+pub(crate) fn gpu_processor_film_grain(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let amount = cx.argument::<JsNumber>(3)?.value(&mut cx) as f32;
+    let grain_size = cx.argument::<JsNumber>(4)?.value(&mut cx) as f32;
+    let seed = cx.argument::<JsNumber>(5)?.value(&mut cx) as i64 as u32;
+    let monochrome = cx.argument::<JsBoolean>(6)?.value(&mut cx);
+    let output_buffer = output_argument(&mut cx, 7)?;
+
+    let grain = FilmGrain { amount, grain_size, seed, monochrome };
+    let result = run_gpu(|device| {
+        let mut image = upload_rgba(device, input_data.as_slice(&cx), width, height)?;
+        grain.apply(device, &mut image, width, height)?;
+        download(device, &image)
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+const FILTER_FUNCTIONS: &[&str] = &["vignette", "film_grain"];
+
+const FILTERS_KERNEL: &str = r#"
+__device__ __forceinline__ unsigned char to_byte(float v) {
+    return (unsigned char)fminf(fmaxf(v + 0.5f, 0.0f), 255.0f);
+}
+
+extern "C" __global__ void vignette(
+    unsigned char* image,
+    int width,
+    int height,
+    float strength,
+    float radius,
+    float feather,
+    float center_x,
+    float center_y,
+    float color_r,
+    float color_g,
+    float color_b
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+
+    if (x >= width || y >= height) return;
+
+    float cx = center_x * (float)width;
+    float cy = center_y * (float)height;
+
+    // Normalize by the distance to the farthest corner so radius 1.0 reaches
+    // every pixel regardless of where the center is.
+    float far_x = fmaxf(cx, (float)width - cx);
+    float far_y = fmaxf(cy, (float)height - cy);
+    float max_dist = fmaxf(hypotf(far_x, far_y), 1.0f);
+    float dist = hypotf((float)x + 0.5f - cx, (float)y + 0.5f - cy) / max_dist;
+
+    float t;
+    if (dist <= radius) {
+        t = 0.0f;
+    } else if (feather <= 0.0f || dist >= radius + feather) {
+        t = 1.0f;
+    } else {
+        t = (dist - radius) / feather;
+        t = t * t * (3.0f - 2.0f * t);
+    }
+    float amount = t * strength;
+
+    unsigned char* px = image + (y * width + x) * 4;
+    px[0] = to_byte((float)px[0] + (color_r - (float)px[0]) * amount);
+    px[1] = to_byte((float)px[1] + (color_g - (float)px[1]) * amount);
+    px[2] = to_byte((float)px[2] + (color_b - (float)px[2]) * amount);
+}
+
+// Integer hash (lowbias32); stateless so every thread draws the same value
+// for the same cell on every run.
+__device__ __forceinline__ unsigned int hash_u32(unsigned int v) {
+    v ^= v >> 16;
+    v *= 0x7feb352du;
+    v ^= v >> 15;
+    v *= 0x846ca68bu;
+    v ^= v >> 16;
+    return v;
+}
+
+// Standard normal sample for grid cell (gx, gy) of `channel`.
+__device__ float cell_gaussian(int gx, int gy, int channel, unsigned int seed) {
+    unsigned int h = hash_u32(seed ^ hash_u32((unsigned int)gx * 0x9e3779b9u ^ hash_u32((unsigned int)gy * 0x85ebca6bu + (unsigned int)channel)));
+    float u1 = ((float)(h >> 8) + 1.0f) / 16777217.0f;
+    float u2 = (float)(hash_u32(h) >> 8) / 16777216.0f;
+    return sqrtf(-2.0f * logf(u1)) * cosf(6.28318531f * u2);
+}
+
+extern "C" __global__ void film_grain(
+    unsigned char* image,
+    int width,
+    int height,
+    float amount,
+    float grain_size,
+    unsigned int seed,
+    int monochrome
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+
+    if (x >= width || y >= height) return;
+
+    // Smoothly interpolated value noise on a grid with `grain_size` pitch.
+    float fx = (float)x / grain_size;
+    float fy = (float)y / grain_size;
+    int gx = (int)floorf(fx);
+    int gy = (int)floorf(fy);
+    float tx = fx - (float)gx;
+    float ty = fy - (float)gy;
+    tx = tx * tx * (3.0f - 2.0f * tx);
+    ty = ty * ty * (3.0f - 2.0f * ty);
+
+    unsigned char* px = image + (y * width + x) * 4;
+    float luma = (0.299f * (float)px[0] + 0.587f * (float)px[1] + 0.114f * (float)px[2]) / 255.0f;
+    float response = 0.25f + 3.0f * luma * (1.0f - luma);
+
+    for (int c = 0; c < 3; c++) {
+        int channel = monochrome ? 0 : c;
+        float n00 = cell_gaussian(gx, gy, channel, seed);
+        float n10 = cell_gaussian(gx + 1, gy, channel, seed);
+        float n01 = cell_gaussian(gx, gy + 1, channel, seed);
+        float n11 = cell_gaussian(gx + 1, gy + 1, channel, seed);
+        float n = (n00 * (1.0f - tx) + n10 * tx) * (1.0f - ty) + (n01 * (1.0f - tx) + n11 * tx) * ty;
+        px[c] = to_byte((float)px[c] + n * amount * response);
+    }
+}
+"#;
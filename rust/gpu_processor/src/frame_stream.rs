@@ -0,0 +1,260 @@
+//! Streaming resize for frame sequences.
+//!
+//! JS pushes frames and gets each resized frame back through a callback.
+//! Every stream owns a worker thread with two device slots on separate CUDA
+//! streams, so the upload of one frame overlaps the resize and download of
+//! the previous one.
+
+use crate::{gpu_device, launch_config_2d, load_kernel, BILINEAR_RESIZE_KERNEL, RESIZE_FUNCTIONS};
+use crate::validation::{dimension_argument, image_bytes};
+use cudarc::driver::{result, CudaDevice, CudaSlice, CudaStream, DevicePtr, DeviceSlice, LaunchAsync};
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+/// Frames a stream holds beyond the two in flight. Each is a full copy of
+/// the pushed buffer, so this bounds the memory a fast producer can pin.
+const MAX_QUEUED_FRAMES: usize = 8;
+
+#[derive(Clone, Copy)]
+struct FrameGeometry {
+    input_width: usize,
+    input_height: usize,
+    output_width: usize,
+    output_height: usize,
+}
+
+struct FrameJob {
+    frame: Vec<u8>,
+    callback: Root<JsFunction>,
+}
+
+struct ResizeStream {
+    sender: SyncSender<FrameJob>,
+    worker: JoinHandle<()>,
+}
+
+thread_local! {
+    /// Streams deliver callbacks through their creator's channel, so a stream
+    /// is only reachable from the JS thread that created it.
+    static RESIZE_STREAMS: RefCell<(u32, HashMap<u32, ResizeStream>)> = RefCell::new((0, HashMap::new()));
+}
+
+struct Slot {
+    stream: CudaStream,
+    input: CudaSlice<u8>,
+    output: CudaSlice<u8>,
+    host_output: Vec<u8>,
+}
+
+impl Slot {
+    fn new(device: &Arc<CudaDevice>, geometry: FrameGeometry) -> Result<Slot, f64> {
+        let stream = device.fork_default_stream().map_err(|_| -3.0)?; // Memory allocation failed
+        let input = device
+            .alloc_zeros::<u8>(geometry.input_width * geometry.input_height * 4)
+            .map_err(|_| -3.0)?; // Memory allocation failed
+        let output_size = geometry.output_width * geometry.output_height * 4;
+        let output = device.alloc_zeros::<u8>(output_size).map_err(|_| -4.0)?; // Output allocation failed
+        Ok(Slot { stream, input, output, host_output: vec![0u8; output_size] })
+    }
+
+    /// Queues upload, resize and download of `frame` on this slot's stream.
+    fn submit(&mut self, device: &Arc<CudaDevice>, geometry: FrameGeometry, frame: &[u8]) -> Result<(), f64> {
+        if frame.len() != self.input.len() {
+            return Err(-2.0); // Invalid input size
+        }
+        unsafe { result::memcpy_htod_async(*self.input.device_ptr(), frame, self.stream.stream) }
+            .map_err(|_| -3.0)?; // Memory allocation failed
+
+        let kernel = load_kernel(device, "resize_module", BILINEAR_RESIZE_KERNEL, RESIZE_FUNCTIONS, "bilinear_resize")?;
+        let params = (
+            &self.input,
+            geometry.input_width as i32,
+            geometry.input_height as i32,
+            &mut self.output,
+            geometry.output_width as i32,
+            geometry.output_height as i32,
+        );
+        let cfg = launch_config_2d(geometry.output_width, geometry.output_height);
+        unsafe { kernel.launch_on_stream(&self.stream, cfg, params) }.map_err(|_| -8.0)?; // Kernel launch failed
+
+        unsafe { result::memcpy_dtoh_async(&mut self.host_output, *self.output.device_ptr(), self.stream.stream) }
+            .map_err(|_| -9.0) // Copy back failed
+    }
+
+    fn wait(&self) -> Result<(), f64> {
+        unsafe { result::stream::synchronize(self.stream.stream) }.map_err(|_| -9.0) // Copy back failed
+    }
+}
+
+fn deliver(channel: &Channel, callback: Root<JsFunction>, result: Result<Vec<u8>, f64>) {
+    // Sending fails once the owning worker has shut down; nobody is left to
+    // call back then, so the frame is dropped.
+    let _ = channel.try_send(move |mut cx| {
+        let callback = callback.into_inner(&mut cx);
+        let (status, frame) = match result {
+            Ok(data) => (cx.number(0.0), JsBuffer::from_slice(&mut cx, &data)?.upcast::<JsValue>()),
+            Err(code) => (cx.number(code), cx.null().upcast::<JsValue>()),
+        };
+        callback.call_with(&cx).arg(status).arg(frame).exec(&mut cx)
+    });
+}
+
+fn run_worker(device: Arc<CudaDevice>, geometry: FrameGeometry, jobs: Receiver<FrameJob>, channel: Channel) {
+    let slots = device
+        .bind_to_thread()
+        .map_err(|_| -3.0) // Memory allocation failed
+        .and_then(|_| Ok([Slot::new(&device, geometry)?, Slot::new(&device, geometry)?]));
+    let mut slots = match slots {
+        Ok(slots) => slots,
+        Err(code) => {
+            for job in jobs {
+                deliver(&channel, job.callback, Err(code));
+            }
+            return;
+        }
+    };
+
+    // Frames in flight, oldest first, with the slot each one occupies.
+    let mut pending: VecDeque<(usize, Root<JsFunction>)> = VecDeque::new();
+    let mut next_slot = 0;
+
+    let finish_oldest = |slots: &mut [Slot; 2], pending: &mut VecDeque<(usize, Root<JsFunction>)>| {
+        if let Some((slot, callback)) = pending.pop_front() {
+            let result = slots[slot].wait().map(|_| slots[slot].host_output.clone());
+            deliver(&channel, callback, result);
+        }
+    };
+
+    loop {
+        let job = match jobs.try_recv() {
+            Ok(job) => job,
+            Err(TryRecvError::Empty) if !pending.is_empty() => {
+                finish_oldest(&mut slots, &mut pending);
+                continue;
+            }
+            Err(TryRecvError::Empty) => match jobs.recv() {
+                Ok(job) => job,
+                Err(_) => break,
+            },
+            Err(TryRecvError::Disconnected) => break,
+        };
+
+        // Both slots busy: the oldest frame has to come back first.
+        if pending.len() == slots.len() {
+            finish_oldest(&mut slots, &mut pending);
+        }
+
+        let slot = next_slot;
+        next_slot = (next_slot + 1) % slots.len();
+        match slots[slot].submit(&device, geometry, &job.frame) {
+            Ok(()) => pending.push_back((slot, job.callback)),
+            Err(code) => {
+                // Keep callbacks in push order.
+                while !pending.is_empty() {
+                    finish_oldest(&mut slots, &mut pending);
+                }
+                deliver(&channel, job.callback, Err(code));
+            }
+        }
+    }
+
+    while !pending.is_empty() {
+        finish_oldest(&mut slots, &mut pending);
+    }
+}
+
+/// `gpu_processor_create_resize_stream(input_width, input_height, output_width, output_height)`
+///
+/// Starts a streaming resizer for RGBA frames of a fixed size and returns its
+/// id, or a negative status code.
+pub(crate) fn gpu_processor_create_resize_stream(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let geometry = FrameGeometry {
+        input_width: dimension_argument(&mut cx, 0)?,
+        input_height: dimension_argument(&mut cx, 1)?,
+        output_width: dimension_argument(&mut cx, 2)?,
+        output_height: dimension_argument(&mut cx, 3)?,
+    };
+
+    let device = match gpu_device() {
+        Ok(device) => device,
+        Err(code) => return Ok(cx.number(code)),
+    };
+    if let Err(code) = image_bytes(geometry.input_width, geometry.input_height, 4)
+        .and(image_bytes(geometry.output_width, geometry.output_height, 4))
+    {
+        return Ok(cx.number(code));
+    }
+
+    let (sender, jobs) = mpsc::sync_channel(MAX_QUEUED_FRAMES);
+    let channel = cx.channel();
+    let worker = std::thread::spawn(move || run_worker(device, geometry, jobs, channel));
+
+    let stream_id = RESIZE_STREAMS.with(|streams| {
+        let mut streams = streams.borrow_mut();
+        let (next_id, table) = &mut *streams;
+        *next_id = next_id.wrapping_add(1).max(1);
+        table.insert(*next_id, ResizeStream { sender, worker });
+        *next_id
+    });
+    Ok(cx.number(stream_id))
+}
+
+/// `gpu_processor_push_frame(stream_id, frame, callback)`
+///
+/// Queues a frame. `callback(status, resized)` runs on the JS thread once the
+/// frame is done; callbacks fire in push order and `resized` is `null` when
+/// `status` is negative. Returns -14 (queue full), without queuing, while
+/// the stream already holds `MAX_QUEUED_FRAMES` (8) frames waiting for the
+/// device; wait for a callback before pushing again.
+pub(crate) fn gpu_processor_push_frame(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let stream_id = cx.argument::<JsNumber>(0)?.value(&mut cx) as u32;
+    let frame = cx.argument::<JsBuffer>(1)?.as_slice(&cx).to_vec();
+    let callback = cx.argument::<JsFunction>(2)?.root(&mut cx);
+
+    let sent = RESIZE_STREAMS.with(|streams| match streams.borrow().1.get(&stream_id) {
+        Some(stream) => stream.sender.try_send(FrameJob { frame, callback }),
+        None => Err(TrySendError::Disconnected(FrameJob { frame: Vec::new(), callback })),
+    });
+    let status = match sent {
+        Ok(()) => 0.0, // Queued
+        Err(TrySendError::Full(job)) => {
+            job.callback.drop(&mut cx);
+            -14.0 // Queue full
+        }
+        Err(TrySendError::Disconnected(job)) => {
+            job.callback.drop(&mut cx);
+            -12.0 // Unknown handle
+        }
+    };
+    Ok(cx.number(status))
+}
+
+/// Finishes and tears down every stream created on this thread.
+pub(crate) fn close_all() {
+    let streams = RESIZE_STREAMS.with(|streams| std::mem::take(&mut streams.borrow_mut().1));
+    for (_, ResizeStream { sender, worker }) in streams {
+        drop(sender);
+        let _ = worker.join();
+    }
+}
+
+/// `gpu_processor_close_resize_stream(stream_id)`
+///
+/// Waits for queued frames to finish, then tears the stream down.
+pub(crate) fn gpu_processor_close_resize_stream(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let stream_id = cx.argument::<JsNumber>(0)?.value(&mut cx) as u32;
+    let stream = RESIZE_STREAMS.with(|streams| streams.borrow_mut().1.remove(&stream_id));
+    match stream {
+        Some(ResizeStream { sender, worker }) => {
+            drop(sender);
+            let _ = worker.join();
+            Ok(cx.number(0.0)) // Success
+        }
+        None => Ok(cx.number(-12.0)), // Unknown handle
+    }
+}
@@ -0,0 +1,243 @@
+//! Device-resident images referenced from JS by numeric handle, so chained
+//! operations can stay on the GPU instead of round-tripping through host
+//! buffers between every step.
+
+use crate::{download, output_argument, run_gpu, write_result};
+use crate::config::config;
+use crate::validation::{dimension_argument, image_bytes};
+use cudarc::driver::{CudaSlice, DevicePtr, DeviceSlice};
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+use neon::types::JsBigInt;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum PixelFormat {
+    Rgba8,
+    RgbaF16,
+    RgbaF32,
+}
+
+impl PixelFormat {
+    pub(crate) fn parse(name: &str) -> Option<PixelFormat> {
+        match name {
+            "rgba8" => Some(PixelFormat::Rgba8),
+            "rgba16f" => Some(PixelFormat::RgbaF16),
+            "rgba32f" => Some(PixelFormat::RgbaF32),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            PixelFormat::Rgba8 => "rgba8",
+            PixelFormat::RgbaF16 => "rgba16f",
+            PixelFormat::RgbaF32 => "rgba32f",
+        }
+    }
+
+    pub(crate) fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgba8 => 4,
+            PixelFormat::RgbaF16 => 8,
+            PixelFormat::RgbaF32 => 16,
+        }
+    }
+}
+
+pub(crate) struct GpuImage {
+    pub(crate) data: CudaSlice<u8>,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) format: PixelFormat,
+}
+
+#[derive(Default)]
+struct HandleTable {
+    next: u32,
+    images: HashMap<u32, GpuImage>,
+}
+
+thread_local! {
+    /// Handles belong to the JS thread (main or worker) that created them.
+    static HANDLES: RefCell<HandleTable> = RefCell::new(HandleTable::default());
+}
+
+/// Stores `image` and returns its handle. Handles start at 1 so they never
+/// collide with the zero/negative status codes.
+pub(crate) fn insert(image: GpuImage) -> u32 {
+    HANDLES.with(|table| {
+        let mut table = table.borrow_mut();
+        table.next = table.next.wrapping_add(1).max(1);
+        while table.images.contains_key(&table.next) {
+            table.next = table.next.wrapping_add(1).max(1);
+        }
+        let handle = table.next;
+        table.images.insert(handle, image);
+        handle
+    })
+}
+
+/// Releases every handle created on this thread.
+pub(crate) fn release_all() {
+    HANDLES.with(|table| table.borrow_mut().images.clear());
+}
+
+/// Runs `f` against the image behind `handle`. `f` must not create or
+/// release handles itself.
+pub(crate) fn with_image<T>(handle: u32, f: impl FnOnce(&GpuImage) -> Result<T, f64>) -> Result<T, f64> {
+    HANDLES.with(|table| {
+        let table = table.borrow();
+        let image = table.images.get(&handle).ok_or(-12.0)?; // Unknown handle
+        f(image)
+    })
+}
+
+/// Reads a handle argument. Anything that isn't a positive integer maps to 0,
+/// which is never a valid handle.
+pub(crate) fn handle_argument(cx: &mut FunctionContext, index: usize) -> NeonResult<u32> {
+    let value = cx.argument::<JsNumber>(index)?.value(cx);
+    Ok(if value >= 1.0 && value <= u32::MAX as f64 { value as u32 } else { 0 })
+}
+
+/// Turns the outcome of a handle-producing operation into the number
+/// returned to JS: the new handle on success, a status code otherwise.
+pub(crate) fn handle_result<'a>(cx: &mut FunctionContext<'a>, result: Result<GpuImage, f64>) -> JsResult<'a, JsNumber> {
+    match result {
+        Ok(image) => Ok(cx.number(insert(image))),
+        Err(code) => Ok(cx.number(code)),
+    }
+}
+
+/// `gpu_processor_upload(input, width, height, format)`
+///
+/// Copies a tightly packed image to the device and returns its handle.
+/// `format` is `"rgba8"` (the default), `"rgba16f"` or `"rgba32f"`.
+pub(crate) fn gpu_processor_upload(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let format = match cx.argument_opt(3) {
+        Some(value) => {
+            let name = value.downcast_or_throw::<JsString, _>(&mut cx)?.value(&mut cx);
+            match PixelFormat::parse(&name) {
+                Some(format) => format,
+                None => return cx.throw_type_error(format!("Unknown pixel format `{}`", name)),
+            }
+        }
+        None => PixelFormat::Rgba8,
+    };
+
+    let result = run_gpu(|device| {
+        let input = input_data.as_slice(&cx);
+        if input.len() != image_bytes(width, height, format.bytes_per_pixel())? {
+            return Err(-2.0); // Invalid input size
+        }
+        let data = device.htod_sync_copy(input).map_err(|_| -3.0)?; // Memory allocation failed
+        Ok(GpuImage { data, width, height, format })
+    });
+
+    handle_result(&mut cx, result)
+}
+
+/// `gpu_processor_download(handle, output?)`
+///
+/// Copies the image behind `handle` into `output`, which must match its
+/// size, or into a new Buffer that is returned when `output` is left out.
+pub(crate) fn gpu_processor_download(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let handle = handle_argument(&mut cx, 0)?;
+    let output_buffer = output_argument(&mut cx, 1)?;
+
+    let output_len = output_buffer.map(|buffer| buffer.as_slice(&cx).len());
+    let result = run_gpu(|device| {
+        with_image(handle, |image| {
+            if output_len.is_some_and(|len| len != image.data.len()) {
+                return Err(-2.0); // Invalid output size
+            }
+            download(device, &image.data)
+        })
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+/// `gpu_processor_handle_info(handle)`
+///
+/// Returns `{ width, height, format, byteLength }`, or `null` for an unknown
+/// handle.
+pub(crate) fn gpu_processor_handle_info(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let handle = handle_argument(&mut cx, 0)?;
+    let info = with_image(handle, |image| Ok((image.width, image.height, image.format.name(), image.data.len())));
+
+    let (width, height, format, byte_length) = match info {
+        Ok(info) => info,
+        Err(_) => return Ok(cx.null().upcast()),
+    };
+    let obj = cx.empty_object();
+    let value = cx.number(width as f64);
+    obj.set(&mut cx, "width", value)?;
+    let value = cx.number(height as f64);
+    obj.set(&mut cx, "height", value)?;
+    let value = cx.string(format);
+    obj.set(&mut cx, "format", value)?;
+    let value = cx.number(byte_length as f64);
+    obj.set(&mut cx, "byteLength", value)?;
+    Ok(obj.upcast())
+}
+
+/// `gpu_processor_release(handle)`
+///
+/// Frees the device memory behind `handle`.
+pub(crate) fn gpu_processor_release(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let handle = handle_argument(&mut cx, 0)?;
+    let released = HANDLES.with(|table| table.borrow_mut().images.remove(&handle)).is_some();
+    Ok(cx.number(if released { 0.0 } else { -12.0 })) // Unknown handle
+}
+
+/// `gpu_processor_handle_device_pointer(handle)`
+///
+/// Unsafe interop for other CUDA-aware native modules. Returns
+/// `{ pointer, byteLength, deviceOrdinal, width, height, format }` where
+/// `pointer` is the `CUdeviceptr` as a BigInt, in the primary context of
+/// `deviceOrdinal`. All work queued by this addon has finished when the call
+/// returns. The address is only valid until the handle is released, and
+/// nothing stops the consumer from writing out of bounds, so this throws
+/// unless `gpu_processor_init` was called with `allowRawPointers: true`.
+pub(crate) fn gpu_processor_handle_device_pointer(mut cx: FunctionContext) -> JsResult<JsValue> {
+    if !config().allow_raw_pointers {
+        return cx.throw_error("raw device pointers are disabled; pass allowRawPointers: true to gpu_processor_init");
+    }
+    let handle = handle_argument(&mut cx, 0)?;
+    let info = with_image(handle, |image| {
+        let device = image.data.device();
+        device.synchronize().map_err(|_| -9.0)?; // Copy back failed
+        Ok((
+            *image.data.device_ptr(),
+            image.data.len(),
+            device.ordinal(),
+            image.width,
+            image.height,
+            image.format.name(),
+        ))
+    });
+
+    let (pointer, byte_length, ordinal, width, height, format) = match info {
+        Ok(info) => info,
+        Err(code) => return Ok(cx.number(code).upcast()),
+    };
+    let obj = cx.empty_object();
+    let value = JsBigInt::from_u64(&mut cx, pointer);
+    obj.set(&mut cx, "pointer", value)?;
+    let value = cx.number(byte_length as f64);
+    obj.set(&mut cx, "byteLength", value)?;
+    let value = cx.number(ordinal as f64);
+    obj.set(&mut cx, "deviceOrdinal", value)?;
+    let value = cx.number(width as f64);
+    obj.set(&mut cx, "width", value)?;
+    let value = cx.number(height as f64);
+    obj.set(&mut cx, "height", value)?;
+    let value = cx.string(format);
+    obj.set(&mut cx, "format", value)?;
+    Ok(obj.upcast())
+}
@@ -0,0 +1,197 @@
+//! Motion-compensated frame interpolation between two keyframes.
+
+use crate::{download, launch_config_2d, load_kernel, run_gpu, upload_rgba, output_argument, write_result};
+use crate::validation::{dimension_argument, image_bytes};
+use cudarc::driver::LaunchAsync;
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+
+/// Side of the square blocks the flow field is estimated on.
+const FLOW_BLOCK_SIZE: usize = 8;
+
+/// `gpu_processor_interpolate_frames(frame_a, frame_b, width, height, steps, search_radius, output?)`
+///
+/// Estimates block motion from `frame_a` to `frame_b` once, then synthesizes
+/// `steps` evenly spaced in-between frames by warping both keyframes along
+/// the flow and cross-fading them. `output` holds the frames back to back
+/// (`steps * width * height * 4` bytes). `search_radius` bounds the motion
+/// searched per block, in pixels.
+pub(crate) fn gpu_processor_interpolate_frames(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let frame_a = cx.argument::<JsBuffer>(0)?;
+    let frame_b = cx.argument::<JsBuffer>(1)?;
+    let width = dimension_argument(&mut cx, 2)?;
+    let height = dimension_argument(&mut cx, 3)?;
+    let steps = cx.argument::<JsNumber>(4)?.value(&mut cx) as usize;
+    let search_radius = (cx.argument::<JsNumber>(5)?.value(&mut cx) as i32).clamp(0, 64);
+    let output_buffer = output_argument(&mut cx, 6)?;
+
+    if steps == 0 {
+        return cx.throw_range_error("steps must be at least 1");
+    }
+    // All frames count against the pixel limit together, checked before any
+    // GPU work.
+    if height.checked_mul(steps).is_none_or(|rows| image_bytes(width, rows, 4).is_err()) {
+        return Ok(cx.number(-2.0).upcast()); // Invalid input size
+    }
+
+    let result = run_gpu(|device| {
+        let dev_a = upload_rgba(device, frame_a.as_slice(&cx), width, height)?;
+        let dev_b = upload_rgba(device, frame_b.as_slice(&cx), width, height)?;
+
+        let blocks_x = width.div_ceil(FLOW_BLOCK_SIZE);
+        let blocks_y = height.div_ceil(FLOW_BLOCK_SIZE);
+        let mut flow = device.alloc_zeros::<f32>(blocks_x * blocks_y * 2).map_err(|_| -4.0)?; // Output allocation failed
+
+        let kernel = load_kernel(device, "interpolate_module", INTERPOLATE_KERNEL, INTERPOLATE_FUNCTIONS, "block_match")?;
+        let params = (
+            &dev_a,
+            &dev_b,
+            width as i32,
+            height as i32,
+            FLOW_BLOCK_SIZE as i32,
+            search_radius,
+            &mut flow,
+        );
+        unsafe { kernel.launch(launch_config_2d(blocks_x, blocks_y), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+
+        let frame_size = image_bytes(width, height, 4)?;
+        let mut frames = device.alloc_zeros::<u8>(frame_size * steps).map_err(|_| -4.0)?; // Output allocation failed
+        let kernel = load_kernel(device, "interpolate_module", INTERPOLATE_KERNEL, INTERPOLATE_FUNCTIONS, "warp_blend")?;
+        for step in 0..steps {
+            let t = (step + 1) as f32 / (steps + 1) as f32;
+            let mut frame = frames.slice_mut(step * frame_size..(step + 1) * frame_size);
+            let params = (
+                &dev_a,
+                &dev_b,
+                &flow,
+                width as i32,
+                height as i32,
+                FLOW_BLOCK_SIZE as i32,
+                t,
+                &mut frame,
+            );
+            unsafe { kernel.clone().launch(launch_config_2d(width, height), params) }
+                .map_err(|_| -8.0)?; // Kernel launch failed
+        }
+        download(device, &frames)
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+const INTERPOLATE_FUNCTIONS: &[&str] = &["block_match", "warp_blend"];
+
+const INTERPOLATE_KERNEL: &str = r#"
+__device__ __forceinline__ float luma(const unsigned char* image, int width, int height, int x, int y) {
+    x = min(max(x, 0), width - 1);
+    y = min(max(y, 0), height - 1);
+    const unsigned char* px = image + (y * width + x) * 4;
+    return 0.299f * (float)px[0] + 0.587f * (float)px[1] + 0.114f * (float)px[2];
+}
+
+// Exhaustive SAD search per block. Ties prefer the smallest motion so flat
+// areas stay still instead of drifting.
+extern "C" __global__ void block_match(
+    const unsigned char* a,
+    const unsigned char* b,
+    int width,
+    int height,
+    int block,
+    int radius,
+    float* flow
+) {
+    int bx = blockIdx.x * blockDim.x + threadIdx.x;
+    int by = blockIdx.y * blockDim.y + threadIdx.y;
+    int blocks_x = (width + block - 1) / block;
+    int blocks_y = (height + block - 1) / block;
+    if (bx >= blocks_x || by >= blocks_y) return;
+
+    int x0 = bx * block;
+    int y0 = by * block;
+
+    float best = 3.4e38f;
+    int best_dx = 0;
+    int best_dy = 0;
+    for (int dy = -radius; dy <= radius; dy++) {
+        for (int dx = -radius; dx <= radius; dx++) {
+            float sad = 0.0f;
+            for (int y = y0; y < y0 + block; y++) {
+                for (int x = x0; x < x0 + block; x++) {
+                    sad += fabsf(luma(a, width, height, x, y) - luma(b, width, height, x + dx, y + dy));
+                }
+            }
+            sad += 0.01f * (float)(dx * dx + dy * dy);
+            if (sad < best) {
+                best = sad;
+                best_dx = dx;
+                best_dy = dy;
+            }
+        }
+    }
+
+    flow[(by * blocks_x + bx) * 2] = (float)best_dx;
+    flow[(by * blocks_x + bx) * 2 + 1] = (float)best_dy;
+}
+
+__device__ __forceinline__ float sample(const unsigned char* image, int width, int height, float x, float y, int c) {
+    x = fminf(fmaxf(x, 0.0f), (float)(width - 1));
+    y = fminf(fmaxf(y, 0.0f), (float)(height - 1));
+    int x1 = (int)x;
+    int y1 = (int)y;
+    int x2 = min(x1 + 1, width - 1);
+    int y2 = min(y1 + 1, height - 1);
+    float dx = x - (float)x1;
+    float dy = y - (float)y1;
+    float v1 = (float)image[(y1 * width + x1) * 4 + c] * (1.0f - dx) + (float)image[(y1 * width + x2) * 4 + c] * dx;
+    float v2 = (float)image[(y2 * width + x1) * 4 + c] * (1.0f - dx) + (float)image[(y2 * width + x2) * 4 + c] * dx;
+    return v1 * (1.0f - dy) + v2 * dy;
+}
+
+extern "C" __global__ void warp_blend(
+    const unsigned char* a,
+    const unsigned char* b,
+    const float* flow,
+    int width,
+    int height,
+    int block,
+    float t,
+    unsigned char* output
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= width || y >= height) return;
+
+    // Bilinearly interpolate the block flow at this pixel, using block centers.
+    int blocks_x = (width + block - 1) / block;
+    int blocks_y = (height + block - 1) / block;
+    float fx = fminf(fmaxf(((float)x + 0.5f) / (float)block - 0.5f, 0.0f), (float)(blocks_x - 1));
+    float fy = fminf(fmaxf(((float)y + 0.5f) / (float)block - 0.5f, 0.0f), (float)(blocks_y - 1));
+    int bx1 = (int)fx;
+    int by1 = (int)fy;
+    int bx2 = min(bx1 + 1, blocks_x - 1);
+    int by2 = min(by1 + 1, blocks_y - 1);
+    float wx = fx - (float)bx1;
+    float wy = fy - (float)by1;
+
+    float motion[2];
+    for (int k = 0; k < 2; k++) {
+        float f11 = flow[(by1 * blocks_x + bx1) * 2 + k];
+        float f12 = flow[(by1 * blocks_x + bx2) * 2 + k];
+        float f21 = flow[(by2 * blocks_x + bx1) * 2 + k];
+        float f22 = flow[(by2 * blocks_x + bx2) * 2 + k];
+        motion[k] = (f11 * (1.0f - wx) + f12 * wx) * (1.0f - wy) + (f21 * (1.0f - wx) + f22 * wx) * wy;
+    }
+
+    // The pixel sits t of the way along its motion path from a to b.
+    float ax = (float)x - t * motion[0];
+    float ay = (float)y - t * motion[1];
+    float bx = (float)x + (1.0f - t) * motion[0];
+    float by = (float)y + (1.0f - t) * motion[1];
+
+    unsigned char* px = output + (y * width + x) * 4;
+    for (int c = 0; c < 4; c++) {
+        float v = sample(a, width, height, ax, ay, c) * (1.0f - t) + sample(b, width, height, bx, by, c) * t;
+        px[c] = (unsigned char)fminf(fmaxf(v + 0.5f, 0.0f), 255.0f);
+    }
+}
+"#;
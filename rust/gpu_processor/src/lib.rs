@@ -1,117 +1,542 @@
 use neon::prelude::*;
 use neon::types::buffer::TypedArray;
-use cudarc::driver::CudaDevice;
-use cudarc::driver::DevicePtr;
-use cudarc::nvrtc::Ptx;
-use std::sync::Arc;
+use config::{config, limited, GpuConfig};
+use logging::{log_debug, log_error, log_info};
+use validation::{dimension_argument, image_bytes};
+use cudarc::driver::{result, sys, CudaDevice, CudaFunction, CudaSlice, LaunchAsync, LaunchConfig};
+use cudarc::nvrtc::{compile_ptx_with_opts, CompileOptions};
+use std::sync::{Arc, Mutex, RwLock};
 use lazy_static::lazy_static;
-use std::println;
+
+mod benchmark;
+mod chroma_key;
+mod composite;
+mod config;
+mod cpu_fallback;
+mod curves;
+mod custom_kernels;
+mod dither;
+mod equalize;
+mod exposure_fusion;
+mod faces;
+mod filters;
+mod frame_stream;
+mod handles;
+mod interpolate;
+mod logging;
+mod mask;
+mod matting;
+mod panorama;
+mod pipeline;
+mod primaries;
+mod pyramid;
+mod qr_overlay;
+mod redact;
+mod sat;
+mod shadow;
+mod skin_smooth;
+mod smart_crop;
+mod stereo;
+mod temporal_denoise;
+mod tone_map;
+mod validation;
+
+// Process-wide state is the device, the settings and job counters in
+// `config`, the JS logger in `logging` and pipeline presets, plus the
+// read-only face model and the kernel load lock below. Every Node
+// worker_thread that loads this addon shares them, and cudarc binds the
+// device context to whichever thread makes a call. Handles, streams and
+// registered kernels live in per-thread tables instead, so each worker only
+// sees its own resources and they are freed when the worker exits.
+// `None` until first use or after `gpu_processor_shutdown`, `Some(None)` when
+// no GPU could be opened.
+static CUDA_DEVICE: RwLock<Option<Option<Arc<CudaDevice>>>> = RwLock::new(None);
 
 lazy_static! {
-    static ref CUDA_DEVICE: Option<Arc<CudaDevice>> = {
-        CudaDevice::new(0).ok()
-    };
+    /// Serializes the check-then-load in `load_kernel` across workers.
+    static ref KERNEL_LOAD_LOCK: Mutex<()> = Mutex::new(());
 }
 
-fn gpu_processor_get_device_count(mut cx: FunctionContext) -> JsResult<JsNumber> {
-    match CUDA_DEVICE.as_ref() {
-        Some(_) => Ok(cx.number(1.0)),
-        None => Ok(cx.number(0.0)),
+/// Returns `func_name` from `module_name`, compiling `source` with NVRTC and
+/// loading all of `func_names` on the first request. Later calls reuse the
+/// loaded module. Errors map onto the status codes returned to JS.
+fn load_kernel(
+    device: &Arc<CudaDevice>,
+    module_name: &str,
+    source: &str,
+    func_names: &[&'static str],
+    func_name: &str,
+) -> Result<CudaFunction, f64> {
+    load_kernel_with_options(device, module_name, source, func_names, func_name, CompileOptions::default())
+}
+
+/// `load_kernel` for sources that need NVRTC options, such as a minimum
+/// architecture.
+fn load_kernel_with_options(
+    device: &Arc<CudaDevice>,
+    module_name: &str,
+    source: &str,
+    func_names: &[&'static str],
+    func_name: &str,
+    options: CompileOptions,
+) -> Result<CudaFunction, f64> {
+    let _guard = KERNEL_LOAD_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if !device.has_func(module_name, func_name) {
+        let ptx = compile_ptx_with_opts(source, options).map_err(|err| {
+            log_error!("failed to compile {}: {}", module_name, err);
+            -7.0 // Kernel compilation failed
+        })?;
+        device
+            .load_ptx(ptx, module_name, func_names)
+            .map_err(|_| -5.0)?; // Module loading failed
     }
+    device.get_func(module_name, func_name).ok_or(-6.0) // Function loading failed
 }
 
-fn gpu_processor_resize_image(mut cx: FunctionContext) -> JsResult<JsNumber> {
-    let input_data = cx.argument::<JsBuffer>(0)?;
-    let input_width = cx.argument::<JsNumber>(1)?.value(&mut cx) as usize;
-    let input_height = cx.argument::<JsNumber>(2)?.value(&mut cx) as usize;
-    let output_width = cx.argument::<JsNumber>(3)?.value(&mut cx) as usize;
-    let output_height = cx.argument::<JsNumber>(4)?.value(&mut cx) as usize;
-    let output_buffer = cx.argument::<JsBuffer>(5)?;
-
-    println!(
-        "GPU processor resize_image called with input: {}x{}, output: {}x{}",
-        input_width, input_height, output_width, output_height
-    );
+/// One thread per pixel in 16x16 blocks.
+fn launch_config_2d(width: usize, height: usize) -> LaunchConfig {
+    LaunchConfig {
+        grid_dim: ((width as u32).div_ceil(16), (height as u32).div_ceil(16), 1),
+        block_dim: (16, 16, 1),
+        shared_mem_bytes: 0,
+    }
+}
 
-    let device = match CUDA_DEVICE.as_ref() {
-        Some(dev) => dev,
-        None => return Ok(cx.number(-1.0)), // No GPU available
+/// Opens the configured device. An out-of-range ordinal or a missing driver
+/// leaves the addon without a GPU instead of panicking.
+fn open_device(config: &GpuConfig) -> Option<Arc<CudaDevice>> {
+    if let Err(err) = result::init() {
+        log_info!("CUDA driver unavailable: {}", err);
+        return None;
+    }
+    let count = result::device::get_count().unwrap_or(0);
+    if config.device_ordinal >= count.max(0) as usize {
+        log_error!("device {} requested but {} CUDA device(s) present", config.device_ordinal, count);
+        return None;
+    }
+    let device = match CudaDevice::new(config.device_ordinal) {
+        Ok(device) => device,
+        Err(err) => {
+            log_error!("failed to open device {}: {}", config.device_ordinal, err);
+            return None;
+        }
     };
+    apply_memory_pool(&device, config);
+    log_info!("opened CUDA device {}", config.device_ordinal);
+    Some(device)
+}
 
-    // Get input data
-    let input_slice = input_data.as_slice(&cx);
-    let input_vec = input_slice.to_vec();
-    let input_size = input_width * input_height * 4; // RGBA
+/// The device's default memory pool, which is what cudarc allocates from on
+/// devices with stream-ordered allocation.
+fn default_memory_pool(device: &Arc<CudaDevice>) -> Option<sys::CUmemoryPool> {
+    let mut pool: sys::CUmemoryPool = std::ptr::null_mut();
+    let status = unsafe { sys::cuDeviceGetDefaultMemPool(&mut pool, *device.cu_device()) };
+    (status == sys::CUresult::CUDA_SUCCESS).then_some(pool)
+}
+
+/// Sets how much freed memory the pool keeps cached instead of returning it
+/// to the driver.
+fn apply_memory_pool(device: &Arc<CudaDevice>, config: &GpuConfig) {
+    if config.memory_pool_bytes == 0 {
+        return;
+    }
+    if let Some(pool) = default_memory_pool(device) {
+        let mut threshold = config.memory_pool_bytes;
+        unsafe {
+            let _ = sys::cuMemPoolSetAttribute(
+                pool,
+                sys::CUmemPool_attribute::CU_MEMPOOL_ATTR_RELEASE_THRESHOLD,
+                &mut threshold as *mut u64 as *mut std::ffi::c_void,
+            );
+        }
+    }
+}
 
-    if input_vec.len() != input_size {
-        return Ok(cx.number(-2.0)); // Invalid input size
+/// Returns cached pool memory to the driver once pending frees have landed.
+fn trim_memory_pool(device: &Arc<CudaDevice>) {
+    let _ = device.synchronize();
+    if let Some(pool) = default_memory_pool(device) {
+        let _ = unsafe { sys::cuMemPoolTrimTo(pool, 0) };
     }
+}
 
-    let output_size = output_width * output_height * 4;
+fn gpu_device() -> Result<Arc<CudaDevice>, f64> {
+    if let Some(state) = &*CUDA_DEVICE.read().unwrap_or_else(|poisoned| poisoned.into_inner()) {
+        return state.clone().ok_or(-1.0); // No GPU available
+    }
+    let mut state = CUDA_DEVICE.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+    state.get_or_insert_with(|| open_device(&config())).clone().ok_or(-1.0) // No GPU available
+}
 
-    // Allocate device memory
-    let dev_input = match device.htod_copy(input_vec) {
-        Ok(mem) => mem,
-        Err(_) => return Ok(cx.number(-3.0)), // Memory allocation failed
-    };
+/// Allocation, launch and copy failures are usually memory pressure, which a
+/// second attempt on a trimmed pool can get past. Bad input, compile errors
+/// and unknown handles fail the same way every time.
+fn is_transient(code: f64) -> bool {
+    matches!(code as i32, -3 | -4 | -8 | -9)
+}
 
-    let dev_output = match device.alloc_zeros::<u8>(output_size) {
-        Ok(mem) => mem,
-        Err(_) => return Ok(cx.number(-4.0)), // Output allocation failed
-    };
+/// Runs `op` on the device, retrying once after a transient failure. `op`
+/// must build everything it touches from its inputs so a retry starts
+/// clean.
+fn run_gpu<T>(mut op: impl FnMut(&Arc<CudaDevice>) -> Result<T, f64>) -> Result<T, f64> {
+    let device = gpu_device()?;
+    match op(&device) {
+        Err(code) if is_transient(code) => {
+            log_info!("GPU operation failed with status {}, retrying after trimming the memory pool", code);
+            trim_memory_pool(&device);
+            op(&device)
+        }
+        result => result,
+    }
+}
+
+/// Like `run_gpu`, but runs `fallback` on the host when there is no GPU or
+/// the retry failed too. Only resize has a host implementation; every other
+/// operation goes through `run_gpu` and returns -1 without a device.
+fn run_with_fallback<T>(
+    op: impl FnMut(&Arc<CudaDevice>) -> Result<T, f64>,
+    fallback: impl FnOnce() -> Result<T, f64>,
+) -> Result<T, f64> {
+    match run_gpu(op) {
+        Err(code) if code == -1.0 || is_transient(code) => {
+            log_info!("GPU path unavailable (status {}), using the CPU fallback", code);
+            fallback()
+        }
+        result => result,
+    }
+}
+
+/// Copies a tightly packed RGBA image to the device after checking that the
+/// buffer matches its dimensions.
+fn upload_rgba(
+    device: &Arc<CudaDevice>,
+    data: &[u8],
+    width: usize,
+    height: usize,
+) -> Result<CudaSlice<u8>, f64> {
+    if data.len() != image_bytes(width, height, 4)? {
+        return Err(-2.0); // Invalid input size
+    }
+    device.htod_sync_copy(data).map_err(|_| -3.0) // Memory allocation failed
+}
+
+fn download(device: &Arc<CudaDevice>, memory: &CudaSlice<u8>) -> Result<Vec<u8>, f64> {
+    device.dtoh_sync_copy(memory).map_err(|_| -9.0) // Copy back failed
+}
+
+/// Reads the output buffer argument. `null`, `undefined` or leaving it out
+/// asks for a newly allocated buffer of the right size instead.
+fn output_argument<'a>(cx: &mut FunctionContext<'a>, index: usize) -> NeonResult<Option<Handle<'a, JsBuffer>>> {
+    match cx.argument_opt(index) {
+        Some(value) if !value.is_a::<JsUndefined, _>(cx) && !value.is_a::<JsNull, _>(cx) => {
+            Ok(Some(value.downcast_or_throw::<JsBuffer, _>(cx)?))
+        }
+        _ => Ok(None),
+    }
+}
 
-    // Load and compile kernel
-    let ptx = Ptx::from_src(BILINEAR_RESIZE_KERNEL);
-    let module = match device.load_ptx(ptx, "resize_module", &["bilinear_resize"]) {
-        Ok(m) => m,
-        Err(_) => return Ok(cx.number(-5.0)), // Module loading failed
+/// Finishes an operation for JS. With a caller buffer, the result is copied
+/// in only if the sizes match exactly and the status code is returned;
+/// without one, the result comes back as a new Buffer. Failures are always
+/// the status code.
+fn write_result<'a>(
+    cx: &mut FunctionContext<'a>,
+    output_buffer: Option<Handle<'a, JsBuffer>>,
+    result: Result<Vec<u8>, f64>,
+) -> JsResult<'a, JsValue> {
+    let output = match result {
+        Ok(data) => data,
+        Err(code) => return Ok(cx.number(code).upcast()),
     };
-    let kernel = match module.function("bilinear_resize") {
-        Ok(k) => k,
-        Err(_) => return Ok(cx.number(-6.0)), // Function loading failed
+    let Some(mut output_buffer) = output_buffer else {
+        return Ok(JsBuffer::from_slice(cx, &output)?.upcast());
     };
+    let output_slice = output_buffer.as_mut_slice(cx);
+    if output_slice.len() != output.len() {
+        return Ok(cx.number(-2.0).upcast()); // Invalid output size
+    }
+    output_slice.copy_from_slice(&output);
+    Ok(cx.number(0.0).upcast()) // Success
+}
 
-    // Launch kernel
-    let cfg = cudarc::driver::LaunchConfig {
-        grid_dim: ((output_width as u32 + 15) / 16, (output_height as u32 + 15) / 16, 1),
-        block_dim: (16, 16, 1),
-        shared_mem_bytes: 0,
-    };
+fn gpu_processor_get_device_count(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    match gpu_device() {
+        Ok(_) => Ok(cx.number(1.0)),
+        Err(_) => Ok(cx.number(0.0)),
+    }
+}
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ResizeMode {
+    Standard,
+    /// Filters in linear light instead of sRGB.
+    LinearLight,
+    /// Interpolates in FP16 on devices `has_fast_half` accepts.
+    HalfPrecision,
+}
+
+/// True when the device has native FP16 arithmetic that is not rate-limited:
+/// sm_53, sm_60, sm_62 and Volta (compute capability 7.0) onwards. Consumer
+/// Pascal (sm_61) runs FP16 at a small fraction of its FP32 rate.
+fn has_fast_half(device: &Arc<CudaDevice>) -> bool {
+    let major = device.attribute(sys::CUdevice_attribute::CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MAJOR);
+    let minor = device.attribute(sys::CUdevice_attribute::CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MINOR);
+    match (major, minor) {
+        (Ok(major), Ok(minor)) => major >= 7 || matches!((major, minor), (5, 3) | (6, 0) | (6, 2)),
+        _ => false,
+    }
+}
+
+/// Bilinearly resizes an RGBA image already on the device.
+fn resize(
+    device: &Arc<CudaDevice>,
+    input: &CudaSlice<u8>,
+    input_width: usize,
+    input_height: usize,
+    output_width: usize,
+    output_height: usize,
+    mode: ResizeMode,
+) -> Result<CudaSlice<u8>, f64> {
+    let output_size = image_bytes(output_width, output_height, 4)?;
+    let mut output = device.alloc_zeros::<u8>(output_size).map_err(|_| -4.0)?; // Output allocation failed
+
+    let kernel = match mode {
+        ResizeMode::HalfPrecision if has_fast_half(device) => {
+            let options = CompileOptions { arch: Some("compute_53"), ..Default::default() };
+            let functions = &["bilinear_resize_half"];
+            load_kernel_with_options(device, "resize_half_module", HALF_RESIZE_KERNEL, functions, functions[0], options)?
+        }
+        ResizeMode::LinearLight => {
+            load_kernel(device, "resize_module", BILINEAR_RESIZE_KERNEL, RESIZE_FUNCTIONS, "bilinear_resize_linear")?
+        }
+        _ => load_kernel(device, "resize_module", BILINEAR_RESIZE_KERNEL, RESIZE_FUNCTIONS, "bilinear_resize")?,
+    };
+    let cfg = launch_config_2d(output_width, output_height);
     let params = (
-        dev_input.device_ptr(),
+        input,
         input_width as i32,
         input_height as i32,
-        dev_output.device_ptr(),
+        &mut output,
         output_width as i32,
         output_height as i32,
     );
+    unsafe { kernel.launch(cfg, params) }.map_err(|_| -8.0)?; // Kernel launch failed
+    Ok(output)
+}
 
-    if let Err(_) = kernel.launch(cfg, &params) {
-        return Ok(cx.number(-8.0)); // Kernel launch failed
-    }
+/// `gpu_processor_resize_image(input, input_width, input_height, output_width, output_height, output, options?)`
+///
+/// Bilinear RGBA resize. Pass `null` for `output` to get a new Buffer back
+/// instead of a status code; the same goes for every operation taking an
+/// `output?` buffer. With `{ linearLight: true }` every sample is
+/// decoded from sRGB to linear light before filtering and re-encoded after,
+/// in the same kernel, which avoids the darkening gamma-space filtering
+/// causes around high-contrast detail. `{ halfPrecision: true }` does the
+/// interpolation in FP16 on devices with native FP16 arithmetic (Volta,
+/// compute capability 7.0, and later, plus sm_53/60/62), which can differ from
+/// the FP32 result by one level; elsewhere, and together with `linearLight`,
+/// it is ignored.
+///
+/// Resize is the one operation with a CPU fallback: without a GPU, or when
+/// the retry after a transient failure fails too, it runs the same bilinear
+/// math on the host (in FP32). Every other operation returns -1 when there is
+/// no device.
+fn gpu_processor_resize_image(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let input_width = dimension_argument(&mut cx, 1)?;
+    let input_height = dimension_argument(&mut cx, 2)?;
+    let output_width = dimension_argument(&mut cx, 3)?;
+    let output_height = dimension_argument(&mut cx, 4)?;
+    let output_buffer = output_argument(&mut cx, 5)?;
+    let (linear_light, half_precision) = match cx.argument_opt(6) {
+        Some(value) if value.is_a::<JsObject, _>(&mut cx) => {
+            let options = value.downcast_or_throw::<JsObject, _>(&mut cx)?;
+            let mut flag = |key: &str| -> NeonResult<bool> {
+                Ok(options.get_opt::<JsBoolean, _, _>(&mut cx, key)?.is_some_and(|flag| flag.value(&mut cx)))
+            };
+            (flag("linearLight")?, flag("halfPrecision")?)
+        }
+        _ => (false, false),
+    };
+    let mode = if linear_light {
+        ResizeMode::LinearLight
+    } else if half_precision {
+        ResizeMode::HalfPrecision
+    } else {
+        ResizeMode::Standard
+    };
 
-    // Copy result back to host
-    let mut output_vec = vec![0u8; output_size];
-    if let Err(_) = device.dtoh_sync_copy_into(&dev_output, &mut output_vec) {
-        return Ok(cx.number(-9.0)); // Copy back failed
-    }
+    log_debug!(
+        "resize_image called with input: {}x{}, output: {}x{}, linear light: {}",
+        input_width, input_height, output_width, output_height, linear_light
+    );
 
-    // Write to output buffer
-    let output_slice = output_buffer.as_mut_slice(&mut cx);
-    output_slice.copy_from_slice(&output_vec);
+    let input_slice = input_data.as_slice(&cx);
+    let result = run_with_fallback(
+        |device| {
+            let dev_input = upload_rgba(device, input_slice, input_width, input_height)?;
+            let dev_output = resize(device, &dev_input, input_width, input_height, output_width, output_height, mode)?;
+            download(device, &dev_output)
+        },
+        || cpu_fallback::bilinear_resize(input_slice, input_width, input_height, output_width, output_height, linear_light),
+    );
 
-    Ok(cx.number(0.0)) // Success
+    write_result(&mut cx, output_buffer, result)
 }
 
+/// `gpu_processor_init(options?)`
+///
+/// Applies `{ deviceOrdinal, memoryPoolBytes, maxConcurrentJobs, logLevel,
+/// logger, allowRawPointers, maxPixels }` and opens the device. `logLevel`
+/// is `"off"`, `"error"` (the default), `"info"` or `"debug"`;
+/// `logger(level, message)` receives log lines instead of stderr, and
+/// `logger: null` restores stderr. `maxPixels` lowers the largest image any
+/// call accepts; it must be at least 1, and values above 2^28 act as 2^28
+/// (the default). With `maxConcurrentJobs` set, GPU calls made while that
+/// many are running return -14 (queue full) rather than waiting; see
+/// `gpu_processor_queue_status` for when to retry. The device is shared by
+/// every worker in the process, so once it is open a different
+/// `deviceOrdinal` is reported rather than applied; the other settings
+/// always take effect.
 fn gpu_processor_init(mut cx: FunctionContext) -> JsResult<JsString> {
-    let status = match CUDA_DEVICE.as_ref() {
-        Some(_) => "GPU processor initialized successfully",
-        None => "GPU processor initialized (no GPU available)",
+    let config = config::options_argument(&mut cx, 0)?;
+    config::set_config(config);
+    logging::logger_option(&mut cx, 0)?;
+
+    let already_open = CUDA_DEVICE.read().unwrap_or_else(|poisoned| poisoned.into_inner()).is_some();
+    let status = match gpu_device() {
+        Ok(device) if device.ordinal() != config.device_ordinal => {
+            format!("GPU processor already initialized on device {}", device.ordinal())
+        }
+        Ok(device) => {
+            if already_open {
+                apply_memory_pool(&device, &config);
+            }
+            "GPU processor initialized successfully".to_string()
+        }
+        Err(_) => "GPU processor initialized (no GPU available)".to_string(),
     };
     Ok(cx.string(status))
 }
 
+/// `gpu_processor_shutdown()`
+///
+/// Waits for jobs running on other threads, then frees what the calling thread
+/// holds (handles, resize streams once their queued frames are done,
+/// registered kernels), trims the memory pool and releases the device
+/// context, so a hot-reloaded server starts from a clean device. Resources
+/// still held by other worker threads keep the context alive until they are
+/// released or the worker exits. The next GPU call opens the device again.
+fn gpu_processor_shutdown(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    config::wait_for_idle();
+    frame_stream::close_all();
+    handles::release_all();
+    custom_kernels::unregister_all();
+
+    let device = CUDA_DEVICE.write().unwrap_or_else(|poisoned| poisoned.into_inner()).take().flatten();
+    if let Some(device) = device {
+        trim_memory_pool(&device);
+        match Arc::strong_count(&device) - 1 {
+            0 => log_info!("released CUDA device {}", device.ordinal()),
+            held => log_info!("CUDA device {} stays open for {} allocations on other threads", device.ordinal(), held),
+        }
+    }
+    Ok(cx.number(0.0)) // Success
+}
+
+const RESIZE_FUNCTIONS: &[&str] = &["bilinear_resize", "bilinear_resize_linear"];
+
+// FP16 math goes through inline PTX, the way cuda_fp16.h implements it,
+// because NVRTC is not guaranteed to find that header.
+const HALF_RESIZE_KERNEL: &str = r#"
+__device__ __forceinline__ unsigned int pack_half2(float lo, float hi) {
+    unsigned short l, h;
+    unsigned int packed;
+    asm("cvt.rn.f16.f32 %0, %1;" : "=h"(l) : "f"(lo));
+    asm("cvt.rn.f16.f32 %0, %1;" : "=h"(h) : "f"(hi));
+    asm("mov.b32 %0, {%1, %2};" : "=r"(packed) : "h"(l), "h"(h));
+    return packed;
+}
+
+__device__ __forceinline__ float2 unpack_half2(unsigned int packed) {
+    unsigned short l, h;
+    float lo, hi;
+    asm("mov.b32 {%0, %1}, %2;" : "=h"(l), "=h"(h) : "r"(packed));
+    asm("cvt.f32.f16 %0, %1;" : "=f"(lo) : "h"(l));
+    asm("cvt.f32.f16 %0, %1;" : "=f"(hi) : "h"(h));
+    return make_float2(lo, hi);
+}
+
+__device__ __forceinline__ unsigned int hmul2(unsigned int a, unsigned int b) {
+    unsigned int d;
+    asm("mul.rn.f16x2 %0, %1, %2;" : "=r"(d) : "r"(a), "r"(b));
+    return d;
+}
+
+__device__ __forceinline__ unsigned int hfma2(unsigned int a, unsigned int b, unsigned int c) {
+    unsigned int d;
+    asm("fma.rn.f16x2 %0, %1, %2, %3;" : "=r"(d) : "r"(a), "r"(b), "r"(c));
+    return d;
+}
+
+__device__ __forceinline__ unsigned char to_byte(float v) {
+    return (unsigned char)fmaxf(0.0f, fminf(255.0f, v));
+}
+
+// Same sampling as bilinear_resize. The four taps are weighted and summed
+// as half2 pairs, red/green and blue/alpha.
+extern "C" __global__ void bilinear_resize_half(
+    const unsigned char* input,
+    int input_width,
+    int input_height,
+    unsigned char* output,
+    int output_width,
+    int output_height
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+
+    if (x >= output_width || y >= output_height) return;
+
+    float src_x = (float)x * (float)input_width / (float)output_width;
+    float src_y = (float)y * (float)input_height / (float)output_height;
+
+    int x1 = (int)src_x;
+    int y1 = (int)src_y;
+    int x2 = min(x1 + 1, input_width - 1);
+    int y2 = min(y1 + 1, input_height - 1);
+
+    float dx = src_x - (float)x1;
+    float dy = src_y - (float)y1;
+
+    const unsigned char* taps[4] = {
+        input + (y1 * input_width + x1) * 4,
+        input + (y1 * input_width + x2) * 4,
+        input + (y2 * input_width + x1) * 4,
+        input + (y2 * input_width + x2) * 4
+    };
+    float weights[4] = {(1.0f - dx) * (1.0f - dy), dx * (1.0f - dy), (1.0f - dx) * dy, dx * dy};
+
+    unsigned int rg = 0u;
+    unsigned int ba = 0u;
+    for (int i = 0; i < 4; i++) {
+        unsigned int w = pack_half2(weights[i], weights[i]);
+        unsigned int tap_rg = pack_half2((float)taps[i][0], (float)taps[i][1]);
+        unsigned int tap_ba = pack_half2((float)taps[i][2], (float)taps[i][3]);
+        rg = i == 0 ? hmul2(tap_rg, w) : hfma2(tap_rg, w, rg);
+        ba = i == 0 ? hmul2(tap_ba, w) : hfma2(tap_ba, w, ba);
+    }
+
+    float2 out_rg = unpack_half2(rg);
+    float2 out_ba = unpack_half2(ba);
+    unsigned char* px = output + (y * output_width + x) * 4;
+    px[0] = to_byte(out_rg.x);
+    px[1] = to_byte(out_rg.y);
+    px[2] = to_byte(out_ba.x);
+    px[3] = to_byte(out_ba.y);
+}
+"#;
+
 const BILINEAR_RESIZE_KERNEL: &str = r#"
 __device__ __forceinline__ float clamp(float val, float min_val, float max_val) {
     return fmaxf(min_val, fminf(max_val, val));
@@ -156,12 +581,114 @@ extern "C" __global__ void bilinear_resize(
         output[(y * output_width + x) * 4 + c] = (unsigned char)clamp(final_val, 0.0f, 255.0f);
     }
 }
+
+__device__ __forceinline__ float srgb_to_linear(float v) {
+    v /= 255.0f;
+    return v <= 0.04045f ? v / 12.92f : powf((v + 0.055f) / 1.055f, 2.4f);
+}
+
+__device__ __forceinline__ float linear_to_srgb(float v) {
+    v = clamp(v, 0.0f, 1.0f);
+    return 255.0f * (v <= 0.0031308f ? v * 12.92f : 1.055f * powf(v, 1.0f / 2.4f) - 0.055f);
+}
+
+// Same sampling as bilinear_resize, but color channels are decoded to linear
+// light per tap and encoded back once filtered. Alpha is already linear.
+extern "C" __global__ void bilinear_resize_linear(
+    const unsigned char* input,
+    int input_width,
+    int input_height,
+    unsigned char* output,
+    int output_width,
+    int output_height
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+
+    if (x >= output_width || y >= output_height) return;
+
+    float src_x = (float)x * (float)input_width / (float)output_width;
+    float src_y = (float)y * (float)input_height / (float)output_height;
+
+    int x1 = (int)src_x;
+    int y1 = (int)src_y;
+    int x2 = min(x1 + 1, input_width - 1);
+    int y2 = min(y1 + 1, input_height - 1);
+
+    float dx = src_x - (float)x1;
+    float dy = src_y - (float)y1;
+
+    for (int c = 0; c < 4; c++) {
+        float val11 = (float)input[(y1 * input_width + x1) * 4 + c];
+        float val12 = (float)input[(y1 * input_width + x2) * 4 + c];
+        float val21 = (float)input[(y2 * input_width + x1) * 4 + c];
+        float val22 = (float)input[(y2 * input_width + x2) * 4 + c];
+        if (c < 3) {
+            val11 = srgb_to_linear(val11);
+            val12 = srgb_to_linear(val12);
+            val21 = srgb_to_linear(val21);
+            val22 = srgb_to_linear(val22);
+        }
+
+        float val1 = val11 * (1.0f - dx) + val12 * dx;
+        float val2 = val21 * (1.0f - dx) + val22 * dx;
+        float final_val = val1 * (1.0f - dy) + val2 * dy;
+
+        output[(y * output_width + x) * 4 + c] = c < 3
+            ? (unsigned char)(linear_to_srgb(final_val) + 0.5f)
+            : (unsigned char)clamp(final_val, 0.0f, 255.0f);
+    }
+}
 "#;
 
 #[neon::main]
 fn main(mut cx: ModuleContext) -> NeonResult<()> {
     cx.export_function("gpu_processor_init", gpu_processor_init)?;
     cx.export_function("gpu_processor_get_device_count", gpu_processor_get_device_count)?;
-    cx.export_function("gpu_processor_resize_image", gpu_processor_resize_image)?;
+    cx.export_function("gpu_processor_resize_image", limited(gpu_processor_resize_image))?;
+    cx.export_function("gpu_processor_register_kernel", limited(custom_kernels::gpu_processor_register_kernel))?;
+    cx.export_function("gpu_processor_launch_kernel", limited(custom_kernels::gpu_processor_launch_kernel))?;
+    cx.export_function("gpu_processor_chroma_key", limited(chroma_key::gpu_processor_chroma_key))?;
+    cx.export_function("gpu_processor_composite", limited(composite::gpu_processor_composite))?;
+    cx.export_function("gpu_processor_vignette", limited(filters::gpu_processor_vignette))?;
+    cx.export_function("gpu_processor_pixelate_regions", limited(redact::gpu_processor_pixelate_regions))?;
+    cx.export_function("gpu_processor_blur_regions", limited(redact::gpu_processor_blur_regions))?;
+    cx.export_function("gpu_processor_upload", limited(handles::gpu_processor_upload))?;
+    cx.export_function("gpu_processor_download", limited(handles::gpu_processor_download))?;
+    cx.export_function("gpu_processor_handle_info", handles::gpu_processor_handle_info)?;
+    cx.export_function("gpu_processor_release", handles::gpu_processor_release)?;
+    cx.export_function("gpu_processor_tone_map", limited(tone_map::gpu_processor_tone_map))?;
+    cx.export_function("gpu_processor_tone_map_handle", limited(tone_map::gpu_processor_tone_map_handle))?;
+    cx.export_function("gpu_processor_dither", limited(dither::gpu_processor_dither))?;
+    cx.export_function("gpu_processor_summed_area_table", limited(sat::gpu_processor_summed_area_table))?;
+    cx.export_function("gpu_processor_smart_crop", limited(smart_crop::gpu_processor_smart_crop))?;
+    cx.export_function("gpu_processor_create_resize_stream", frame_stream::gpu_processor_create_resize_stream)?;
+    cx.export_function("gpu_processor_push_frame", frame_stream::gpu_processor_push_frame)?;
+    cx.export_function("gpu_processor_close_resize_stream", frame_stream::gpu_processor_close_resize_stream)?;
+    cx.export_function("gpu_processor_interpolate_frames", limited(interpolate::gpu_processor_interpolate_frames))?;
+    cx.export_function("gpu_processor_exposure_fusion", limited(exposure_fusion::gpu_processor_exposure_fusion))?;
+    cx.export_function("gpu_processor_blend_panorama", limited(panorama::gpu_processor_blend_panorama))?;
+    cx.export_function("gpu_processor_film_grain", limited(filters::gpu_processor_film_grain))?;
+    cx.export_function("gpu_processor_shadow_border_size", shadow::gpu_processor_shadow_border_size)?;
+    cx.export_function("gpu_processor_shadow_border", limited(shadow::gpu_processor_shadow_border))?;
+    cx.export_function("gpu_processor_round_corners", limited(mask::gpu_processor_round_corners))?;
+    cx.export_function("gpu_processor_apply_mask", limited(mask::gpu_processor_apply_mask))?;
+    cx.export_function("gpu_processor_convert_primaries", limited(primaries::gpu_processor_convert_primaries))?;
+    cx.export_function("gpu_processor_stereo_convert", limited(stereo::gpu_processor_stereo_convert))?;
+    cx.export_function("gpu_processor_qr_overlay", limited(qr_overlay::gpu_processor_qr_overlay))?;
+    cx.export_function("gpu_processor_handle_device_pointer", handles::gpu_processor_handle_device_pointer)?;
+    cx.export_function("gpu_processor_equalize_histogram", limited(equalize::gpu_processor_equalize_histogram))?;
+    cx.export_function("gpu_processor_clahe", limited(equalize::gpu_processor_clahe))?;
+    cx.export_function("gpu_processor_smooth_skin", limited(skin_smooth::gpu_processor_smooth_skin))?;
+    cx.export_function("gpu_processor_run_pipeline", limited(pipeline::gpu_processor_run_pipeline))?;
+    cx.export_function("gpu_processor_define_preset", pipeline::gpu_processor_define_preset)?;
+    cx.export_function("gpu_processor_run_preset", limited(pipeline::gpu_processor_run_preset))?;
+    cx.export_function("gpu_processor_queue_status", config::gpu_processor_queue_status)?;
+    cx.export_function("gpu_processor_shutdown", gpu_processor_shutdown)?;
+    cx.export_function("gpu_processor_benchmark", limited(benchmark::gpu_processor_benchmark))?;
+    cx.export_function("gpu_processor_detect_faces", limited(faces::gpu_processor_detect_faces))?;
+    cx.export_function("gpu_processor_temporal_denoise", limited(temporal_denoise::gpu_processor_temporal_denoise))?;
+    cx.export_function("gpu_processor_apply_curves", limited(curves::gpu_processor_apply_curves))?;
+    cx.export_function("gpu_processor_refine_matte", limited(matting::gpu_processor_refine_matte))?;
     Ok(())
 }
@@ -0,0 +1,119 @@
+//! Leveled logging for the addon.
+//!
+//! Messages at or below the configured level go to stderr, or to a JS
+//! callback installed through the `logger` init option. The callback runs on
+//! the JS thread that installed it, whichever thread logged the message.
+
+use crate::config::config;
+use lazy_static::lazy_static;
+use neon::prelude::*;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub(crate) enum LogLevel {
+    Off,
+    Error,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    pub(crate) fn parse(name: &str) -> Option<LogLevel> {
+        match name {
+            "off" => Some(LogLevel::Off),
+            "error" => Some(LogLevel::Error),
+            "info" => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            LogLevel::Off => "off",
+            LogLevel::Error => "error",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        }
+    }
+}
+
+struct JsLogger {
+    channel: Channel,
+    callback: Arc<Root<JsFunction>>,
+}
+
+lazy_static! {
+    static ref LOGGER: Mutex<Option<JsLogger>> = Mutex::new(None);
+}
+
+pub(crate) fn enabled(level: LogLevel) -> bool {
+    level != LogLevel::Off && level <= config().log_level
+}
+
+/// Sends `message` to the JS logger, or to stderr when none is installed.
+/// Use the `log_*!` macros rather than calling this directly.
+pub(crate) fn emit(level: LogLevel, message: String) {
+    let logger = LOGGER.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    match logger.as_ref() {
+        Some(logger) => {
+            let callback = logger.callback.clone();
+            // The logger's thread may be gone; there is nobody to tell then.
+            let _ = logger.channel.try_send(move |mut cx| {
+                let callback = callback.to_inner(&mut cx);
+                callback.call_with(&cx).arg(cx.string(level.name())).arg(cx.string(message)).exec(&mut cx)
+            });
+        }
+        None => eprintln!("[gpu_processor] {}: {}", level.name(), message),
+    }
+}
+
+/// Applies the `logger` key of the init options: a function installs it as
+/// the log sink, `null` restores stderr and a missing key leaves it as is.
+pub(crate) fn logger_option(cx: &mut FunctionContext, index: usize) -> NeonResult<()> {
+    let obj = match cx.argument_opt(index) {
+        Some(value) if value.is_a::<JsObject, _>(cx) => value.downcast_or_throw::<JsObject, _>(cx)?,
+        _ => return Ok(()),
+    };
+    let value = match obj.get_opt::<JsValue, _, _>(cx, "logger")? {
+        Some(value) => value,
+        None => return Ok(()),
+    };
+    let logger = if value.is_a::<JsNull, _>(cx) {
+        None
+    } else {
+        let callback = value.downcast_or_throw::<JsFunction, _>(cx)?.root(cx);
+        let mut channel = cx.channel();
+        // Logging must not keep the event loop alive.
+        channel.unref(cx);
+        Some(JsLogger { channel, callback: Arc::new(callback) })
+    };
+    *LOGGER.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = logger;
+    Ok(())
+}
+
+macro_rules! log_error {
+    ($($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::LogLevel::Error) {
+            $crate::logging::emit($crate::logging::LogLevel::Error, format!($($arg)*));
+        }
+    };
+}
+
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::LogLevel::Info) {
+            $crate::logging::emit($crate::logging::LogLevel::Info, format!($($arg)*));
+        }
+    };
+}
+
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::LogLevel::Debug) {
+            $crate::logging::emit($crate::logging::LogLevel::Debug, format!($($arg)*));
+        }
+    };
+}
+
+pub(crate) use {log_debug, log_error, log_info};
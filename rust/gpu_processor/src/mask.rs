@@ -0,0 +1,135 @@
+//! Alpha masking: rounded corners and caller-supplied masks.
+
+use crate::{download, launch_config_2d, load_kernel, run_gpu, upload_rgba, output_argument, write_result};
+use crate::validation::{dimension_argument, image_bytes};
+use cudarc::driver::{CudaDevice, CudaSlice, LaunchAsync};
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+use std::sync::Arc;
+
+/// Clears everything outside a rounded rectangle in place.
+pub(crate) fn round_corners(
+    device: &Arc<CudaDevice>,
+    image: &mut CudaSlice<u8>,
+    width: usize,
+    height: usize,
+    radius: f32,
+) -> Result<(), f64> {
+    let radius = radius.clamp(0.0, width.min(height) as f32 / 2.0);
+    let kernel = load_kernel(device, "mask_module", MASK_KERNEL, MASK_FUNCTIONS, "round_corners")?;
+    let params = (image, width as i32, height as i32, radius);
+    unsafe { kernel.launch(launch_config_2d(width, height), params) }.map_err(|_| -8.0) // Kernel launch failed
+}
+
+/// `gpu_processor_round_corners(input, width, height, radius, output?)`
+///
+/// Makes the area outside a rounded rectangle with corner `radius` (pixels)
+/// transparent, with an anti-aliased edge. A radius of half the shorter side
+/// or more gives a pill, or a circle for square avatars.
+pub(crate) fn gpu_processor_round_corners(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let radius = cx.argument::<JsNumber>(3)?.value(&mut cx) as f32;
+    let output_buffer = output_argument(&mut cx, 4)?;
+
+    let result = run_gpu(|device| {
+        let mut image = upload_rgba(device, input_data.as_slice(&cx), width, height)?;
+        round_corners(device, &mut image, width, height, radius)?;
+        download(device, &image)
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+/// `gpu_processor_apply_mask(input, width, height, mask, mask_width, mask_height, invert, output?)`
+///
+/// Multiplies the alpha channel by a single-channel 8-bit `mask`
+/// (`mask_width * mask_height` bytes, 255 keeps a pixel). A mask of another
+/// size is stretched over the image bilinearly. `invert` uses `255 - mask`.
+pub(crate) fn gpu_processor_apply_mask(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let mask_data = cx.argument::<JsBuffer>(3)?;
+    let mask_width = dimension_argument(&mut cx, 4)?;
+    let mask_height = dimension_argument(&mut cx, 5)?;
+    let invert = cx.argument::<JsBoolean>(6)?.value(&mut cx);
+    let output_buffer = output_argument(&mut cx, 7)?;
+
+    let result = run_gpu(|device| {
+        let mut image = upload_rgba(device, input_data.as_slice(&cx), width, height)?;
+        let mask_slice = mask_data.as_slice(&cx);
+        if mask_slice.len() != image_bytes(mask_width, mask_height, 1)? {
+            return Err(-2.0); // Invalid input size
+        }
+        let mask = device.htod_sync_copy(mask_slice).map_err(|_| -3.0)?; // Memory allocation failed
+        let kernel = load_kernel(device, "mask_module", MASK_KERNEL, MASK_FUNCTIONS, "apply_mask")?;
+        let params = (
+            &mut image,
+            width as i32,
+            height as i32,
+            &mask,
+            mask_width as i32,
+            mask_height as i32,
+            invert as i32,
+        );
+        unsafe { kernel.launch(launch_config_2d(width, height), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+        download(device, &image)
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+const MASK_FUNCTIONS: &[&str] = &["round_corners", "apply_mask"];
+
+const MASK_KERNEL: &str = r#"
+__device__ __forceinline__ void scale_alpha(unsigned char* px, float coverage) {
+    px[3] = (unsigned char)fminf(fmaxf((float)px[3] * coverage + 0.5f, 0.0f), 255.0f);
+}
+
+extern "C" __global__ void round_corners(unsigned char* image, int width, int height, float radius) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= width || y >= height) return;
+
+    // Signed distance from the pixel center to the rounded rectangle.
+    float half_w = 0.5f * (float)width;
+    float half_h = 0.5f * (float)height;
+    float qx = fabsf((float)x + 0.5f - half_w) - (half_w - radius);
+    float qy = fabsf((float)y + 0.5f - half_h) - (half_h - radius);
+    float outside = hypotf(fmaxf(qx, 0.0f), fmaxf(qy, 0.0f));
+    float dist = outside + fminf(fmaxf(qx, qy), 0.0f) - radius;
+
+    float coverage = fminf(fmaxf(0.5f - dist, 0.0f), 1.0f);
+    if (coverage < 1.0f) scale_alpha(image + (y * width + x) * 4, coverage);
+}
+
+extern "C" __global__ void apply_mask(
+    unsigned char* image,
+    int width,
+    int height,
+    const unsigned char* mask,
+    int mask_width,
+    int mask_height,
+    int invert
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= width || y >= height) return;
+
+    float mx = fminf(fmaxf(((float)x + 0.5f) * (float)mask_width / (float)width - 0.5f, 0.0f), (float)(mask_width - 1));
+    float my = fminf(fmaxf(((float)y + 0.5f) * (float)mask_height / (float)height - 0.5f, 0.0f), (float)(mask_height - 1));
+    int x1 = (int)mx;
+    int y1 = (int)my;
+    int x2 = min(x1 + 1, mask_width - 1);
+    int y2 = min(y1 + 1, mask_height - 1);
+    float dx = mx - (float)x1;
+    float dy = my - (float)y1;
+    float m1 = (float)mask[y1 * mask_width + x1] * (1.0f - dx) + (float)mask[y1 * mask_width + x2] * dx;
+    float m2 = (float)mask[y2 * mask_width + x1] * (1.0f - dx) + (float)mask[y2 * mask_width + x2] * dx;
+    float m = (m1 * (1.0f - dy) + m2 * dy) / 255.0f;
+
+    scale_alpha(image + (y * width + x) * 4, invert ? 1.0f - m : m);
+}
+"#;
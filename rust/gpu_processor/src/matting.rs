@@ -0,0 +1,297 @@
+//! Alpha matte refinement with a color guided filter.
+//!
+//! A rough segmentation mask is filtered with the color image as the guide
+//! (He et al., "Guided Image Filtering"): within each window the matte is
+//! modeled as a linear function of the color, so edges snap to the color
+//! boundaries and fine structures such as hair get fractional alpha.
+
+use crate::validation::{dimension_argument, image_bytes};
+use crate::{launch_config_2d, load_kernel, run_gpu, upload_rgba, output_argument, write_result};
+use cudarc::driver::{CudaDevice, CudaSlice, LaunchAsync, LaunchConfig};
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+use std::sync::Arc;
+
+/// Per-pixel planes: color (3), matte, color times matte (3), and the six
+/// distinct color products.
+const GUIDE_PLANES: usize = 13;
+
+/// Linear coefficients of the local model: one weight per color plus offset.
+const COEFFICIENT_PLANES: usize = 4;
+
+/// Replaces the first `planes` planes of `data` with their box means over a
+/// `(2 * radius + 1)` window, using `scratch` as the intermediate.
+fn box_mean(
+    device: &Arc<CudaDevice>,
+    data: &mut CudaSlice<f32>,
+    scratch: &mut CudaSlice<f32>,
+    width: usize,
+    height: usize,
+    planes: usize,
+    radius: i32,
+) -> Result<(), f64> {
+    let config = |lines: usize| LaunchConfig {
+        grid_dim: ((lines as u32).div_ceil(256), planes as u32, 1),
+        block_dim: (256, 1, 1),
+        shared_mem_bytes: 0,
+    };
+    let rows = load_kernel(device, "matting_module", MATTING_KERNEL, MATTING_FUNCTIONS, "box_rows")?;
+    let params = (&*data, &mut *scratch, width as i32, height as i32, planes as i32, radius);
+    unsafe { rows.launch(config(height), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+
+    let cols = load_kernel(device, "matting_module", MATTING_KERNEL, MATTING_FUNCTIONS, "box_cols")?;
+    let params = (&*scratch, data, width as i32, height as i32, planes as i32, radius);
+    unsafe { cols.launch(config(width), params) }.map_err(|_| -8.0) // Kernel launch failed
+}
+
+/// Refines the single-channel `mask` against the RGBA `image` and returns the
+/// refined matte, one byte per pixel.
+pub(crate) fn refine_matte(
+    device: &Arc<CudaDevice>,
+    image: &CudaSlice<u8>,
+    mask: &CudaSlice<u8>,
+    width: usize,
+    height: usize,
+    radius: i32,
+    epsilon: f32,
+) -> Result<CudaSlice<u8>, f64> {
+    let pixels = width * height;
+    let mut planes = device.alloc_zeros::<f32>(pixels * GUIDE_PLANES).map_err(|_| -4.0)?; // Output allocation failed
+    let mut scratch = device.alloc_zeros::<f32>(pixels * GUIDE_PLANES).map_err(|_| -4.0)?;
+    let cfg = launch_config_2d(width, height);
+
+    let kernel = load_kernel(device, "matting_module", MATTING_KERNEL, MATTING_FUNCTIONS, "guided_products")?;
+    let params = (image, mask, &mut planes, width as i32, height as i32);
+    unsafe { kernel.launch(cfg, params) }.map_err(|_| -8.0)?; // Kernel launch failed
+    box_mean(device, &mut planes, &mut scratch, width, height, GUIDE_PLANES, radius)?;
+
+    // The coefficients go into `scratch` and are averaged in turn, reusing
+    // `planes` as the intermediate.
+    let kernel = load_kernel(device, "matting_module", MATTING_KERNEL, MATTING_FUNCTIONS, "guided_coefficients")?;
+    let params = (&planes, &mut scratch, width as i32, height as i32, epsilon);
+    unsafe { kernel.launch(cfg, params) }.map_err(|_| -8.0)?; // Kernel launch failed
+    box_mean(device, &mut scratch, &mut planes, width, height, COEFFICIENT_PLANES, radius)?;
+
+    let mut matte = device.alloc_zeros::<u8>(pixels).map_err(|_| -4.0)?; // Output allocation failed
+    let kernel = load_kernel(device, "matting_module", MATTING_KERNEL, MATTING_FUNCTIONS, "guided_output")?;
+    let params = (image, &scratch, &mut matte, width as i32, height as i32);
+    unsafe { kernel.launch(cfg, params) }.map_err(|_| -8.0)?; // Kernel launch failed
+    Ok(matte)
+}
+
+/// `gpu_processor_refine_matte(input, width, height, mask, radius?, epsilon?, output?)`
+///
+/// Refines a rough segmentation `mask` (`width * height` bytes, 255 is
+/// foreground) against the RGBA `input` with a color guided filter and
+/// writes the refined matte in the same one-byte-per-pixel layout, ready
+/// for `gpu_processor_apply_mask`. Pass `null` as `mask` to refine the
+/// alpha channel of `input` instead. `radius` (1–64, default 8) is the
+/// window radius in pixels and should cover the uncertain band around the
+/// edge; `epsilon` (default 1e-4) is the regularization on the 0–1 color
+/// scale, where larger values smooth the matte more and follow the colors
+/// less.
+pub(crate) fn gpu_processor_refine_matte(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let mask_data = match cx.argument_opt(3) {
+        Some(value) if !value.is_a::<JsNull, _>(&mut cx) => Some(value.downcast_or_throw::<JsBuffer, _>(&mut cx)?),
+        _ => None,
+    };
+    let radius = match cx.argument_opt(4) {
+        Some(value) if !value.is_a::<JsUndefined, _>(&mut cx) => {
+            value.downcast_or_throw::<JsNumber, _>(&mut cx)?.value(&mut cx)
+        }
+        _ => 8.0,
+    };
+    let epsilon = match cx.argument_opt(5) {
+        Some(value) if !value.is_a::<JsUndefined, _>(&mut cx) => {
+            value.downcast_or_throw::<JsNumber, _>(&mut cx)?.value(&mut cx)
+        }
+        _ => 1e-4,
+    };
+    let output_buffer = output_argument(&mut cx, 6)?;
+
+    if !(1.0..=64.0).contains(&radius) {
+        return cx.throw_range_error("radius must be between 1 and 64");
+    }
+    if !epsilon.is_finite() || epsilon <= 0.0 {
+        return cx.throw_range_error("epsilon must be a positive number");
+    }
+
+    let result = run_gpu(|device| {
+        let input = input_data.as_slice(&cx);
+        let image = upload_rgba(device, input, width, height)?;
+        let mask = match mask_data {
+            Some(mask) => {
+                let mask = mask.as_slice(&cx);
+                if mask.len() != image_bytes(width, height, 1)? {
+                    return Err(-2.0); // Invalid input size
+                }
+                device.htod_sync_copy(mask)
+            }
+            None => device.htod_sync_copy(&input.iter().skip(3).step_by(4).copied().collect::<Vec<u8>>()),
+        }
+        .map_err(|_| -3.0)?; // Memory allocation failed
+        let matte = refine_matte(device, &image, &mask, width, height, radius as i32, epsilon as f32)?;
+        device.dtoh_sync_copy(&matte).map_err(|_| -9.0) // Copy back failed
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+const MATTING_FUNCTIONS: &[&str] = &["guided_products", "box_rows", "box_cols", "guided_coefficients", "guided_output"];
+
+const MATTING_KERNEL: &str = r#"
+// Planes are stored one after another, width * height floats each.
+#define PLANE(data, p, n) ((data) + (long long)(p) * (n))
+
+extern "C" __global__ void guided_products(
+    const unsigned char* image,
+    const unsigned char* mask,
+    float* planes,
+    int width,
+    int height
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= width || y >= height) return;
+
+    int n = width * height;
+    int i = y * width + x;
+    const unsigned char* px = image + i * 4;
+    float r = (float)px[0] / 255.0f;
+    float g = (float)px[1] / 255.0f;
+    float b = (float)px[2] / 255.0f;
+    float p = (float)mask[i] / 255.0f;
+
+    float values[13] = {r, g, b, p, r * p, g * p, b * p, r * r, r * g, r * b, g * g, g * b, b * b};
+    for (int k = 0; k < 13; k++) PLANE(planes, k, n)[i] = values[k];
+}
+
+// Sliding-window means along rows, one thread per row and plane. Windows are
+// cut at the border and averaged over the pixels they cover. The running
+// sum is a double so it does not drift along long rows.
+extern "C" __global__ void box_rows(
+    const float* input,
+    float* output,
+    int width,
+    int height,
+    int planes,
+    int radius
+) {
+    int y = blockIdx.x * blockDim.x + threadIdx.x;
+    int p = blockIdx.y;
+    if (y >= height || p >= planes) return;
+
+    int n = width * height;
+    const float* in = PLANE(input, p, n) + y * width;
+    float* out = PLANE(output, p, n) + y * width;
+
+    double sum = 0.0;
+    for (int x = 0; x <= min(radius, width - 1); x++) sum += in[x];
+    for (int x = 0; x < width; x++) {
+        int lo = max(x - radius, 0);
+        int hi = min(x + radius, width - 1);
+        out[x] = (float)(sum / (double)(hi - lo + 1));
+        if (x + radius + 1 < width) sum += in[x + radius + 1];
+        if (x - radius >= 0) sum -= in[x - radius];
+    }
+}
+
+extern "C" __global__ void box_cols(
+    const float* input,
+    float* output,
+    int width,
+    int height,
+    int planes,
+    int radius
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int p = blockIdx.y;
+    if (x >= width || p >= planes) return;
+
+    int n = width * height;
+    const float* in = PLANE(input, p, n) + x;
+    float* out = PLANE(output, p, n) + x;
+
+    double sum = 0.0;
+    for (int y = 0; y <= min(radius, height - 1); y++) sum += in[y * width];
+    for (int y = 0; y < height; y++) {
+        int lo = max(y - radius, 0);
+        int hi = min(y + radius, height - 1);
+        out[y * width] = (float)(sum / (double)(hi - lo + 1));
+        if (y + radius + 1 < height) sum += in[(y + radius + 1) * width];
+        if (y - radius >= 0) sum -= in[(y - radius) * width];
+    }
+}
+
+// Solves (Sigma + epsilon * I) a = cov(I, p) per window and b = mean_p - a . mean_I.
+extern "C" __global__ void guided_coefficients(
+    const float* means,
+    float* coefficients,
+    int width,
+    int height,
+    float epsilon
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= width || y >= height) return;
+
+    int n = width * height;
+    int i = y * width + x;
+    float m[13];
+    for (int k = 0; k < 13; k++) m[k] = PLANE(means, k, n)[i];
+    float mr = m[0], mg = m[1], mb = m[2], mp = m[3];
+
+    float cov_r = m[4] - mr * mp;
+    float cov_g = m[5] - mg * mp;
+    float cov_b = m[6] - mb * mp;
+
+    float s_rr = m[7] - mr * mr + epsilon;
+    float s_rg = m[8] - mr * mg;
+    float s_rb = m[9] - mr * mb;
+    float s_gg = m[10] - mg * mg + epsilon;
+    float s_gb = m[11] - mg * mb;
+    float s_bb = m[12] - mb * mb + epsilon;
+
+    // Inverse of the symmetric 3x3 matrix through its cofactors.
+    float i_rr = s_gg * s_bb - s_gb * s_gb;
+    float i_rg = s_rb * s_gb - s_rg * s_bb;
+    float i_rb = s_rg * s_gb - s_rb * s_gg;
+    float i_gg = s_rr * s_bb - s_rb * s_rb;
+    float i_gb = s_rg * s_rb - s_rr * s_gb;
+    float i_bb = s_rr * s_gg - s_rg * s_rg;
+    float det = s_rr * i_rr + s_rg * i_rg + s_rb * i_rb;
+
+    float a_r = (i_rr * cov_r + i_rg * cov_g + i_rb * cov_b) / det;
+    float a_g = (i_rg * cov_r + i_gg * cov_g + i_gb * cov_b) / det;
+    float a_b = (i_rb * cov_r + i_gb * cov_g + i_bb * cov_b) / det;
+
+    PLANE(coefficients, 0, n)[i] = a_r;
+    PLANE(coefficients, 1, n)[i] = a_g;
+    PLANE(coefficients, 2, n)[i] = a_b;
+    PLANE(coefficients, 3, n)[i] = mp - a_r * mr - a_g * mg - a_b * mb;
+}
+
+extern "C" __global__ void guided_output(
+    const unsigned char* image,
+    const float* coefficients,
+    unsigned char* matte,
+    int width,
+    int height
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= width || y >= height) return;
+
+    int n = width * height;
+    int i = y * width + x;
+    const unsigned char* px = image + i * 4;
+    float q = PLANE(coefficients, 0, n)[i] * (float)px[0] / 255.0f
+            + PLANE(coefficients, 1, n)[i] * (float)px[1] / 255.0f
+            + PLANE(coefficients, 2, n)[i] * (float)px[2] / 255.0f
+            + PLANE(coefficients, 3, n)[i];
+    matte[i] = (unsigned char)fminf(fmaxf(q * 255.0f + 0.5f, 0.0f), 255.0f);
+}
+"#;
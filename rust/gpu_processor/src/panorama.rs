@@ -0,0 +1,168 @@
+//! Panorama stitching from pre-aligned tiles.
+
+use crate::pyramid::{blend_multiband, level_count, to_bytes};
+use crate::{download, launch_config_2d, load_kernel, run_gpu, upload_rgba, output_argument, write_result};
+use crate::validation::{dimension, dimension_argument, image_bytes};
+use cudarc::driver::LaunchAsync;
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+
+struct Tile<'a> {
+    data: Handle<'a, JsBuffer>,
+    width: usize,
+    height: usize,
+    /// Panorama-to-tile mapping, row-major.
+    inverse: [f32; 9],
+}
+
+/// Inverts a row-major 3x3 homography, or `None` if it is singular.
+fn invert_homography(h: &[f64; 9]) -> Option<[f32; 9]> {
+    let cofactors = [
+        h[4] * h[8] - h[5] * h[7],
+        h[2] * h[7] - h[1] * h[8],
+        h[1] * h[5] - h[2] * h[4],
+        h[5] * h[6] - h[3] * h[8],
+        h[0] * h[8] - h[2] * h[6],
+        h[2] * h[3] - h[0] * h[5],
+        h[3] * h[7] - h[4] * h[6],
+        h[1] * h[6] - h[0] * h[7],
+        h[0] * h[4] - h[1] * h[3],
+    ];
+    let det = h[0] * cofactors[0] + h[1] * cofactors[3] + h[2] * cofactors[6];
+    if !det.is_finite() || det.abs() < 1e-12 {
+        return None;
+    }
+    Some(cofactors.map(|c| (c / det) as f32))
+}
+
+fn tile_argument<'a>(cx: &mut FunctionContext<'a>, value: Handle<'a, JsValue>) -> NeonResult<Tile<'a>> {
+    let obj = value.downcast_or_throw::<JsObject, _>(cx)?;
+    let data = obj.get::<JsBuffer, _, _>(cx, "data")?;
+    let width = dimension(obj.get::<JsNumber, _, _>(cx, "width")?.value(cx));
+    let height = dimension(obj.get::<JsNumber, _, _>(cx, "height")?.value(cx));
+    let values = obj.get::<JsArray, _, _>(cx, "homography")?.to_vec(cx)?;
+    if values.len() != 9 {
+        return cx.throw_type_error("homography must have 9 entries");
+    }
+    let mut homography = [0.0f64; 9];
+    for (entry, value) in homography.iter_mut().zip(values) {
+        *entry = value.downcast_or_throw::<JsNumber, _>(cx)?.value(cx);
+    }
+    match invert_homography(&homography) {
+        Some(inverse) => Ok(Tile { data, width, height, inverse }),
+        None => cx.throw_range_error("homography is not invertible"),
+    }
+}
+
+/// `gpu_processor_blend_panorama(tiles, canvas_width, canvas_height, output?)`
+///
+/// `tiles` is an array of `{ data, width, height, homography }` where
+/// `homography` is the row-major 3x3 matrix mapping tile pixel coordinates to
+/// panorama coordinates. Each tile is warped onto the canvas with a weight
+/// that fades towards its edges, and overlaps are merged with multi-band
+/// blending so exposure differences fade out while detail stays sharp.
+/// Canvas pixels no tile covers are left transparent.
+pub(crate) fn gpu_processor_blend_panorama(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let values = cx.argument::<JsArray>(0)?.to_vec(&mut cx)?;
+    let canvas_width = dimension_argument(&mut cx, 1)?;
+    let canvas_height = dimension_argument(&mut cx, 2)?;
+    let output_buffer = output_argument(&mut cx, 3)?;
+
+    if values.is_empty() {
+        return cx.throw_range_error("at least one tile is required");
+    }
+    let mut tiles = Vec::with_capacity(values.len());
+    for value in values {
+        tiles.push(tile_argument(&mut cx, value)?);
+    }
+
+    let result = run_gpu(|device| {
+        let pixels = image_bytes(canvas_width, canvas_height, 1)?;
+        let kernel = load_kernel(device, "panorama_module", PANORAMA_KERNEL, PANORAMA_FUNCTIONS, "warp_tile")?;
+        let mut images = Vec::with_capacity(tiles.len());
+        let mut weights = Vec::with_capacity(tiles.len());
+        for tile in &tiles {
+            let data = upload_rgba(device, tile.data.as_slice(&cx), tile.width, tile.height)?;
+            let inverse = device.htod_sync_copy(&tile.inverse).map_err(|_| -3.0)?; // Memory allocation failed
+            let mut image = device.alloc_zeros::<f32>(pixels * 4).map_err(|_| -4.0)?; // Output allocation failed
+            let mut weight = device.alloc_zeros::<f32>(pixels).map_err(|_| -4.0)?;
+            let params = (
+                &data,
+                tile.width as i32,
+                tile.height as i32,
+                &inverse,
+                &mut image,
+                &mut weight,
+                canvas_width as i32,
+                canvas_height as i32,
+            );
+            unsafe { kernel.clone().launch(launch_config_2d(canvas_width, canvas_height), params) }
+                .map_err(|_| -8.0)?; // Kernel launch failed
+            images.push(image);
+            weights.push(weight);
+        }
+
+        let levels = level_count(canvas_width, canvas_height);
+        let panorama = blend_multiband(device, images, weights, canvas_width, canvas_height, levels)?;
+        download(device, &to_bytes(device, &panorama)?)
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+const PANORAMA_FUNCTIONS: &[&str] = &["warp_tile"];
+
+const PANORAMA_KERNEL: &str = r#"
+// Backward-warps one tile onto the canvas. Colors are edge-extended outside
+// the tile so coarse blend bands see no black border; only the weight marks
+// where the tile really is.
+extern "C" __global__ void warp_tile(
+    const unsigned char* tile,
+    int tile_width,
+    int tile_height,
+    const float* inverse,
+    float* image,
+    float* weight,
+    int canvas_width,
+    int canvas_height
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= canvas_width || y >= canvas_height) return;
+
+    float px = (float)x + 0.5f;
+    float py = (float)y + 0.5f;
+    float w = inverse[6] * px + inverse[7] * py + inverse[8];
+    float u = 0.0f;
+    float v = 0.0f;
+    bool inside = false;
+    if (fabsf(w) > 1e-8f) {
+        u = (inverse[0] * px + inverse[1] * py + inverse[2]) / w - 0.5f;
+        v = (inverse[3] * px + inverse[4] * py + inverse[5]) / w - 0.5f;
+        inside = w > 0.0f && u >= -0.5f && v >= -0.5f
+            && u <= (float)tile_width - 0.5f && v <= (float)tile_height - 0.5f;
+    }
+
+    float su = fminf(fmaxf(u, 0.0f), (float)(tile_width - 1));
+    float sv = fminf(fmaxf(v, 0.0f), (float)(tile_height - 1));
+    int x1 = (int)su;
+    int y1 = (int)sv;
+    int x2 = min(x1 + 1, tile_width - 1);
+    int y2 = min(y1 + 1, tile_height - 1);
+    float dx = su - (float)x1;
+    float dy = sv - (float)y1;
+
+    int i = y * canvas_width + x;
+    for (int c = 0; c < 4; c++) {
+        float v1 = (float)tile[(y1 * tile_width + x1) * 4 + c] * (1.0f - dx) + (float)tile[(y1 * tile_width + x2) * 4 + c] * dx;
+        float v2 = (float)tile[(y2 * tile_width + x1) * 4 + c] * (1.0f - dx) + (float)tile[(y2 * tile_width + x2) * 4 + c] * dx;
+        image[i * 4 + c] = v1 * (1.0f - dy) + v2 * dy;
+    }
+
+    // Distance to the nearest tile edge, as a fraction of the half-size.
+    float edge = fminf(fminf(u + 0.5f, (float)tile_width - 0.5f - u), fminf(v + 0.5f, (float)tile_height - 0.5f - v));
+    float half = 0.5f * (float)min(tile_width, tile_height);
+    float feather = fminf(fmaxf(edge / half, 0.0f), 1.0f);
+    weight[i] = inside ? (feather + 1e-4f) * image[i * 4 + 3] / 255.0f : 0.0f;
+}
+"#;
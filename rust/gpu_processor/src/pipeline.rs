@@ -0,0 +1,312 @@
+//! Operation chains described as data and run on the device in one pass.
+//!
+//! A chain is an array of `{ op, ...settings }` steps, or its JSON text. It
+//! is parsed and validated up front, so a bad step throws before any GPU
+//! work starts, and named presets keep the parsed chain for reuse.
+
+use crate::equalize::equalize;
+use crate::filters::{FilmGrain, Vignette};
+use crate::mask::round_corners;
+use crate::primaries::{convert_primaries, ColorSpace};
+use crate::redact::{blur, pixelate};
+use crate::skin_smooth::smooth_skin;
+use crate::validation::dimension_argument;
+use crate::{download, resize, run_gpu, upload_rgba, ResizeMode};
+use cudarc::driver::{CudaDevice, CudaSlice};
+use lazy_static::lazy_static;
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Operation {
+    Resize { width: usize, height: usize, mode: ResizeMode },
+    Vignette(Vignette),
+    FilmGrain(FilmGrain),
+    RoundCorners { radius: f32 },
+    Blur { sigma: f32 },
+    Pixelate { block_size: i32 },
+    Equalize,
+    Clahe { tiles_x: usize, tiles_y: usize, clip_limit: f32 },
+    SmoothSkin { strength: f32, radius: i32, threshold: f32 },
+    ConvertPrimaries { from: ColorSpace, to: ColorSpace },
+}
+
+lazy_static! {
+    /// Presets hold plain settings, so unlike handles they are shared by
+    /// every worker: define them once at startup and run them anywhere.
+    static ref PRESETS: Mutex<HashMap<String, Arc<Vec<Operation>>>> = Mutex::new(HashMap::new());
+}
+
+/// Reads an optional number setting of step `index`, throwing if it is
+/// present but out of `range`.
+fn number_setting(
+    cx: &mut FunctionContext,
+    step: Handle<JsObject>,
+    index: usize,
+    key: &str,
+    default: f64,
+    range: std::ops::RangeInclusive<f64>,
+) -> NeonResult<f64> {
+    let value = match step.get_opt::<JsNumber, _, _>(cx, key)? {
+        Some(value) => value.value(cx),
+        None => default,
+    };
+    if !range.contains(&value) {
+        return cx.throw_range_error(format!(
+            "operations[{}].{} must be between {} and {}",
+            index,
+            key,
+            range.start(),
+            range.end()
+        ));
+    }
+    Ok(value)
+}
+
+fn required_number(
+    cx: &mut FunctionContext,
+    step: Handle<JsObject>,
+    index: usize,
+    key: &str,
+    range: std::ops::RangeInclusive<f64>,
+) -> NeonResult<f64> {
+    if step.get_opt::<JsNumber, _, _>(cx, key)?.is_none() {
+        return cx.throw_type_error(format!("operations[{}].{} is required", index, key));
+    }
+    number_setting(cx, step, index, key, 0.0, range)
+}
+
+fn bool_setting(cx: &mut FunctionContext, step: Handle<JsObject>, key: &str, default: bool) -> NeonResult<bool> {
+    Ok(match step.get_opt::<JsBoolean, _, _>(cx, key)? {
+        Some(value) => value.value(cx),
+        None => default,
+    })
+}
+
+fn color_space_setting(cx: &mut FunctionContext, step: Handle<JsObject>, index: usize, key: &str) -> NeonResult<ColorSpace> {
+    let name = step.get::<JsString, _, _>(cx, key)?.value(cx);
+    match ColorSpace::parse(&name) {
+        Some(space) => Ok(space),
+        None => cx.throw_type_error(format!("operations[{}]: unknown color space `{}`", index, name)),
+    }
+}
+
+fn color_setting(cx: &mut FunctionContext, step: Handle<JsObject>, key: &str) -> NeonResult<[f32; 3]> {
+    let mut color = [0.0f32; 3];
+    if let Some(values) = step.get_opt::<JsArray, _, _>(cx, key)? {
+        for (slot, value) in color.iter_mut().zip(values.to_vec(cx)?) {
+            *slot = value.downcast_or_throw::<JsNumber, _>(cx)?.value(cx).clamp(0.0, 255.0) as f32;
+        }
+    }
+    Ok(color)
+}
+
+fn parse_operation(cx: &mut FunctionContext, index: usize, step: Handle<JsValue>) -> NeonResult<Operation> {
+    let step = step.downcast_or_throw::<JsObject, _>(cx)?;
+    let name = step.get::<JsString, _, _>(cx, "op")?.value(cx);
+    let operation = match name.as_str() {
+        "resize" => Operation::Resize {
+            width: required_number(cx, step, index, "width", 1.0..=65535.0)? as usize,
+            height: required_number(cx, step, index, "height", 1.0..=65535.0)? as usize,
+            mode: if bool_setting(cx, step, "linearLight", false)? {
+                ResizeMode::LinearLight
+            } else if bool_setting(cx, step, "halfPrecision", false)? {
+                ResizeMode::HalfPrecision
+            } else {
+                ResizeMode::Standard
+            },
+        },
+        "vignette" => Operation::Vignette(Vignette {
+            strength: number_setting(cx, step, index, "strength", 0.5, 0.0..=1.0)? as f32,
+            radius: number_setting(cx, step, index, "radius", 0.5, 0.0..=f64::MAX)? as f32,
+            feather: number_setting(cx, step, index, "feather", 0.5, 0.0..=f64::MAX)? as f32,
+            center_x: number_setting(cx, step, index, "centerX", 0.5, f64::MIN..=f64::MAX)? as f32,
+            center_y: number_setting(cx, step, index, "centerY", 0.5, f64::MIN..=f64::MAX)? as f32,
+            color: color_setting(cx, step, "color")?,
+        }),
+        "film-grain" => Operation::FilmGrain(FilmGrain {
+            amount: required_number(cx, step, index, "amount", 0.0..=255.0)? as f32,
+            grain_size: number_setting(cx, step, index, "grainSize", 1.0, 1.0..=f64::MAX)? as f32,
+            seed: number_setting(cx, step, index, "seed", 0.0, 0.0..=u32::MAX as f64)? as u32,
+            monochrome: bool_setting(cx, step, "monochrome", true)?,
+        }),
+        "round-corners" => Operation::RoundCorners {
+            radius: required_number(cx, step, index, "radius", 0.0..=f64::MAX)? as f32,
+        },
+        "blur" => Operation::Blur {
+            sigma: required_number(cx, step, index, "sigma", 0.1..=100.0)? as f32,
+        },
+        "pixelate" => Operation::Pixelate {
+            block_size: required_number(cx, step, index, "blockSize", 1.0..=4096.0)? as i32,
+        },
+        "equalize" => Operation::Equalize,
+        "clahe" => Operation::Clahe {
+            tiles_x: number_setting(cx, step, index, "tilesX", 8.0, 1.0..=64.0)? as usize,
+            tiles_y: number_setting(cx, step, index, "tilesY", 8.0, 1.0..=64.0)? as usize,
+            clip_limit: number_setting(cx, step, index, "clipLimit", 2.0, 1.0..=f64::MAX)? as f32,
+        },
+        "smooth-skin" => Operation::SmoothSkin {
+            strength: number_setting(cx, step, index, "strength", 0.5, 0.0..=1.0)? as f32,
+            radius: number_setting(cx, step, index, "radius", 8.0, 1.0..=32.0)? as i32,
+            threshold: number_setting(cx, step, index, "threshold", 20.0, 1.0..=255.0)? as f32,
+        },
+        "convert-primaries" => Operation::ConvertPrimaries {
+            from: color_space_setting(cx, step, index, "from")?,
+            to: color_space_setting(cx, step, index, "to")?,
+        },
+        _ => return cx.throw_type_error(format!("operations[{}]: unknown op `{}`", index, name)),
+    };
+    Ok(operation)
+}
+
+/// Reads a chain from argument `index`: an array of steps or its JSON text.
+pub(crate) fn operations_argument(cx: &mut FunctionContext, index: usize) -> NeonResult<Vec<Operation>> {
+    let mut value = cx.argument::<JsValue>(index)?;
+    if let Ok(text) = value.downcast::<JsString, _>(cx) {
+        let json = cx.global::<JsObject>("JSON")?;
+        let parse = json.get::<JsFunction, _, _>(cx, "parse")?;
+        value = parse.call_with(cx).arg(text).apply(cx)?;
+    }
+    let steps = value.downcast_or_throw::<JsArray, _>(cx)?.to_vec(cx)?;
+    if steps.is_empty() {
+        return cx.throw_range_error("an operation chain needs at least one step");
+    }
+    steps
+        .into_iter()
+        .enumerate()
+        .map(|(index, step)| parse_operation(cx, index, step))
+        .collect()
+}
+
+/// Runs `operations` in order on an RGBA image on the device and returns the
+/// result with its dimensions.
+pub(crate) fn run_operations(
+    device: &Arc<CudaDevice>,
+    mut image: CudaSlice<u8>,
+    mut width: usize,
+    mut height: usize,
+    operations: &[Operation],
+) -> Result<(CudaSlice<u8>, usize, usize), f64> {
+    for operation in operations {
+        match *operation {
+            Operation::Resize { width: new_width, height: new_height, mode } => {
+                image = resize(device, &image, width, height, new_width, new_height, mode)?;
+                width = new_width;
+                height = new_height;
+            }
+            Operation::Vignette(vignette) => vignette.apply(device, &mut image, width, height)?,
+            Operation::FilmGrain(grain) => grain.apply(device, &mut image, width, height)?,
+            Operation::RoundCorners { radius } => round_corners(device, &mut image, width, height, radius)?,
+            Operation::Blur { sigma } => {
+                blur(device, &mut image, width, height, &[0, 0, width as i32, height as i32], sigma)?
+            }
+            Operation::Pixelate { block_size } => {
+                pixelate(device, &mut image, width, height, &[0, 0, width as i32, height as i32], block_size)?
+            }
+            Operation::Equalize => equalize(device, &mut image, width, height, 1, 1, 0.0)?,
+            Operation::Clahe { tiles_x, tiles_y, clip_limit } => {
+                equalize(device, &mut image, width, height, tiles_x, tiles_y, clip_limit)?
+            }
+            Operation::SmoothSkin { strength, radius, threshold } => {
+                image = smooth_skin(device, &image, width, height, strength, radius, threshold)?;
+            }
+            Operation::ConvertPrimaries { from, to } => convert_primaries(device, &mut image, width, height, from, to)?,
+        }
+    }
+    Ok((image, width, height))
+}
+
+/// Uploads `input`, runs the chain and returns `{ data, width, height }`, or
+/// the negative status code.
+pub(crate) fn run_chain<'a>(
+    cx: &mut FunctionContext<'a>,
+    operations: &[Operation],
+    input: Handle<'a, JsBuffer>,
+    width: usize,
+    height: usize,
+) -> JsResult<'a, JsValue> {
+    let result = run_gpu(|device| {
+        let image = upload_rgba(device, input.as_slice(cx), width, height)?;
+        let (image, width, height) = run_operations(device, image, width, height, operations)?;
+        Ok((download(device, &image)?, width, height))
+    });
+
+    let (data, width, height) = match result {
+        Ok(output) => output,
+        Err(code) => return Ok(cx.number(code).upcast()),
+    };
+    let obj = cx.empty_object();
+    let value = JsBuffer::from_slice(cx, &data)?;
+    obj.set(cx, "data", value)?;
+    let value = cx.number(width as f64);
+    obj.set(cx, "width", value)?;
+    let value = cx.number(height as f64);
+    obj.set(cx, "height", value)?;
+    Ok(obj.upcast())
+}
+
+/// `gpu_processor_run_pipeline(operations, input, width, height)`
+///
+/// Runs `operations` (an array of steps or its JSON text) on an RGBA image
+/// without leaving the device between steps. Steps:
+///
+/// - `{ op: "resize", width, height, linearLight?, halfPrecision? }`
+/// - `{ op: "vignette", strength?, radius?, feather?, centerX?, centerY?, color? }`
+/// - `{ op: "film-grain", amount, grainSize?, seed?, monochrome? }`
+/// - `{ op: "round-corners", radius }`
+/// - `{ op: "blur", sigma }` (whole image, no minimum sigma)
+/// - `{ op: "pixelate", blockSize }` (whole image)
+/// - `{ op: "equalize" }`
+/// - `{ op: "clahe", tilesX?, tilesY?, clipLimit? }`
+/// - `{ op: "smooth-skin", strength?, radius?, threshold? }`
+/// - `{ op: "convert-primaries", from, to }`
+///
+/// Settings mean the same as the arguments of the matching standalone
+/// function. Throws on the first invalid step before any GPU work; otherwise
+/// returns `{ data, width, height }`, since steps may resize, or a negative
+/// status code.
+pub(crate) fn gpu_processor_run_pipeline(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let operations = operations_argument(&mut cx, 0)?;
+    let input = cx.argument::<JsBuffer>(1)?;
+    let width = dimension_argument(&mut cx, 2)?;
+    let height = dimension_argument(&mut cx, 3)?;
+    run_chain(&mut cx, &operations, input, width, height)
+}
+
+/// `gpu_processor_define_preset(name, operations)`
+///
+/// Parses and validates `operations` as `gpu_processor_run_pipeline` does
+/// and stores the chain as `name`, replacing any preset of the same name.
+pub(crate) fn gpu_processor_define_preset(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let name = cx.argument::<JsString>(0)?.value(&mut cx);
+    let operations = operations_argument(&mut cx, 1)?;
+    PRESETS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(name, Arc::new(operations));
+    Ok(cx.number(0.0)) // Success
+}
+
+/// `gpu_processor_run_preset(name, input, width, height)`
+///
+/// Runs preset `name` like `gpu_processor_run_pipeline`. Throws if no preset
+/// has that name.
+pub(crate) fn gpu_processor_run_preset(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let name = cx.argument::<JsString>(0)?.value(&mut cx);
+    let input = cx.argument::<JsBuffer>(1)?;
+    let width = dimension_argument(&mut cx, 2)?;
+    let height = dimension_argument(&mut cx, 3)?;
+
+    let preset = PRESETS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .get(&name)
+        .cloned();
+    let operations = match preset {
+        Some(operations) => operations,
+        None => return cx.throw_error(format!("Unknown preset `{}`", name)),
+    };
+    run_chain(&mut cx, &operations, input, width, height)
+}
@@ -0,0 +1,172 @@
+//! Conversion between RGB color spaces with different primaries.
+//!
+//! Matrices are derived from each space's chromaticities (all D65), so no
+//! ICC profiles are involved.
+
+use crate::{download, launch_config_2d, load_kernel, run_gpu, upload_rgba, output_argument, write_result};
+use crate::validation::dimension_argument;
+use cudarc::driver::{CudaDevice, CudaSlice, LaunchAsync};
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum ColorSpace {
+    Srgb,
+    DisplayP3,
+    Rec709,
+    Rec2020,
+}
+
+/// Transfer curve ids understood by the kernel.
+const TRANSFER_SRGB: i32 = 0;
+const TRANSFER_BT709: i32 = 1;
+
+const D65_WHITE: [f64; 2] = [0.3127, 0.3290];
+
+impl ColorSpace {
+    pub(crate) fn parse(name: &str) -> Option<ColorSpace> {
+        match name {
+            "srgb" => Some(ColorSpace::Srgb),
+            "display-p3" => Some(ColorSpace::DisplayP3),
+            "rec709" => Some(ColorSpace::Rec709),
+            "rec2020" => Some(ColorSpace::Rec2020),
+            _ => None,
+        }
+    }
+
+    /// Red, green and blue xy chromaticities.
+    fn primaries(self) -> [[f64; 2]; 3] {
+        match self {
+            ColorSpace::Srgb | ColorSpace::Rec709 => [[0.640, 0.330], [0.300, 0.600], [0.150, 0.060]],
+            ColorSpace::DisplayP3 => [[0.680, 0.320], [0.265, 0.690], [0.150, 0.060]],
+            ColorSpace::Rec2020 => [[0.708, 0.292], [0.170, 0.797], [0.131, 0.046]],
+        }
+    }
+
+    fn transfer(self) -> i32 {
+        match self {
+            ColorSpace::Srgb | ColorSpace::DisplayP3 => TRANSFER_SRGB,
+            ColorSpace::Rec709 | ColorSpace::Rec2020 => TRANSFER_BT709,
+        }
+    }
+
+    /// Linear RGB to CIE XYZ, row-major.
+    fn to_xyz(self) -> [[f64; 3]; 3] {
+        let xyz = |[x, y]: [f64; 2]| [x / y, 1.0, (1.0 - x - y) / y];
+        let [r, g, b] = self.primaries().map(xyz);
+        let primaries = [[r[0], g[0], b[0]], [r[1], g[1], b[1]], [r[2], g[2], b[2]]];
+        // Scale each primary so that RGB white lands on the white point.
+        let scale = mul_vec(&invert(&primaries), xyz(D65_WHITE));
+        primaries.map(|row| [row[0] * scale[0], row[1] * scale[1], row[2] * scale[2]])
+    }
+}
+
+fn mul_vec(m: &[[f64; 3]; 3], v: [f64; 3]) -> [f64; 3] {
+    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+fn mul(a: &[[f64; 3]; 3], b: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let mut out = [[0.0; 3]; 3];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, cell) in row.iter_mut().enumerate() {
+            *cell = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    out
+}
+
+fn invert(m: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let det = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1]) - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+    let cof = |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    [
+        [cof(1, 2, 1, 2) / det, -cof(0, 2, 1, 2) / det, cof(0, 1, 1, 2) / det],
+        [-cof(1, 2, 0, 2) / det, cof(0, 2, 0, 2) / det, -cof(0, 1, 0, 2) / det],
+        [cof(1, 2, 0, 1) / det, -cof(0, 2, 0, 1) / det, cof(0, 1, 0, 1) / det],
+    ]
+}
+
+/// Re-encodes `image` from `from` to `to` in place.
+pub(crate) fn convert_primaries(
+    device: &Arc<CudaDevice>,
+    image: &mut CudaSlice<u8>,
+    width: usize,
+    height: usize,
+    from: ColorSpace,
+    to: ColorSpace,
+) -> Result<(), f64> {
+    let matrix = mul(&invert(&to.to_xyz()), &from.to_xyz());
+    let matrix: Vec<f32> = matrix.iter().flatten().map(|&v| v as f32).collect();
+    let matrix = device.htod_sync_copy(&matrix).map_err(|_| -3.0)?; // Memory allocation failed
+    let kernel = load_kernel(device, "primaries_module", PRIMARIES_KERNEL, &["convert_primaries"], "convert_primaries")?;
+    let params = (image, width as i32, height as i32, &matrix, from.transfer(), to.transfer());
+    unsafe { kernel.launch(launch_config_2d(width, height), params) }.map_err(|_| -8.0) // Kernel launch failed
+}
+
+fn color_space_argument(cx: &mut FunctionContext, index: usize) -> NeonResult<ColorSpace> {
+    let name = cx.argument::<JsString>(index)?.value(cx);
+    match ColorSpace::parse(&name) {
+        Some(space) => Ok(space),
+        None => cx.throw_type_error(format!("Unknown color space `{}`", name)),
+    }
+}
+
+/// `gpu_processor_convert_primaries(input, width, height, from, to, output?)`
+///
+/// Re-encodes RGBA pixels from one color space to another: `"srgb"`,
+/// `"display-p3"`, `"rec709"` or `"rec2020"`. Pixels are decoded with the
+/// source transfer curve, mapped through the primaries matrix in linear
+/// light and encoded with the target curve; colors outside the target gamut
+/// are clipped. Alpha is copied unchanged.
+pub(crate) fn gpu_processor_convert_primaries(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let from = color_space_argument(&mut cx, 3)?;
+    let to = color_space_argument(&mut cx, 4)?;
+    let output_buffer = output_argument(&mut cx, 5)?;
+
+    let result = run_gpu(|device| {
+        let mut image = upload_rgba(device, input_data.as_slice(&cx), width, height)?;
+        convert_primaries(device, &mut image, width, height, from, to)?;
+        download(device, &image)
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+const PRIMARIES_KERNEL: &str = r#"
+// 0: sRGB curve, 1: BT.709/BT.2020 OETF.
+__device__ __forceinline__ float decode(float v, int transfer) {
+    if (transfer == 0) return v <= 0.04045f ? v / 12.92f : powf((v + 0.055f) / 1.055f, 2.4f);
+    return v < 0.081f ? v / 4.5f : powf((v + 0.099f) / 1.099f, 1.0f / 0.45f);
+}
+
+__device__ __forceinline__ float encode(float v, int transfer) {
+    v = fminf(fmaxf(v, 0.0f), 1.0f);
+    if (transfer == 0) return v <= 0.0031308f ? v * 12.92f : 1.055f * powf(v, 1.0f / 2.4f) - 0.055f;
+    return v < 0.018f ? v * 4.5f : 1.099f * powf(v, 0.45f) - 0.099f;
+}
+
+extern "C" __global__ void convert_primaries(
+    unsigned char* image,
+    int width,
+    int height,
+    const float* matrix,
+    int from_transfer,
+    int to_transfer
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= width || y >= height) return;
+
+    unsigned char* px = image + (y * width + x) * 4;
+    float rgb[3];
+    for (int c = 0; c < 3; c++) rgb[c] = decode((float)px[c] / 255.0f, from_transfer);
+    for (int c = 0; c < 3; c++) {
+        float v = matrix[c * 3] * rgb[0] + matrix[c * 3 + 1] * rgb[1] + matrix[c * 3 + 2] * rgb[2];
+        px[c] = (unsigned char)(encode(v, to_transfer) * 255.0f + 0.5f);
+    }
+}
+"#;
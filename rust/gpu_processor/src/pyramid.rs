@@ -0,0 +1,276 @@
+//! Gaussian/Laplacian pyramids and multi-band blending of float images.
+//!
+//! Images are `f32` planes on the 0–255 scale with either four (RGBA) or one
+//! (weight) channel per pixel. Blending N images with per-pixel weights in
+//! Laplacian space hides seams and exposure steps that a plain weighted
+//! average would show.
+
+use crate::{launch_config_2d, load_kernel};
+use cudarc::driver::{CudaDevice, CudaFunction, CudaSlice, DeviceSlice, LaunchAsync, LaunchConfig};
+use std::sync::Arc;
+
+/// Levels used for an image of this size: halve until the smaller side drops
+/// below 16 pixels, at most 8 levels.
+pub(crate) fn level_count(width: usize, height: usize) -> usize {
+    let mut levels = 1;
+    let (mut w, mut h) = (width, height);
+    while levels < 8 && w.min(h) >= 32 {
+        w = w.div_ceil(2);
+        h = h.div_ceil(2);
+        levels += 1;
+    }
+    levels
+}
+
+fn level_sizes(width: usize, height: usize, levels: usize) -> Vec<(usize, usize)> {
+    let mut sizes = vec![(width, height)];
+    while sizes.len() < levels {
+        let (w, h) = sizes[sizes.len() - 1];
+        sizes.push((w.div_ceil(2), h.div_ceil(2)));
+    }
+    sizes
+}
+
+fn kernel(device: &Arc<CudaDevice>, name: &str) -> Result<CudaFunction, f64> {
+    load_kernel(device, "pyramid_module", PYRAMID_KERNEL, PYRAMID_FUNCTIONS, name)
+}
+
+fn launch_1d(count: usize) -> LaunchConfig {
+    LaunchConfig::for_num_elems(count as u32)
+}
+
+/// Converts RGBA8 pixels to an RGBA `f32` plane.
+pub(crate) fn to_float(device: &Arc<CudaDevice>, image: &CudaSlice<u8>) -> Result<CudaSlice<f32>, f64> {
+    let count = image.len();
+    let mut out = device.alloc_zeros::<f32>(count).map_err(|_| -4.0)?; // Output allocation failed
+    let params = (image, &mut out, count as i32);
+    unsafe { kernel(device, "u8_to_f32")?.launch(launch_1d(count), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+    Ok(out)
+}
+
+/// Converts an RGBA `f32` plane back to RGBA8, rounding and clamping.
+pub(crate) fn to_bytes(device: &Arc<CudaDevice>, plane: &CudaSlice<f32>) -> Result<CudaSlice<u8>, f64> {
+    let count = plane.len();
+    let mut out = device.alloc_zeros::<u8>(count).map_err(|_| -4.0)?; // Output allocation failed
+    let params = (plane, &mut out, count as i32);
+    unsafe { kernel(device, "f32_to_u8")?.launch(launch_1d(count), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+    Ok(out)
+}
+
+fn downsample(
+    device: &Arc<CudaDevice>,
+    src: &CudaSlice<f32>,
+    (sw, sh): (usize, usize),
+    (dw, dh): (usize, usize),
+    channels: usize,
+) -> Result<CudaSlice<f32>, f64> {
+    let mut dst = device.alloc_zeros::<f32>(dw * dh * channels).map_err(|_| -4.0)?; // Output allocation failed
+    let params = (src, sw as i32, sh as i32, &mut dst, dw as i32, dh as i32, channels as i32);
+    unsafe { kernel(device, "pyr_down")?.launch(launch_config_2d(dw, dh), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+    Ok(dst)
+}
+
+fn upsample(
+    device: &Arc<CudaDevice>,
+    src: &CudaSlice<f32>,
+    (sw, sh): (usize, usize),
+    (dw, dh): (usize, usize),
+    channels: usize,
+) -> Result<CudaSlice<f32>, f64> {
+    let mut dst = device.alloc_zeros::<f32>(dw * dh * channels).map_err(|_| -4.0)?; // Output allocation failed
+    let params = (src, sw as i32, sh as i32, &mut dst, dw as i32, dh as i32, channels as i32);
+    unsafe { kernel(device, "pyr_up")?.launch(launch_config_2d(dw, dh), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+    Ok(dst)
+}
+
+fn gaussian_pyramid(
+    device: &Arc<CudaDevice>,
+    base: CudaSlice<f32>,
+    sizes: &[(usize, usize)],
+    channels: usize,
+) -> Result<Vec<CudaSlice<f32>>, f64> {
+    let mut levels = vec![base];
+    for k in 1..sizes.len() {
+        let next = downsample(device, &levels[k - 1], sizes[k - 1], sizes[k], channels)?;
+        levels.push(next);
+    }
+    Ok(levels)
+}
+
+fn laplacian_pyramid(
+    device: &Arc<CudaDevice>,
+    base: CudaSlice<f32>,
+    sizes: &[(usize, usize)],
+) -> Result<Vec<CudaSlice<f32>>, f64> {
+    let mut levels = gaussian_pyramid(device, base, sizes, 4)?;
+    for k in 0..sizes.len() - 1 {
+        let expanded = upsample(device, &levels[k + 1], sizes[k + 1], sizes[k], 4)?;
+        let count = expanded.len();
+        let params = (&mut levels[k], &expanded, count as i32);
+        unsafe { kernel(device, "sub_inplace")?.launch(launch_1d(count), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+    }
+    Ok(levels)
+}
+
+/// Blends RGBA `images` using the single-channel `weights` (one per image,
+/// any non-negative scale) across `levels` frequency bands and returns the
+/// RGBA result. Pixels where every weight is zero come out transparent black.
+pub(crate) fn blend_multiband(
+    device: &Arc<CudaDevice>,
+    images: Vec<CudaSlice<f32>>,
+    weights: Vec<CudaSlice<f32>>,
+    width: usize,
+    height: usize,
+    levels: usize,
+) -> Result<CudaSlice<f32>, f64> {
+    let sizes = level_sizes(width, height, levels.max(1));
+
+    let mut acc = Vec::with_capacity(sizes.len());
+    let mut weight_sum = Vec::with_capacity(sizes.len());
+    for &(w, h) in &sizes {
+        acc.push(device.alloc_zeros::<f32>(w * h * 4).map_err(|_| -4.0)?); // Output allocation failed
+        weight_sum.push(device.alloc_zeros::<f32>(w * h).map_err(|_| -4.0)?);
+    }
+
+    for (image, weight) in images.into_iter().zip(weights) {
+        let laplacian = laplacian_pyramid(device, image, &sizes)?;
+        let gaussian = gaussian_pyramid(device, weight, &sizes, 1)?;
+        for k in 0..sizes.len() {
+            let pixels = sizes[k].0 * sizes[k].1;
+            let params = (&mut acc[k], &mut weight_sum[k], &laplacian[k], &gaussian[k], pixels as i32);
+            unsafe { kernel(device, "accumulate")?.launch(launch_1d(pixels), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+        }
+    }
+
+    for k in 0..sizes.len() {
+        let pixels = sizes[k].0 * sizes[k].1;
+        let params = (&mut acc[k], &weight_sum[k], pixels as i32);
+        unsafe { kernel(device, "normalize")?.launch(launch_1d(pixels), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+    }
+
+    // Collapse from the coarsest band back up to full resolution.
+    let mut result = acc.pop().ok_or(-2.0)?;
+    for k in (0..acc.len()).rev() {
+        let mut expanded = upsample(device, &result, sizes[k + 1], sizes[k], 4)?;
+        let count = expanded.len();
+        let params = (&mut expanded, &acc[k], count as i32);
+        unsafe { kernel(device, "add_inplace")?.launch(launch_1d(count), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+        result = expanded;
+    }
+
+    let pixels = width * height;
+    let params = (&mut result, &weight_sum[0], pixels as i32);
+    unsafe { kernel(device, "clear_unweighted")?.launch(launch_1d(pixels), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+    Ok(result)
+}
+
+const PYRAMID_FUNCTIONS: &[&str] = &[
+    "u8_to_f32",
+    "f32_to_u8",
+    "pyr_down",
+    "pyr_up",
+    "sub_inplace",
+    "add_inplace",
+    "accumulate",
+    "normalize",
+    "clear_unweighted",
+];
+
+const PYRAMID_KERNEL: &str = r#"
+extern "C" __global__ void u8_to_f32(const unsigned char* src, float* dst, int count) {
+    int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i < count) dst[i] = (float)src[i];
+}
+
+extern "C" __global__ void f32_to_u8(const float* src, unsigned char* dst, int count) {
+    int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i < count) dst[i] = (unsigned char)fminf(fmaxf(src[i] + 0.5f, 0.0f), 255.0f);
+}
+
+// 5x5 binomial filter evaluated at every other source pixel.
+extern "C" __global__ void pyr_down(
+    const float* src, int sw, int sh,
+    float* dst, int dw, int dh,
+    int channels
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= dw || y >= dh) return;
+
+    const float taps[5] = {1.0f, 4.0f, 6.0f, 4.0f, 1.0f};
+    for (int c = 0; c < channels; c++) {
+        float acc = 0.0f;
+        for (int j = -2; j <= 2; j++) {
+            int sy = min(max(2 * y + j, 0), sh - 1);
+            for (int i = -2; i <= 2; i++) {
+                int sx = min(max(2 * x + i, 0), sw - 1);
+                acc += taps[i + 2] * taps[j + 2] * src[(sy * sw + sx) * channels + c];
+            }
+        }
+        dst[(y * dw + x) * channels + c] = acc / 256.0f;
+    }
+}
+
+extern "C" __global__ void pyr_up(
+    const float* src, int sw, int sh,
+    float* dst, int dw, int dh,
+    int channels
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= dw || y >= dh) return;
+
+    float fx = fminf(fmaxf(((float)x + 0.5f) * 0.5f - 0.5f, 0.0f), (float)(sw - 1));
+    float fy = fminf(fmaxf(((float)y + 0.5f) * 0.5f - 0.5f, 0.0f), (float)(sh - 1));
+    int x1 = (int)fx;
+    int y1 = (int)fy;
+    int x2 = min(x1 + 1, sw - 1);
+    int y2 = min(y1 + 1, sh - 1);
+    float wx = fx - (float)x1;
+    float wy = fy - (float)y1;
+
+    for (int c = 0; c < channels; c++) {
+        float v1 = src[(y1 * sw + x1) * channels + c] * (1.0f - wx) + src[(y1 * sw + x2) * channels + c] * wx;
+        float v2 = src[(y2 * sw + x1) * channels + c] * (1.0f - wx) + src[(y2 * sw + x2) * channels + c] * wx;
+        dst[(y * dw + x) * channels + c] = v1 * (1.0f - wy) + v2 * wy;
+    }
+}
+
+extern "C" __global__ void sub_inplace(float* a, const float* b, int count) {
+    int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i < count) a[i] -= b[i];
+}
+
+extern "C" __global__ void add_inplace(float* a, const float* b, int count) {
+    int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i < count) a[i] += b[i];
+}
+
+extern "C" __global__ void accumulate(
+    float* acc,
+    float* weight_sum,
+    const float* band,
+    const float* weight,
+    int pixels
+) {
+    int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= pixels) return;
+    float w = weight[i];
+    for (int c = 0; c < 4; c++) acc[i * 4 + c] += w * band[i * 4 + c];
+    weight_sum[i] += w;
+}
+
+extern "C" __global__ void normalize(float* acc, const float* weight_sum, int pixels) {
+    int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= pixels) return;
+    float w = weight_sum[i];
+    for (int c = 0; c < 4; c++) acc[i * 4 + c] = w > 1e-12f ? acc[i * 4 + c] / w : 0.0f;
+}
+
+// Coarse bands bleed past the covered area; cut the result back to it.
+extern "C" __global__ void clear_unweighted(float* image, const float* weight_sum, int pixels) {
+    int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= pixels || weight_sum[i] > 1e-12f) return;
+    for (int c = 0; c < 4; c++) image[i * 4 + c] = 0.0f;
+}
+"#;
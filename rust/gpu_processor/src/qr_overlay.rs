@@ -0,0 +1,159 @@
+//! QR code rendering composited onto an image.
+//!
+//! Encoding is sequential bit-twiddling and stays on the host; only the
+//! module grid is uploaded and the rasterization and blending run on the GPU.
+
+use crate::shadow::rgba_value;
+use crate::{download, launch_config_2d, load_kernel, run_gpu, upload_rgba, output_argument, write_result};
+use crate::validation::dimension_argument;
+use cudarc::driver::LaunchAsync;
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+use qrcodegen::{QrCode, QrCodeEcc};
+
+struct QrPlacement {
+    x: i32,
+    y: i32,
+    size: i32,
+    margin: i32,
+    foreground: [f32; 4],
+    background: [f32; 4],
+    ecc: QrCodeEcc,
+}
+
+/// Reads `{ x, y, size, margin, foreground, background, errorCorrection }`.
+fn placement_argument(cx: &mut FunctionContext, index: usize) -> NeonResult<QrPlacement> {
+    let options = cx.argument::<JsObject>(index)?;
+    let x = options.get::<JsNumber, _, _>(cx, "x")?.value(cx) as i32;
+    let y = options.get::<JsNumber, _, _>(cx, "y")?.value(cx) as i32;
+    let size = options.get::<JsNumber, _, _>(cx, "size")?.value(cx) as i32;
+    let margin = options.get_opt::<JsNumber, _, _>(cx, "margin")?.map_or(4.0, |v| v.value(cx)) as i32;
+    let foreground = match options.get_opt::<JsValue, _, _>(cx, "foreground")? {
+        Some(value) => rgba_value(cx, value)?,
+        None => [0.0, 0.0, 0.0, 255.0],
+    };
+    let background = match options.get_opt::<JsValue, _, _>(cx, "background")? {
+        Some(value) => rgba_value(cx, value)?,
+        None => [255.0, 255.0, 255.0, 255.0],
+    };
+    let ecc = match options.get_opt::<JsString, _, _>(cx, "errorCorrection")? {
+        Some(name) => match name.value(cx).as_str() {
+            "low" => QrCodeEcc::Low,
+            "medium" => QrCodeEcc::Medium,
+            "quartile" => QrCodeEcc::Quartile,
+            "high" => QrCodeEcc::High,
+            other => return cx.throw_type_error(format!("Unknown error correction level `{}`", other)),
+        },
+        None => QrCodeEcc::Medium,
+    };
+    if size <= 0 {
+        return cx.throw_range_error("size must be positive");
+    }
+    if !(0..=16).contains(&margin) {
+        return cx.throw_range_error("margin must be between 0 and 16 modules");
+    }
+    Ok(QrPlacement { x, y, size, margin, foreground, background, ecc })
+}
+
+/// `gpu_processor_qr_overlay(input, width, height, text, options, output?)`
+///
+/// Encodes `text` as a QR code and draws it as a `size` x `size` square with
+/// its top-left corner at (`x`, `y`), including a quiet zone of `margin`
+/// modules (default 4). `foreground` and `background` are `[r, g, b, a]`
+/// (black on white by default) and are alpha-blended over the image;
+/// `errorCorrection` is `"low"`, `"medium"` (default), `"quartile"` or
+/// `"high"`. Parts outside the image are clipped.
+pub(crate) fn gpu_processor_qr_overlay(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let text = cx.argument::<JsString>(3)?.value(&mut cx);
+    let placement = placement_argument(&mut cx, 4)?;
+    let output_buffer = output_argument(&mut cx, 5)?;
+
+    let code = match QrCode::encode_text(&text, placement.ecc) {
+        Ok(code) => code,
+        Err(_) => return cx.throw_range_error("text is too long for a QR code"),
+    };
+    let modules = code.size();
+    let grid: Vec<u8> = (0..modules)
+        .flat_map(|y| (0..modules).map(move |x| (x, y)))
+        .map(|(x, y)| code.get_module(x, y) as u8)
+        .collect();
+
+    let result = run_gpu(|device| {
+        let mut image = upload_rgba(device, input_data.as_slice(&cx), width, height)?;
+        let grid = device.htod_sync_copy(&grid).map_err(|_| -3.0)?; // Memory allocation failed
+        let colors: Vec<f32> = placement.foreground.iter().chain(&placement.background).copied().collect();
+        let colors = device.htod_sync_copy(&colors).map_err(|_| -3.0)?;
+
+        // Only the part of the square that overlaps the image needs threads.
+        let x0 = placement.x.clamp(0, width as i32);
+        let y0 = placement.y.clamp(0, height as i32);
+        let x1 = (placement.x + placement.size).clamp(0, width as i32);
+        let y1 = (placement.y + placement.size).clamp(0, height as i32);
+        if x1 <= x0 || y1 <= y0 {
+            return download(device, &image);
+        }
+
+        let kernel = load_kernel(device, "qr_overlay_module", QR_KERNEL, &["qr_overlay"], "qr_overlay")?;
+        let params = (
+            &mut image,
+            width as i32,
+            height as i32,
+            &grid,
+            modules,
+            placement.margin,
+            placement.x,
+            placement.y,
+            placement.size,
+            x0,
+            y0,
+            &colors,
+        );
+        let cfg = launch_config_2d((x1 - x0) as usize, (y1 - y0) as usize);
+        unsafe { kernel.launch(cfg, params) }.map_err(|_| -8.0)?; // Kernel launch failed
+        download(device, &image)
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+const QR_KERNEL: &str = r#"
+// colors: foreground rgba then background rgba, 0-255.
+extern "C" __global__ void qr_overlay(
+    unsigned char* image,
+    int width,
+    int height,
+    const unsigned char* grid,
+    int modules,
+    int margin,
+    int qr_x,
+    int qr_y,
+    int qr_size,
+    int x0,
+    int y0,
+    const float* colors
+) {
+    int x = x0 + blockIdx.x * blockDim.x + threadIdx.x;
+    int y = y0 + blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= qr_x + qr_size || y >= qr_y + qr_size || x >= width || y >= height) return;
+
+    int total = modules + 2 * margin;
+    int mx = (int)(((long long)(x - qr_x) * total) / qr_size) - margin;
+    int my = (int)(((long long)(y - qr_y) * total) / qr_size) - margin;
+    bool dark = mx >= 0 && my >= 0 && mx < modules && my < modules && grid[my * modules + mx];
+
+    const float* color = dark ? colors : colors + 4;
+    float a = color[3] / 255.0f;
+    unsigned char* px = image + (y * width + x) * 4;
+    float dst_a = (float)px[3] / 255.0f;
+    float out_a = a + dst_a * (1.0f - a);
+    if (out_a <= 0.0f) return;
+    for (int c = 0; c < 3; c++) {
+        float v = (color[c] * a + (float)px[c] * dst_a * (1.0f - a)) / out_a;
+        px[c] = (unsigned char)fminf(fmaxf(v + 0.5f, 0.0f), 255.0f);
+    }
+    px[3] = (unsigned char)fminf(out_a * 255.0f + 0.5f, 255.0f);
+}
+"#;
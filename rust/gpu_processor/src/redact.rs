@@ -0,0 +1,294 @@
+//! Region redaction for faces, license plates and other sensitive areas.
+
+use crate::{download, launch_config_2d, load_kernel, run_gpu, upload_rgba, output_argument, write_result};
+use crate::validation::dimension_argument;
+use cudarc::driver::{CudaDevice, CudaSlice, LaunchAsync, LaunchConfig};
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+use std::sync::Arc;
+
+/// Reads `[{ x, y, width, height }, ...]`, such as the result of
+/// `gpu_processor_detect_faces`, clipping each rectangle to the image and
+/// dropping the ones that end up empty. The result is flattened to `x, y,
+/// width, height` quadruples for the device.
+pub(crate) fn rects_argument(
+    cx: &mut FunctionContext,
+    index: usize,
+    image_width: usize,
+    image_height: usize,
+) -> NeonResult<Vec<i32>> {
+    let rects = cx.argument::<JsArray>(index)?.to_vec(cx)?;
+    let mut flat = Vec::with_capacity(rects.len() * 4);
+    for rect in rects {
+        let rect = rect.downcast_or_throw::<JsObject, _>(cx)?;
+        let x = rect.get::<JsNumber, _, _>(cx, "x")?.value(cx);
+        let y = rect.get::<JsNumber, _, _>(cx, "y")?.value(cx);
+        let width = rect.get::<JsNumber, _, _>(cx, "width")?.value(cx);
+        let height = rect.get::<JsNumber, _, _>(cx, "height")?.value(cx);
+
+        let x0 = x.max(0.0).min(image_width as f64) as i32;
+        let y0 = y.max(0.0).min(image_height as f64) as i32;
+        let x1 = (x + width).max(0.0).min(image_width as f64) as i32;
+        let y1 = (y + height).max(0.0).min(image_height as f64) as i32;
+        if x1 > x0 && y1 > y0 {
+            flat.extend_from_slice(&[x0, y0, x1 - x0, y1 - y0]);
+        }
+    }
+    Ok(flat)
+}
+
+/// Largest mosaic block; bigger ones are clamped to it.
+pub(crate) const MAX_BLOCK_SIZE: i32 = 4096;
+
+/// Rectangles go on the grid's z dimension, which stops at 65535.
+const MAX_PIXELATE_RECTS: usize = 65_535;
+
+/// Pixelates the flattened `rects` of `image` in place.
+pub(crate) fn pixelate(
+    device: &Arc<CudaDevice>,
+    image: &mut CudaSlice<u8>,
+    width: usize,
+    height: usize,
+    rects: &[i32],
+    block_size: i32,
+) -> Result<(), f64> {
+    if rects.is_empty() {
+        return Ok(());
+    }
+    // One thread per mosaic block, with the rectangle index on z.
+    let rect_count = rects.len() / 4;
+    if rect_count > MAX_PIXELATE_RECTS {
+        return Err(-2.0); // Invalid input size
+    }
+    let block_size = block_size.clamp(1, MAX_BLOCK_SIZE);
+    let dev_rects = device.htod_sync_copy(rects).map_err(|_| -3.0)?; // Memory allocation failed
+
+    let max_blocks = |dim: usize| {
+        rects
+            .chunks(4)
+            .map(|r| (r[dim] as u32).div_ceil(block_size as u32))
+            .max()
+            .unwrap_or(1)
+    };
+    let cfg = LaunchConfig {
+        grid_dim: (max_blocks(2).div_ceil(16), max_blocks(3).div_ceil(16), rect_count as u32),
+        block_dim: (16, 16, 1),
+        shared_mem_bytes: 0,
+    };
+
+    let kernel = load_kernel(device, "redact_module", REDACT_KERNEL, REDACT_FUNCTIONS, "pixelate_regions")?;
+    let params = (image, width as i32, height as i32, &dev_rects, block_size);
+    unsafe { kernel.launch(cfg, params) }.map_err(|_| -8.0) // Kernel launch failed
+}
+
+/// Gaussian-blurs the flattened `rects` of `image` in place.
+pub(crate) fn blur(
+    device: &Arc<CudaDevice>,
+    image: &mut CudaSlice<u8>,
+    width: usize,
+    height: usize,
+    rects: &[i32],
+    sigma: f32,
+) -> Result<(), f64> {
+    if !(sigma > 0.0 && sigma <= MAX_REDACTION_SIGMA) {
+        return Err(-2.0); // Invalid input size
+    }
+    if rects.is_empty() {
+        return Ok(());
+    }
+    let dev_rects = device.htod_sync_copy(rects).map_err(|_| -3.0)?; // Memory allocation failed
+    let mut scratch = device.alloc_zeros::<f32>(width * height * 4).map_err(|_| -4.0)?; // Output allocation failed
+
+    let rect_count = (rects.len() / 4) as i32;
+    let radius = (sigma * 3.0).ceil() as i32;
+    let cfg = launch_config_2d(width, height);
+
+    let horizontal = load_kernel(device, "redact_module", REDACT_KERNEL, REDACT_FUNCTIONS, "blur_regions_h")?;
+    let params = (&*image, &mut scratch, width as i32, height as i32, &dev_rects, rect_count, sigma, radius);
+    unsafe { horizontal.launch(cfg, params) }.map_err(|_| -8.0)?; // Kernel launch failed
+
+    let vertical = load_kernel(device, "redact_module", REDACT_KERNEL, REDACT_FUNCTIONS, "blur_regions_v")?;
+    let params = (&scratch, image, width as i32, height as i32, &dev_rects, rect_count, sigma, radius);
+    unsafe { vertical.launch(cfg, params) }.map_err(|_| -8.0) // Kernel launch failed
+}
+
+/// `gpu_processor_pixelate_regions(input, width, height, rects, block_size, output?)`
+///
+/// Replaces each rectangle with a mosaic of `block_size` squares, each filled
+/// with the average color of the pixels it covers. Blocks are aligned to the
+/// rectangle's top-left corner so the mosaic never bleeds outside it.
+/// Overlapping rectangles are processed concurrently, so the overlap takes
+/// the mosaic of either one. `block_size` is clamped to 1..=4096, and more
+/// than 65535 rectangles return -2.
+pub(crate) fn gpu_processor_pixelate_regions(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let rects = rects_argument(&mut cx, 3, width, height)?;
+    let block_size = (cx.argument::<JsNumber>(4)?.value(&mut cx) as i32).clamp(1, MAX_BLOCK_SIZE);
+    let output_buffer = output_argument(&mut cx, 5)?;
+
+    let result = run_gpu(|device| {
+        let mut image = upload_rgba(device, input_data.as_slice(&cx), width, height)?;
+        pixelate(device, &mut image, width, height, &rects, block_size)?;
+        download(device, &image)
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+/// Below this the content stays recognizable, so weaker blurs are raised to it.
+const MIN_REDACTION_SIGMA: f32 = 20.0;
+
+/// Largest accepted sigma. It bounds the kernel radius at 300 taps each way,
+/// so a blur cannot run into the watchdog.
+pub(crate) const MAX_REDACTION_SIGMA: f32 = 100.0;
+
+/// `gpu_processor_blur_regions(input, width, height, rects, sigma, output?)`
+///
+/// Applies a separable Gaussian blur inside each rectangle and leaves the
+/// rest of the image untouched. Samples are clamped to the rectangle so
+/// surrounding content neither bleeds in nor gets smeared out. `sigma` is
+/// raised to at least 20; above 100 the call returns -2.
+pub(crate) fn gpu_processor_blur_regions(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let rects = rects_argument(&mut cx, 3, width, height)?;
+    let sigma = (cx.argument::<JsNumber>(4)?.value(&mut cx) as f32).max(MIN_REDACTION_SIGMA);
+    let output_buffer = output_argument(&mut cx, 5)?;
+    if sigma > MAX_REDACTION_SIGMA {
+        return Ok(cx.number(-2.0).upcast()); // Invalid input size
+    }
+
+    let result = run_gpu(|device| {
+        let mut image = upload_rgba(device, input_data.as_slice(&cx), width, height)?;
+        blur(device, &mut image, width, height, &rects, sigma)?;
+        download(device, &image)
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+const REDACT_FUNCTIONS: &[&str] = &["pixelate_regions", "blur_regions_h", "blur_regions_v"];
+
+const REDACT_KERNEL: &str = r#"
+extern "C" __global__ void pixelate_regions(
+    unsigned char* image,
+    int width,
+    int height,
+    const int* rects,
+    int block_size
+) {
+    const int* rect = rects + blockIdx.z * 4;
+    int rx = rect[0];
+    int ry = rect[1];
+    int rw = rect[2];
+    int rh = rect[3];
+
+    int bx = rx + (blockIdx.x * blockDim.x + threadIdx.x) * block_size;
+    int by = ry + (blockIdx.y * blockDim.y + threadIdx.y) * block_size;
+    if (bx >= rx + rw || by >= ry + rh) return;
+
+    int ex = min(bx + block_size, rx + rw);
+    int ey = min(by + block_size, ry + rh);
+
+    unsigned int sum[4] = {0, 0, 0, 0};
+    for (int y = by; y < ey; y++) {
+        for (int x = bx; x < ex; x++) {
+            const unsigned char* px = image + (y * width + x) * 4;
+            for (int c = 0; c < 4; c++) sum[c] += px[c];
+        }
+    }
+
+    unsigned int count = (unsigned int)((ex - bx) * (ey - by));
+    unsigned char avg[4];
+    for (int c = 0; c < 4; c++) avg[c] = (unsigned char)((sum[c] + count / 2) / count);
+
+    for (int y = by; y < ey; y++) {
+        for (int x = bx; x < ex; x++) {
+            unsigned char* px = image + (y * width + x) * 4;
+            for (int c = 0; c < 4; c++) px[c] = avg[c];
+        }
+    }
+}
+
+// Index of the first rectangle containing (x, y), or -1.
+__device__ int find_rect(const int* rects, int rect_count, int x, int y) {
+    for (int i = 0; i < rect_count; i++) {
+        const int* r = rects + i * 4;
+        if (x >= r[0] && y >= r[1] && x < r[0] + r[2] && y < r[1] + r[3]) return i;
+    }
+    return -1;
+}
+
+extern "C" __global__ void blur_regions_h(
+    const unsigned char* image,
+    float* scratch,
+    int width,
+    int height,
+    const int* rects,
+    int rect_count,
+    float sigma,
+    int radius
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= width || y >= height) return;
+
+    int i = find_rect(rects, rect_count, x, y);
+    if (i < 0) return;
+    int x0 = rects[i * 4];
+    int x1 = x0 + rects[i * 4 + 2] - 1;
+
+    float inv = -0.5f / (sigma * sigma);
+    float acc[4] = {0.0f, 0.0f, 0.0f, 0.0f};
+    float total = 0.0f;
+    for (int k = -radius; k <= radius; k++) {
+        int sx = min(max(x + k, x0), x1);
+        float w = expf((float)(k * k) * inv);
+        const unsigned char* px = image + (y * width + sx) * 4;
+        for (int c = 0; c < 4; c++) acc[c] += w * (float)px[c];
+        total += w;
+    }
+
+    float* out = scratch + (y * width + x) * 4;
+    for (int c = 0; c < 4; c++) out[c] = acc[c] / total;
+}
+
+extern "C" __global__ void blur_regions_v(
+    const float* scratch,
+    unsigned char* image,
+    int width,
+    int height,
+    const int* rects,
+    int rect_count,
+    float sigma,
+    int radius
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= width || y >= height) return;
+
+    int i = find_rect(rects, rect_count, x, y);
+    if (i < 0) return;
+    int y0 = rects[i * 4 + 1];
+    int y1 = y0 + rects[i * 4 + 3] - 1;
+
+    float inv = -0.5f / (sigma * sigma);
+    float acc[4] = {0.0f, 0.0f, 0.0f, 0.0f};
+    float total = 0.0f;
+    for (int k = -radius; k <= radius; k++) {
+        int sy = min(max(y + k, y0), y1);
+        float w = expf((float)(k * k) * inv);
+        const float* px = scratch + (sy * width + x) * 4;
+        for (int c = 0; c < 4; c++) acc[c] += w * px[c];
+        total += w;
+    }
+
+    unsigned char* out = image + (y * width + x) * 4;
+    for (int c = 0; c < 4; c++) {
+        out[c] = (unsigned char)fminf(fmaxf(acc[c] / total + 0.5f, 0.0f), 255.0f);
+    }
+}
+"#;
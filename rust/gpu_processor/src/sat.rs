@@ -0,0 +1,95 @@
+//! Summed-area tables (integral images) for constant-time box sums.
+
+use crate::{load_kernel, run_gpu, upload_rgba, output_argument, write_result};
+use crate::validation::{dimension_argument, image_bytes};
+use cudarc::driver::{CudaDevice, CudaSlice, LaunchAsync, LaunchConfig};
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+use std::sync::Arc;
+
+/// Builds the per-channel summed-area table of an RGBA image on the device.
+/// Entry `(x, y)` holds the sum of all pixels in `[0, x] x [0, y]`; doubles
+/// keep the sums exact for any realistic image size.
+pub(crate) fn summed_area_table(
+    device: &Arc<CudaDevice>,
+    image: &CudaSlice<u8>,
+    width: usize,
+    height: usize,
+) -> Result<CudaSlice<f64>, f64> {
+    // Four doubles per pixel, 32 bytes.
+    let table_bytes = image_bytes(width, height, 32)?;
+    let mut table = device.alloc_zeros::<f64>(table_bytes / 8).map_err(|_| -4.0)?; // Output allocation failed
+
+    let rows = load_kernel(device, "sat_module", SAT_KERNEL, SAT_FUNCTIONS, "sat_rows")?;
+    let params = (image, &mut table, width as i32, height as i32);
+    unsafe { rows.launch(LaunchConfig::for_num_elems(height as u32), params) }
+        .map_err(|_| -8.0)?; // Kernel launch failed
+
+    let cols = load_kernel(device, "sat_module", SAT_KERNEL, SAT_FUNCTIONS, "sat_cols")?;
+    let params = (&mut table, width as i32, height as i32);
+    unsafe { cols.launch(LaunchConfig::for_num_elems(width as u32), params) }
+        .map_err(|_| -8.0)?; // Kernel launch failed
+
+    Ok(table)
+}
+
+/// `gpu_processor_summed_area_table(input, width, height, output?)`
+///
+/// Writes the table as little-endian `f64` RGBA quadruples, so `output` must
+/// be `width * height * 32` bytes and can be viewed as a `Float64Array`.
+pub(crate) fn gpu_processor_summed_area_table(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let output_buffer = output_argument(&mut cx, 3)?;
+
+    let result = run_gpu(|device| {
+        let image = upload_rgba(device, input_data.as_slice(&cx), width, height)?;
+        let table = summed_area_table(device, &image, width, height)?;
+        let host = device.dtoh_sync_copy(&table).map_err(|_| -9.0)?; // Copy back failed
+        Ok(bytemuck::cast_slice::<f64, u8>(&host).to_vec())
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+const SAT_FUNCTIONS: &[&str] = &["sat_rows", "sat_cols"];
+
+const SAT_KERNEL: &str = r#"
+extern "C" __global__ void sat_rows(
+    const unsigned char* image,
+    double* table,
+    int width,
+    int height
+) {
+    int y = blockIdx.x * blockDim.x + threadIdx.x;
+    if (y >= height) return;
+
+    double acc[4] = {0.0, 0.0, 0.0, 0.0};
+    for (int x = 0; x < width; x++) {
+        int i = (y * width + x) * 4;
+        for (int c = 0; c < 4; c++) {
+            acc[c] += (double)image[i + c];
+            table[i + c] = acc[c];
+        }
+    }
+}
+
+extern "C" __global__ void sat_cols(
+    double* table,
+    int width,
+    int height
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    if (x >= width) return;
+
+    double acc[4] = {0.0, 0.0, 0.0, 0.0};
+    for (int y = 0; y < height; y++) {
+        int i = (y * width + x) * 4;
+        for (int c = 0; c < 4; c++) {
+            acc[c] += table[i + c];
+            table[i + c] = acc[c];
+        }
+    }
+}
+"#;
@@ -0,0 +1,334 @@
+//! Drop shadows and solid borders, rendered onto an enlarged canvas.
+
+use crate::{download, launch_config_2d, load_kernel, run_gpu, upload_rgba, output_argument, write_result};
+use crate::validation::{dimension_argument, image_bytes};
+use cudarc::driver::LaunchAsync;
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+
+#[derive(Clone, Copy)]
+struct Shadow {
+    color: [f32; 4],
+    offset_x: i32,
+    offset_y: i32,
+    blur: f32,
+}
+
+#[derive(Clone, Copy)]
+struct Decoration {
+    border: usize,
+    border_color: [f32; 4],
+    shadow: Option<Shadow>,
+}
+
+/// Where everything lands on the enlarged canvas.
+struct Layout {
+    canvas_width: usize,
+    canvas_height: usize,
+    /// Top-left corner of the bordered box.
+    box_x: i32,
+    box_y: i32,
+}
+
+impl Decoration {
+    fn layout(&self, width: usize, height: usize) -> Layout {
+        let box_width = width + 2 * self.border;
+        let box_height = height + 2 * self.border;
+        let (left, right, top, bottom) = match self.shadow {
+            // Three sigmas of blur on each side of the offset silhouette.
+            Some(shadow) => {
+                let reach = (3.0 * shadow.blur).ceil() as i32;
+                (
+                    (reach - shadow.offset_x).max(0),
+                    (reach + shadow.offset_x).max(0),
+                    (reach - shadow.offset_y).max(0),
+                    (reach + shadow.offset_y).max(0),
+                )
+            }
+            None => (0, 0, 0, 0),
+        };
+        Layout {
+            canvas_width: box_width + (left + right) as usize,
+            canvas_height: box_height + (top + bottom) as usize,
+            box_x: left,
+            box_y: top,
+        }
+    }
+}
+
+/// Reads `[r, g, b, a?]` (0–255, alpha defaults to opaque).
+pub(crate) fn rgba_value(cx: &mut FunctionContext, value: Handle<JsValue>) -> NeonResult<[f32; 4]> {
+    let values = value.downcast_or_throw::<JsArray, _>(cx)?.to_vec(cx)?;
+    let mut color = [0.0, 0.0, 0.0, 255.0];
+    for (slot, value) in color.iter_mut().zip(values) {
+        *slot = value.downcast_or_throw::<JsNumber, _>(cx)?.value(cx).clamp(0.0, 255.0) as f32;
+    }
+    Ok(color)
+}
+
+/// Reads `{ shadow: { color, offsetX, offsetY, blur }, border: { color, width } }`;
+/// either part may be left out.
+fn decoration_argument(cx: &mut FunctionContext, index: usize) -> NeonResult<Decoration> {
+    let options = cx.argument::<JsObject>(index)?;
+    let mut decoration = Decoration { border: 0, border_color: [0.0; 4], shadow: None };
+
+    if let Some(shadow) = options.get_opt::<JsObject, _, _>(cx, "shadow")? {
+        let color = match shadow.get_opt::<JsValue, _, _>(cx, "color")? {
+            Some(value) => rgba_value(cx, value)?,
+            None => [0.0, 0.0, 0.0, 128.0],
+        };
+        let number = |cx: &mut FunctionContext, key: &str| -> NeonResult<f64> {
+            Ok(shadow.get_opt::<JsNumber, _, _>(cx, key)?.map_or(0.0, |v| v.value(cx)))
+        };
+        let offset_x = number(cx, "offsetX")?;
+        let offset_y = number(cx, "offsetY")?;
+        let blur = number(cx, "blur")?;
+        if !offset_x.is_finite() || !offset_y.is_finite() || offset_x.abs() > 4096.0 || offset_y.abs() > 4096.0 {
+            return cx.throw_range_error("shadow offset must be within ±4096 pixels");
+        }
+        if !(0.0..=256.0).contains(&blur) {
+            return cx.throw_range_error("shadow blur must be between 0 and 256");
+        }
+        decoration.shadow = Some(Shadow {
+            color,
+            offset_x: offset_x.round() as i32,
+            offset_y: offset_y.round() as i32,
+            blur: blur as f32,
+        });
+    }
+
+    if let Some(border) = options.get_opt::<JsObject, _, _>(cx, "border")? {
+        let width = border.get::<JsNumber, _, _>(cx, "width")?.value(cx);
+        if !(0.0..=4096.0).contains(&width) {
+            return cx.throw_range_error("border width must be between 0 and 4096");
+        }
+        decoration.border = width.round() as usize;
+        decoration.border_color = match border.get_opt::<JsValue, _, _>(cx, "color")? {
+            Some(value) => rgba_value(cx, value)?,
+            None => [0.0, 0.0, 0.0, 255.0],
+        };
+    }
+
+    Ok(decoration)
+}
+
+/// `gpu_processor_shadow_border_size(width, height, options)`
+///
+/// Returns `{ width, height, imageX, imageY }`: the canvas size
+/// `gpu_processor_shadow_border` renders for these options and where the
+/// original image sits on it.
+pub(crate) fn gpu_processor_shadow_border_size(mut cx: FunctionContext) -> JsResult<JsObject> {
+    let width = dimension_argument(&mut cx, 0)?;
+    let height = dimension_argument(&mut cx, 1)?;
+    let decoration = decoration_argument(&mut cx, 2)?;
+    let layout = decoration.layout(width, height);
+
+    let obj = cx.empty_object();
+    let value = cx.number(layout.canvas_width as f64);
+    obj.set(&mut cx, "width", value)?;
+    let value = cx.number(layout.canvas_height as f64);
+    obj.set(&mut cx, "height", value)?;
+    let value = cx.number((layout.box_x as usize + decoration.border) as f64);
+    obj.set(&mut cx, "imageX", value)?;
+    let value = cx.number((layout.box_y as usize + decoration.border) as f64);
+    obj.set(&mut cx, "imageY", value)?;
+    Ok(obj)
+}
+
+/// `gpu_processor_shadow_border(input, width, height, options, output?)`
+///
+/// Surrounds the image with a solid border of `border.width` pixels and
+/// draws a Gaussian drop shadow of the bordered silhouette (following the
+/// image's alpha) offset by `shadow.offsetX`/`offsetY` with standard
+/// deviation `shadow.blur`. Colors are `[r, g, b, a]`. The canvas grows to
+/// fit; size `output` with `gpu_processor_shadow_border_size`. Pixels
+/// outside the shadow stay transparent.
+pub(crate) fn gpu_processor_shadow_border(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let decoration = decoration_argument(&mut cx, 3)?;
+    let output_buffer = output_argument(&mut cx, 4)?;
+
+    let result = run_gpu(|device| {
+        let image = upload_rgba(device, input_data.as_slice(&cx), width, height)?;
+        let layout = decoration.layout(width, height);
+        let (cw, ch) = (layout.canvas_width, layout.canvas_height);
+        let canvas_size = image_bytes(cw, ch, 4)?;
+
+        let mut shadow_plane = device.alloc_zeros::<f32>(cw * ch).map_err(|_| -4.0)?; // Output allocation failed
+        let shadow_color = match decoration.shadow {
+            Some(shadow) => {
+                let kernel = load_kernel(device, "shadow_module", SHADOW_KERNEL, SHADOW_FUNCTIONS, "shadow_silhouette")?;
+                let params = (
+                    &image,
+                    width as i32,
+                    height as i32,
+                    decoration.border as i32,
+                    layout.box_x + shadow.offset_x,
+                    layout.box_y + shadow.offset_y,
+                    &mut shadow_plane,
+                    cw as i32,
+                    ch as i32,
+                );
+                unsafe { kernel.launch(launch_config_2d(cw, ch), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+
+                if shadow.blur > 0.0 {
+                    let radius = (3.0 * shadow.blur).ceil() as i32;
+                    let mut scratch = device.alloc_zeros::<f32>(cw * ch).map_err(|_| -4.0)?; // Output allocation failed
+                    let kernel = load_kernel(device, "shadow_module", SHADOW_KERNEL, SHADOW_FUNCTIONS, "blur_plane_h")?;
+                    let params = (&shadow_plane, &mut scratch, cw as i32, ch as i32, shadow.blur, radius);
+                    unsafe { kernel.launch(launch_config_2d(cw, ch), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+                    let kernel = load_kernel(device, "shadow_module", SHADOW_KERNEL, SHADOW_FUNCTIONS, "blur_plane_v")?;
+                    let params = (&scratch, &mut shadow_plane, cw as i32, ch as i32, shadow.blur, radius);
+                    unsafe { kernel.launch(launch_config_2d(cw, ch), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+                }
+                shadow.color
+            }
+            None => [0.0; 4],
+        };
+
+        let colors: Vec<f32> = shadow_color.iter().chain(&decoration.border_color).copied().collect();
+        let colors = device.htod_sync_copy(&colors).map_err(|_| -3.0)?; // Memory allocation failed
+        let mut output = device.alloc_zeros::<u8>(canvas_size).map_err(|_| -4.0)?; // Output allocation failed
+        let kernel = load_kernel(device, "shadow_module", SHADOW_KERNEL, SHADOW_FUNCTIONS, "decorate")?;
+        let params = (
+            &image,
+            width as i32,
+            height as i32,
+            decoration.border as i32,
+            layout.box_x,
+            layout.box_y,
+            &shadow_plane,
+            &colors,
+            &mut output,
+            cw as i32,
+            ch as i32,
+        );
+        unsafe { kernel.launch(launch_config_2d(cw, ch), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+        download(device, &output)
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+const SHADOW_FUNCTIONS: &[&str] = &["shadow_silhouette", "blur_plane_h", "blur_plane_v", "decorate"];
+
+const SHADOW_KERNEL: &str = r#"
+// Coverage (0-1) of the bordered box whose top-left corner is (box_x, box_y):
+// the border ring is solid, the image contributes its alpha.
+__device__ __forceinline__ float box_coverage(
+    const unsigned char* image, int width, int height, int border, int bx, int by
+) {
+    if (bx < 0 || by < 0 || bx >= width + 2 * border || by >= height + 2 * border) return 0.0f;
+    int ix = bx - border;
+    int iy = by - border;
+    if (ix < 0 || iy < 0 || ix >= width || iy >= height) return 1.0f;
+    return (float)image[(iy * width + ix) * 4 + 3] / 255.0f;
+}
+
+extern "C" __global__ void shadow_silhouette(
+    const unsigned char* image,
+    int width,
+    int height,
+    int border,
+    int box_x,
+    int box_y,
+    float* plane,
+    int canvas_width,
+    int canvas_height
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= canvas_width || y >= canvas_height) return;
+    plane[y * canvas_width + x] = box_coverage(image, width, height, border, x - box_x, y - box_y);
+}
+
+extern "C" __global__ void blur_plane_h(
+    const float* src, float* dst, int width, int height, float sigma, int radius
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= width || y >= height) return;
+
+    float acc = 0.0f;
+    float total = 0.0f;
+    for (int k = -radius; k <= radius; k++) {
+        float w = __expf(-(float)(k * k) / (2.0f * sigma * sigma));
+        int sx = x + k;
+        // Outside the canvas counts as empty, not clamped.
+        if (sx >= 0 && sx < width) acc += w * src[y * width + sx];
+        total += w;
+    }
+    dst[y * width + x] = acc / total;
+}
+
+extern "C" __global__ void blur_plane_v(
+    const float* src, float* dst, int width, int height, float sigma, int radius
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= width || y >= height) return;
+
+    float acc = 0.0f;
+    float total = 0.0f;
+    for (int k = -radius; k <= radius; k++) {
+        float w = __expf(-(float)(k * k) / (2.0f * sigma * sigma));
+        int sy = y + k;
+        if (sy >= 0 && sy < height) acc += w * src[sy * width + x];
+        total += w;
+    }
+    dst[y * width + x] = acc / total;
+}
+
+// Straight-alpha source-over of (r, g, b, a) onto dst, a in 0-1.
+__device__ __forceinline__ void blend_over(float* dst, float r, float g, float b, float a) {
+    float out_a = a + dst[3] * (1.0f - a);
+    if (out_a <= 0.0f) return;
+    float keep = dst[3] * (1.0f - a);
+    dst[0] = (r * a + dst[0] * keep) / out_a;
+    dst[1] = (g * a + dst[1] * keep) / out_a;
+    dst[2] = (b * a + dst[2] * keep) / out_a;
+    dst[3] = out_a;
+}
+
+// colors: shadow rgba then border rgba, all 0-255.
+extern "C" __global__ void decorate(
+    const unsigned char* image,
+    int width,
+    int height,
+    int border,
+    int box_x,
+    int box_y,
+    const float* shadow,
+    const float* colors,
+    unsigned char* output,
+    int canvas_width,
+    int canvas_height
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= canvas_width || y >= canvas_height) return;
+
+    float px[4] = {0.0f, 0.0f, 0.0f, 0.0f};
+    int i = y * canvas_width + x;
+    blend_over(px, colors[0], colors[1], colors[2], colors[3] / 255.0f * shadow[i]);
+
+    int bx = x - box_x;
+    int by = y - box_y;
+    if (bx >= 0 && by >= 0 && bx < width + 2 * border && by < height + 2 * border) {
+        int ix = bx - border;
+        int iy = by - border;
+        if (ix < 0 || iy < 0 || ix >= width || iy >= height) {
+            blend_over(px, colors[4], colors[5], colors[6], colors[7] / 255.0f);
+        } else {
+            const unsigned char* src = image + (iy * width + ix) * 4;
+            blend_over(px, (float)src[0], (float)src[1], (float)src[2], (float)src[3] / 255.0f);
+        }
+    }
+
+    unsigned char* out = output + i * 4;
+    for (int c = 0; c < 3; c++) out[c] = (unsigned char)fminf(fmaxf(px[c] + 0.5f, 0.0f), 255.0f);
+    out[3] = (unsigned char)fminf(fmaxf(px[3] * 255.0f + 0.5f, 0.0f), 255.0f);
+}
+"#;
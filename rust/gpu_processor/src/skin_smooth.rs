@@ -0,0 +1,124 @@
+//! Skin smoothing for portraits.
+
+use crate::{download, launch_config_2d, load_kernel, run_gpu, upload_rgba, output_argument, write_result};
+use crate::validation::dimension_argument;
+use cudarc::driver::{CudaDevice, CudaSlice, LaunchAsync};
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+use std::sync::Arc;
+
+pub(crate) fn smooth_skin(
+    device: &Arc<CudaDevice>,
+    input: &CudaSlice<u8>,
+    width: usize,
+    height: usize,
+    strength: f32,
+    radius: i32,
+    threshold: f32,
+) -> Result<CudaSlice<u8>, f64> {
+    // A NaN strength would survive the clamp below and corrupt the output.
+    if !strength.is_finite() {
+        return Err(-2.0); // Invalid input size
+    }
+    let mut output = device.alloc_zeros::<u8>(width * height * 4).map_err(|_| -4.0)?; // Output allocation failed
+    let kernel = load_kernel(device, "skin_smooth_module", SKIN_SMOOTH_KERNEL, &["smooth_skin"], "smooth_skin")?;
+    let params = (
+        input,
+        &mut output,
+        width as i32,
+        height as i32,
+        strength.clamp(0.0, 1.0),
+        radius.clamp(1, 32),
+        threshold.max(1.0),
+    );
+    unsafe { kernel.launch(launch_config_2d(width, height), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+    Ok(output)
+}
+
+/// `gpu_processor_smooth_skin(input, width, height, strength, radius, threshold, output?)`
+///
+/// Surface-blurs skin-colored areas: each pixel averages neighbors within
+/// `radius` pixels whose color differs by less than `threshold` (0–255), so
+/// blemishes and pores soften while eyes, lips and hair edges stay sharp.
+/// `strength` (0–1) is how much of the blurred result replaces the original;
+/// a hint of the original fine texture is always kept so skin does not look
+/// plastic. A non-finite `strength` returns -2.
+pub(crate) fn gpu_processor_smooth_skin(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let strength = cx.argument::<JsNumber>(3)?.value(&mut cx) as f32;
+    let radius = cx.argument::<JsNumber>(4)?.value(&mut cx) as i32;
+    let threshold = cx.argument::<JsNumber>(5)?.value(&mut cx) as f32;
+    let output_buffer = output_argument(&mut cx, 6)?;
+
+    let result = run_gpu(|device| {
+        let dev_input = upload_rgba(device, input_data.as_slice(&cx), width, height)?;
+        let dev_output = smooth_skin(device, &dev_input, width, height, strength, radius, threshold)?;
+        download(device, &dev_output)
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+const SKIN_SMOOTH_KERNEL: &str = r#"
+// Soft skin likelihood from the chroma of a full-range YCbCr conversion; the
+// classic Cb 77–127 / Cr 133–173 box with a feathered edge.
+__device__ float skin_weight(float r, float g, float b) {
+    float cb = 128.0f - 0.168736f * r - 0.331264f * g + 0.5f * b;
+    float cr = 128.0f + 0.5f * r - 0.418688f * g - 0.081312f * b;
+    float in_cb = fminf(fminf(cb - 77.0f, 127.0f - cb) / 8.0f + 1.0f, 1.0f);
+    float in_cr = fminf(fminf(cr - 133.0f, 173.0f - cr) / 8.0f + 1.0f, 1.0f);
+    return fmaxf(fminf(in_cb, in_cr), 0.0f);
+}
+
+extern "C" __global__ void smooth_skin(
+    const unsigned char* input,
+    unsigned char* output,
+    int width,
+    int height,
+    float strength,
+    int radius,
+    float threshold
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= width || y >= height) return;
+
+    const unsigned char* center = input + (y * width + x) * 4;
+    float r0 = (float)center[0];
+    float g0 = (float)center[1];
+    float b0 = (float)center[2];
+
+    // Surface blur: the weight falls linearly to zero at 2.5x the threshold
+    // per channel, and with a Gaussian in distance.
+    float sigma = (float)radius * 0.5f;
+    float inv_spatial = 1.0f / (2.0f * sigma * sigma);
+    float inv_range = 1.0f / (2.5f * threshold);
+    float sum[3] = {0.0f, 0.0f, 0.0f};
+    float total = 0.0f;
+    for (int dy = -radius; dy <= radius; dy++) {
+        int sy = min(max(y + dy, 0), height - 1);
+        for (int dx = -radius; dx <= radius; dx++) {
+            int sx = min(max(x + dx, 0), width - 1);
+            const unsigned char* px = input + (sy * width + sx) * 4;
+            float diff = fmaxf(fmaxf(fabsf((float)px[0] - r0), fabsf((float)px[1] - g0)), fabsf((float)px[2] - b0));
+            float w = fmaxf(1.0f - diff * inv_range, 0.0f) * __expf(-(float)(dx * dx + dy * dy) * inv_spatial);
+            sum[0] += (float)px[0] * w;
+            sum[1] += (float)px[1] * w;
+            sum[2] += (float)px[2] * w;
+            total += w;
+        }
+    }
+
+    // The center always has weight 1, so total > 0.
+    float amount = strength * skin_weight(r0, g0, b0) * 0.9f;
+    float original[3] = {r0, g0, b0};
+    unsigned char* out = output + (y * width + x) * 4;
+    for (int c = 0; c < 3; c++) {
+        float v = original[c] + (sum[c] / total - original[c]) * amount;
+        out[c] = (unsigned char)fminf(fmaxf(v + 0.5f, 0.0f), 255.0f);
+    }
+    out[3] = center[3];
+}
+"#;
@@ -0,0 +1,267 @@
+//! Saliency-driven crop selection for thumbnails.
+
+use crate::faces::{detect_faces, FaceOptions};
+use crate::{launch_config_2d, load_kernel, run_gpu, upload_rgba};
+use crate::validation::dimension_argument;
+use cudarc::driver::{CudaDevice, CudaSlice, LaunchAsync, LaunchConfig};
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct CropRect {
+    pub(crate) x: usize,
+    pub(crate) y: usize,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) score: f64,
+}
+
+/// Largest `aspect_ratio` (width / height) rectangle that fits the image.
+fn crop_size(width: usize, height: usize, aspect_ratio: f64) -> (usize, usize) {
+    if width as f64 / height as f64 > aspect_ratio {
+        (((height as f64 * aspect_ratio).round() as usize).clamp(1, width), height)
+    } else {
+        (width, ((width as f64 / aspect_ratio).round() as usize).clamp(1, height))
+    }
+}
+
+/// Energy added per pixel inside each detected face, enough to outweigh the
+/// busiest background.
+const FACE_ENERGY: f32 = 1024.0;
+
+/// Computes the saliency map of `image` and returns the crop window of the
+/// requested aspect ratio that covers the most salient energy. `faces` are
+/// weighted on top of the saliency.
+pub(crate) fn find_best_crop(
+    device: &Arc<CudaDevice>,
+    image: &CudaSlice<u8>,
+    width: usize,
+    height: usize,
+    aspect_ratio: f64,
+    faces: &[CropRect],
+) -> Result<CropRect, f64> {
+    let (crop_width, crop_height) = crop_size(width, height, aspect_ratio);
+
+    let mut energy = device.alloc_zeros::<f64>(width * height).map_err(|_| -4.0)?; // Output allocation failed
+    let kernel = load_kernel(device, "smart_crop_module", SMART_CROP_KERNEL, SMART_CROP_FUNCTIONS, "saliency_energy")?;
+    let params = (image, &mut energy, width as i32, height as i32);
+    unsafe { kernel.launch(launch_config_2d(width, height), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+
+    if !faces.is_empty() {
+        let rects: Vec<f32> = faces
+            .iter()
+            .flat_map(|f| [f.x as f32, f.y as f32, f.width as f32, f.height as f32, FACE_ENERGY])
+            .collect();
+        let rects = device.htod_sync_copy(&rects).map_err(|_| -3.0)?; // Memory allocation failed
+        let kernel = load_kernel(device, "smart_crop_module", SMART_CROP_KERNEL, SMART_CROP_FUNCTIONS, "boost_regions")?;
+        let params = (&mut energy, width as i32, height as i32, &rects, faces.len() as i32);
+        unsafe { kernel.launch(launch_config_2d(width, height), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+    }
+
+    // Turn the energy map into its own summed-area table in place.
+    let rows = load_kernel(device, "smart_crop_module", SMART_CROP_KERNEL, SMART_CROP_FUNCTIONS, "prefix_rows")?;
+    let params = (&mut energy, width as i32, height as i32);
+    unsafe { rows.launch(LaunchConfig::for_num_elems(height as u32), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+    let cols = load_kernel(device, "smart_crop_module", SMART_CROP_KERNEL, SMART_CROP_FUNCTIONS, "prefix_cols")?;
+    let params = (&mut energy, width as i32, height as i32);
+    unsafe { cols.launch(LaunchConfig::for_num_elems(width as u32), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+
+    let candidates_x = width - crop_width + 1;
+    let candidates_y = height - crop_height + 1;
+    let mut scores = device
+        .alloc_zeros::<f64>(candidates_x * candidates_y)
+        .map_err(|_| -4.0)?; // Output allocation failed
+    let kernel = load_kernel(device, "smart_crop_module", SMART_CROP_KERNEL, SMART_CROP_FUNCTIONS, "score_windows")?;
+    let params = (
+        &energy,
+        width as i32,
+        crop_width as i32,
+        crop_height as i32,
+        candidates_x as i32,
+        candidates_y as i32,
+        &mut scores,
+    );
+    unsafe { kernel.launch(launch_config_2d(candidates_x, candidates_y), params) }
+        .map_err(|_| -8.0)?; // Kernel launch failed
+
+    let scores = device.dtoh_sync_copy(&scores).map_err(|_| -9.0)?; // Copy back failed
+    let (best, score) = scores
+        .iter()
+        .copied()
+        .enumerate()
+        .fold((0, f64::MIN), |best, (i, s)| if s > best.1 { (i, s) } else { best });
+
+    Ok(CropRect {
+        x: best % candidates_x,
+        y: best / candidates_x,
+        width: crop_width,
+        height: crop_height,
+        score,
+    })
+}
+
+pub(crate) fn crop_rect_object<'a>(cx: &mut FunctionContext<'a>, rect: CropRect) -> JsResult<'a, JsObject> {
+    let obj = cx.empty_object();
+    let value = cx.number(rect.x as f64);
+    obj.set(cx, "x", value)?;
+    let value = cx.number(rect.y as f64);
+    obj.set(cx, "y", value)?;
+    let value = cx.number(rect.width as f64);
+    obj.set(cx, "width", value)?;
+    let value = cx.number(rect.height as f64);
+    obj.set(cx, "height", value)?;
+    let value = cx.number(rect.score);
+    obj.set(cx, "score", value)?;
+    Ok(obj)
+}
+
+/// `gpu_processor_smart_crop(input, width, height, aspect_ratio, options?)`
+///
+/// Returns `{ x, y, width, height, score }` for the largest crop of
+/// `aspect_ratio` (width / height) that keeps the most salient content, or a
+/// negative status code. Saliency combines edge energy and color saturation.
+/// With `{ faces: true }`, regions found by `gpu_processor_detect_faces` are
+/// weighted far above everything else, so the crop keeps them whenever they
+/// fit.
+pub(crate) fn gpu_processor_smart_crop(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let aspect_ratio = cx.argument::<JsNumber>(3)?.value(&mut cx);
+
+    let use_faces = match cx.argument_opt(4) {
+        Some(value) if value.is_a::<JsObject, _>(&mut cx) => {
+            let options = value.downcast_or_throw::<JsObject, _>(&mut cx)?;
+            options.get_opt::<JsBoolean, _, _>(&mut cx, "faces")?.is_some_and(|flag| flag.value(&mut cx))
+        }
+        _ => false,
+    };
+
+    if !aspect_ratio.is_finite() || aspect_ratio <= 0.0 {
+        return cx.throw_range_error("aspect_ratio must be a positive number");
+    }
+
+    let input_slice = input_data.as_slice(&cx);
+    let faces = if use_faces { detect_faces(input_slice, width, height, &FaceOptions::default()) } else { Ok(Vec::new()) };
+    let result = faces.and_then(|faces| {
+        run_gpu(|device| {
+            let image = upload_rgba(device, input_slice, width, height)?;
+            find_best_crop(device, &image, width, height, aspect_ratio, &faces)
+        })
+    });
+
+    match result {
+        Ok(rect) => Ok(crop_rect_object(&mut cx, rect)?.upcast()),
+        Err(code) => Ok(cx.number(code).upcast()),
+    }
+}
+
+const SMART_CROP_FUNCTIONS: &[&str] = &["saliency_energy", "boost_regions", "prefix_rows", "prefix_cols", "score_windows"];
+
+const SMART_CROP_KERNEL: &str = r#"
+__device__ __forceinline__ float luma_at(const unsigned char* image, int width, int height, int x, int y) {
+    x = min(max(x, 0), width - 1);
+    y = min(max(y, 0), height - 1);
+    const unsigned char* px = image + (y * width + x) * 4;
+    return 0.299f * (float)px[0] + 0.587f * (float)px[1] + 0.114f * (float)px[2];
+}
+
+extern "C" __global__ void saliency_energy(
+    const unsigned char* image,
+    double* energy,
+    int width,
+    int height
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= width || y >= height) return;
+
+    // Sobel gradient magnitude of luma.
+    float gx = -luma_at(image, width, height, x - 1, y - 1) - 2.0f * luma_at(image, width, height, x - 1, y)
+               - luma_at(image, width, height, x - 1, y + 1) + luma_at(image, width, height, x + 1, y - 1)
+               + 2.0f * luma_at(image, width, height, x + 1, y) + luma_at(image, width, height, x + 1, y + 1);
+    float gy = -luma_at(image, width, height, x - 1, y - 1) - 2.0f * luma_at(image, width, height, x, y - 1)
+               - luma_at(image, width, height, x + 1, y - 1) + luma_at(image, width, height, x - 1, y + 1)
+               + 2.0f * luma_at(image, width, height, x, y + 1) + luma_at(image, width, height, x + 1, y + 1);
+    float edge = fminf(hypotf(gx, gy) / 4.0f, 255.0f);
+
+    const unsigned char* px = image + (y * width + x) * 4;
+    float r = (float)px[0];
+    float g = (float)px[1];
+    float b = (float)px[2];
+    float max_c = fmaxf(r, fmaxf(g, b));
+    float min_c = fminf(r, fminf(g, b));
+    float saturation = max_c > 0.0f ? (max_c - min_c) / max_c : 0.0f;
+
+    float alpha = (float)px[3] / 255.0f;
+    energy[y * width + x] = (double)((edge + 64.0f * saturation) * alpha);
+}
+
+// regions: x, y, width, height, energy per rectangle.
+extern "C" __global__ void boost_regions(
+    double* energy,
+    int width,
+    int height,
+    const float* regions,
+    int region_count
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= width || y >= height) return;
+
+    double boost = 0.0;
+    for (int i = 0; i < region_count; i++) {
+        const float* r = regions + i * 5;
+        if ((float)x >= r[0] && (float)y >= r[1] && (float)x < r[0] + r[2] && (float)y < r[1] + r[3]) {
+            boost += (double)r[4];
+        }
+    }
+    energy[y * width + x] += boost;
+}
+
+extern "C" __global__ void prefix_rows(double* table, int width, int height) {
+    int y = blockIdx.x * blockDim.x + threadIdx.x;
+    if (y >= height) return;
+    double acc = 0.0;
+    for (int x = 0; x < width; x++) {
+        acc += table[y * width + x];
+        table[y * width + x] = acc;
+    }
+}
+
+extern "C" __global__ void prefix_cols(double* table, int width, int height) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    if (x >= width) return;
+    double acc = 0.0;
+    for (int y = 0; y < height; y++) {
+        acc += table[y * width + x];
+        table[y * width + x] = acc;
+    }
+}
+
+__device__ __forceinline__ double sat_at(const double* table, int width, int x, int y) {
+    return (x < 0 || y < 0) ? 0.0 : table[y * width + x];
+}
+
+extern "C" __global__ void score_windows(
+    const double* table,
+    int width,
+    int crop_width,
+    int crop_height,
+    int candidates_x,
+    int candidates_y,
+    double* scores
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= candidates_x || y >= candidates_y) return;
+
+    int x1 = x + crop_width - 1;
+    int y1 = y + crop_height - 1;
+    scores[y * candidates_x + x] = sat_at(table, width, x1, y1)
+        - sat_at(table, width, x - 1, y1)
+        - sat_at(table, width, x1, y - 1)
+        + sat_at(table, width, x - 1, y - 1);
+}
+"#;
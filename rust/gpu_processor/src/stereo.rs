@@ -0,0 +1,128 @@
+//! Stereo frame conversion for previews of VR/3D content.
+
+use crate::{download, launch_config_2d, load_kernel, run_gpu, upload_rgba, output_argument, write_result};
+use crate::validation::{dimension_argument, image_bytes};
+use cudarc::driver::LaunchAsync;
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum StereoLayout {
+    SideBySide,
+    TopBottom,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum StereoMode {
+    Anaglyph,
+    LeftEye,
+    RightEye,
+}
+
+/// `gpu_processor_stereo_convert(input, width, height, layout, mode, output?)`
+///
+/// `input` is a stereo frame whose two eyes sit next to each other
+/// (`layout` `"side-by-side"`, left eye first) or stacked (`"top-bottom"`,
+/// left eye on top). `mode` `"anaglyph"` merges them into a red-cyan
+/// anaglyph using Dubois' least-squares matrices, `"left"` / `"right"` crop
+/// a single eye. `output` is one eye in size: `width / 2 x height` for
+/// side-by-side, `width x height / 2` for top-bottom.
+pub(crate) fn gpu_processor_stereo_convert(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let layout = cx.argument::<JsString>(3)?.value(&mut cx);
+    let layout = match layout.as_str() {
+        "side-by-side" => StereoLayout::SideBySide,
+        "top-bottom" => StereoLayout::TopBottom,
+        other => return cx.throw_type_error(format!("Unknown stereo layout `{}`", other)),
+    };
+    let mode = cx.argument::<JsString>(4)?.value(&mut cx);
+    let mode = match mode.as_str() {
+        "anaglyph" => StereoMode::Anaglyph,
+        "left" => StereoMode::LeftEye,
+        "right" => StereoMode::RightEye,
+        other => return cx.throw_type_error(format!("Unknown stereo mode `{}`", other)),
+    };
+    let output_buffer = output_argument(&mut cx, 5)?;
+
+    let (eye_width, eye_height, right_x, right_y) = match layout {
+        StereoLayout::SideBySide => (width / 2, height, width / 2, 0),
+        StereoLayout::TopBottom => (width, height / 2, 0, height / 2),
+    };
+
+    let result = run_gpu(|device| {
+        let image = upload_rgba(device, input_data.as_slice(&cx), width, height)?;
+        let eye_size = image_bytes(eye_width, eye_height, 4)?;
+        let mut output = device.alloc_zeros::<u8>(eye_size).map_err(|_| -4.0)?; // Output allocation failed
+        let mode = match mode {
+            StereoMode::Anaglyph => 0,
+            StereoMode::LeftEye => 1,
+            StereoMode::RightEye => 2,
+        };
+        let kernel = load_kernel(device, "stereo_module", STEREO_KERNEL, &["stereo_convert"], "stereo_convert")?;
+        let params = (
+            &image,
+            width as i32,
+            right_x as i32,
+            right_y as i32,
+            mode,
+            &mut output,
+            eye_width as i32,
+            eye_height as i32,
+        );
+        unsafe { kernel.launch(launch_config_2d(eye_width, eye_height), params) }.map_err(|_| -8.0)?; // Kernel launch failed
+        download(device, &output)
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+const STEREO_KERNEL: &str = r#"
+// Dubois red-cyan anaglyph (least-squares projection), rows are output r, g, b.
+__constant__ float ANAGLYPH_LEFT[9] = {
+     0.456f,  0.500f,  0.176f,
+    -0.040f, -0.038f, -0.016f,
+    -0.015f, -0.021f, -0.005f
+};
+__constant__ float ANAGLYPH_RIGHT[9] = {
+    -0.043f, -0.088f, -0.002f,
+     0.378f,  0.734f, -0.018f,
+    -0.072f, -0.113f,  1.226f
+};
+
+// mode 0: anaglyph, 1: left eye, 2: right eye.
+extern "C" __global__ void stereo_convert(
+    const unsigned char* input,
+    int input_width,
+    int right_x,
+    int right_y,
+    int mode,
+    unsigned char* output,
+    int eye_width,
+    int eye_height
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= eye_width || y >= eye_height) return;
+
+    const unsigned char* left = input + (y * input_width + x) * 4;
+    const unsigned char* right = input + ((y + right_y) * input_width + x + right_x) * 4;
+    unsigned char* px = output + (y * eye_width + x) * 4;
+
+    if (mode != 0) {
+        const unsigned char* eye = mode == 1 ? left : right;
+        for (int c = 0; c < 4; c++) px[c] = eye[c];
+        return;
+    }
+
+    for (int c = 0; c < 3; c++) {
+        float v = 0.0f;
+        for (int k = 0; k < 3; k++) {
+            v += ANAGLYPH_LEFT[c * 3 + k] * (float)left[k] + ANAGLYPH_RIGHT[c * 3 + k] * (float)right[k];
+        }
+        px[c] = (unsigned char)fminf(fmaxf(v + 0.5f, 0.0f), 255.0f);
+    }
+    px[3] = max(left[3], right[3]);
+}
+"#;
@@ -0,0 +1,133 @@
+//! Recursive temporal denoising for frame sequences such as webcam feeds.
+//!
+//! Each output frame is kept on the device as a handle and fed back with the
+//! next input, so static areas average noise out over many frames while
+//! moving areas follow the current frame and do not ghost.
+
+use crate::handles::{self, GpuImage, PixelFormat};
+use crate::validation::dimension_argument;
+use crate::{launch_config_2d, load_kernel, run_gpu, upload_rgba};
+use cudarc::driver::{CudaDevice, CudaSlice, LaunchAsync};
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+use std::sync::Arc;
+
+/// Blends `previous` into `current` in place wherever the two agree.
+fn temporal_denoise(
+    device: &Arc<CudaDevice>,
+    current: &mut CudaSlice<u8>,
+    previous: &CudaSlice<u8>,
+    width: usize,
+    height: usize,
+    strength: f32,
+    motion_threshold: f32,
+) -> Result<(), f64> {
+    // Read from a copy, since the motion test looks at neighboring pixels.
+    let source = current.try_clone().map_err(|_| -3.0)?; // Memory allocation failed
+    let kernel = load_kernel(
+        device,
+        "temporal_denoise_module",
+        TEMPORAL_DENOISE_KERNEL,
+        &["temporal_denoise"],
+        "temporal_denoise",
+    )?;
+    let params = (&source, previous, current, width as i32, height as i32, strength, motion_threshold);
+    unsafe { kernel.launch(launch_config_2d(width, height), params) }.map_err(|_| -8.0) // Kernel launch failed
+}
+
+/// `gpu_processor_temporal_denoise(input, width, height, previous, strength?, motion_threshold?)`
+///
+/// Denoises RGBA frame `input` against `previous`, the handle returned for
+/// the frame before it, and returns the handle of the result; pass 0 for the
+/// first frame. Per pixel, up to `strength` (0–1, default 0.75) of the
+/// previous output is blended in, fading out as the local difference between
+/// the frames rises past `motion_threshold` (in 8-bit levels, default 12)
+/// so moving content follows the current frame. Raise the threshold for
+/// noisier, low-light sources. Download the handle to display the frame, and
+/// release the previous one once the next frame has been processed. Returns
+/// -12 if `previous` is unknown, -2 if its size differs and -13 if it is not
+/// RGBA8.
+pub(crate) fn gpu_processor_temporal_denoise(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let previous = cx.argument::<JsNumber>(3)?.value(&mut cx);
+    let strength = match cx.argument_opt(4) {
+        Some(value) => value.downcast_or_throw::<JsNumber, _>(&mut cx)?.value(&mut cx),
+        None => 0.75,
+    };
+    let motion_threshold = match cx.argument_opt(5) {
+        Some(value) => value.downcast_or_throw::<JsNumber, _>(&mut cx)?.value(&mut cx),
+        None => 12.0,
+    };
+
+    if !(0.0..=1.0).contains(&strength) {
+        return cx.throw_range_error("strength must be between 0 and 1");
+    }
+    if motion_threshold.is_nan() || motion_threshold <= 0.0 {
+        return cx.throw_range_error("motion_threshold must be positive");
+    }
+    let previous = if previous == 0.0 { None } else { Some(handles::handle_argument(&mut cx, 3)?) };
+
+    let result = run_gpu(|device| {
+        let mut data = upload_rgba(device, input_data.as_slice(&cx), width, height)?;
+        if let Some(previous) = previous {
+            handles::with_image(previous, |image| {
+                if image.format != PixelFormat::Rgba8 {
+                    return Err(-13.0); // Unsupported pixel format
+                }
+                if image.width != width || image.height != height {
+                    return Err(-2.0); // Invalid input size
+                }
+                temporal_denoise(device, &mut data, &image.data, width, height, strength as f32, motion_threshold as f32)
+            })?;
+        }
+        Ok(GpuImage { data, width, height, format: PixelFormat::Rgba8 })
+    });
+
+    handles::handle_result(&mut cx, result)
+}
+
+const TEMPORAL_DENOISE_KERNEL: &str = r#"
+__device__ __forceinline__ float luma(const unsigned char* px) {
+    return 0.299f * (float)px[0] + 0.587f * (float)px[1] + 0.114f * (float)px[2];
+}
+
+// Motion is the luma difference of the 3x3 neighborhood means, which keeps
+// per-pixel sensor noise from reading as movement.
+extern "C" __global__ void temporal_denoise(
+    const unsigned char* current,
+    const unsigned char* previous,
+    unsigned char* output,
+    int width,
+    int height,
+    float strength,
+    float motion_threshold
+) {
+    int x = blockIdx.x * blockDim.x + threadIdx.x;
+    int y = blockIdx.y * blockDim.y + threadIdx.y;
+    if (x >= width || y >= height) return;
+
+    float diff = 0.0f;
+    for (int dy = -1; dy <= 1; dy++) {
+        for (int dx = -1; dx <= 1; dx++) {
+            int sx = min(max(x + dx, 0), width - 1);
+            int sy = min(max(y + dy, 0), height - 1);
+            int i = (sy * width + sx) * 4;
+            diff += luma(current + i) - luma(previous + i);
+        }
+    }
+    diff = fabsf(diff) / 9.0f;
+
+    // Full blending below half the threshold, none above one and a half.
+    float motion = fminf(fmaxf(diff / motion_threshold - 0.5f, 0.0f), 1.0f);
+    float weight = strength * (1.0f - motion);
+
+    int i = (y * width + x) * 4;
+    for (int c = 0; c < 4; c++) {
+        float cur = (float)current[i + c];
+        float v = cur + ((float)previous[i + c] - cur) * weight;
+        output[i + c] = (unsigned char)fminf(fmaxf(v + 0.5f, 0.0f), 255.0f);
+    }
+}
+"#;
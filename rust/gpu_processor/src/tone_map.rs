@@ -0,0 +1,144 @@
+//! Tone mapping of linear HDR images to display-referred 8-bit sRGB.
+
+use crate::handles::{self, GpuImage, PixelFormat};
+use crate::{download, load_kernel, run_gpu, output_argument, write_result};
+use crate::validation::{dimension_argument, image_bytes};
+use cudarc::driver::{CudaDevice, CudaSlice, LaunchAsync, LaunchConfig};
+use neon::prelude::*;
+use neon::types::buffer::TypedArray;
+use std::sync::Arc;
+
+#[derive(Clone, Copy)]
+enum ToneMapOperator {
+    Reinhard = 0,
+    Aces = 1,
+}
+
+fn operator_argument(cx: &mut FunctionContext, index: usize) -> NeonResult<ToneMapOperator> {
+    let name = cx.argument::<JsString>(index)?.value(cx);
+    match name.as_str() {
+        "reinhard" => Ok(ToneMapOperator::Reinhard),
+        "aces" => Ok(ToneMapOperator::Aces),
+        _ => cx.throw_type_error(format!("Unknown tone mapping operator `{}`", name)),
+    }
+}
+
+/// Maps a float or half RGBA image to 8-bit sRGB on the device.
+fn tone_map(
+    device: &Arc<CudaDevice>,
+    input: &CudaSlice<u8>,
+    width: usize,
+    height: usize,
+    format: PixelFormat,
+    operator: ToneMapOperator,
+    exposure: f32,
+) -> Result<CudaSlice<u8>, f64> {
+    let is_half = match format {
+        PixelFormat::RgbaF16 => 1,
+        PixelFormat::RgbaF32 => 0,
+        PixelFormat::Rgba8 => return Err(-13.0), // Unsupported pixel format
+    };
+    let pixel_count = width * height;
+    let mut output = device.alloc_zeros::<u8>(pixel_count * 4).map_err(|_| -4.0)?; // Output allocation failed
+
+    let kernel = load_kernel(device, "tone_map_module", TONE_MAP_KERNEL, &["tone_map"], "tone_map")?;
+    let params = (input, is_half, &mut output, pixel_count as i32, operator as i32, exposure);
+    unsafe { kernel.launch(LaunchConfig::for_num_elems(pixel_count as u32), params) }
+        .map_err(|_| -8.0)?; // Kernel launch failed
+    Ok(output)
+}
+
+/// `gpu_processor_tone_map(input, width, height, format, operator, exposure, output?)`
+///
+/// `format` is `"rgba16f"` or `"rgba32f"` (linear light), `operator` is
+/// `"reinhard"` or `"aces"`, and `exposure` is in stops. Writes RGBA8 sRGB.
+pub(crate) fn gpu_processor_tone_map(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let input_data = cx.argument::<JsBuffer>(0)?;
+    let width = dimension_argument(&mut cx, 1)?;
+    let height = dimension_argument(&mut cx, 2)?;
+    let format_name = cx.argument::<JsString>(3)?.value(&mut cx);
+    let operator = operator_argument(&mut cx, 4)?;
+    let exposure = cx.argument::<JsNumber>(5)?.value(&mut cx) as f32;
+    let output_buffer = output_argument(&mut cx, 6)?;
+
+    let format = match PixelFormat::parse(&format_name) {
+        Some(format) => format,
+        None => return cx.throw_type_error(format!("Unknown pixel format `{}`", format_name)),
+    };
+
+    let result = run_gpu(|device| {
+        let input = input_data.as_slice(&cx);
+        if input.len() != image_bytes(width, height, format.bytes_per_pixel())? {
+            return Err(-2.0); // Invalid input size
+        }
+        let dev_input = device.htod_sync_copy(input).map_err(|_| -3.0)?; // Memory allocation failed
+        let output = tone_map(device, &dev_input, width, height, format, operator, exposure)?;
+        download(device, &output)
+    });
+
+    write_result(&mut cx, output_buffer, result)
+}
+
+/// `gpu_processor_tone_map_handle(handle, operator, exposure)`
+///
+/// Final stage of a handle-based pipeline: tone maps an HDR handle and
+/// returns a new RGBA8 handle. The input handle is left alive.
+pub(crate) fn gpu_processor_tone_map_handle(mut cx: FunctionContext) -> JsResult<JsNumber> {
+    let handle = handles::handle_argument(&mut cx, 0)?;
+    let operator = operator_argument(&mut cx, 1)?;
+    let exposure = cx.argument::<JsNumber>(2)?.value(&mut cx) as f32;
+
+    let result = run_gpu(|device| {
+        handles::with_image(handle, |image| {
+            let data = tone_map(device, &image.data, image.width, image.height, image.format, operator, exposure)?;
+            Ok(GpuImage { data, width: image.width, height: image.height, format: PixelFormat::Rgba8 })
+        })
+    });
+
+    handles::handle_result(&mut cx, result)
+}
+
+const TONE_MAP_KERNEL: &str = r#"
+__device__ __forceinline__ float half_to_float(unsigned short h) {
+    float f;
+    asm("cvt.f32.f16 %0, %1;" : "=f"(f) : "h"(h));
+    return f;
+}
+
+__device__ __forceinline__ float linear_to_srgb(float v) {
+    v = fminf(fmaxf(v, 0.0f), 1.0f);
+    return v <= 0.0031308f ? v * 12.92f : 1.055f * powf(v, 1.0f / 2.4f) - 0.055f;
+}
+
+// Narkowicz's fit of the ACES filmic curve.
+__device__ __forceinline__ float aces(float v) {
+    return (v * (2.51f * v + 0.03f)) / (v * (2.43f * v + 0.59f) + 0.14f);
+}
+
+extern "C" __global__ void tone_map(
+    const void* input,
+    int is_half,
+    unsigned char* output,
+    int pixel_count,
+    int op,
+    float exposure
+) {
+    int i = blockIdx.x * blockDim.x + threadIdx.x;
+    if (i >= pixel_count) return;
+
+    float px[4];
+    for (int c = 0; c < 4; c++) {
+        px[c] = is_half
+            ? half_to_float(((const unsigned short*)input)[i * 4 + c])
+            : ((const float*)input)[i * 4 + c];
+    }
+
+    float scale = exp2f(exposure);
+    for (int c = 0; c < 3; c++) {
+        float v = fmaxf(px[c] * scale, 0.0f);
+        v = op == 1 ? aces(v) : v / (1.0f + v);
+        output[i * 4 + c] = (unsigned char)(linear_to_srgb(v) * 255.0f + 0.5f);
+    }
+    output[i * 4 + 3] = (unsigned char)(fminf(fmaxf(px[3], 0.0f), 1.0f) * 255.0f + 0.5f);
+}
+"#;
@@ -0,0 +1,72 @@
+//! Bounds on caller-supplied sizes, checked before anything is allocated.
+
+use crate::config::config;
+use neon::prelude::*;
+
+/// Largest accepted width or height.
+pub(crate) const MAX_DIMENSION: usize = 65_535;
+
+/// Hard ceiling on pixels per image, whatever `maxPixels` says. Kernels
+/// index RGBA elements with 32-bit ints, so `pixels * 4` has to stay below
+/// `i32::MAX`.
+pub(crate) const MAX_PIXELS: usize = 1 << 28;
+
+/// Anything that isn't a whole number in `1..=MAX_DIMENSION` maps to 0,
+/// which every size check rejects.
+pub(crate) fn dimension(value: f64) -> usize {
+    if value.fract() == 0.0 && value >= 1.0 && value <= MAX_DIMENSION as f64 { value as usize } else { 0 }
+}
+
+/// Reads a width or height argument; see `dimension`.
+pub(crate) fn dimension_argument(cx: &mut FunctionContext, index: usize) -> NeonResult<usize> {
+    let value = cx.argument::<JsNumber>(index)?.value(cx);
+    Ok(dimension(value))
+}
+
+/// Byte length of a `width` x `height` image, or -2 when either side is 0 or
+/// the image has more pixels than allowed.
+pub(crate) fn image_bytes(width: usize, height: usize, bytes_per_pixel: usize) -> Result<usize, f64> {
+    let limit = config().max_pixels.min(MAX_PIXELS);
+    match width.checked_mul(height) {
+        Some(pixels) if pixels > 0 && pixels <= limit => pixels.checked_mul(bytes_per_pixel).ok_or(-2.0),
+        _ => Err(-2.0), // Invalid input size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dimensions_must_be_whole_and_in_range() {
+        assert_eq!(dimension(1.0), 1);
+        assert_eq!(dimension(1920.0), 1920);
+        assert_eq!(dimension(MAX_DIMENSION as f64), MAX_DIMENSION);
+        for value in [0.0, -1.0, 0.5, 100.25, MAX_DIMENSION as f64 + 1.0, 1e300, f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert_eq!(dimension(value), 0, "{}", value);
+        }
+    }
+
+    #[test]
+    fn image_bytes_counts_every_channel() {
+        assert_eq!(image_bytes(1, 1, 4), Ok(4));
+        assert_eq!(image_bytes(640, 480, 4), Ok(640 * 480 * 4));
+        assert_eq!(image_bytes(640, 480, 32), Ok(640 * 480 * 32));
+    }
+
+    #[test]
+    fn image_bytes_rejects_empty_and_oversized_images() {
+        assert_eq!(image_bytes(0, 480, 4), Err(-2.0));
+        assert_eq!(image_bytes(640, 0, 4), Err(-2.0));
+        assert_eq!(image_bytes(MAX_DIMENSION, MAX_DIMENSION, 4), Err(-2.0));
+        assert_eq!(image_bytes(usize::MAX, 2, 4), Err(-2.0));
+        assert_eq!(image_bytes(MAX_PIXELS, 1, usize::MAX), Err(-2.0));
+    }
+
+    #[test]
+    fn image_bytes_allows_up_to_max_pixels() {
+        let limit = config().max_pixels.min(MAX_PIXELS);
+        assert_eq!(image_bytes(limit, 1, 4), Ok(limit * 4));
+        assert_eq!(image_bytes(limit + 1, 1, 4), Err(-2.0));
+    }
+}
@@ -0,0 +1,116 @@
+// Basic color adjustments, as the editor's sliders: brightness and contrast
+// on each channel, saturation and hue with the CSS filter matrices, then
+// gamma. Colors are worked in 0–1 floats and alpha is left alone.
+
+use crate::error;
+use image::DynamicImage;
+use js_sys::Reflect;
+use wasm_bindgen::prelude::*;
+
+#[derive(Clone, Copy)]
+pub(crate) struct Adjustments {
+    brightness: f32,
+    contrast: f32,
+    saturation: f32,
+    hue: f32,
+    gamma: f32,
+}
+
+fn number(options: &JsValue, key: &str, default: f64) -> Result<f64, JsValue> {
+    if options.is_undefined() || options.is_null() {
+        return Ok(default);
+    }
+    let value = Reflect::get(options, &key.into())?;
+    if value.is_undefined() || value.is_null() {
+        return Ok(default);
+    }
+    value.as_f64().filter(|v| v.is_finite()).ok_or_else(|| error::invalid_argument(format!("Adjustment `{}` must be a number", key)))
+}
+
+impl Adjustments {
+    // Reads `{ brightness?, contrast?, saturation?, hue?, gamma? }`:
+    // brightness, contrast and saturation from -1 to 1 with 0 unchanged,
+    // hue as a rotation in degrees, and gamma above 0 with 1 unchanged.
+    pub(crate) fn from_js(options: &JsValue) -> Result<Adjustments, JsValue> {
+        let unit = |key: &str| -> Result<f32, JsValue> {
+            let value = number(options, key, 0.0)?;
+            if !(-1.0..=1.0).contains(&value) {
+                return Err(error::invalid_argument(format!("Adjustment `{}` must be between -1 and 1", key)));
+            }
+            Ok(value as f32)
+        };
+        let gamma = number(options, "gamma", 1.0)?;
+        if !(0.01..=100.0).contains(&gamma) {
+            return Err(error::invalid_argument("Adjustment `gamma` must be between 0.01 and 100"));
+        }
+        Ok(Adjustments {
+            brightness: unit("brightness")?,
+            contrast: unit("contrast")?,
+            saturation: unit("saturation")?,
+            hue: number(options, "hue", 0.0)? as f32,
+            gamma: gamma as f32,
+        })
+    }
+
+    // The saturate matrix followed by hue-rotate, from Filter Effects.
+    fn color_matrix(&self) -> [[f32; 3]; 3] {
+        let s = 1.0 + self.saturation;
+        let saturate = [
+            [0.213 + 0.787 * s, 0.715 - 0.715 * s, 0.072 - 0.072 * s],
+            [0.213 - 0.213 * s, 0.715 + 0.285 * s, 0.072 - 0.072 * s],
+            [0.213 - 0.213 * s, 0.715 - 0.715 * s, 0.072 + 0.928 * s],
+        ];
+        let (sin, cos) = self.hue.to_radians().sin_cos();
+        let rotate = [
+            [0.213 + cos * 0.787 - sin * 0.213, 0.715 - cos * 0.715 - sin * 0.715, 0.072 - cos * 0.072 + sin * 0.928],
+            [0.213 - cos * 0.213 + sin * 0.143, 0.715 + cos * 0.285 + sin * 0.140, 0.072 - cos * 0.072 - sin * 0.283],
+            [0.213 - cos * 0.213 - sin * 0.787, 0.715 - cos * 0.715 + sin * 0.715, 0.072 + cos * 0.928 + sin * 0.072],
+        ];
+        std::array::from_fn(|row| std::array::from_fn(|col| (0..3).map(|k| rotate[row][k] * saturate[k][col]).sum()))
+    }
+
+    pub(crate) fn apply(&self, img: &DynamicImage) -> DynamicImage {
+        let matrix = (self.saturation != 0.0 || self.hue % 360.0 != 0.0).then(|| self.color_matrix());
+        let adjust = |rgb: [f32; 3]| -> [f32; 3] {
+            let toned = rgb.map(|v| (v + self.brightness - 0.5) * (1.0 + self.contrast) + 0.5);
+            let mixed = match &matrix {
+                Some(m) => std::array::from_fn(|row| (0..3).map(|k| m[row][k] * toned[k]).sum()),
+                None => toned,
+            };
+            mixed.map(|v| {
+                let v = v.clamp(0.0, 1.0);
+                if self.gamma == 1.0 { v } else { v.powf(1.0 / self.gamma) }
+            })
+        };
+        let alpha = img.color().has_alpha();
+        let deep = matches!(
+            img,
+            DynamicImage::ImageLuma16(_)
+                | DynamicImage::ImageLumaA16(_)
+                | DynamicImage::ImageRgb16(_)
+                | DynamicImage::ImageRgba16(_)
+                | DynamicImage::ImageRgb32F(_)
+                | DynamicImage::ImageRgba32F(_)
+        );
+        if deep {
+            let mut rgba = img.to_rgba16();
+            for pixel in rgba.pixels_mut() {
+                let rgb = adjust([pixel[0], pixel[1], pixel[2]].map(|c| c as f32 / 65535.0));
+                for c in 0..3 {
+                    pixel[c] = (rgb[c] * 65535.0).round() as u16;
+                }
+            }
+            let out = DynamicImage::ImageRgba16(rgba);
+            return if alpha { out } else { DynamicImage::ImageRgb16(out.to_rgb16()) };
+        }
+        let mut rgba = img.to_rgba8();
+        for pixel in rgba.pixels_mut() {
+            let rgb = adjust([pixel[0], pixel[1], pixel[2]].map(|c| c as f32 / 255.0));
+            for c in 0..3 {
+                pixel[c] = (rgb[c] * 255.0).round() as u8;
+            }
+        }
+        let out = DynamicImage::ImageRgba8(rgba);
+        if alpha { out } else { DynamicImage::ImageRgb8(out.to_rgb8()) }
+    }
+}
@@ -0,0 +1,84 @@
+// Statistics for judging uploads before they are sent: histograms,
+// exposure and sharpness.
+
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+// The sharpness score depends on scale, so images are measured fitted
+// within this size.
+pub(crate) const SHARPNESS_SIDE: u32 = 1024;
+
+pub(crate) struct Histogram {
+    pub(crate) red: [u32; 256],
+    pub(crate) green: [u32; 256],
+    pub(crate) blue: [u32; 256],
+    // Present only for images with an alpha channel.
+    pub(crate) alpha: Option<[u32; 256]>,
+    // BT.709 luma, rounded to the nearest level.
+    pub(crate) luminance: [u32; 256],
+    pub(crate) mean_luminance: f64,
+    // Percentages of pixels at luma 0 and 255, lost detail at either end.
+    pub(crate) shadows_clipped: f64,
+    pub(crate) highlights_clipped: f64,
+}
+
+fn luma(r: u8, g: u8, b: u8) -> f64 {
+    0.2126 * r as f64 + 0.7152 * g as f64 + 0.0722 * b as f64
+}
+
+pub(crate) fn histogram(img: &DynamicImage) -> Histogram {
+    let rgba = img.to_rgba8();
+    let mut histogram = Histogram {
+        red: [0; 256],
+        green: [0; 256],
+        blue: [0; 256],
+        alpha: img.color().has_alpha().then_some([0; 256]),
+        luminance: [0; 256],
+        mean_luminance: 0.0,
+        shadows_clipped: 0.0,
+        highlights_clipped: 0.0,
+    };
+    let mut total = 0.0;
+    for pixel in rgba.pixels() {
+        let [r, g, b, a] = pixel.0;
+        histogram.red[r as usize] += 1;
+        histogram.green[g as usize] += 1;
+        histogram.blue[b as usize] += 1;
+        if let Some(alpha) = &mut histogram.alpha {
+            alpha[a as usize] += 1;
+        }
+        let luma = luma(r, g, b);
+        histogram.luminance[luma.round() as usize] += 1;
+        total += luma;
+    }
+    let pixels = rgba.pixels().len().max(1) as f64;
+    histogram.mean_luminance = total / pixels;
+    histogram.shadows_clipped = histogram.luminance[0] as f64 * 100.0 / pixels;
+    histogram.highlights_clipped = histogram.luminance[255] as f64 * 100.0 / pixels;
+    histogram
+}
+
+// Variance of the 4-neighbor Laplacian of the luma, with the image fitted
+// within SHARPNESS_SIDE: blur removes the fine edges that make it large.
+pub(crate) fn sharpness(img: &DynamicImage) -> f64 {
+    let img = if img.width().max(img.height()) > SHARPNESS_SIDE {
+        std::borrow::Cow::Owned(img.resize(SHARPNESS_SIDE, SHARPNESS_SIDE, FilterType::Triangle))
+    } else {
+        std::borrow::Cow::Borrowed(img)
+    };
+    let rgb = img.to_rgb8();
+    let (width, height) = (rgb.width() as usize, rgb.height() as usize);
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+    let values: Vec<f64> = rgb.pixels().map(|p| luma(p[0], p[1], p[2])).collect();
+    let mut laplacian = Vec::with_capacity((width - 2) * (height - 2));
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let at = |x: usize, y: usize| values[y * width + x];
+            laplacian.push(at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1) - 4.0 * at(x, y));
+        }
+    }
+    let mean = laplacian.iter().sum::<f64>() / laplacian.len() as f64;
+    laplacian.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / laplacian.len() as f64
+}
@@ -0,0 +1,359 @@
+// Animated images: every frame of a GIF or APNG, decoded onto the full
+// canvas with its delay, so frames can be processed independently and
+// written back out as GIF, APNG or animated WebP.
+
+use crate::error;
+use crate::limits::{self, Limits};
+use crate::vp8;
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::codecs::png::{CompressionType, FilterType, PngDecoder};
+use image::codecs::webp::WebPEncoder;
+use image::{AnimationDecoder, ColorType, Delay, DynamicImage, Frame, Frames, ImageDecoder, RgbaImage};
+use std::io::Cursor;
+use wasm_bindgen::prelude::*;
+
+// NeuQuant sampling factor for GIF palettes, 1 (best) to 30. The default of
+// 1 takes seconds per frame on a single WebAssembly thread.
+const GIF_SPEED: i32 = 10;
+
+// Times the GIF plays, 0 meaning forever. The NETSCAPE2.0 extension stores
+// repetitions after the first play; GIFs without it play once.
+fn read_loop_count(image_data: &[u8]) -> Result<u16, gif::DecodingError> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(Cursor::new(image_data))?;
+    // The extension precedes the first frame, so its header is far enough.
+    decoder.next_frame_info()?;
+    Ok(match decoder.repeat() {
+        gif::Repeat::Infinite => 0,
+        gif::Repeat::Finite(n) => n.saturating_add(1),
+    })
+}
+
+// Decodes frames one at a time, stopping once all of them together pass the
+// pixel limit, since a small file can hold thousands of full-canvas frames.
+fn collect_frames(frames: Frames, limits: &Limits) -> Result<Vec<Frame>, JsValue> {
+    let mut collected = Vec::new();
+    let mut pixels = 0u64;
+    for frame in frames {
+        let frame = frame.map_err(limits::decode_error)?;
+        let (width, height) = frame.buffer().dimensions();
+        pixels += width as u64 * height as u64;
+        if limits.max_pixels > 0 && pixels > limits.max_pixels {
+            return Err(error::size_limit(format!("Animation exceeds the limit of {} pixels across frames", limits.max_pixels)));
+        }
+        collected.push(frame);
+    }
+    Ok(collected)
+}
+
+fn decode_gif(image_data: &[u8], limits: &Limits) -> Result<(Vec<Frame>, u16), JsValue> {
+    let decoder = GifDecoder::new(Cursor::new(image_data)).map_err(limits::decode_error)?;
+    let (width, height) = decoder.dimensions();
+    limits.check_pixels(width, height)?;
+    let frames = collect_frames(decoder.into_frames(), limits)?;
+    Ok((frames, read_loop_count(image_data).map_err(error::decode_failed)?))
+}
+
+// Frames and play count of a PNG, 0 meaning forever. A PNG without an
+// animation control chunk is a single still frame.
+fn decode_png(image_data: &[u8], limits: &Limits) -> Result<(Vec<Frame>, u16), JsValue> {
+    let decoder = PngDecoder::new(Cursor::new(image_data)).map_err(limits::decode_error)?;
+    if !decoder.is_apng() {
+        let frame = crate::decode(image_data, limits)?.into_rgba8();
+        return Ok((vec![Frame::new(frame)], 0));
+    }
+    let (width, height) = decoder.dimensions();
+    limits.check_pixels(width, height)?;
+    let frames = collect_frames(decoder.apng().into_frames(), limits)?;
+    let reader = png::Decoder::new(Cursor::new(image_data)).read_info().map_err(error::decode_failed)?;
+    let plays = reader.info().animation_control().map_or(0, |control| control.num_plays);
+    Ok((frames, plays.min(u16::MAX as u32) as u16))
+}
+
+fn delay_ms(frame: &Frame) -> u32 {
+    let (numer, denom) = frame.delay().numer_denom_ms();
+    (numer as f64 / denom as f64).round() as u32
+}
+
+fn no_frames() -> JsValue {
+    error::invalid_argument("Animation has no frames")
+}
+
+pub(crate) fn push_u24(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes()[..3]);
+}
+
+pub(crate) fn push_chunk(out: &mut Vec<u8>, fourcc: &[u8; 4], payload: &[u8]) {
+    out.extend_from_slice(fourcc);
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(payload);
+    if payload.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+// The VP8L chunk, header included, of a lossless single-frame WebP: the
+// encoder writes it straight after the 12-byte RIFF header.
+fn encode_vp8l(pixels: &[u8], width: u32, height: u32) -> image::ImageResult<Vec<u8>> {
+    let mut file = Vec::new();
+    WebPEncoder::new_lossless(&mut file).encode(pixels, width, height, ColorType::Rgba8)?;
+    Ok(file.split_off(12))
+}
+
+#[wasm_bindgen]
+pub struct AnimatedImage {
+    frames: Vec<Frame>,
+    width: u32,
+    height: u32,
+    loop_count: u16,
+    near_lossless: bool,
+}
+
+#[wasm_bindgen]
+impl AnimatedImage {
+    // Decodes all frames of an animated (or still) GIF or PNG. The default
+    // ImageProcessor limits apply, with the pixel limit covering all frames
+    // together.
+    #[wasm_bindgen(constructor)]
+    pub fn new(image_data: &[u8]) -> Result<AnimatedImage, JsValue> {
+        let limits = Limits::default();
+        limits.check_input(image_data)?;
+        let (frames, loop_count) = if image_data.starts_with(b"GIF8") {
+            decode_gif(image_data, &limits)?
+        } else if image_data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            decode_png(image_data, &limits)?
+        } else {
+            return Err(error::unsupported_format("Unsupported animation format"));
+        };
+        let (width, height) = frames.first().ok_or_else(no_frames)?.buffer().dimensions();
+        Ok(AnimatedImage { frames, width, height, loop_count, near_lossless: false })
+    }
+
+    // An animation with no frames yet, to be filled with add_frame. It
+    // loops forever unless loop_count is set.
+    #[wasm_bindgen]
+    pub fn with_size(width: u32, height: u32) -> Result<AnimatedImage, JsValue> {
+        if width == 0 || height == 0 {
+            return Err(error::invalid_argument("Animation size must not be zero"));
+        }
+        Ok(AnimatedImage { frames: Vec::new(), width, height, loop_count: 0, near_lossless: false })
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    // Times to play the animation, 0 meaning forever.
+    #[wasm_bindgen(getter)]
+    pub fn loop_count(&self) -> u16 {
+        self.loop_count
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_loop_count(&mut self, loop_count: u16) {
+        self.loop_count = loop_count;
+    }
+
+    // Whether to_apng and to_webp below quality 100 are near-lossless, as
+    // with ImageProcessor's near_lossless. Off by default, when to_apng is
+    // lossless with its compression effort picked by quality and to_webp is
+    // lossy below 100.
+    #[wasm_bindgen(getter)]
+    pub fn near_lossless(&self) -> bool {
+        self.near_lossless
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_near_lossless(&mut self, near_lossless: bool) {
+        self.near_lossless = near_lossless;
+    }
+
+    // How long each frame is shown, in milliseconds.
+    #[wasm_bindgen]
+    pub fn delays(&self) -> Vec<u32> {
+        self.frames.iter().map(delay_ms).collect()
+    }
+
+    // RGBA pixels of frame `index`, width × height × 4 bytes.
+    #[wasm_bindgen]
+    pub fn frame(&self, index: usize) -> Result<Vec<u8>, JsValue> {
+        self.frames
+            .get(index)
+            .map(|frame| frame.buffer().as_raw().clone())
+            .ok_or_else(|| error::invalid_argument("Frame index out of range"))
+    }
+
+    fn frame_buffer(&self, rgba: &[u8]) -> Result<RgbaImage, JsValue> {
+        RgbaImage::from_raw(self.width, self.height, rgba.to_vec())
+            .ok_or_else(|| error::invalid_argument("Frame data does not match the image size"))
+    }
+
+    // Replaces the pixels of frame `index`, keeping its delay. `rgba` must
+    // match the current canvas size.
+    #[wasm_bindgen]
+    pub fn set_frame(&mut self, index: usize, rgba: &[u8]) -> Result<(), JsValue> {
+        let buffer = self.frame_buffer(rgba)?;
+        let frame = self.frames.get_mut(index).ok_or_else(|| error::invalid_argument("Frame index out of range"))?;
+        *frame = Frame::from_parts(buffer, 0, 0, frame.delay());
+        Ok(())
+    }
+
+    // Appends a canvas-sized RGBA frame shown for `delay_ms` milliseconds.
+    #[wasm_bindgen]
+    pub fn add_frame(&mut self, rgba: &[u8], delay_ms: u32) -> Result<(), JsValue> {
+        let buffer = self.frame_buffer(rgba)?;
+        self.frames.push(Frame::from_parts(buffer, 0, 0, Delay::from_numer_denom_ms(delay_ms, 1)));
+        Ok(())
+    }
+
+    // Resizes every frame to fit within `width` × `height`, keeping the
+    // aspect ratio, as resize_image does for still images.
+    #[wasm_bindgen]
+    pub fn resize(&mut self, width: u32, height: u32) {
+        for frame in &mut self.frames {
+            let delay = frame.delay();
+            let resized = DynamicImage::ImageRgba8(frame.buffer().clone())
+                .resize(width, height, image::imageops::FilterType::Lanczos3);
+            *frame = Frame::from_parts(resized.into_rgba8(), 0, 0, delay);
+        }
+        if let Some(frame) = self.frames.first() {
+            (self.width, self.height) = frame.buffer().dimensions();
+        }
+    }
+
+    // Encodes all frames as an animated GIF with their delays and the loop
+    // count.
+    #[wasm_bindgen]
+    pub fn to_gif(&self) -> Result<Vec<u8>, JsValue> {
+        if self.frames.is_empty() {
+            return Err(no_frames());
+        }
+        let mut buffer = Vec::new();
+        {
+            let mut encoder = GifEncoder::new_with_speed(&mut buffer, GIF_SPEED);
+            let repeat = if self.loop_count == 0 { Repeat::Infinite } else { Repeat::Finite(self.loop_count - 1) };
+            encoder
+                .set_repeat(repeat)
+                .and_then(|_| encoder.encode_frames(self.frames.iter().cloned()))
+                .map_err(|e| error::encode_failed("GIF", e))?;
+        }
+        Ok(buffer)
+    }
+
+    // Encodes all frames as an APNG with their delays and the loop count,
+    // compressed as PNG is for `quality`. With near_lossless set, frames
+    // below quality 100 are near-lossless and use the strongest compression,
+    // as PNG does in convert_format.
+    #[wasm_bindgen]
+    pub fn to_apng(&self, quality: u8) -> Result<Vec<u8>, JsValue> {
+        if self.frames.is_empty() {
+            return Err(no_frames());
+        }
+        let (width, height) = (self.width, self.height);
+        let bits = crate::near_lossless_bits(quality, self.near_lossless);
+        let encode_error = |e: png::EncodingError| error::encode_failed("APNG", e);
+
+        let mut buffer = Vec::new();
+        let mut encoder = png::Encoder::new(&mut buffer, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let (compression, filter) = crate::png_settings(quality, bits > 0);
+        encoder.set_compression(match compression {
+            CompressionType::Fast => png::Compression::Fast,
+            CompressionType::Best => png::Compression::Best,
+            _ => png::Compression::Default,
+        });
+        if filter == FilterType::Adaptive {
+            encoder.set_adaptive_filter(png::AdaptiveFilterType::Adaptive);
+        } else {
+            encoder.set_filter(png::FilterType::Sub);
+        }
+        encoder.set_animated(self.frames.len() as u32, self.loop_count as u32).map_err(encode_error)?;
+        encoder.set_blend_op(png::BlendOp::Source).map_err(encode_error)?;
+        encoder.set_dispose_op(png::DisposeOp::None).map_err(encode_error)?;
+
+        let mut writer = encoder.write_header().map_err(encode_error)?;
+        for frame in &self.frames {
+            // Delays are a fraction of a second with 16-bit terms, so
+            // milliseconds only fit below about 65 seconds.
+            let ms = delay_ms(frame);
+            let (numerator, denominator) = match u16::try_from(ms) {
+                Ok(ms) => (ms, 1000),
+                Err(_) => ((ms / 1000).min(u16::MAX as u32) as u16, 1),
+            };
+            writer.set_frame_delay(numerator, denominator).map_err(encode_error)?;
+            let mut pixels = frame.buffer().as_raw().clone();
+            if bits > 0 {
+                pixels = crate::near_lossless(&pixels, width, height, 4, bits);
+            }
+            writer.write_image_data(&pixels).map_err(encode_error)?;
+        }
+        writer.finish().map_err(encode_error)?;
+        Ok(buffer)
+    }
+
+    // Encodes all frames as an animated WebP with their delays and the loop
+    // count. Frames are lossy VP8 below quality 100, or near-lossless VP8L
+    // there with near_lossless set, and lossless VP8L at 100. Each one
+    // replaces the whole canvas.
+    #[wasm_bindgen]
+    pub fn to_webp(&self, quality: u8) -> Result<Vec<u8>, JsValue> {
+        if self.frames.is_empty() {
+            return Err(no_frames());
+        }
+        let (width, height) = (self.width, self.height);
+        if width > 16384 || height > 16384 {
+            return Err(error::size_limit("WebP images are limited to 16384 pixels per side"));
+        }
+        let bits = crate::near_lossless_bits(quality, self.near_lossless);
+        let has_alpha = self.frames.iter().any(|frame| frame.buffer().pixels().any(|p| p[3] < 255));
+
+        let mut vp8x = vec![if has_alpha { 0x12 } else { 0x02 }, 0, 0, 0]; // Animation, alpha
+        push_u24(&mut vp8x, width - 1);
+        push_u24(&mut vp8x, height - 1);
+        let mut anim = vec![0; 4]; // Transparent background
+        anim.extend_from_slice(&self.loop_count.to_le_bytes());
+
+        let mut body = b"WEBP".to_vec();
+        push_chunk(&mut body, b"VP8X", &vp8x);
+        push_chunk(&mut body, b"ANIM", &anim);
+        let lossy = quality < 100 && !self.near_lossless;
+        for frame in &self.frames {
+            let image = if lossy {
+                vp8::encode_chunks(frame.buffer().as_raw(), width, height, 4, quality)?.0
+            } else {
+                let mut pixels = frame.buffer().as_raw().clone();
+                if bits > 0 {
+                    pixels = crate::near_lossless(&pixels, width, height, 4, bits);
+                }
+                encode_vp8l(&pixels, width, height).map_err(|e| error::encode_failed("WebP", e))?
+            };
+
+            let mut anmf = Vec::with_capacity(16 + image.len());
+            push_u24(&mut anmf, 0); // Frame offset, in pairs of pixels
+            push_u24(&mut anmf, 0);
+            push_u24(&mut anmf, width - 1);
+            push_u24(&mut anmf, height - 1);
+            push_u24(&mut anmf, delay_ms(frame).min(0xFF_FFFF));
+            anmf.push(0x02); // No blending, no disposal
+            anmf.extend_from_slice(&image);
+            push_chunk(&mut body, b"ANMF", &anmf);
+        }
+
+        let mut buffer = Vec::with_capacity(8 + body.len());
+        push_chunk(&mut buffer, b"RIFF", &body);
+        Ok(buffer)
+    }
+}
@@ -0,0 +1,78 @@
+// Barcode reading with rxing, a Rust port of ZXing: QR, Micro QR, Data
+// Matrix, Aztec, PDF417 and MaxiCode symbols, and the EAN, UPC, Code 39,
+// 93 and 128, ITF, Codabar and GS1 DataBar linear codes. Formats are named
+// as the Barcode Detection API names them, so results can stand in for
+// BarcodeDetector's.
+
+use image::DynamicImage;
+use rxing::common::HybridBinarizer;
+use rxing::multi::{GenericMultipleBarcodeReader, MultipleBarcodeReader};
+use rxing::{BarcodeFormat, BinaryBitmap, DecodeHints, Luma8LuminanceSource, MultiUseMultiFormatReader};
+
+pub(crate) struct Barcode {
+    pub(crate) format: &'static str,
+    pub(crate) value: String,
+    // The points the detector located: the finder patterns of QR codes,
+    // the corners of other 2D symbols, and the ends of the scan line
+    // through linear codes.
+    pub(crate) points: Vec<(f32, f32)>,
+}
+
+impl Barcode {
+    // The smallest axis-aligned box around the points, as (x, y, width,
+    // height). Linear codes found on a single scan line have no height.
+    pub(crate) fn bounds(&self) -> (f32, f32, f32, f32) {
+        let min_x = self.points.iter().map(|p| p.0).fold(f32::INFINITY, f32::min);
+        let min_y = self.points.iter().map(|p| p.1).fold(f32::INFINITY, f32::min);
+        let max_x = self.points.iter().map(|p| p.0).fold(f32::NEG_INFINITY, f32::max);
+        let max_y = self.points.iter().map(|p| p.1).fold(f32::NEG_INFINITY, f32::max);
+        if self.points.is_empty() { (0.0, 0.0, 0.0, 0.0) } else { (min_x, min_y, max_x - min_x, max_y - min_y) }
+    }
+}
+
+fn format_name(format: &BarcodeFormat) -> &'static str {
+    match format {
+        BarcodeFormat::AZTEC => "aztec",
+        BarcodeFormat::CODABAR => "codabar",
+        BarcodeFormat::CODE_39 => "code_39",
+        BarcodeFormat::CODE_93 => "code_93",
+        BarcodeFormat::CODE_128 => "code_128",
+        BarcodeFormat::DATA_MATRIX => "data_matrix",
+        BarcodeFormat::EAN_8 => "ean_8",
+        BarcodeFormat::EAN_13 => "ean_13",
+        BarcodeFormat::ITF => "itf",
+        BarcodeFormat::MAXICODE => "maxi_code",
+        BarcodeFormat::PDF_417 => "pdf417",
+        BarcodeFormat::QR_CODE => "qr_code",
+        BarcodeFormat::MICRO_QR_CODE => "micro_qr_code",
+        BarcodeFormat::RECTANGULAR_MICRO_QR_CODE => "rm_qr_code",
+        BarcodeFormat::RSS_14 => "databar",
+        BarcodeFormat::RSS_EXPANDED => "databar_expanded",
+        BarcodeFormat::UPC_A => "upc_a",
+        BarcodeFormat::UPC_E => "upc_e",
+        _ => "unknown",
+    }
+}
+
+// Every symbol found in the image, trying harder than the default for
+// rotated and low contrast codes. An image without any gives none.
+pub(crate) fn decode(img: &DynamicImage) -> Vec<Barcode> {
+    let luma = img.to_luma8();
+    let (width, height) = luma.dimensions();
+    let Ok(source) = Luma8LuminanceSource::new(luma.into_raw(), width, height) else {
+        return Vec::new();
+    };
+    let mut bitmap = BinaryBitmap::new(HybridBinarizer::new(source));
+    let hints = DecodeHints { TryHarder: Some(true), ..DecodeHints::default() };
+    let mut reader = GenericMultipleBarcodeReader::new(MultiUseMultiFormatReader::default());
+    // rxing reports finding nothing as an error.
+    let results = reader.decode_multiple_with_hints(&mut bitmap, &hints).unwrap_or_default();
+    results
+        .iter()
+        .map(|result| Barcode {
+            format: format_name(result.getBarcodeFormat()),
+            value: result.getText().to_string(),
+            points: result.getPoints().iter().map(|point| (point.x, point.y)).collect(),
+        })
+        .collect()
+}
@@ -0,0 +1,78 @@
+// Collages: several images laid out in equal cells of a grid.
+
+use crate::{error, transform};
+use image::{imageops, DynamicImage, Rgba, RgbaImage};
+use js_sys::{Reflect, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+// One grid entry: encoded image data and the fit mode overriding the grid's.
+pub(crate) struct Entry {
+    pub(crate) data: Vec<u8>,
+    pub(crate) fit: Option<String>,
+}
+
+// Reads `images` as an Array of Uint8Arrays or `{ data, fit? }` objects.
+pub(crate) fn parse_entries(images: &js_sys::Array) -> Result<Vec<Entry>, JsValue> {
+    if images.length() == 0 {
+        return Err(error::invalid_argument("A grid needs at least one image"));
+    }
+    images
+        .iter()
+        .map(|value| {
+            if let Some(data) = value.dyn_ref::<Uint8Array>() {
+                return Ok(Entry { data: data.to_vec(), fit: None });
+            }
+            let invalid = || error::invalid_argument("Grid images must be Uint8Arrays or { data, fit? } objects");
+            if !value.is_object() {
+                return Err(invalid());
+            }
+            let data = Reflect::get(&value, &"data".into())?.dyn_into::<Uint8Array>().map_err(|_| invalid())?;
+            let fit = Reflect::get(&value, &"fit".into())?;
+            let fit = if fit.is_undefined() || fit.is_null() { None } else { Some(fit.as_string().ok_or_else(invalid)?) };
+            Ok(Entry { data: data.to_vec(), fit })
+        })
+        .collect()
+}
+
+// The size of a grid of `count` cells, `columns` wide, with `gap` pixels
+// between cells.
+pub(crate) fn grid_size(count: usize, columns: u32, cell: (u32, u32), gap: u32) -> Result<(u32, u32), JsValue> {
+    if columns == 0 {
+        return Err(error::invalid_argument("A grid needs at least one column"));
+    }
+    let rows = (count as u64).div_ceil(columns as u64);
+    let side = |cells: u64, length: u32| cells * length as u64 + cells.saturating_sub(1) * gap as u64;
+    let (width, height) = (side(columns as u64, cell.0), side(rows, cell.1));
+    if width > u32::MAX as u64 || height > u32::MAX as u64 {
+        return Err(error::size_limit("Grid is too large"));
+    }
+    Ok((width as u32, height as u32))
+}
+
+// Fits each image into its `cell` with its own fit mode or `fit`, row by
+// row, centered and clipped to the cell, on `background`.
+pub(crate) fn compose(
+    images: &[(DynamicImage, Option<String>)],
+    columns: u32,
+    cell: (u32, u32),
+    gap: u32,
+    background: Rgba<u8>,
+    fit: &str,
+) -> Result<DynamicImage, JsValue> {
+    let (width, height) = grid_size(images.len(), columns, cell, gap)?;
+    let mut canvas = RgbaImage::from_pixel(width, height, background);
+    for (index, (img, own_fit)) in images.iter().enumerate() {
+        let fitted = transform::fit(img, cell.0, cell.1, own_fit.as_deref().unwrap_or(fit), background)?;
+        // "outside" can overflow the cell; keep its center.
+        let (w, h) = (fitted.width().min(cell.0), fitted.height().min(cell.1));
+        let fitted = fitted.crop_imm((fitted.width() - w) / 2, (fitted.height() - h) / 2, w, h);
+        let column = index as u32 % columns;
+        let row = index as u32 / columns;
+        let x = column * (cell.0 + gap) + (cell.0 - w) / 2;
+        let y = row * (cell.1 + gap) + (cell.1 - h) / 2;
+        imageops::overlay(&mut canvas, &fitted.to_rgba8(), x as i64, y as i64);
+    }
+    let opaque = background[3] == 255 && images.iter().all(|(img, _)| !img.color().has_alpha());
+    Ok(if opaque { DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(canvas).to_rgb8()) } else { DynamicImage::ImageRgba8(canvas) })
+}
@@ -0,0 +1,61 @@
+// Color vision deficiency simulation with the matrices of Machado, Oliveira
+// and Fernandes (2009), applied in linear RGB. Partial severities blend the
+// full matrix with the identity.
+
+use crate::error;
+use crate::styles::map_rgb;
+use image::DynamicImage;
+use wasm_bindgen::prelude::*;
+
+const PROTANOPIA: [[f32; 3]; 3] = [
+    [0.152286, 1.052583, -0.204868],
+    [0.114503, 0.786281, 0.099216],
+    [-0.003882, -0.048116, 1.051998],
+];
+
+const DEUTERANOPIA: [[f32; 3]; 3] = [
+    [0.367322, 0.860646, -0.227968],
+    [0.280085, 0.672501, 0.047413],
+    [-0.011820, 0.042940, 0.968881],
+];
+
+const TRITANOPIA: [[f32; 3]; 3] = [
+    [1.255528, -0.076749, -0.178779],
+    [-0.078411, 0.930809, 0.147602],
+    [0.004733, 0.691367, 0.303900],
+];
+
+fn to_linear(v: f32) -> f32 {
+    let v = v / 255.0;
+    if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+}
+
+fn to_srgb(v: f32) -> f32 {
+    let v = v.clamp(0.0, 1.0);
+    255.0 * if v <= 0.0031308 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 }
+}
+
+// Shows `img` as seen with `deficiency` "protanopia", "deuteranopia" or
+// "tritanopia" at `severity` (0–1).
+pub(crate) fn simulate(img: &DynamicImage, deficiency: &str, severity: f32) -> Result<DynamicImage, JsValue> {
+    let matrix = match deficiency.to_lowercase().as_str() {
+        "protanopia" => PROTANOPIA,
+        "deuteranopia" => DEUTERANOPIA,
+        "tritanopia" => TRITANOPIA,
+        _ => return Err(error::invalid_argument("Unsupported color vision deficiency")),
+    };
+    if !(0.0..=1.0).contains(&severity) {
+        return Err(error::invalid_argument("Severity must be between 0 and 1"));
+    }
+    let blended: [[f32; 3]; 3] = std::array::from_fn(|row| {
+        std::array::from_fn(|col| {
+            let identity = if row == col { 1.0 } else { 0.0 };
+            identity + (matrix[row][col] - identity) * severity
+        })
+    });
+    let linear: Vec<f32> = (0..256).map(|v| to_linear(v as f32)).collect();
+    Ok(map_rgb(img, |_, _, rgb| {
+        let rgb = rgb.map(|v| linear[v as usize]);
+        std::array::from_fn(|row| to_srgb((0..3).map(|k| blended[row][k] * rgb[k]).sum()))
+    }))
+}
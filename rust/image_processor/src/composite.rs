@@ -0,0 +1,118 @@
+// Drawing one image over another, for watermarks and logos.
+
+use crate::error;
+use image::{DynamicImage, RgbaImage};
+use wasm_bindgen::prelude::*;
+
+#[derive(Clone, Copy)]
+enum BlendMode {
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Darken,
+    Lighten,
+    Difference,
+    Add,
+}
+
+fn parse_blend_mode(mode: &str) -> Result<BlendMode, JsValue> {
+    match mode.to_lowercase().as_str() {
+        "normal" | "" => Ok(BlendMode::Normal),
+        "multiply" => Ok(BlendMode::Multiply),
+        "screen" => Ok(BlendMode::Screen),
+        "overlay" => Ok(BlendMode::Overlay),
+        "darken" => Ok(BlendMode::Darken),
+        "lighten" => Ok(BlendMode::Lighten),
+        "difference" => Ok(BlendMode::Difference),
+        "add" => Ok(BlendMode::Add),
+        _ => Err(error::invalid_argument("Unsupported blend mode")),
+    }
+}
+
+// Blended color of backdrop `b` and source `s`, both 0–1.
+fn blend(mode: BlendMode, b: f32, s: f32) -> f32 {
+    match mode {
+        BlendMode::Normal => s,
+        BlendMode::Multiply => b * s,
+        BlendMode::Screen => b + s - b * s,
+        BlendMode::Overlay if b <= 0.5 => 2.0 * b * s,
+        BlendMode::Overlay => 1.0 - 2.0 * (1.0 - b) * (1.0 - s),
+        BlendMode::Darken => b.min(s),
+        BlendMode::Lighten => b.max(s),
+        BlendMode::Difference => (b - s).abs(),
+        BlendMode::Add => (b + s).min(1.0),
+    }
+}
+
+// Top-left corner of an `overlay`-sized box at `position` inside `base`,
+// `margin` pixels in from the edges it is anchored to.
+pub(crate) fn preset_position(
+    position: &str,
+    margin: u32,
+    base: (u32, u32),
+    overlay: (u32, u32),
+) -> Result<(i64, i64), JsValue> {
+    let (vertical, horizontal) = match position.to_lowercase().as_str() {
+        "top-left" => ("top", "left"),
+        "top" => ("top", "center"),
+        "top-right" => ("top", "right"),
+        "left" => ("center", "left"),
+        "center" | "centre" => ("center", "center"),
+        "right" => ("center", "right"),
+        "bottom-left" => ("bottom", "left"),
+        "bottom" => ("bottom", "center"),
+        "bottom-right" => ("bottom", "right"),
+        _ => return Err(error::invalid_argument("Unsupported position")),
+    };
+    let place = |anchor: &str, base: u32, size: u32| match anchor {
+        "top" | "left" => margin as i64,
+        "bottom" | "right" => base as i64 - size as i64 - margin as i64,
+        _ => (base as i64 - size as i64) / 2,
+    };
+    Ok((place(horizontal, base.0, overlay.0), place(vertical, base.1, overlay.1)))
+}
+
+// Draws `overlay` over `base` with its top-left corner at `x`, `y`, scaled
+// in alpha by `opacity` (0–1), following the W3C compositing model:
+// `blend_mode` mixes colors where both images are opaque and plain
+// source-over applies elsewhere. Parts outside `base` are clipped.
+pub(crate) fn composite(
+    base: &DynamicImage,
+    overlay: &DynamicImage,
+    x: i64,
+    y: i64,
+    opacity: f32,
+    blend_mode: &str,
+) -> Result<DynamicImage, JsValue> {
+    if !(0.0..=1.0).contains(&opacity) {
+        return Err(error::invalid_argument("Opacity must be between 0 and 1"));
+    }
+    let mode = parse_blend_mode(blend_mode)?;
+    let mut output: RgbaImage = base.to_rgba8();
+    let overlay = overlay.to_rgba8();
+
+    for (ox, oy, source) in overlay.enumerate_pixels() {
+        let (bx, by) = (x + ox as i64, y + oy as i64);
+        if bx < 0 || by < 0 || bx >= output.width() as i64 || by >= output.height() as i64 {
+            continue;
+        }
+        let backdrop = output.get_pixel_mut(bx as u32, by as u32);
+        let alpha_s = source[3] as f32 / 255.0 * opacity;
+        if alpha_s == 0.0 {
+            continue;
+        }
+        let alpha_b = backdrop[3] as f32 / 255.0;
+        let alpha = alpha_s + alpha_b * (1.0 - alpha_s);
+        for c in 0..3 {
+            let (cb, cs) = (backdrop[c] as f32 / 255.0, source[c] as f32 / 255.0);
+            let mixed = (1.0 - alpha_b) * cs + alpha_b * blend(mode, cb, cs);
+            let value = (alpha_s * mixed + (1.0 - alpha_s) * alpha_b * cb) / alpha;
+            backdrop[c] = (value * 255.0).round().clamp(0.0, 255.0) as u8;
+        }
+        backdrop[3] = (alpha * 255.0).round() as u8;
+    }
+
+    let output = DynamicImage::ImageRgba8(output);
+    Ok(if base.color().has_alpha() { output } else { DynamicImage::ImageRgb8(output.to_rgb8()) })
+}
@@ -0,0 +1,159 @@
+// Borders with solid or gradient fills and soft drop shadows, both growing
+// the canvas around the image.
+
+use crate::{error, filter, transform};
+use image::{imageops, DynamicImage, Rgba, RgbaImage};
+use js_sys::Reflect;
+use wasm_bindgen::prelude::*;
+
+fn property(options: &JsValue, key: &str) -> Result<Option<JsValue>, JsValue> {
+    if options.is_undefined() || options.is_null() {
+        return Ok(None);
+    }
+    let value = Reflect::get(options, &key.into())?;
+    Ok((!value.is_undefined() && !value.is_null()).then_some(value))
+}
+
+fn number(options: &JsValue, key: &str, default: f64) -> Result<f64, JsValue> {
+    match property(options, key)? {
+        Some(value) => value.as_f64().filter(|v| v.is_finite()).ok_or_else(|| error::invalid_argument(format!("Option `{}` must be a number", key))),
+        None => Ok(default),
+    }
+}
+
+fn color(options: &JsValue, key: &str, default: &str) -> Result<Rgba<u8>, JsValue> {
+    match property(options, key)? {
+        Some(value) => {
+            let value = value.as_string().ok_or_else(|| error::invalid_argument(format!("Option `{}` must be a color string", key)))?;
+            transform::parse_color(&value)
+        }
+        None => transform::parse_color(default),
+    }
+}
+
+// A side length in pixels, 0 to 10000.
+fn side(options: &JsValue, key: &str, default: f64) -> Result<u32, JsValue> {
+    let value = number(options, key, default)?;
+    if !(0.0..=10000.0).contains(&value) {
+        return Err(error::invalid_argument(format!("Option `{}` must be between 0 and 10000", key)));
+    }
+    Ok(value.round() as u32)
+}
+
+pub(crate) struct BorderOptions {
+    top: u32,
+    right: u32,
+    bottom: u32,
+    left: u32,
+    color: Rgba<u8>,
+    gradient_to: Option<Rgba<u8>>,
+    // CSS linear-gradient angle: 0 points up, 90 right, 180 down.
+    gradient_angle: f64,
+}
+
+impl BorderOptions {
+    // Reads `{ width?, top?, right?, bottom?, left?, color?, gradientTo?,
+    // gradientAngle? }`, where the sides default to `width` (20).
+    pub(crate) fn from_js(options: &JsValue) -> Result<BorderOptions, JsValue> {
+        let width = number(options, "width", 20.0)?;
+        Ok(BorderOptions {
+            top: side(options, "top", width)?,
+            right: side(options, "right", width)?,
+            bottom: side(options, "bottom", width)?,
+            left: side(options, "left", width)?,
+            color: color(options, "color", "#ffffff")?,
+            gradient_to: property(options, "gradientTo")?.map(|_| color(options, "gradientTo", "")).transpose()?,
+            gradient_angle: number(options, "gradientAngle", 180.0)?,
+        })
+    }
+}
+
+// The color at `x`, `y` of a CSS-style linear gradient over the canvas.
+fn gradient(from: Rgba<u8>, to: Rgba<u8>, angle: f64, width: u32, height: u32) -> impl Fn(u32, u32) -> Rgba<u8> {
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let (dx, dy) = (sin, -cos);
+    // Half the gradient line's length, so it reaches the far corners.
+    let half = (width as f64 * dx.abs() + height as f64 * dy.abs()) / 2.0;
+    move |x, y| {
+        let (px, py) = (x as f64 + 0.5 - width as f64 / 2.0, y as f64 + 0.5 - height as f64 / 2.0);
+        let t = if half > 0.0 { ((px * dx + py * dy) / half + 1.0) / 2.0 } else { 0.0 }.clamp(0.0, 1.0);
+        Rgba(std::array::from_fn(|c| (from[c] as f64 + (to[c] as f64 - from[c] as f64) * t).round() as u8))
+    }
+}
+
+pub(crate) fn border(img: &DynamicImage, options: &BorderOptions) -> DynamicImage {
+    let width = img.width() + options.left + options.right;
+    let height = img.height() + options.top + options.bottom;
+    let mut canvas = match options.gradient_to {
+        Some(to) => {
+            let fill = gradient(options.color, to, options.gradient_angle, width, height);
+            RgbaImage::from_fn(width, height, fill)
+        }
+        None => RgbaImage::from_pixel(width, height, options.color),
+    };
+    imageops::overlay(&mut canvas, &img.to_rgba8(), options.left as i64, options.top as i64);
+    DynamicImage::ImageRgba8(canvas)
+}
+
+pub(crate) struct ShadowOptions {
+    offset_x: i64,
+    offset_y: i64,
+    blur: f32,
+    color: Rgba<u8>,
+    background: Rgba<u8>,
+}
+
+impl ShadowOptions {
+    // Reads `{ offsetX?, offsetY?, blur?, color?, background? }`: the offset
+    // in pixels (default 0, 8), the blur's sigma (8), the shadow color
+    // ("#00000080") and the canvas background ("transparent").
+    pub(crate) fn from_js(options: &JsValue) -> Result<ShadowOptions, JsValue> {
+        let offset = |key: &str, default: f64| -> Result<i64, JsValue> {
+            let value = number(options, key, default)?;
+            if value.abs() > 10000.0 {
+                return Err(error::invalid_argument(format!("Option `{}` must be between -10000 and 10000", key)));
+            }
+            Ok(value.round() as i64)
+        };
+        let blur = number(options, "blur", 8.0)?;
+        if !(0.0..=100.0).contains(&blur) {
+            return Err(error::invalid_argument("Option `blur` must be between 0 and 100"));
+        }
+        Ok(ShadowOptions {
+            offset_x: offset("offsetX", 0.0)?,
+            offset_y: offset("offsetY", 8.0)?,
+            blur: blur as f32,
+            color: color(options, "color", "#00000080")?,
+            background: color(options, "background", "transparent")?,
+        })
+    }
+}
+
+// The image over its silhouette in the shadow color, offset and blurred,
+// on a canvas grown to hold the whole shadow.
+pub(crate) fn drop_shadow(img: &DynamicImage, options: &ShadowOptions) -> Result<DynamicImage, JsValue> {
+    let reach = (options.blur * 3.0).ceil() as i64;
+    let left = (reach - options.offset_x).max(0);
+    let top = (reach - options.offset_y).max(0);
+    let right = (reach + options.offset_x).max(0);
+    let bottom = (reach + options.offset_y).max(0);
+    let (width, height) = (img.width() as i64 + left + right, img.height() as i64 + top + bottom);
+
+    let rgba = img.to_rgba8();
+    let mut shadow = RgbaImage::new(width as u32, height as u32);
+    for (x, y, pixel) in rgba.enumerate_pixels() {
+        let alpha = (options.color[3] as u32 * pixel[3] as u32 + 127) / 255;
+        let (sx, sy) = (x as i64 + left + options.offset_x, y as i64 + top + options.offset_y);
+        shadow.put_pixel(sx as u32, sy as u32, Rgba([options.color[0], options.color[1], options.color[2], alpha as u8]));
+    }
+    let shadow = if options.blur > 0.0 {
+        filter::gaussian_blur(&DynamicImage::ImageRgba8(shadow), options.blur)?.to_rgba8()
+    } else {
+        shadow
+    };
+
+    let mut canvas = RgbaImage::from_pixel(width as u32, height as u32, options.background);
+    imageops::overlay(&mut canvas, &shadow, 0, 0);
+    imageops::overlay(&mut canvas, &rgba, left, top);
+    Ok(DynamicImage::ImageRgba8(canvas))
+}
@@ -0,0 +1,160 @@
+// High bit depth input: tone mapping linear floating point images (OpenEXR,
+// Radiance HDR, float TIFF) down to display range on decoding, and reducing
+// 16-bit images to 8 bits, dithered, for outputs that cannot store more.
+
+use crate::{error, quantize};
+use image::{DynamicImage, ImageBuffer, Rgba};
+use js_sys::Reflect;
+use wasm_bindgen::prelude::*;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum ToneMap {
+    // Clamps to 0–1, for scene values already in display range.
+    Clip,
+    // x / (1 + x), which keeps shadows and compresses highlights smoothly.
+    Reinhard,
+    // Narkowicz's fit of the ACES filmic curve, with more contrast.
+    Aces,
+}
+
+fn parse_tone_map(operator: &str) -> Result<ToneMap, JsValue> {
+    match operator.to_lowercase().as_str() {
+        "clip" => Ok(ToneMap::Clip),
+        "reinhard" => Ok(ToneMap::Reinhard),
+        "aces" | "" => Ok(ToneMap::Aces),
+        _ => Err(error::invalid_argument("Unsupported tone mapping operator")),
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct DepthOptions {
+    tone_map: ToneMap,
+    // Exposure adjustment in stops, applied before tone mapping.
+    exposure: f32,
+    dither: bool,
+    keep_16bit: bool,
+}
+
+impl Default for DepthOptions {
+    fn default() -> DepthOptions {
+        DepthOptions { tone_map: ToneMap::Aces, exposure: 0.0, dither: true, keep_16bit: true }
+    }
+}
+
+impl DepthOptions {
+    // Reads `{ toneMapping?, exposure?, dither?, keep16Bit? }`; see
+    // ImageProcessor.depth_options.
+    pub(crate) fn from_js(options: &JsValue) -> Result<DepthOptions, JsValue> {
+        let defaults = DepthOptions::default();
+        if options.is_undefined() || options.is_null() {
+            return Ok(defaults);
+        }
+        let get = |key: &str| -> Result<Option<JsValue>, JsValue> {
+            let value = Reflect::get(options, &key.into())?;
+            Ok((!value.is_undefined() && !value.is_null()).then_some(value))
+        };
+        let boolean = |key: &str, default: bool| -> Result<bool, JsValue> {
+            match get(key)? {
+                Some(value) => value.as_bool().ok_or_else(|| error::invalid_argument(format!("Option `{}` must be a boolean", key))),
+                None => Ok(default),
+            }
+        };
+        let tone_map = match get("toneMapping")? {
+            Some(value) => parse_tone_map(&value.as_string().ok_or_else(|| error::invalid_argument("Option `toneMapping` must be a string"))?)?,
+            None => defaults.tone_map,
+        };
+        let exposure = match get("exposure")? {
+            Some(value) => value
+                .as_f64()
+                .filter(|v| (-20.0..=20.0).contains(v))
+                .ok_or_else(|| error::invalid_argument("Option `exposure` must be between -20 and 20"))? as f32,
+            None => defaults.exposure,
+        };
+        Ok(DepthOptions { tone_map, exposure, dither: boolean("dither", defaults.dither)?, keep_16bit: boolean("keep16Bit", defaults.keep_16bit)? })
+    }
+
+    pub(crate) fn keep_16bit(&self) -> bool {
+        self.keep_16bit
+    }
+
+    pub(crate) fn dither(&self) -> bool {
+        self.dither
+    }
+}
+
+pub(crate) fn is_16bit(img: &DynamicImage) -> bool {
+    matches!(
+        img,
+        DynamicImage::ImageLuma16(_) | DynamicImage::ImageLumaA16(_) | DynamicImage::ImageRgb16(_) | DynamicImage::ImageRgba16(_)
+    )
+}
+
+fn is_float(img: &DynamicImage) -> bool {
+    matches!(img, DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_))
+}
+
+// The sRGB transfer function, from linear light to encoded, both 0–1.
+pub(crate) fn srgb_encode(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Maps a linear floating point image, as OpenEXR and Radiance HDR decode
+// to, into display range and sRGB with `options`, as a 16-bit image so the
+// dynamic range compressed into it keeps its gradations. Other images are
+// returned as they are.
+pub(crate) fn tone_map(img: DynamicImage, options: &DepthOptions) -> DynamicImage {
+    if !is_float(&img) {
+        return img;
+    }
+    let has_alpha = img.color().has_alpha();
+    let scale = 2f32.powf(options.exposure);
+    let curve = |v: f32| {
+        let v = (v * scale).max(0.0);
+        let mapped = match options.tone_map {
+            ToneMap::Clip => v,
+            ToneMap::Reinhard => v / (1.0 + v),
+            ToneMap::Aces => {
+                let v = v * 0.6;
+                (v * (2.51 * v + 0.03)) / (v * (2.43 * v + 0.59) + 0.14)
+            }
+        };
+        (srgb_encode(mapped.clamp(0.0, 1.0)) * 65535.0).round() as u16
+    };
+    let rgba = img.into_rgba32f();
+    let mapped: ImageBuffer<Rgba<u16>, Vec<u16>> = ImageBuffer::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        Rgba([curve(r), curve(g), curve(b), (a.clamp(0.0, 1.0) * 65535.0).round() as u16])
+    });
+    let mapped = DynamicImage::ImageRgba16(mapped);
+    if has_alpha { mapped } else { DynamicImage::ImageRgb16(mapped.into_rgb16()) }
+}
+
+// Reduces a 16-bit image to 8 bits, with an ordered dither of up to half a
+// level either way if `dither` is set, which hides the banding plain
+// rounding leaves in smooth gradients. Alpha is rounded. Other images are
+// returned as they are.
+pub(crate) fn to_8bit(img: &DynamicImage, dither: bool) -> DynamicImage {
+    if !is_16bit(img) {
+        return img.clone();
+    }
+    let has_alpha = img.color().has_alpha();
+    let gray = !img.color().has_color();
+    let rgba = img.to_rgba16();
+    let reduced = image::RgbaImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let offset = if dither { (quantize::BAYER[y as usize % 8][x as usize % 8] as f32 + 0.5) / 64.0 - 0.5 } else { 0.0 };
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let level = |v: u16| (v as f32 / 257.0 + offset).round().clamp(0.0, 255.0) as u8;
+        Rgba([level(r), level(g), level(b), (a as f32 / 257.0).round() as u8])
+    });
+    let reduced = DynamicImage::ImageRgba8(reduced);
+    match (gray, has_alpha) {
+        (true, false) => DynamicImage::ImageLuma8(reduced.into_luma8()),
+        (true, true) => DynamicImage::ImageLumaA8(reduced.into_luma_alpha8()),
+        (false, false) => DynamicImage::ImageRgb8(reduced.into_rgb8()),
+        (false, true) => reduced,
+    }
+}
@@ -0,0 +1,122 @@
+// One-shot enhancement for the editor's "Enhance" button: gray-world white
+// balance, then a levels stretch clipped at a percentile at each end, so a
+// few specular highlights or black borders do not hold the range back.
+// Statistics skip fully transparent pixels; alpha is left alone.
+
+use crate::{error, styles};
+use image::DynamicImage;
+use js_sys::Reflect;
+use wasm_bindgen::prelude::*;
+
+// Largest gain white balance gives a channel, either way, so that scenes
+// that really are mostly one color are not turned gray.
+const MAX_GAIN: f32 = 2.0;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Stretch {
+    None,
+    // One range for all three channels, which keeps hues.
+    Contrast,
+    // A range per channel, which also removes color casts.
+    Levels,
+}
+
+fn property(options: &JsValue, key: &str) -> Result<Option<JsValue>, JsValue> {
+    if options.is_undefined() || options.is_null() {
+        return Ok(None);
+    }
+    let value = Reflect::get(options, &key.into())?;
+    Ok((!value.is_undefined() && !value.is_null()).then_some(value))
+}
+
+pub(crate) struct EnhanceOptions {
+    white_balance: bool,
+    stretch: Stretch,
+    clip: f32,
+}
+
+impl EnhanceOptions {
+    // Reads `{ whiteBalance?, stretch?, clip? }`: gray-world white balance
+    // (true), the stretch as "contrast" (the default), "levels" or "none",
+    // and the percentage of pixels clipped at each end of it (0.5, up to 10).
+    pub(crate) fn from_js(options: &JsValue) -> Result<EnhanceOptions, JsValue> {
+        let white_balance = match property(options, "whiteBalance")? {
+            Some(value) => value.as_bool().ok_or_else(|| error::invalid_argument("Option `whiteBalance` must be a boolean"))?,
+            None => true,
+        };
+        let stretch = match property(options, "stretch")?.map(|value| value.as_string()) {
+            None => Stretch::Contrast,
+            Some(Some(stretch)) if stretch == "contrast" => Stretch::Contrast,
+            Some(Some(stretch)) if stretch == "levels" => Stretch::Levels,
+            Some(Some(stretch)) if stretch == "none" => Stretch::None,
+            Some(_) => return Err(error::invalid_argument("Option `stretch` must be \"contrast\", \"levels\" or \"none\"")),
+        };
+        let clip = match property(options, "clip")? {
+            Some(value) => value.as_f64().filter(|v| v.is_finite()).ok_or_else(|| error::invalid_argument("Option `clip` must be a number"))?,
+            None => 0.5,
+        };
+        if !(0.0..=10.0).contains(&clip) {
+            return Err(error::invalid_argument("Option `clip` must be between 0 and 10"));
+        }
+        Ok(EnhanceOptions { white_balance, stretch, clip: clip as f32 })
+    }
+}
+
+// Gains that bring the mean of each channel to the mean of all three.
+fn gray_world(pixels: &[[u8; 3]]) -> [f32; 3] {
+    if pixels.is_empty() {
+        return [1.0; 3];
+    }
+    let means: [f32; 3] = std::array::from_fn(|c| pixels.iter().map(|p| p[c] as f64).sum::<f64>() as f32 / pixels.len() as f32);
+    let gray = means.iter().sum::<f32>() / 3.0;
+    means.map(|mean| if mean > 0.0 { (gray / mean).clamp(1.0 / MAX_GAIN, MAX_GAIN) } else { 1.0 })
+}
+
+// The levels below and above which `clip` percent of `histogram` lies.
+fn clipped_range(histogram: &[u64; 256], clip: f32) -> (f32, f32) {
+    let total: u64 = histogram.iter().sum();
+    let limit = (total as f64 * clip as f64 / 100.0) as u64;
+    let mut seen = 0;
+    let low = (0..256).find(|&level| {
+        seen += histogram[level];
+        seen > limit
+    });
+    seen = 0;
+    let high = (0..256).rev().find(|&level| {
+        seen += histogram[level];
+        seen > limit
+    });
+    match (low, high) {
+        (Some(low), Some(high)) if high > low => (low as f32, high as f32),
+        _ => (0.0, 255.0),
+    }
+}
+
+pub(crate) fn auto_enhance(img: &DynamicImage, options: &EnhanceOptions) -> DynamicImage {
+    let rgba = img.to_rgba8();
+    let visible: Vec<[u8; 3]> = rgba.pixels().filter(|p| p[3] > 0).map(|p| [p[0], p[1], p[2]]).collect();
+    let gains = if options.white_balance { gray_world(&visible) } else { [1.0; 3] };
+    let balance = |rgb: [u8; 3]| -> [f32; 3] { std::array::from_fn(|c| (rgb[c] as f32 * gains[c]).clamp(0.0, 255.0)) };
+
+    let mut histograms = [[0u64; 256]; 3];
+    for &rgb in &visible {
+        for (histogram, value) in histograms.iter_mut().zip(balance(rgb)) {
+            histogram[value.round() as usize] += 1;
+        }
+    }
+    let ranges: [(f32, f32); 3] = match options.stretch {
+        Stretch::None => [(0.0, 255.0); 3],
+        Stretch::Levels => histograms.map(|histogram| clipped_range(&histogram, options.clip)),
+        Stretch::Contrast => {
+            let combined: [u64; 256] = std::array::from_fn(|level| histograms.iter().map(|h| h[level]).sum());
+            [clipped_range(&combined, options.clip); 3]
+        }
+    };
+    styles::map_rgb(img, |_, _, rgb| {
+        let balanced = balance(rgb.map(|c| c as u8));
+        std::array::from_fn(|c| {
+            let (low, high) = ranges[c];
+            (balanced[c] - low) * 255.0 / (high - low)
+        })
+    })
+}
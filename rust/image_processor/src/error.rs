@@ -0,0 +1,105 @@
+// Errors thrown to JS. Every failure is an ImageError with a stable `code`
+// to branch on, a human-readable `message`, and the underlying library
+// error, if any, as `detail`.
+
+use js_sys::{Object, Reflect};
+use std::fmt::Display;
+use wasm_bindgen::prelude::*;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum ErrorCode {
+    // The input could not be decoded.
+    DecodeFailed,
+    // The encoder rejected the image.
+    EncodeFailed,
+    // The input or requested output format is not supported.
+    UnsupportedFormat,
+    // An argument or option has an invalid value.
+    InvalidArgument,
+    // The image is larger than a format or a configured limit allows.
+    SizeLimit,
+}
+
+impl ErrorCode {
+    fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::DecodeFailed => "decode_failed",
+            ErrorCode::EncodeFailed => "encode_failed",
+            ErrorCode::UnsupportedFormat => "unsupported_format",
+            ErrorCode::InvalidArgument => "invalid_argument",
+            ErrorCode::SizeLimit => "size_limit",
+        }
+    }
+}
+
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct ImageError {
+    code: ErrorCode,
+    message: String,
+    detail: Option<String>,
+}
+
+#[wasm_bindgen]
+impl ImageError {
+    // "decode_failed", "encode_failed", "unsupported_format",
+    // "invalid_argument" or "size_limit".
+    #[wasm_bindgen(getter)]
+    pub fn code(&self) -> String {
+        self.code.as_str().to_string()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> String {
+        self.message.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn detail(&self) -> Option<String> {
+        self.detail.clone()
+    }
+
+    // `{ code, message, detail }`, so errors survive JSON.stringify and
+    // postMessage from a worker.
+    #[wasm_bindgen(js_name = toJSON)]
+    pub fn to_json(&self) -> Object {
+        let object = Object::new();
+        // Setting a property on a plain object cannot fail.
+        let _ = Reflect::set(&object, &"code".into(), &self.code().into());
+        let _ = Reflect::set(&object, &"message".into(), &self.message.clone().into());
+        let _ = Reflect::set(&object, &"detail".into(), &self.detail.clone().into());
+        object
+    }
+
+    #[wasm_bindgen(js_name = toString)]
+    pub fn to_string_js(&self) -> String {
+        match &self.detail {
+            Some(detail) => format!("{}: {}", self.message, detail),
+            None => self.message.clone(),
+        }
+    }
+}
+
+fn error(code: ErrorCode, message: impl Into<String>, detail: Option<String>) -> JsValue {
+    ImageError { code, message: message.into(), detail }.into()
+}
+
+pub(crate) fn decode_failed(detail: impl Display) -> JsValue {
+    error(ErrorCode::DecodeFailed, "Failed to load image", Some(detail.to_string()))
+}
+
+pub(crate) fn encode_failed(format: &str, detail: impl Display) -> JsValue {
+    error(ErrorCode::EncodeFailed, format!("Failed to encode {}", format), Some(detail.to_string()))
+}
+
+pub(crate) fn unsupported_format(message: impl Into<String>) -> JsValue {
+    error(ErrorCode::UnsupportedFormat, message, None)
+}
+
+pub(crate) fn invalid_argument(message: impl Into<String>) -> JsValue {
+    error(ErrorCode::InvalidArgument, message, None)
+}
+
+pub(crate) fn size_limit(message: impl Into<String>) -> JsValue {
+    error(ErrorCode::SizeLimit, message, None)
+}
@@ -0,0 +1,41 @@
+// Favicon sets: a multi-resolution ICO for browsers plus the PNG sizes web
+// app manifests (192, 512) and iOS home screens (180) ask for. Every size is
+// the image fitted into a square on transparent, and every PNG, inside the
+// ICO or not, is written with optimize_png.
+
+use crate::{error, optimize, transform};
+use image::codecs::ico::{IcoEncoder, IcoFrame};
+use image::{ColorType, DynamicImage};
+use wasm_bindgen::prelude::*;
+
+pub(crate) const DEFAULT_ICO_SIZES: [u32; 3] = [16, 32, 48];
+pub(crate) const PNG_SIZES: [u32; 3] = [180, 192, 512];
+
+fn square(img: &DynamicImage, size: u32) -> Result<Vec<u8>, JsValue> {
+    let fitted = transform::fit(img, size, size, "contain", image::Rgba([0, 0, 0, 0]))?;
+    optimize::optimize_png(&fitted, 1)
+}
+
+// One ICO with an entry per size of `sizes` (1–256), smallest first and
+// without repeats.
+pub(crate) fn ico(img: &DynamicImage, sizes: &[u32]) -> Result<Vec<u8>, JsValue> {
+    if sizes.iter().any(|size| !(1..=256).contains(size)) {
+        return Err(error::invalid_argument("ICO sizes must be between 1 and 256"));
+    }
+    let mut sizes = sizes.to_vec();
+    sizes.sort_unstable();
+    sizes.dedup();
+    let encode_error = |e| error::encode_failed("ICO", e);
+    let frames = sizes
+        .iter()
+        .map(|&size| IcoFrame::with_encoded(square(img, size)?, size, size, ColorType::Rgba8).map_err(encode_error))
+        .collect::<Result<Vec<_>, JsValue>>()?;
+    let mut buffer = Vec::new();
+    IcoEncoder::new(&mut buffer).encode_images(&frames).map_err(encode_error)?;
+    Ok(buffer)
+}
+
+// The standalone PNGs, as (size, data).
+pub(crate) fn pngs(img: &DynamicImage) -> Result<Vec<(u32, Vec<u8>)>, JsValue> {
+    PNG_SIZES.iter().map(|&size| Ok((size, square(img, size)?))).collect()
+}
@@ -0,0 +1,181 @@
+// Blurs, sharpening and noise reduction, at 8 bits per channel. Images with alpha are blurred
+// premultiplied, so the colors hidden under transparent pixels do not bleed
+// into their neighbors.
+
+use crate::error;
+use image::{imageops, DynamicImage, RgbImage, RgbaImage};
+use wasm_bindgen::prelude::*;
+
+fn premultiply(rgba: &mut RgbaImage) {
+    for pixel in rgba.pixels_mut() {
+        let a = pixel[3] as u32;
+        for c in 0..3 {
+            pixel[c] = ((pixel[c] as u32 * a + 127) / 255) as u8;
+        }
+    }
+}
+
+fn unpremultiply(rgba: &mut RgbaImage) {
+    for pixel in rgba.pixels_mut() {
+        let a = pixel[3] as u32;
+        for c in 0..3 {
+            pixel[c] = (pixel[c] as u32 * 255 + a / 2).checked_div(a).unwrap_or(0).min(255) as u8;
+        }
+    }
+}
+
+// Applies `blur` to the RGB pixels, or to the premultiplied RGBA pixels of
+// images with alpha.
+fn blurred(img: &DynamicImage, blur_rgb: impl Fn(&RgbImage) -> RgbImage, blur_rgba: impl Fn(&RgbaImage) -> RgbaImage) -> DynamicImage {
+    if !img.color().has_alpha() {
+        return DynamicImage::ImageRgb8(blur_rgb(&img.to_rgb8()));
+    }
+    let mut rgba = img.to_rgba8();
+    premultiply(&mut rgba);
+    let mut out = blur_rgba(&rgba);
+    unpremultiply(&mut out);
+    DynamicImage::ImageRgba8(out)
+}
+
+fn check_sigma(sigma: f32) -> Result<(), JsValue> {
+    if !(sigma > 0.0 && sigma <= 100.0) {
+        return Err(error::invalid_argument("Blur sigma must be above 0 and at most 100"));
+    }
+    Ok(())
+}
+
+pub(crate) fn gaussian_blur(img: &DynamicImage, sigma: f32) -> Result<DynamicImage, JsValue> {
+    check_sigma(sigma)?;
+    Ok(blurred(img, |rgb| imageops::blur(rgb, sigma), |rgba| imageops::blur(rgba, sigma)))
+}
+
+// Running sums along one axis over `2 × radius + 1` samples, repeating the
+// edge samples beyond the ends.
+fn box_pass(data: &[u8], width: usize, height: usize, channels: usize, radius: usize, horizontal: bool) -> Vec<u8> {
+    let (lines, length) = if horizontal { (height, width) } else { (width, height) };
+    let index = |line: usize, i: usize| if horizontal { (line * width + i) * channels } else { (i * width + line) * channels };
+    let window = (2 * radius + 1) as u32;
+    let mut out = vec![0u8; data.len()];
+    for line in 0..lines {
+        for c in 0..channels {
+            let sample = |i: isize| data[index(line, i.clamp(0, length as isize - 1) as usize) + c] as u32;
+            let mut sum: u32 = (-(radius as isize)..=radius as isize).map(sample).sum();
+            for i in 0..length {
+                out[index(line, i) + c] = ((sum + window / 2) / window) as u8;
+                sum = sum + sample(i as isize + radius as isize + 1) - sample(i as isize - radius as isize);
+            }
+        }
+    }
+    out
+}
+
+// The mean over a `2 × radius + 1` square, much faster than a Gaussian of
+// the same size.
+pub(crate) fn box_blur(img: &DynamicImage, radius: u32) -> Result<DynamicImage, JsValue> {
+    if !(1..=1000).contains(&radius) {
+        return Err(error::invalid_argument("Box blur radius must be between 1 and 1000"));
+    }
+    let pass = |data: &[u8], width: u32, height: u32, channels: usize| {
+        let (w, h) = (width as usize, height as usize);
+        let rows = box_pass(data, w, h, channels, radius as usize, true);
+        box_pass(&rows, w, h, channels, radius as usize, false)
+    };
+    Ok(blurred(
+        img,
+        |rgb| RgbImage::from_raw(rgb.width(), rgb.height(), pass(rgb, rgb.width(), rgb.height(), 3)).expect("same size"),
+        |rgba| RgbaImage::from_raw(rgba.width(), rgba.height(), pass(rgba, rgba.width(), rgba.height(), 4)).expect("same size"),
+    ))
+}
+
+// One axis of a separable bilateral filter: each sample becomes the mean
+// of its neighbors along the line, weighted by distance, as a Gaussian of
+// `sigma` samples out to `radius`, and by how close they are in color, as a
+// Gaussian of `range` levels of the mean absolute difference across
+// channels, so that edges stay sharp.
+#[allow(clippy::too_many_arguments)]
+fn bilateral_pass(data: &[u8], width: usize, height: usize, channels: usize, sigma: f32, radius: usize, range: f32, horizontal: bool) -> Vec<u8> {
+    let (lines, length) = if horizontal { (height, width) } else { (width, height) };
+    let index = |line: usize, i: usize| if horizontal { (line * width + i) * channels } else { (i * width + line) * channels };
+    let spatial: Vec<f32> = (0..=radius).map(|d| (-((d * d) as f32) / (2.0 * sigma * sigma)).exp()).collect();
+    let similarity: Vec<f32> = (0..=255 * channels)
+        .map(|difference| {
+            let d = difference as f32 / channels as f32 / range;
+            (-d * d / 2.0).exp()
+        })
+        .collect();
+    let mut out = vec![0u8; data.len()];
+    for line in 0..lines {
+        for i in 0..length {
+            let center = &data[index(line, i)..][..channels];
+            let (mut sums, mut total) = ([0.0f32; 4], 0.0);
+            for j in i.saturating_sub(radius)..(i + radius + 1).min(length) {
+                let sample = &data[index(line, j)..][..channels];
+                let difference: usize = sample.iter().zip(center).map(|(&a, &b)| a.abs_diff(b) as usize).sum();
+                let weight = spatial[i.abs_diff(j)] * similarity[difference];
+                for (sum, &value) in sums.iter_mut().zip(sample) {
+                    *sum += weight * value as f32;
+                }
+                total += weight;
+            }
+            let offset = index(line, i);
+            for (c, sum) in sums[..channels].iter().enumerate() {
+                out[offset + c] = (sum / total).round() as u8;
+            }
+        }
+    }
+    out
+}
+
+// Edge-preserving noise reduction at `strength` from 0 (unchanged) to 1,
+// for the grain of high-ISO photos: stronger smooths over a wider area and
+// larger color differences. The bilateral filter runs along rows then
+// columns, which is close to the full square and far cheaper.
+pub(crate) fn denoise(img: &DynamicImage, strength: f32) -> Result<DynamicImage, JsValue> {
+    if !(0.0..=1.0).contains(&strength) {
+        return Err(error::invalid_argument("Denoise strength must be between 0 and 1"));
+    }
+    if strength == 0.0 {
+        return Ok(img.clone());
+    }
+    let sigma = 1.0 + 1.5 * strength;
+    let radius = (2.0 * sigma).ceil() as usize;
+    let range = 4.0 + 36.0 * strength;
+    let pass = |data: &[u8], width: u32, height: u32, channels: usize| {
+        let (w, h) = (width as usize, height as usize);
+        let rows = bilateral_pass(data, w, h, channels, sigma, radius, range, true);
+        bilateral_pass(&rows, w, h, channels, sigma, radius, range, false)
+    };
+    Ok(blurred(
+        img,
+        |rgb| RgbImage::from_raw(rgb.width(), rgb.height(), pass(rgb, rgb.width(), rgb.height(), 3)).expect("same size"),
+        |rgba| RgbaImage::from_raw(rgba.width(), rgba.height(), pass(rgba, rgba.width(), rgba.height(), 4)).expect("same size"),
+    ))
+}
+
+// A light fixed sharpen, the classic 3 × 3 Laplacian kernel.
+pub(crate) fn sharpen(img: &DynamicImage) -> DynamicImage {
+    img.filter3x3(&[0.0, -1.0, 0.0, -1.0, 5.0, -1.0, 0.0, -1.0, 0.0])
+}
+
+// Adds `amount` times the difference from a Gaussian blur of `sigma` back
+// to the color channels, where it is at least `threshold` levels, so flat
+// areas and noise can be left alone.
+pub(crate) fn unsharp_mask(img: &DynamicImage, sigma: f32, amount: f32, threshold: u8) -> Result<DynamicImage, JsValue> {
+    check_sigma(sigma)?;
+    if !(0.0..=10.0).contains(&amount) {
+        return Err(error::invalid_argument("Unsharp mask amount must be between 0 and 10"));
+    }
+    let original = img.to_rgba8();
+    let blurred = gaussian_blur(img, sigma)?.to_rgba8();
+    let mut out = original.clone();
+    for (pixel, soft) in out.pixels_mut().zip(blurred.pixels()) {
+        for c in 0..3 {
+            let difference = pixel[c] as f32 - soft[c] as f32;
+            if difference.abs() >= threshold as f32 {
+                pixel[c] = (pixel[c] as f32 + amount * difference).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+    let out = DynamicImage::ImageRgba8(out);
+    Ok(if img.color().has_alpha() { out } else { DynamicImage::ImageRgb8(out.to_rgb8()) })
+}
@@ -0,0 +1,72 @@
+// Perceptual hashes for finding near-duplicate images: 64 bits each, most
+// significant first in row-major order, compared by Hamming distance. They
+// follow the common definitions, as in the imagehash Python package.
+
+use crate::error;
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage};
+use wasm_bindgen::prelude::*;
+
+// Side JPEGs need to be decoded at, comfortably above the 32 × 32 pHash.
+pub(crate) const DECODE_SIDE: u32 = 256;
+
+#[derive(Clone, Copy)]
+pub(crate) enum Algorithm {
+    // Pixels brighter than the mean of an 8 × 8 thumbnail.
+    Average,
+    // Pixels brighter than their right neighbor in a 9 × 8 thumbnail.
+    Difference,
+    // The lowest 8 × 8 DCT frequencies of a 32 × 32 thumbnail above their
+    // median.
+    Perceptual,
+}
+
+pub(crate) fn parse_algorithm(algorithm: &str) -> Result<Algorithm, JsValue> {
+    match algorithm.to_lowercase().as_str() {
+        "ahash" | "average" => Ok(Algorithm::Average),
+        "dhash" | "difference" => Ok(Algorithm::Difference),
+        "phash" | "perceptual" => Ok(Algorithm::Perceptual),
+        _ => Err(error::invalid_argument("Unsupported hash algorithm")),
+    }
+}
+
+fn gray(img: &DynamicImage, width: u32, height: u32) -> GrayImage {
+    img.resize_exact(width, height, FilterType::Lanczos3).to_luma8()
+}
+
+fn bits(values: impl Iterator<Item = bool>) -> u64 {
+    values.fold(0, |hash, bit| (hash << 1) | bit as u64)
+}
+
+pub(crate) fn hash(img: &DynamicImage, algorithm: Algorithm) -> u64 {
+    match algorithm {
+        Algorithm::Average => {
+            let pixels = gray(img, 8, 8);
+            let mean = pixels.iter().map(|&v| v as u32).sum::<u32>() as f64 / 64.0;
+            bits(pixels.iter().map(|&v| v as f64 > mean))
+        }
+        Algorithm::Difference => {
+            let pixels = gray(img, 9, 8);
+            bits((0..8).flat_map(|y| (0..8).map(move |x| (x, y))).map(|(x, y)| pixels.get_pixel(x, y)[0] > pixels.get_pixel(x + 1, y)[0]))
+        }
+        Algorithm::Perceptual => {
+            let pixels = gray(img, 32, 32);
+            // Separable DCT-II, keeping only the 8 lowest frequencies of
+            // each pass.
+            let basis: Vec<[f64; 32]> = (0..8)
+                .map(|k| std::array::from_fn(|n| (std::f64::consts::PI / 32.0 * (n as f64 + 0.5) * k as f64).cos()))
+                .collect();
+            let rows: Vec<[f64; 8]> = (0..32)
+                .map(|y| std::array::from_fn(|k| (0..32).map(|x| pixels.get_pixel(x, y)[0] as f64 * basis[k][x as usize]).sum()))
+                .collect();
+            let low: Vec<f64> = (0..8)
+                .flat_map(|v| (0..8).map(move |u| (u, v)))
+                .map(|(u, v)| (0..32).map(|y| rows[y][u] * basis[v][y]).sum())
+                .collect();
+            let mut sorted = low.clone();
+            sorted.sort_by(f64::total_cmp);
+            let median = (sorted[31] + sorted[32]) / 2.0;
+            bits(low.iter().map(|&value| value > median))
+        }
+    }
+}
@@ -0,0 +1,78 @@
+// HEIC (HEVC in HEIF) decoding through heic-decoder, which is pure Rust.
+// The container's rotation, mirroring and crop are applied while decoding,
+// so EXIF orientation is not applied again.
+
+use crate::error;
+use image::{DynamicImage, ImageBuffer};
+use wasm_bindgen::prelude::*;
+
+// HEVC image brands, and the generic still image brand when no AV1 (AVIF)
+// brand comes with it.
+const HEVC_BRANDS: &[&[u8; 4]] = &[b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx"];
+
+// An `ftyp` box naming an HEVC brand, major or compatible.
+pub(crate) fn is_heif(data: &[u8]) -> bool {
+    if data.len() < 16 || &data[4..8] != b"ftyp" {
+        return false;
+    }
+    let size = (u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize).clamp(16, data.len());
+    // Major brand, minor version, then the compatible brands.
+    let brands: Vec<&[u8]> = std::iter::once(&data[8..12]).chain(data[16..size].chunks_exact(4)).collect();
+    let has = |brand: &[u8; 4]| brands.iter().any(|b| *b == brand);
+    HEVC_BRANDS.iter().any(|brand| has(brand)) || (has(b"mif1") && !has(b"avif") && !has(b"avis"))
+}
+
+// Pixel size from the container alone.
+pub(crate) fn dimensions(data: &[u8]) -> Result<(u32, u32), JsValue> {
+    let info = heic_decoder::probe(data).map_err(error::decode_failed)?;
+    Ok((info.width as u32, info.height as u32))
+}
+
+// The ICC profile from the primary image's `colr` property.
+pub(crate) fn icc_profile(data: &[u8]) -> Option<Vec<u8>> {
+    heic_decoder::read_metadata(data).ok()?.icc_profile
+}
+
+// Decodes the primary image to RGB, with alpha when it has an alpha plane,
+// as 8-bit or, for 10-bit and deeper HEVC, 16-bit.
+pub(crate) fn decode(data: &[u8]) -> Result<DynamicImage, JsValue> {
+    let decoded = heic_decoder::decode(data).map_err(error::decode_failed)?;
+    let (width, height) = (decoded.width as u32, decoded.height as u32);
+    let img = match (decoded.alpha.is_some(), decoded.bit_depth_luma > 8) {
+        (false, false) => {
+            let rgb = decoded.to_rgb8().map_err(error::decode_failed)?;
+            ImageBuffer::from_raw(width, height, rgb.data).map(DynamicImage::ImageRgb8)
+        }
+        (false, true) => {
+            let rgb = decoded.to_rgb16().map_err(error::decode_failed)?;
+            ImageBuffer::from_raw(width, height, rgb.data).map(DynamicImage::ImageRgb16)
+        }
+        (true, false) => {
+            let mut rgba = decoded.to_rgba8().map_err(error::decode_failed)?;
+            if rgba.premultiplied {
+                unpremultiply(&mut rgba.data, u8::MAX);
+            }
+            ImageBuffer::from_raw(width, height, rgba.data).map(DynamicImage::ImageRgba8)
+        }
+        (true, true) => {
+            let mut rgba = decoded.to_rgba16().map_err(error::decode_failed)?;
+            if rgba.premultiplied {
+                unpremultiply(&mut rgba.data, u16::MAX);
+            }
+            ImageBuffer::from_raw(width, height, rgba.data).map(DynamicImage::ImageRgba16)
+        }
+    };
+    img.ok_or_else(|| error::decode_failed("unexpected HEIC pixel layout"))
+}
+
+// Divides color by opacity for files with a `prem` alpha link.
+fn unpremultiply<T: Copy + Into<u32> + TryFrom<u32>>(pixels: &mut [T], max: T) {
+    let max: u32 = max.into();
+    for pixel in pixels.chunks_exact_mut(4) {
+        let a: u32 = pixel[3].into();
+        for channel in &mut pixel[..3] {
+            let value = ((*channel).into() * max + a / 2).checked_div(a).unwrap_or(0).min(max);
+            *channel = T::try_from(value).unwrap_or(*channel);
+        }
+    }
+}
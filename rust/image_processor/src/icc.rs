@@ -0,0 +1,534 @@
+// ICC color management for matrix/TRC profiles, the kind Adobe RGB, Display
+// P3 and most camera and editor exports use: pixels are converted from the
+// embedded profile to a target RGB space with relative colorimetric intent,
+// and the target's own profile can be written for embedding.
+
+use crate::error;
+use image::{DynamicImage, Rgba32FImage, RgbaImage};
+use js_sys::Reflect;
+use wasm_bindgen::prelude::*;
+
+// The ICC profile connection space white, D50, as its s15Fixed16 values.
+const D50: [f64; 3] = [0.9642, 1.0, 0.8249];
+
+// Entries in the linear-light to 8-bit output table.
+const OUTPUT_STEPS: usize = 16384;
+
+// Bradford cone response matrix, for chromatic adaptation.
+const BRADFORD: [[f64; 3]; 3] = [[0.8951, 0.2664, -0.1614], [-0.7502, 1.7135, 0.0367], [0.0389, -0.0685, 1.0296]];
+
+type Matrix = [[f64; 3]; 3];
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    std::array::from_fn(|i| std::array::from_fn(|j| (0..3).map(|k| a[i][k] * b[k][j]).sum()))
+}
+
+fn apply(m: &Matrix, v: [f64; 3]) -> [f64; 3] {
+    std::array::from_fn(|i| m[i][0] * v[0] + m[i][1] * v[1] + m[i][2] * v[2])
+}
+
+fn invert(m: &Matrix) -> Option<Matrix> {
+    let cofactor = |i: usize, j: usize| {
+        let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+        let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let determinant: f64 = (0..3).map(|j| m[0][j] * cofactor(0, j)).sum();
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+    Some(std::array::from_fn(|i| std::array::from_fn(|j| cofactor(j, i) / determinant)))
+}
+
+// A tone reproduction curve, mapping encoded values to linear light, both 0–1.
+#[derive(Clone, Debug)]
+enum Curve {
+    // ICC parametric function type 4, which the other types are special
+    // cases of: (a·x + b)^g + e from `d` up, c·x + f below it.
+    Parametric { g: f64, a: f64, b: f64, c: f64, d: f64, e: f64, f: f64 },
+    // Evenly spaced samples, interpolated linearly.
+    Table(Vec<f64>),
+}
+
+impl Curve {
+    fn gamma(g: f64) -> Curve {
+        Curve::Parametric { g, a: 1.0, b: 0.0, c: 0.0, d: 0.0, e: 0.0, f: 0.0 }
+    }
+
+    fn srgb() -> Curve {
+        Curve::Parametric { g: 2.4, a: 1.0 / 1.055, b: 0.055 / 1.055, c: 1.0 / 12.92, d: 0.04045, e: 0.0, f: 0.0 }
+    }
+
+    fn eval(&self, x: f64) -> f64 {
+        let x = x.clamp(0.0, 1.0);
+        let y = match self {
+            Curve::Parametric { g, a, b, c, d, e, f } => {
+                if x >= *d {
+                    (a * x + b).max(0.0).powf(*g) + e
+                } else {
+                    c * x + f
+                }
+            }
+            Curve::Table(samples) => {
+                let position = x * (samples.len() - 1) as f64;
+                let index = (position as usize).min(samples.len() - 2);
+                let t = position - index as f64;
+                samples[index] + (samples[index + 1] - samples[index]) * t
+            }
+        };
+        y.clamp(0.0, 1.0)
+    }
+
+    fn inverse(&self, y: f64) -> f64 {
+        let y = y.clamp(0.0, 1.0);
+        let x = match self {
+            Curve::Parametric { g, a, b, c, d, e, f } => {
+                if y >= (a * d + b).max(0.0).powf(*g) + e {
+                    ((y - e).max(0.0).powf(1.0 / g) - b) / a
+                } else if *c != 0.0 {
+                    (y - f) / c
+                } else {
+                    0.0
+                }
+            }
+            Curve::Table(samples) => {
+                // Tables are monotonic; find the segment holding `y`.
+                let rising = samples[samples.len() - 1] >= samples[0];
+                let index = samples.partition_point(|&s| if rising { s < y } else { s > y });
+                if index == 0 {
+                    return 0.0;
+                }
+                if index >= samples.len() {
+                    return 1.0;
+                }
+                let (low, high) = (samples[index - 1], samples[index]);
+                let t = if high != low { (y - low) / (high - low) } else { 0.0 };
+                (index as f64 - 1.0 + t) / (samples.len() - 1) as f64
+            }
+        };
+        x.clamp(0.0, 1.0)
+    }
+}
+
+// A parsed profile: RGB with its colorants, or gray with one curve.
+#[derive(Debug)]
+pub(crate) struct Profile {
+    // Linear RGB to PCS XYZ; None for gray profiles.
+    matrix: Option<Matrix>,
+    curves: [Curve; 3],
+    description: Option<String>,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn s15_fixed16(data: &[u8], offset: usize) -> Option<f64> {
+    Some(u32_at(data, offset)? as i32 as f64 / 65536.0)
+}
+
+// The tag table as (signature, data).
+fn tags(icc: &[u8]) -> Option<Vec<([u8; 4], &[u8])>> {
+    let count = u32_at(icc, 128)? as usize;
+    (0..count.min(1024))
+        .map(|index| {
+            let entry = 132 + index * 12;
+            let signature: [u8; 4] = icc.get(entry..entry + 4)?.try_into().ok()?;
+            let offset = u32_at(icc, entry + 4)? as usize;
+            let size = u32_at(icc, entry + 8)? as usize;
+            Some((signature, icc.get(offset..offset.checked_add(size)?)?))
+        })
+        .collect()
+}
+
+fn parse_xyz(tag: &[u8]) -> Option<[f64; 3]> {
+    if !tag.starts_with(b"XYZ ") {
+        return None;
+    }
+    Some([s15_fixed16(tag, 8)?, s15_fixed16(tag, 12)?, s15_fixed16(tag, 16)?])
+}
+
+fn parse_curve(tag: &[u8]) -> Option<Curve> {
+    if tag.starts_with(b"curv") {
+        let count = u32_at(tag, 8)? as usize;
+        if tag.len() < 12 + count.checked_mul(2)? {
+            return None;
+        }
+        return match count {
+            0 => Some(Curve::gamma(1.0)),
+            1 => Some(Curve::gamma(u16_at(tag, 12)? as f64 / 256.0)),
+            _ => (0..count).map(|i| Some(u16_at(tag, 12 + i * 2)? as f64 / 65535.0)).collect::<Option<_>>().map(Curve::Table),
+        };
+    }
+    if tag.starts_with(b"para") {
+        let kind = u16_at(tag, 8)?;
+        let param = |i: usize| s15_fixed16(tag, 12 + i * 4);
+        let g = param(0)?;
+        return Some(match kind {
+            0 => Curve::gamma(g),
+            1 | 2 => {
+                let (a, b) = (param(1)?, param(2)?);
+                let c = if kind == 2 { param(3)? } else { 0.0 };
+                let d = if a != 0.0 { -b / a } else { 0.0 };
+                Curve::Parametric { g, a, b, c: 0.0, d, e: c, f: c }
+            }
+            3 => Curve::Parametric { g, a: param(1)?, b: param(2)?, c: param(3)?, d: param(4)?, e: 0.0, f: 0.0 },
+            4 => Curve::Parametric { g, a: param(1)?, b: param(2)?, c: param(3)?, d: param(4)?, e: param(5)?, f: param(6)? },
+            _ => return None,
+        });
+    }
+    None
+}
+
+// The English, or else the first, text of a `desc` tag, from a v2
+// textDescriptionType or a v4 multiLocalizedUnicodeType.
+fn parse_description(tag: &[u8]) -> Option<String> {
+    if tag.starts_with(b"desc") {
+        let length = u32_at(tag, 8)? as usize;
+        let text = tag.get(12..12usize.checked_add(length)?)?;
+        return Some(String::from_utf8_lossy(text).trim_end_matches('\0').to_string());
+    }
+    if tag.starts_with(b"mluc") {
+        let count = u32_at(tag, 8)? as usize;
+        let records: Vec<usize> = (0..count.min(256)).map(|i| 16 + i * 12).collect();
+        let record = records.iter().find(|&&r| tag.get(r..r + 2) == Some(b"en")).or(records.first())?;
+        let length = u32_at(tag, record + 4)? as usize;
+        let offset = u32_at(tag, record + 8)? as usize;
+        let units: Vec<u16> = tag.get(offset..offset.checked_add(length)?)?.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+        return Some(String::from_utf16_lossy(&units).trim_end_matches('\0').to_string());
+    }
+    None
+}
+
+impl Profile {
+    // Parses a matrix/TRC RGB or gray display profile. Profiles of other
+    // kinds, such as CMYK or lookup table based ones, give None.
+    pub(crate) fn parse(icc: &[u8]) -> Option<Profile> {
+        if icc.get(36..40)? != b"acsp" || icc.get(20..24)? != b"XYZ " {
+            return None;
+        }
+        let tags = tags(icc)?;
+        let tag = |signature: &[u8; 4]| tags.iter().find(|(s, _)| s == signature).map(|(_, data)| *data);
+        let description = tag(b"desc").and_then(parse_description);
+        match icc.get(16..20)? {
+            b"RGB " => {
+                let [r, g, b] = [b"rXYZ", b"gXYZ", b"bXYZ"].map(|s| tag(s).and_then(parse_xyz));
+                let (r, g, b) = (r?, g?, b?);
+                let matrix = std::array::from_fn(|i| [r[i], g[i], b[i]]);
+                invert(&matrix)?;
+                let [rc, gc, bc] = [b"rTRC", b"gTRC", b"bTRC"].map(|s| tag(s).and_then(parse_curve));
+                Some(Profile { matrix: Some(matrix), curves: [rc?, gc?, bc?], description })
+            }
+            b"GRAY" => {
+                let curve = tag(b"kTRC").and_then(parse_curve)?;
+                Some(Profile { matrix: None, curves: [curve.clone(), curve.clone(), curve], description })
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum ColorSpace {
+    Srgb,
+    DisplayP3,
+    AdobeRgb,
+}
+
+impl ColorSpace {
+    fn name(self) -> &'static str {
+        match self {
+            ColorSpace::Srgb => "sRGB",
+            ColorSpace::DisplayP3 => "Display P3",
+            ColorSpace::AdobeRgb => "Adobe RGB (1998)",
+        }
+    }
+
+    // Red, green and blue chromaticities; all three have a D65 white.
+    fn primaries(self) -> [(f64, f64); 3] {
+        match self {
+            ColorSpace::Srgb => [(0.64, 0.33), (0.30, 0.60), (0.15, 0.06)],
+            ColorSpace::DisplayP3 => [(0.680, 0.320), (0.265, 0.690), (0.150, 0.060)],
+            ColorSpace::AdobeRgb => [(0.64, 0.33), (0.21, 0.71), (0.15, 0.06)],
+        }
+    }
+
+    fn curve(self) -> Curve {
+        match self {
+            ColorSpace::Srgb | ColorSpace::DisplayP3 => Curve::srgb(),
+            ColorSpace::AdobeRgb => Curve::gamma(563.0 / 256.0),
+        }
+    }
+
+    // The D65 to D50 Bradford adaptation.
+    fn adaptation() -> Matrix {
+        let d65 = [0.3127 / 0.3290, 1.0, (1.0 - 0.3127 - 0.3290) / 0.3290];
+        let inverse = invert(&BRADFORD).expect("Bradford matrix is invertible");
+        let (source, target) = (apply(&BRADFORD, d65), apply(&BRADFORD, D50));
+        let scale = std::array::from_fn(|i| std::array::from_fn(|j| if i == j { target[i] / source[i] } else { 0.0 }));
+        multiply(&inverse, &multiply(&scale, &BRADFORD))
+    }
+
+    // Linear RGB to D50-adapted XYZ.
+    fn matrix(self) -> Matrix {
+        let primaries = self.primaries().map(|(x, y)| [x / y, 1.0, (1.0 - x - y) / y]);
+        let columns: Matrix = std::array::from_fn(|i| [primaries[0][i], primaries[1][i], primaries[2][i]]);
+        let white = [0.3127 / 0.3290, 1.0, (1.0 - 0.3127 - 0.3290) / 0.3290];
+        let scale = apply(&invert(&columns).expect("primaries are independent"), white);
+        let d65: Matrix = std::array::from_fn(|i| std::array::from_fn(|j| columns[i][j] * scale[j]));
+        // Round to the precision the profile stores them at.
+        multiply(&ColorSpace::adaptation(), &d65).map(|row| row.map(|v| (v * 65536.0).round() / 65536.0))
+    }
+
+    fn profile(self) -> Profile {
+        let curve = self.curve();
+        Profile { matrix: Some(self.matrix()), curves: [curve.clone(), curve.clone(), curve], description: Some(self.name().into()) }
+    }
+
+    // An ICC v4 display profile for the space, about 530 bytes.
+    pub(crate) fn icc(self) -> Vec<u8> {
+        let fixed = |v: f64| ((v * 65536.0).round() as i32).to_be_bytes();
+        let mluc = |text: &str| {
+            let units: Vec<u8> = text.encode_utf16().flat_map(|u| u.to_be_bytes()).collect();
+            let mut tag = b"mluc\0\0\0\0".to_vec();
+            tag.extend_from_slice(&1u32.to_be_bytes());
+            tag.extend_from_slice(&12u32.to_be_bytes());
+            tag.extend_from_slice(b"enUS");
+            tag.extend_from_slice(&(units.len() as u32).to_be_bytes());
+            tag.extend_from_slice(&28u32.to_be_bytes());
+            tag.extend_from_slice(&units);
+            tag
+        };
+        let xyz = |v: [f64; 3]| {
+            let mut tag = b"XYZ \0\0\0\0".to_vec();
+            v.iter().for_each(|&c| tag.extend_from_slice(&fixed(c)));
+            tag
+        };
+        let matrix = self.matrix();
+        let mut chad = b"sf32\0\0\0\0".to_vec();
+        ColorSpace::adaptation().iter().flatten().for_each(|&v| chad.extend_from_slice(&fixed(v)));
+        let mut trc = b"para\0\0\0\0".to_vec();
+        match self.curve() {
+            Curve::Parametric { g, a, b, c, d, .. } if c != 0.0 => {
+                trc.extend_from_slice(&[0, 3, 0, 0]);
+                [g, a, b, c, d].iter().for_each(|&v| trc.extend_from_slice(&fixed(v)));
+            }
+            Curve::Parametric { g, .. } => {
+                trc.extend_from_slice(&[0, 0, 0, 0]);
+                trc.extend_from_slice(&fixed(g));
+            }
+            Curve::Table(_) => unreachable!("target curves are parametric"),
+        }
+
+        let elements: Vec<(&[u8; 4], Vec<u8>)> = vec![
+            (b"desc", mluc(self.name())),
+            (b"cprt", mluc("No copyright, use freely")),
+            (b"wtpt", xyz(D50)),
+            (b"chad", chad),
+            (b"rXYZ", xyz(std::array::from_fn(|i| matrix[i][0]))),
+            (b"gXYZ", xyz(std::array::from_fn(|i| matrix[i][1]))),
+            (b"bXYZ", xyz(std::array::from_fn(|i| matrix[i][2]))),
+            (b"rTRC", trc),
+        ];
+        // The three curves share one element.
+        let table_len = 4 + (elements.len() + 2) * 12;
+        let mut table = ((elements.len() + 2) as u32).to_be_bytes().to_vec();
+        let mut data = Vec::new();
+        let mut trc_entry = (0, 0);
+        for (signature, element) in &elements {
+            let offset = 128 + table_len + data.len();
+            table.extend_from_slice(*signature);
+            table.extend_from_slice(&(offset as u32).to_be_bytes());
+            table.extend_from_slice(&(element.len() as u32).to_be_bytes());
+            if *signature == b"rTRC" {
+                trc_entry = (offset, element.len());
+            }
+            data.extend_from_slice(element);
+            while data.len() % 4 != 0 {
+                data.push(0);
+            }
+        }
+        for signature in [b"gTRC", b"bTRC"] {
+            table.extend_from_slice(signature);
+            table.extend_from_slice(&(trc_entry.0 as u32).to_be_bytes());
+            table.extend_from_slice(&(trc_entry.1 as u32).to_be_bytes());
+        }
+
+        let size = 128 + table.len() + data.len();
+        let mut icc = Vec::with_capacity(size);
+        icc.extend_from_slice(&(size as u32).to_be_bytes());
+        icc.extend_from_slice(&[0; 4]); // preferred CMM
+        icc.extend_from_slice(&[4, 0x30, 0, 0]); // version 4.3
+        icc.extend_from_slice(b"mntrRGB XYZ ");
+        icc.extend_from_slice(&[0x07, 0xE6, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0]); // 2022-01-01
+        icc.extend_from_slice(b"acsp");
+        icc.extend_from_slice(&[0; 24]); // platform, flags, manufacturer, model, attributes
+        icc.extend_from_slice(&[0; 4]); // perceptual intent
+        D50.iter().for_each(|&v| icc.extend_from_slice(&fixed(v)));
+        icc.extend_from_slice(&[0; 4]); // creator
+        icc.extend_from_slice(&[0; 16]); // profile ID, not computed
+        icc.extend_from_slice(&[0; 28]);
+        icc.extend_from_slice(&table);
+        icc.extend_from_slice(&data);
+        icc
+    }
+}
+
+pub(crate) fn parse_color_space(space: &str) -> Result<Option<ColorSpace>, JsValue> {
+    match space.to_lowercase().as_str() {
+        "srgb" | "" => Ok(Some(ColorSpace::Srgb)),
+        "display-p3" | "p3" => Ok(Some(ColorSpace::DisplayP3)),
+        "adobe-rgb" | "adobergb" => Ok(Some(ColorSpace::AdobeRgb)),
+        "none" => Ok(None),
+        _ => Err(error::invalid_argument("Unsupported color space")),
+    }
+}
+
+// How decoded pixels are brought into a working space and what profile the
+// output is tagged with.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ColorManagement {
+    // None leaves pixels as decoded.
+    pub(crate) target: Option<ColorSpace>,
+    pub(crate) embed: bool,
+}
+
+impl Default for ColorManagement {
+    fn default() -> ColorManagement {
+        ColorManagement { target: Some(ColorSpace::Srgb), embed: false }
+    }
+}
+
+impl ColorManagement {
+    // Reads `{ target?, embedProfile? }`; see ImageProcessor.color_management.
+    pub(crate) fn from_js(options: &JsValue) -> Result<ColorManagement, JsValue> {
+        if options.is_undefined() || options.is_null() {
+            return Ok(ColorManagement::default());
+        }
+        let get = |key: &str| -> Result<Option<JsValue>, JsValue> {
+            let value = Reflect::get(options, &key.into())?;
+            Ok((!value.is_undefined() && !value.is_null()).then_some(value))
+        };
+        let target = match get("target")? {
+            Some(value) => parse_color_space(&value.as_string().ok_or_else(|| error::invalid_argument("Option `target` must be a string"))?)?,
+            None => Some(ColorSpace::Srgb),
+        };
+        let embed = match get("embedProfile")? {
+            Some(value) => value.as_bool().ok_or_else(|| error::invalid_argument("Option `embedProfile` must be a boolean"))?,
+            None => target.is_some_and(|space| space != ColorSpace::Srgb),
+        };
+        Ok(ColorManagement { target, embed })
+    }
+
+    // The profile to embed in output, if any.
+    pub(crate) fn output_profile(&self) -> Option<Vec<u8>> {
+        self.target.filter(|_| self.embed).map(ColorSpace::icc)
+    }
+
+    // Whether convert would change the pixels of a source embedding `icc`.
+    pub(crate) fn converts(&self, icc: Option<&[u8]>) -> bool {
+        match (self.target, icc.and_then(Profile::parse)) {
+            (Some(target), Some(source)) => !same(&source, &target.profile()),
+            _ => false,
+        }
+    }
+
+    // Converts `img` from the profile embedded in its source, if there is a
+    // supported one, to the target space.
+    pub(crate) fn convert(&self, icc: Option<&[u8]>, img: DynamicImage) -> DynamicImage {
+        match (self.target, icc.and_then(Profile::parse)) {
+            (Some(target), Some(source)) => convert(img, &source, target),
+            _ => img,
+        }
+    }
+}
+
+// Whether two profiles produce the same colors to within about a code value.
+fn same(a: &Profile, b: &Profile) -> bool {
+    let close = |x: f64, y: f64| (x - y).abs() < 1.0 / 512.0;
+    let matrices = match (&a.matrix, &b.matrix) {
+        (Some(m), Some(n)) => m.iter().flatten().zip(n.iter().flatten()).all(|(&x, &y)| close(x, y)),
+        _ => false,
+    };
+    matrices && a.curves.iter().zip(&b.curves).all(|(c, d)| (0..=16).all(|i| close(c.eval(i as f64 / 16.0), d.eval(i as f64 / 16.0))))
+}
+
+// Converts `img` from `source` to `target`, keeping its color type. Gray
+// stays gray, since neutrals map to neutrals between D50-adapted profiles.
+fn convert(img: DynamicImage, source: &Profile, target: ColorSpace) -> DynamicImage {
+    let destination = target.profile();
+    if same(source, &destination) {
+        return img;
+    }
+    let to_target = source.matrix.map(|m| multiply(&invert(&destination.matrix.expect("targets are RGB")).expect("targets invert"), &m));
+    let output_curve = &destination.curves[0];
+    let transform = |rgb: [f64; 3]| -> [f64; 3] {
+        let linear: [f64; 3] = std::array::from_fn(|c| source.curves[c].eval(rgb[c]));
+        let mapped = match &to_target {
+            Some(m) => apply(m, linear),
+            None => linear,
+        };
+        mapped.map(|v| output_curve.inverse(v))
+    };
+
+    let color = img.color();
+    let eight_bit = matches!(
+        img,
+        DynamicImage::ImageLuma8(_) | DynamicImage::ImageLumaA8(_) | DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_)
+    );
+    let converted = if eight_bit {
+        // Lookup tables on the way in, and a fine one on the way out.
+        let input: [Vec<f32>; 3] = std::array::from_fn(|c| (0..256).map(|v| source.curves[c].eval(v as f64 / 255.0) as f32).collect());
+        let output: Vec<u8> = (0..=OUTPUT_STEPS).map(|i| (output_curve.inverse(i as f64 / OUTPUT_STEPS as f64) * 255.0).round() as u8).collect();
+        let m = to_target.map(|m| m.map(|row| row.map(|v| v as f32)));
+        let encode = |v: f32| {
+            // The table is too coarse right above black, where the curves
+            // are steepest; compute those directly.
+            if v < 1.0 / 256.0 {
+                (output_curve.inverse(v as f64) * 255.0).round() as u8
+            } else {
+                output[(v.min(1.0) * OUTPUT_STEPS as f32).round() as usize]
+            }
+        };
+        let mut rgba: RgbaImage = img.into_rgba8();
+        for pixel in rgba.pixels_mut() {
+            let [r, g, b] = [input[0][pixel[0] as usize], input[1][pixel[1] as usize], input[2][pixel[2] as usize]];
+            let mapped = match &m {
+                Some(m) => std::array::from_fn(|i| m[i][0] * r + m[i][1] * g + m[i][2] * b),
+                None => [r, g, b],
+            };
+            for c in 0..3 {
+                pixel[c] = encode(mapped[c]);
+            }
+        }
+        DynamicImage::ImageRgba8(rgba)
+    } else {
+        let mut rgba: Rgba32FImage = img.into_rgba32f();
+        for pixel in rgba.pixels_mut() {
+            let mapped = transform([pixel[0] as f64, pixel[1] as f64, pixel[2] as f64]);
+            for c in 0..3 {
+                pixel[c] = mapped[c] as f32;
+            }
+        }
+        DynamicImage::ImageRgba32F(rgba)
+    };
+    match color {
+        image::ColorType::L8 => DynamicImage::ImageLuma8(converted.into_luma8()),
+        image::ColorType::La8 => DynamicImage::ImageLumaA8(converted.into_luma_alpha8()),
+        image::ColorType::Rgb8 => DynamicImage::ImageRgb8(converted.into_rgb8()),
+        image::ColorType::L16 => DynamicImage::ImageLuma16(converted.into_luma16()),
+        image::ColorType::La16 => DynamicImage::ImageLumaA16(converted.into_luma_alpha16()),
+        image::ColorType::Rgb16 => DynamicImage::ImageRgb16(converted.into_rgb16()),
+        image::ColorType::Rgba16 => DynamicImage::ImageRgba16(converted.into_rgba16()),
+        image::ColorType::Rgb32F => DynamicImage::ImageRgb32F(converted.into_rgb32f()),
+        _ => converted,
+    }
+}
@@ -0,0 +1,1146 @@
+// Lossless JPEG transforms, in the manner of jpegtran: the entropy-coded
+// data is decoded only as far as the quantized DCT coefficients, which are
+// then rearranged and written back as a baseline JPEG with the original
+// quantization tables and optimized Huffman tables. Nothing is requantized,
+// so no quality is lost.
+//
+// Flips and rotations need whole MCUs (8 or 16 pixel blocks) along the axis
+// they mirror; a partial MCU at that edge is trimmed off, as with jpegtran's
+// -trim. Crops start on an MCU boundary, so the top-left corner may move up
+// and left by up to one MCU.
+
+use crate::error;
+use crate::limits::Limits;
+use wasm_bindgen::prelude::*;
+
+// Largest DC difference and AC coefficient sizes for 8-bit samples
+// (F.1.2.1, F.1.2.2). Anything larger is corrupt and would overflow the
+// bit reader.
+const MAX_DC_SIZE: u8 = 11;
+const MAX_AC_SIZE: u8 = 10;
+
+// Natural (row-major) index of each zigzag position.
+pub(crate) const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20, 13, 6, 7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47,
+    55, 62, 63,
+];
+
+pub(crate) type Block = [i16; 64];
+
+fn corrupt() -> JsValue {
+    error::decode_failed("Corrupt JPEG data")
+}
+
+#[derive(Clone)]
+pub(crate) struct Component {
+    id: u8,
+    pub(crate) h: usize,
+    pub(crate) v: usize,
+    quant_table: usize,
+    // Blocks in rows of `blocks_w`, covering whole MCUs.
+    pub(crate) blocks_w: usize,
+    blocks_h: usize,
+    pub(crate) blocks: Vec<Block>,
+}
+
+pub(crate) struct Coefficients {
+    width: usize,
+    height: usize,
+    components: Vec<Component>,
+    // In natural order, as the coefficients.
+    quant_tables: [Option<[u16; 64]>; 4],
+    // ICC profile (APP2) and Adobe (APP14) segments, copied to the output:
+    // the profile gives the colors meaning, and the Adobe segment tells CMYK
+    // and RGB files apart from YCbCr.
+    kept_segments: Vec<(u8, Vec<u8>)>,
+}
+
+impl Coefficients {
+    // A new YCbCr image from each component's sampling factors and blocks,
+    // in rows covering whole MCUs. Luma uses the first quantization table
+    // and chroma the second.
+    pub(crate) fn new(width: usize, height: usize, components: Vec<(usize, usize, Vec<Block>)>, quant_tables: [[u16; 64]; 2]) -> Coefficients {
+        let max_h = components.iter().map(|c| c.0).max().unwrap_or(1);
+        let max_v = components.iter().map(|c| c.1).max().unwrap_or(1);
+        let (mcus_x, mcus_y) = (width.div_ceil(8 * max_h), height.div_ceil(8 * max_v));
+        let components = components
+            .into_iter()
+            .enumerate()
+            .map(|(index, (h, v, blocks))| Component { id: index as u8 + 1, h, v, quant_table: index.min(1), blocks_w: mcus_x * h, blocks_h: mcus_y * v, blocks })
+            .collect();
+        Coefficients { width, height, components, quant_tables: [Some(quant_tables[0]), Some(quant_tables[1]), None, None], kept_segments: Vec::new() }
+    }
+
+    // Pixel size, components and quantization tables, for recompression
+    // into JPEG XL.
+    pub(crate) fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    pub(crate) fn components(&self) -> &[Component] {
+        &self.components
+    }
+
+    pub(crate) fn quant_table(&self, component: &Component) -> Option<&[u16; 64]> {
+        self.quant_tables[component.quant_table].as_ref()
+    }
+
+    fn max_h(&self) -> usize {
+        self.components.iter().map(|c| c.h).max().unwrap_or(1)
+    }
+
+    fn max_v(&self) -> usize {
+        self.components.iter().map(|c| c.v).max().unwrap_or(1)
+    }
+
+    fn mcus(&self) -> (usize, usize) {
+        (self.width.div_ceil(8 * self.max_h()), self.height.div_ceil(8 * self.max_v()))
+    }
+}
+
+// Huffman decoding after Annex F.2.2.3.
+#[derive(Clone, Default)]
+struct HuffmanDecoder {
+    max_code: [i32; 17],
+    min_code: [i32; 17],
+    value_offset: [usize; 17],
+    values: Vec<u8>,
+}
+
+impl HuffmanDecoder {
+    fn new(counts: &[u8], values: &[u8]) -> HuffmanDecoder {
+        let mut table = HuffmanDecoder { values: values.to_vec(), ..HuffmanDecoder::default() };
+        let (mut code, mut offset) = (0i32, 0usize);
+        for length in 1..=16 {
+            let count = counts[length - 1] as i32;
+            table.value_offset[length] = offset;
+            table.min_code[length] = code;
+            code += count;
+            offset += count as usize;
+            table.max_code[length] = if count > 0 { code - 1 } else { -1 };
+            code <<= 1;
+        }
+        table
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u8, JsValue> {
+        let bits = reader.peek(16);
+        for length in 1..=16 {
+            let code = (bits >> (16 - length)) as i32;
+            if code <= self.max_code[length] {
+                reader.consume(length as u32);
+                let index = self.value_offset[length] + (code - self.min_code[length]) as usize;
+                return self.values.get(index).copied().ok_or_else(corrupt);
+            }
+        }
+        Err(corrupt())
+    }
+}
+
+fn dc_size(size: u8) -> Result<u8, JsValue> {
+    if size > MAX_DC_SIZE { Err(corrupt()) } else { Ok(size) }
+}
+
+fn ac_size(size: u8) -> Result<u8, JsValue> {
+    if size > MAX_AC_SIZE { Err(corrupt()) } else { Ok(size) }
+}
+
+// A decoded coefficient, which a valid file keeps within 16 bits.
+fn coefficient(value: i32) -> Result<i16, JsValue> {
+    i16::try_from(value).map_err(|_| corrupt())
+}
+
+// Entropy-coded data of one scan, with stuffed zero bytes removed and
+// restart markers handled by `restart`. Past the end it reads zeros.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn fill(&mut self) {
+        while self.count <= 24 {
+            let byte = match self.data.get(self.pos) {
+                Some(0xFF) if self.data.get(self.pos + 1) == Some(&0) => {
+                    self.pos += 2;
+                    0xFF
+                }
+                Some(0xFF) | None => 0,
+                Some(&byte) => {
+                    self.pos += 1;
+                    byte
+                }
+            };
+            self.bits |= (byte as u32) << (24 - self.count);
+            self.count += 8;
+        }
+    }
+
+    fn peek(&mut self, count: u32) -> u32 {
+        self.fill();
+        self.bits >> (32 - count)
+    }
+
+    fn consume(&mut self, count: u32) {
+        self.bits <<= count;
+        self.count -= count;
+    }
+
+    fn bits(&mut self, count: u32) -> u32 {
+        if count == 0 {
+            return 0;
+        }
+        let value = self.peek(count);
+        self.consume(count);
+        value
+    }
+
+    fn bit(&mut self) -> bool {
+        self.bits(1) == 1
+    }
+
+    // A `size`-bit magnitude as a signed value (F.2.2.1).
+    fn receive_extend(&mut self, size: u8) -> i32 {
+        if size == 0 {
+            return 0;
+        }
+        let value = self.bits(size as u32) as i32;
+        if value < 1 << (size - 1) { value - (1 << size) + 1 } else { value }
+    }
+
+    // Skips the padding bits and the RSTn marker ending an interval.
+    fn restart(&mut self) {
+        (self.bits, self.count) = (0, 0);
+        if self.data.get(self.pos) == Some(&0xFF) && self.data.get(self.pos + 1).is_some_and(|m| (0xD0..=0xD7).contains(m)) {
+            self.pos += 2;
+        }
+    }
+}
+
+struct Scan {
+    // Indices into the frame components, with their DC and AC tables.
+    components: Vec<(usize, usize, usize)>,
+    start: usize,
+    end: usize,
+    high: u8,
+    low: u8,
+}
+
+struct Decoder {
+    dc_tables: [HuffmanDecoder; 4],
+    ac_tables: [HuffmanDecoder; 4],
+    restart_interval: usize,
+    progressive: bool,
+    eob_run: u32,
+}
+
+impl Decoder {
+    fn decode_block(&mut self, reader: &mut BitReader, block: &mut Block, predictor: &mut i32, dc: usize, ac: usize, scan: &Scan) -> Result<(), JsValue> {
+        if !self.progressive {
+            let size = dc_size(self.dc_tables[dc].decode(reader)?)?;
+            *predictor += reader.receive_extend(size);
+            block[0] = coefficient(*predictor)?;
+            let mut k = 1;
+            while k < 64 {
+                let symbol = self.ac_tables[ac].decode(reader)?;
+                let (run, size) = ((symbol >> 4) as usize, ac_size(symbol & 15)?);
+                if size == 0 {
+                    if run != 15 {
+                        break;
+                    }
+                    k += 16;
+                    continue;
+                }
+                k += run;
+                if k > 63 {
+                    return Err(corrupt());
+                }
+                block[ZIGZAG[k]] = coefficient(reader.receive_extend(size))?;
+                k += 1;
+            }
+            return Ok(());
+        }
+
+        if scan.start == 0 {
+            if scan.high == 0 {
+                let size = dc_size(self.dc_tables[dc].decode(reader)?)?;
+                *predictor += reader.receive_extend(size);
+                block[0] = coefficient(*predictor << scan.low)?;
+            } else if reader.bit() {
+                block[0] |= 1 << scan.low;
+            }
+            return Ok(());
+        }
+
+        if scan.high == 0 {
+            // AC first pass (G.1.2.2).
+            if self.eob_run > 0 {
+                self.eob_run -= 1;
+                return Ok(());
+            }
+            let mut k = scan.start;
+            while k <= scan.end {
+                let symbol = self.ac_tables[ac].decode(reader)?;
+                let (run, size) = ((symbol >> 4) as u32, ac_size(symbol & 15)?);
+                if size == 0 {
+                    if run < 15 {
+                        self.eob_run = (1 << run) - 1 + reader.bits(run);
+                        break;
+                    }
+                    k += 16;
+                    continue;
+                }
+                k += run as usize;
+                if k > 63 {
+                    return Err(corrupt());
+                }
+                block[ZIGZAG[k]] = coefficient(reader.receive_extend(size) << scan.low)?;
+                k += 1;
+            }
+            return Ok(());
+        }
+
+        // AC refinement (G.1.2.3), following libjpeg's decode_mcu_AC_refine.
+        let p1 = 1i16 << scan.low;
+        let m1 = -1i16 << scan.low;
+        let refine = |reader: &mut BitReader, coefficient: &mut i16| {
+            if reader.bit() && *coefficient & p1 == 0 {
+                *coefficient = coefficient.saturating_add(if *coefficient >= 0 { p1 } else { m1 });
+            }
+        };
+        let mut k = scan.start;
+        if self.eob_run == 0 {
+            while k <= scan.end {
+                let symbol = self.ac_tables[ac].decode(reader)?;
+                let (mut run, size) = ((symbol >> 4) as i32, symbol & 15);
+                let mut value = 0;
+                if size != 0 {
+                    value = if reader.bit() { p1 } else { m1 };
+                } else if run != 15 {
+                    self.eob_run = (1 << run) + reader.bits(run as u32);
+                    break;
+                }
+                while k <= scan.end {
+                    let coefficient = &mut block[ZIGZAG[k]];
+                    if *coefficient != 0 {
+                        refine(reader, coefficient);
+                    } else {
+                        run -= 1;
+                        if run < 0 {
+                            break;
+                        }
+                    }
+                    k += 1;
+                }
+                if value != 0 {
+                    if k > 63 {
+                        return Err(corrupt());
+                    }
+                    block[ZIGZAG[k]] = value;
+                }
+                k += 1;
+            }
+        }
+        if self.eob_run > 0 {
+            while k <= scan.end {
+                let coefficient = &mut block[ZIGZAG[k]];
+                if *coefficient != 0 {
+                    refine(reader, coefficient);
+                }
+                k += 1;
+            }
+            self.eob_run -= 1;
+        }
+        Ok(())
+    }
+
+    fn decode_scan(&mut self, frame: &mut Coefficients, scan: &Scan, data: &[u8]) -> Result<(), JsValue> {
+        let mut reader = BitReader { data, pos: 0, bits: 0, count: 0 };
+        let mut predictors = vec![0i32; frame.components.len()];
+        self.eob_run = 0;
+        // Units in scan order: MCUs when interleaved, otherwise single
+        // blocks over just the blocks that cover the component.
+        let single = scan.components.len() == 1;
+        let (units_x, units_y) = if single {
+            let component = &frame.components[scan.components[0].0];
+            (
+                (frame.width * component.h).div_ceil(frame.max_h()).div_ceil(8),
+                (frame.height * component.v).div_ceil(frame.max_v()).div_ceil(8),
+            )
+        } else {
+            frame.mcus()
+        };
+        for unit in 0..units_x * units_y {
+            if self.restart_interval > 0 && unit > 0 && unit % self.restart_interval == 0 {
+                reader.restart();
+                predictors.iter_mut().for_each(|p| *p = 0);
+                self.eob_run = 0;
+            }
+            let (ux, uy) = (unit % units_x, unit / units_x);
+            for &(index, dc, ac) in &scan.components {
+                let component = &mut frame.components[index];
+                let (h, v) = if single { (1, 1) } else { (component.h, component.v) };
+                for y in 0..v {
+                    for x in 0..h {
+                        let block = &mut component.blocks[(uy * v + y) * component.blocks_w + ux * h + x];
+                        self.decode_block(&mut reader, block, &mut predictors[index], dc, ac, scan)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Result<usize, JsValue> {
+    data.get(pos..pos + 2).map(|b| u16::from_be_bytes([b[0], b[1]]) as usize).ok_or_else(corrupt)
+}
+
+// End of the entropy-coded data starting at `pos`: the next marker other
+// than a restart marker.
+pub(crate) fn entropy_end(data: &[u8], mut pos: usize) -> usize {
+    while pos + 1 < data.len() {
+        if data[pos] == 0xFF && data[pos + 1] != 0 && !(0xD0..=0xD7).contains(&data[pos + 1]) {
+            return pos;
+        }
+        pos += 1;
+    }
+    data.len()
+}
+
+pub(crate) fn decode(data: &[u8], limits: &Limits) -> Result<Coefficients, JsValue> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err(error::unsupported_format("Lossless transforms need a JPEG"));
+    }
+    let mut frame: Option<Coefficients> = None;
+    let mut quant_tables = [None; 4];
+    let mut kept_segments = Vec::new();
+    let mut decoder = Decoder {
+        dc_tables: Default::default(),
+        ac_tables: Default::default(),
+        restart_interval: 0,
+        progressive: false,
+        eob_run: 0,
+    };
+    let mut pos = 2;
+    while pos + 1 < data.len() {
+        if data[pos] != 0xFF {
+            return Err(corrupt());
+        }
+        let marker = data[pos + 1];
+        pos += 2;
+        match marker {
+            0xFF => pos -= 1, // Fill byte
+            0xD9 => break,
+            0x01 | 0xD0..=0xD8 => {}
+            _ => {
+                let length = u16_at(data, pos)?;
+                let segment = data.get(pos + 2..pos + length).ok_or_else(corrupt)?;
+                pos += length;
+                match marker {
+                    0xDB => {
+                        let mut rest = segment;
+                        while !rest.is_empty() {
+                            let (precision, id) = ((rest[0] >> 4) as usize, (rest[0] & 15) as usize);
+                            let size = if precision == 0 { 64 } else { 128 };
+                            let values = rest.get(1..1 + size).filter(|_| id < 4).ok_or_else(corrupt)?;
+                            let mut table = [0u16; 64];
+                            for (k, &natural) in ZIGZAG.iter().enumerate() {
+                                table[natural] = if precision == 0 {
+                                    values[k] as u16
+                                } else {
+                                    u16::from_be_bytes([values[2 * k], values[2 * k + 1]])
+                                };
+                            }
+                            quant_tables[id] = Some(table);
+                            rest = &rest[1 + size..];
+                        }
+                    }
+                    0xC4 => {
+                        let mut rest = segment;
+                        while rest.len() >= 17 {
+                            let (class, id) = (rest[0] >> 4, (rest[0] & 15) as usize);
+                            let counts = &rest[1..17];
+                            let total: usize = counts.iter().map(|&c| c as usize).sum();
+                            let values = rest.get(17..17 + total).filter(|_| id < 4).ok_or_else(corrupt)?;
+                            let table = HuffmanDecoder::new(counts, values);
+                            if class == 0 {
+                                decoder.dc_tables[id] = table;
+                            } else {
+                                decoder.ac_tables[id] = table;
+                            }
+                            rest = &rest[17 + total..];
+                        }
+                    }
+                    0xC0..=0xC2 => {
+                        if segment.len() < 6 || segment[0] != 8 {
+                            return Err(error::unsupported_format("Only 8-bit JPEGs can be transformed losslessly"));
+                        }
+                        decoder.progressive = marker == 0xC2;
+                        let height = u16::from_be_bytes([segment[1], segment[2]]) as usize;
+                        let width = u16::from_be_bytes([segment[3], segment[4]]) as usize;
+                        let count = segment[5] as usize;
+                        if width == 0 || height == 0 || count == 0 || segment.len() < 6 + 3 * count {
+                            return Err(corrupt());
+                        }
+                        limits.check_pixels(width as u32, height as u32)?;
+                        let mut components: Vec<Component> = segment[6..6 + 3 * count]
+                            .chunks(3)
+                            .map(|c| Component {
+                                id: c[0],
+                                h: (c[1] >> 4).max(1) as usize,
+                                v: (c[1] & 15).max(1) as usize,
+                                quant_table: (c[2] & 3) as usize,
+                                blocks_w: 0,
+                                blocks_h: 0,
+                                blocks: Vec::new(),
+                            })
+                            .collect();
+                        if count == 1 {
+                            // A lone component is coded block by block,
+                            // whatever its sampling factors say.
+                            (components[0].h, components[0].v) = (1, 1);
+                        }
+                        let mut coefficients = Coefficients { width, height, components, quant_tables: [None; 4], kept_segments: Vec::new() };
+                        let (mcus_x, mcus_y) = coefficients.mcus();
+                        for component in &mut coefficients.components {
+                            component.blocks_w = mcus_x * component.h;
+                            component.blocks_h = mcus_y * component.v;
+                            component.blocks = vec![[0; 64]; component.blocks_w * component.blocks_h];
+                        }
+                        frame = Some(coefficients);
+                    }
+                    0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => {
+                        return Err(error::unsupported_format("Lossless and arithmetic-coded JPEGs cannot be transformed"));
+                    }
+                    0xDD => decoder.restart_interval = u16_at(segment, 0)?,
+                    0xE2 if segment.starts_with(b"ICC_PROFILE\0") => kept_segments.push((marker, segment.to_vec())),
+                    0xEE if segment.starts_with(b"Adobe") => kept_segments.push((marker, segment.to_vec())),
+                    0xDA => {
+                        let frame = frame.as_mut().ok_or_else(corrupt)?;
+                        let count = *segment.first().ok_or_else(corrupt)? as usize;
+                        let tail = segment.get(1 + 2 * count..1 + 2 * count + 3).ok_or_else(corrupt)?;
+                        let components = segment[1..1 + 2 * count]
+                            .chunks(2)
+                            .map(|c| {
+                                let index = frame.components.iter().position(|component| component.id == c[0]).ok_or_else(corrupt)?;
+                                Ok((index, (c[1] >> 4) as usize & 3, (c[1] & 15) as usize & 3))
+                            })
+                            .collect::<Result<Vec<_>, JsValue>>()?;
+                        let scan = Scan {
+                            components,
+                            start: tail[0] as usize,
+                            end: (tail[1] as usize).min(63),
+                            high: tail[2] >> 4,
+                            low: tail[2] & 15,
+                        };
+                        // Spectral selection and successive approximation
+                        // as G.1.1.1.1 allows them.
+                        if decoder.progressive
+                            && (scan.start > scan.end || (scan.start == 0) != (scan.end == 0) || scan.high > 13 || scan.low > 13)
+                        {
+                            return Err(corrupt());
+                        }
+                        let end = entropy_end(data, pos);
+                        decoder.decode_scan(frame, &scan, &data[pos..end])?;
+                        pos = end;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    let mut frame = frame.ok_or_else(corrupt)?;
+    for component in &frame.components {
+        if quant_tables[component.quant_table].is_none() {
+            return Err(corrupt());
+        }
+    }
+    frame.quant_tables = quant_tables;
+    frame.kept_segments = kept_segments;
+    Ok(frame)
+}
+
+impl Coefficients {
+    // Mirrors left to right, trimming a partial MCU column on the right.
+    pub(crate) fn flip_horizontal(&mut self) -> Result<(), JsValue> {
+        let mcu_width = 8 * self.max_h();
+        let width = self.width / mcu_width * mcu_width;
+        if width == 0 {
+            return Err(error::invalid_argument("Image is too narrow to flip losslessly"));
+        }
+        self.width = width;
+        let mcus_x = width / mcu_width;
+        for component in &mut self.components {
+            let blocks_w = mcus_x * component.h;
+            let mut blocks = Vec::with_capacity(blocks_w * component.blocks_h);
+            for by in 0..component.blocks_h {
+                for bx in (0..blocks_w).rev() {
+                    let mut block = component.blocks[by * component.blocks_w + bx];
+                    for (index, coefficient) in block.iter_mut().enumerate() {
+                        if index % 2 == 1 {
+                            *coefficient = coefficient.saturating_neg();
+                        }
+                    }
+                    blocks.push(block);
+                }
+            }
+            (component.blocks_w, component.blocks) = (blocks_w, blocks);
+        }
+        Ok(())
+    }
+
+    // Mirrors top to bottom, trimming a partial MCU row at the bottom.
+    pub(crate) fn flip_vertical(&mut self) -> Result<(), JsValue> {
+        let mcu_height = 8 * self.max_v();
+        let height = self.height / mcu_height * mcu_height;
+        if height == 0 {
+            return Err(error::invalid_argument("Image is too short to flip losslessly"));
+        }
+        self.height = height;
+        let mcus_y = height / mcu_height;
+        for component in &mut self.components {
+            let blocks_h = mcus_y * component.v;
+            let mut blocks = Vec::with_capacity(component.blocks_w * blocks_h);
+            for by in (0..blocks_h).rev() {
+                for bx in 0..component.blocks_w {
+                    let mut block = component.blocks[by * component.blocks_w + bx];
+                    for (index, coefficient) in block.iter_mut().enumerate() {
+                        if (index / 8) % 2 == 1 {
+                            *coefficient = coefficient.saturating_neg();
+                        }
+                    }
+                    blocks.push(block);
+                }
+            }
+            (component.blocks_h, component.blocks) = (blocks_h, blocks);
+        }
+        Ok(())
+    }
+
+    // Mirrors across the main diagonal, which swaps the sampling factors and
+    // transposes the quantization tables along with the coefficients.
+    pub(crate) fn transpose(&mut self) {
+        let transposed = |block: &Block| -> Block { std::array::from_fn(|index| block[(index % 8) * 8 + index / 8]) };
+        (self.width, self.height) = (self.height, self.width);
+        for component in &mut self.components {
+            let mut blocks = Vec::with_capacity(component.blocks.len());
+            for bx in 0..component.blocks_w {
+                for by in 0..component.blocks_h {
+                    blocks.push(transposed(&component.blocks[by * component.blocks_w + bx]));
+                }
+            }
+            (component.h, component.v) = (component.v, component.h);
+            (component.blocks_w, component.blocks_h) = (component.blocks_h, component.blocks_w);
+            component.blocks = blocks;
+        }
+        for table in self.quant_tables.iter_mut().flatten() {
+            *table = std::array::from_fn(|index| table[(index % 8) * 8 + index / 8]);
+        }
+    }
+
+    // Applies the clockwise rotation or mirroring `orientation` (as EXIF, 1
+    // to 8) undoes, so the image is stored upright.
+    pub(crate) fn orient(&mut self, orientation: u32) -> Result<(), JsValue> {
+        match orientation {
+            2 => self.flip_horizontal(),
+            3 => self.flip_horizontal().and_then(|_| self.flip_vertical()),
+            4 => self.flip_vertical(),
+            5 => {
+                self.transpose();
+                Ok(())
+            }
+            6 => {
+                self.transpose();
+                self.flip_horizontal()
+            }
+            7 => {
+                self.transpose();
+                self.flip_vertical().and_then(|_| self.flip_horizontal())
+            }
+            8 => {
+                self.transpose();
+                self.flip_vertical()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Keeps the `width` × `height` rectangle at `x`, `y`, with the corner
+    // moved to the MCU boundary at or above and left of it.
+    pub(crate) fn crop(&mut self, x: u32, y: u32, width: u32, height: u32) -> Result<(), JsValue> {
+        let (x, y, width, height) = (x as usize, y as usize, width as usize, height as usize);
+        if width == 0 || height == 0 {
+            return Err(error::invalid_argument("Crop size must not be zero"));
+        }
+        if x.checked_add(width).is_none_or(|right| right > self.width) || y.checked_add(height).is_none_or(|bottom| bottom > self.height) {
+            return Err(error::invalid_argument("Crop rectangle is outside the image"));
+        }
+        let (mcu_width, mcu_height) = (8 * self.max_h(), 8 * self.max_v());
+        let (mcu_x, mcu_y) = (x / mcu_width, y / mcu_height);
+        self.width = x + width - mcu_x * mcu_width;
+        self.height = y + height - mcu_y * mcu_height;
+        let (mcus_x, mcus_y) = self.mcus();
+        for component in &mut self.components {
+            let (blocks_w, blocks_h) = (mcus_x * component.h, mcus_y * component.v);
+            let (left, top) = (mcu_x * component.h, mcu_y * component.v);
+            let mut blocks = Vec::with_capacity(blocks_w * blocks_h);
+            for by in top..top + blocks_h {
+                let row = by * component.blocks_w;
+                blocks.extend_from_slice(&component.blocks[row + left..row + left + blocks_w]);
+            }
+            (component.blocks_w, component.blocks_h, component.blocks) = (blocks_w, blocks_h, blocks);
+        }
+        Ok(())
+    }
+}
+
+// Huffman coding for output: the same block walk first counts symbols, to
+// build optimal tables, then writes them.
+trait Emit {
+    fn symbol(&mut self, table: usize, symbol: u8);
+    fn bits(&mut self, bits: u32, count: u8);
+}
+
+struct Counter {
+    frequencies: [[u32; 257]; 4],
+}
+
+impl Emit for Counter {
+    fn symbol(&mut self, table: usize, symbol: u8) {
+        self.frequencies[table][symbol as usize] += 1;
+    }
+
+    fn bits(&mut self, _: u32, _: u8) {}
+}
+
+struct Writer {
+    codes: [[(u32, u8); 256]; 4],
+    out: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl Writer {
+    fn flush_bytes(&mut self) {
+        while self.count >= 8 {
+            let byte = (self.buffer >> (self.count - 8)) as u8;
+            self.out.push(byte);
+            if byte == 0xFF {
+                self.out.push(0);
+            }
+            self.count -= 8;
+        }
+        self.buffer &= (1 << self.count) - 1;
+    }
+
+    // Pads the last byte with one bits.
+    fn finish(&mut self) {
+        let padding = (8 - self.count % 8) % 8;
+        self.bits((1 << padding) - 1, padding as u8);
+    }
+}
+
+impl Emit for Writer {
+    fn symbol(&mut self, table: usize, symbol: u8) {
+        let (code, length) = self.codes[table][symbol as usize];
+        self.bits(code, length);
+    }
+
+    fn bits(&mut self, bits: u32, count: u8) {
+        self.buffer = (self.buffer << count) | bits as u64;
+        self.count += count as u32;
+        self.flush_bytes();
+    }
+}
+
+// Size category and extra bits of a coefficient or DC difference.
+fn category(value: i32) -> (u8, u32) {
+    let size = 32 - value.unsigned_abs().leading_zeros();
+    let bits = if value < 0 { (value - 1) as u32 & ((1 << size) - 1) } else { value as u32 };
+    (size as u8, bits)
+}
+
+fn encode_block(out: &mut impl Emit, block: &Block, predictor: &mut i32, dc: usize, ac: usize) {
+    let (size, bits) = category(block[0] as i32 - *predictor);
+    *predictor = block[0] as i32;
+    out.symbol(dc, size);
+    out.bits(bits, size);
+    let mut run = 0;
+    for &natural in &ZIGZAG[1..] {
+        let value = block[natural] as i32;
+        if value == 0 {
+            run += 1;
+            continue;
+        }
+        while run >= 16 {
+            out.symbol(ac, 0xF0);
+            run -= 16;
+        }
+        let (size, bits) = category(value);
+        out.symbol(ac, (run << 4) | size);
+        out.bits(bits, size);
+        run = 0;
+    }
+    if run > 0 {
+        out.symbol(ac, 0x00);
+    }
+}
+
+// Code lengths for `frequencies` limited to 16 bits (Annex K.2), as the
+// DHT counts and symbols.
+fn huffman_table(frequencies: &[u32; 257]) -> ([u8; 16], Vec<u8>) {
+    let mut frequencies: Vec<u64> = frequencies.iter().map(|&f| f as u64).collect();
+    // A reserved symbol keeps any code from being all ones.
+    frequencies[256] = 1;
+    let mut code_size = [0usize; 257];
+    let mut others = [usize::MAX; 257];
+    loop {
+        let smallest = |exclude: usize, frequencies: &[u64]| {
+            (0..257).filter(|&i| i != exclude && frequencies[i] > 0).min_by_key(|&i| (frequencies[i], std::cmp::Reverse(i)))
+        };
+        let Some(mut c1) = smallest(usize::MAX, &frequencies) else { break };
+        let Some(mut c2) = smallest(c1, &frequencies) else { break };
+        frequencies[c1] += frequencies[c2];
+        frequencies[c2] = 0;
+        code_size[c1] += 1;
+        while others[c1] != usize::MAX {
+            c1 = others[c1];
+            code_size[c1] += 1;
+        }
+        others[c1] = c2;
+        code_size[c2] += 1;
+        while others[c2] != usize::MAX {
+            c2 = others[c2];
+            code_size[c2] += 1;
+        }
+    }
+    let mut lengths = [0usize; 33];
+    for &size in code_size.iter().filter(|&&size| size > 0) {
+        lengths[size.min(32)] += 1;
+    }
+    for i in (17..=32).rev() {
+        while lengths[i] > 0 {
+            let mut j = i - 2;
+            while lengths[j] == 0 {
+                j -= 1;
+            }
+            lengths[i] -= 2;
+            lengths[i - 1] += 1;
+            lengths[j + 1] += 2;
+            lengths[j] -= 1;
+        }
+    }
+    let mut longest = 16;
+    while lengths[longest] == 0 {
+        longest -= 1;
+    }
+    lengths[longest] -= 1;
+
+    let mut counts = [0u8; 16];
+    for (count, &length) in counts.iter_mut().zip(&lengths[1..17]) {
+        *count = length as u8;
+    }
+    let mut symbols: Vec<usize> = (0..256).filter(|&s| code_size[s] > 0).collect();
+    symbols.sort_by_key(|&s| (code_size[s], s));
+    (counts, symbols.into_iter().map(|s| s as u8).collect())
+}
+
+fn canonical_codes(counts: &[u8; 16], symbols: &[u8]) -> [(u32, u8); 256] {
+    let mut codes = [(0, 0); 256];
+    let (mut code, mut next) = (0u32, 0);
+    for (length, &count) in counts.iter().enumerate() {
+        for _ in 0..count {
+            codes[symbols[next] as usize] = (code, length as u8 + 1);
+            code += 1;
+            next += 1;
+        }
+        code <<= 1;
+    }
+    codes
+}
+
+fn push_segment(out: &mut Vec<u8>, marker: u8, payload: &[u8]) {
+    out.extend_from_slice(&[0xFF, marker]);
+    out.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+impl Coefficients {
+    fn walk(&self, out: &mut impl Emit) {
+        let (mcus_x, mcus_y) = self.mcus();
+        let mut predictors = vec![0i32; self.components.len()];
+        let tables = |index: usize| if index == 0 { (0, 1) } else { (2, 3) };
+        if self.components.len() == 1 {
+            let component = &self.components[0];
+            for by in 0..self.height.div_ceil(8) {
+                for bx in 0..self.width.div_ceil(8) {
+                    encode_block(out, &component.blocks[by * component.blocks_w + bx], &mut predictors[0], 0, 1);
+                }
+            }
+            return;
+        }
+        for my in 0..mcus_y {
+            for mx in 0..mcus_x {
+                for (index, component) in self.components.iter().enumerate() {
+                    let (dc, ac) = tables(index);
+                    for y in 0..component.v {
+                        for x in 0..component.h {
+                            let block = &component.blocks[(my * component.v + y) * component.blocks_w + mx * component.h + x];
+                            encode_block(out, block, &mut predictors[index], dc, ac);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Code length of each symbol in the optimized Huffman tables, which are
+    // luma DC and AC, then chroma DC and AC. Unused symbols have length 0.
+    pub(crate) fn code_lengths(&self) -> [[u8; 256]; 4] {
+        let mut counter = Counter { frequencies: [[0; 257]; 4] };
+        self.walk(&mut counter);
+        let mut lengths = [[0; 256]; 4];
+        for (table, frequencies) in counter.frequencies.iter().enumerate() {
+            if frequencies.iter().all(|&f| f == 0) {
+                continue;
+            }
+            let (counts, symbols) = huffman_table(frequencies);
+            for (length, (_, code_length)) in lengths[table].iter_mut().zip(canonical_codes(&counts, &symbols)) {
+                *length = code_length;
+            }
+        }
+        lengths
+    }
+
+    // A baseline JPEG with the kept segments, and a JFIF header unless an
+    // Adobe segment describes the color space instead.
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut counter = Counter { frequencies: [[0; 257]; 4] };
+        self.walk(&mut counter);
+
+        let mut out = vec![0xFF, 0xD8];
+        if !self.kept_segments.iter().any(|(marker, _)| *marker == 0xEE) {
+            push_segment(&mut out, 0xE0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+        }
+        for (marker, payload) in &self.kept_segments {
+            push_segment(&mut out, *marker, payload);
+        }
+        let mut extended = false;
+        for (id, table) in self.quant_tables.iter().enumerate() {
+            let Some(table) = table.filter(|_| self.components.iter().any(|c| c.quant_table == id)) else { continue };
+            let wide = table.iter().any(|&q| q > 255);
+            extended |= wide;
+            let mut payload = vec![(wide as u8) << 4 | id as u8];
+            for &natural in &ZIGZAG {
+                if wide {
+                    payload.extend_from_slice(&table[natural].to_be_bytes());
+                } else {
+                    payload.push(table[natural] as u8);
+                }
+            }
+            push_segment(&mut out, 0xDB, &payload);
+        }
+
+        let mut sof = vec![8];
+        sof.extend_from_slice(&(self.height as u16).to_be_bytes());
+        sof.extend_from_slice(&(self.width as u16).to_be_bytes());
+        sof.push(self.components.len() as u8);
+        for component in &self.components {
+            sof.extend_from_slice(&[component.id, (component.h as u8) << 4 | component.v as u8, component.quant_table as u8]);
+        }
+        // 16-bit quantization tables need the extended sequential process.
+        push_segment(&mut out, if extended { 0xC1 } else { 0xC0 }, &sof);
+
+        let mut codes = [[(0, 0); 256]; 4];
+        for (table, frequencies) in counter.frequencies.iter().enumerate() {
+            if frequencies.iter().all(|&f| f == 0) {
+                continue;
+            }
+            let (counts, symbols) = huffman_table(frequencies);
+            let mut payload = vec![((table % 2) as u8) << 4 | (table / 2) as u8];
+            payload.extend_from_slice(&counts);
+            payload.extend_from_slice(&symbols);
+            push_segment(&mut out, 0xC4, &payload);
+            codes[table] = canonical_codes(&counts, &symbols);
+        }
+
+        let mut sos = vec![self.components.len() as u8];
+        for (index, component) in self.components.iter().enumerate() {
+            let table = if index == 0 { 0x00 } else { 0x11 };
+            sos.extend_from_slice(&[component.id, table]);
+        }
+        sos.extend_from_slice(&[0, 63, 0]);
+        push_segment(&mut out, 0xDA, &sos);
+
+        let mut writer = Writer { codes, out, buffer: 0, count: 0 };
+        self.walk(&mut writer);
+        writer.finish();
+        let mut out = writer.out;
+        out.extend_from_slice(&[0xFF, 0xD9]);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ImageProcessor;
+    use jpeg_encoder::{ColorType, Encoder, SamplingFactor};
+
+    // A 45 × 29 test photo, so that neither side is a whole number of MCUs.
+    fn test_jpeg(subsampling: SamplingFactor) -> Vec<u8> {
+        encode_test_jpeg(subsampling, false)
+    }
+
+    fn encode_test_jpeg(subsampling: SamplingFactor, progressive: bool) -> Vec<u8> {
+        let (width, height) = (45usize, 29usize);
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.extend_from_slice(&[(x * 5) as u8, (y * 8) as u8, ((x * y) % 256) as u8]);
+            }
+        }
+        let mut buffer = Vec::new();
+        let mut encoder = Encoder::new(&mut buffer, 90);
+        encoder.set_sampling_factor(subsampling);
+        encoder.set_progressive(progressive);
+        encoder.encode(&pixels, width as u16, height as u16, ColorType::Rgb).unwrap();
+        buffer
+    }
+
+    fn coefficients(data: &[u8]) -> Coefficients {
+        decode(data, &Limits::default()).unwrap()
+    }
+
+    // Whether every block of `part` equals the block of `whole` offset by
+    // `mcu_x`, `mcu_y` MCUs.
+    fn same_blocks(part: &Coefficients, whole: &Coefficients, mcu_x: usize, mcu_y: usize) -> bool {
+        part.components.iter().zip(&whole.components).all(|(p, w)| {
+            (0..p.blocks_h).all(|by| {
+                (0..p.blocks_w).all(|bx| p.blocks[by * p.blocks_w + bx] == w.blocks[(by + mcu_y * w.v) * w.blocks_w + bx + mcu_x * w.h])
+            })
+        })
+    }
+
+    #[test]
+    fn encode_round_trips_coefficients() {
+        let original = coefficients(&test_jpeg(SamplingFactor::R_4_2_0));
+        let copy = coefficients(&original.encode());
+        assert_eq!((copy.width, copy.height), (45, 29));
+        assert!(same_blocks(&copy, &original, 0, 0));
+        assert_eq!(copy.quant_tables, original.quant_tables);
+    }
+
+    #[test]
+    fn full_turns_keep_the_trimmed_image() {
+        let jpeg = test_jpeg(SamplingFactor::R_4_2_0);
+        let original = coefficients(&jpeg);
+        let processor = ImageProcessor::new();
+        for (transform, times) in [("rotate90", 4), ("rotate180", 2), ("rotate270", 4), ("transpose", 2)] {
+            let mut output = jpeg.clone();
+            for _ in 0..times {
+                output = processor.jpeg_transform(&output, transform).unwrap();
+            }
+            let turned = coefficients(&output);
+            // Transposing needs no whole MCUs; the rest trim the partial
+            // 16-pixel MCUs on both axes.
+            let expected = if transform == "transpose" { (45, 29) } else { (32, 16) };
+            assert_eq!((turned.width, turned.height), expected, "{}", transform);
+            assert!(same_blocks(&turned, &original, 0, 0), "{}", transform);
+        }
+    }
+
+    #[test]
+    fn flip_mirrors_pixels() {
+        let jpeg = test_jpeg(SamplingFactor::R_4_4_4);
+        let flipped = ImageProcessor::new().jpeg_transform(&jpeg, "flip-horizontal").unwrap();
+        let original = image::load_from_memory(&jpeg).unwrap().to_rgb8();
+        let flipped = image::load_from_memory(&flipped).unwrap().to_rgb8();
+        // The partial MCU column, 5 pixels of 8, is trimmed.
+        assert_eq!(flipped.dimensions(), (40, 29));
+        for (x, y, pixel) in flipped.enumerate_pixels() {
+            let source = original.get_pixel(39 - x, y);
+            assert!(pixel.0.iter().zip(source.0).all(|(a, b)| a.abs_diff(b) <= 2), "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn crop_moves_the_corner_to_the_mcu_boundary() {
+        let jpeg = test_jpeg(SamplingFactor::R_4_2_0);
+        let original = coefficients(&jpeg);
+        let processor = ImageProcessor::new();
+        // With 16-pixel MCUs, x 20 starts in the second MCU column and y 9
+        // in the first row, so 4 and 9 pixels are kept before the request.
+        for ((x, y, width, height), (mcu_x, mcu_y), size) in [
+            ((20, 9, 10, 10), (1, 0), (14, 19)),
+            ((16, 16, 16, 13), (1, 1), (16, 13)),
+            ((0, 0, 45, 29), (0, 0), (45, 29)),
+            ((40, 20, 5, 9), (2, 1), (13, 13)),
+        ] {
+            let cropped = processor.jpeg_crop(&jpeg, x, y, width, height).unwrap();
+            assert_eq!(image::load_from_memory(&cropped).unwrap().to_rgb8().dimensions(), size);
+            let cropped = coefficients(&cropped);
+            assert_eq!((cropped.width as u32, cropped.height as u32), size);
+            assert!(same_blocks(&cropped, &original, mcu_x, mcu_y), "crop at {}, {}", x, y);
+        }
+    }
+
+    #[test]
+    fn flips_saturate_the_most_negative_coefficient() {
+        let mut coefficients = coefficients(&test_jpeg(SamplingFactor::R_4_4_4));
+        coefficients.components[0].blocks[0][1] = i16::MIN;
+        coefficients.components[0].blocks[0][8] = i16::MIN;
+        coefficients.flip_horizontal().unwrap();
+        coefficients.flip_vertical().unwrap();
+        let last = coefficients.components[0].blocks.len() - 1;
+        let block = coefficients.components[0].blocks[last];
+        assert_eq!((block[1], block[8]), (i16::MAX, i16::MAX));
+    }
+
+    // Corrupted files must fail with an error, never overflow or index out
+    // of range. Natively an error panics with the message below, since a
+    // JsValue cannot be built outside WebAssembly, so any other panic is a
+    // bug.
+    #[test]
+    fn corrupt_input_is_rejected_without_overflow() {
+        const ERROR_PANIC: &str = "cannot convert to JsValue outside of the Wasm target";
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut random = move |bound: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % bound as u64) as usize
+        };
+        for progressive in [false, true] {
+            let jpeg = encode_test_jpeg(SamplingFactor::R_4_2_0, progressive);
+            for _ in 0..2000 {
+                let mut data = jpeg.clone();
+                for _ in 0..1 + random(4) {
+                    let index = 2 + random(data.len() - 2);
+                    data[index] = random(256) as u8;
+                }
+                let result = std::panic::catch_unwind(|| {
+                    if let Ok(mut coefficients) = decode(&data, &Limits::default()) {
+                        let _ = coefficients.encode();
+                        let _ = coefficients.flip_horizontal();
+                        let _ = coefficients.flip_vertical();
+                        coefficients.transpose();
+                        let _ = coefficients.encode();
+                    }
+                });
+                if let Err(payload) = result {
+                    let message = payload
+                        .downcast_ref::<&str>()
+                        .map(|m| m.to_string())
+                        .or_else(|| payload.downcast_ref::<String>().cloned())
+                        .unwrap_or_default();
+                    assert_eq!(message, ERROR_PANIC, "progressive: {}", progressive);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,330 @@
+// Trellis quantization for JPEG output, as in mozjpeg. Rounding each DCT
+// coefficient to the nearest level ignores what it costs to code; here each
+// block's AC levels are chosen together, by dynamic programming over the
+// zigzag order, to minimize the squared error plus the Huffman-coded size.
+// Levels whose bits buy little error are lowered or dropped, which mostly
+// lengthens zero runs and ends blocks earlier.
+//
+// The bit costs come from Huffman tables optimized for the rounded levels.
+// The result is written through jpeg_lossless's baseline encoder, which
+// optimizes the tables again for the levels actually chosen.
+
+use crate::jpeg_lossless::{Block, Coefficients, ZIGZAG};
+use image::RgbImage;
+
+// Example tables of Annex K (K.1), in natural order.
+const ANNEX_K_LUMA: [u16; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56, 14, 17, 22, 29, 51, 87, 80, 62,
+    18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113, 92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112,
+    100, 103, 99,
+];
+
+const ANNEX_K_CHROMA: [u16; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99, 99, 47, 66, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+// N. Robidoux's table, which mozjpeg and jpeg-encoder's ImageMagick option
+// use for both luma and chroma.
+const ROBIDOUX: [u16; 64] = [
+    16, 16, 16, 18, 25, 37, 56, 85, 16, 17, 20, 27, 34, 40, 53, 75, 16, 20, 24, 31, 43, 62, 91, 135, 18, 27, 31, 40, 53, 74, 106,
+    156, 25, 34, 43, 53, 69, 94, 131, 189, 37, 40, 62, 74, 94, 124, 169, 238, 56, 53, 91, 106, 131, 169, 226, 311, 85, 75, 135,
+    156, 189, 238, 311, 418,
+];
+
+// Weight of the squared error against one bit, with the error measured in
+// units of the table's smallest AC step. Every frequency is weighed alike,
+// which suits PSNR: weighing the error per step, as mozjpeg does, made
+// files larger at equal PSNR.
+const LAMBDA: f32 = 4.0;
+
+// Largest AC level for 8-bit samples (F.1.2.2).
+const MAX_LEVEL: f32 = 1023.0;
+
+// libjpeg's quality scaling, as jpeg-encoder applies it.
+fn scaled_table(table: &[u16; 64], quality: u8) -> [u16; 64] {
+    let quality = quality.clamp(1, 100) as u32;
+    let scale = if quality < 50 { 5000 / quality } else { 200 - quality * 2 };
+    table.map(|q| ((q as u32 * scale + 50) / 100).clamp(1, 255) as u16)
+}
+
+// One JFIF YCbCr component, padded to whole MCUs.
+struct Plane {
+    width: usize,
+    height: usize,
+    samples: Vec<u8>,
+}
+
+fn to_u8(value: f32) -> u8 {
+    value.round().clamp(0.0, 255.0) as u8
+}
+
+// The Y, Cb and Cr planes, with the edges repeated out to whole MCUs and
+// chroma averaged over `h` x `v` pixels.
+fn planes(img: &RgbImage, h: usize, v: usize) -> [Plane; 3] {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let (mcus_x, mcus_y) = (width.div_ceil(8 * h), height.div_ceil(8 * v));
+    let pixel = |x: usize, y: usize| {
+        let p = img.get_pixel(x.min(width - 1) as u32, y.min(height - 1) as u32).0;
+        [p[0] as f32, p[1] as f32, p[2] as f32]
+    };
+
+    let (luma_width, luma_height) = (mcus_x * 8 * h, mcus_y * 8 * v);
+    let mut luma = Vec::with_capacity(luma_width * luma_height);
+    for y in 0..luma_height {
+        for x in 0..luma_width {
+            let [r, g, b] = pixel(x, y);
+            luma.push(to_u8(0.299 * r + 0.587 * g + 0.114 * b));
+        }
+    }
+
+    let (chroma_width, chroma_height) = (mcus_x * 8, mcus_y * 8);
+    let mut cb = Vec::with_capacity(chroma_width * chroma_height);
+    let mut cr = Vec::with_capacity(chroma_width * chroma_height);
+    for cy in 0..chroma_height {
+        for cx in 0..chroma_width {
+            let mut sum = [0.0f32; 3];
+            for y in cy * v..(cy + 1) * v {
+                for x in cx * h..(cx + 1) * h {
+                    for (total, channel) in sum.iter_mut().zip(pixel(x, y)) {
+                        *total += channel;
+                    }
+                }
+            }
+            let [r, g, b] = sum.map(|total| total / (h * v) as f32);
+            cb.push(to_u8(-0.168736 * r - 0.331264 * g + 0.5 * b + 128.0));
+            cr.push(to_u8(0.5 * r - 0.418688 * g - 0.081312 * b + 128.0));
+        }
+    }
+
+    [
+        Plane { width: luma_width, height: luma_height, samples: luma },
+        Plane { width: chroma_width, height: chroma_height, samples: cb },
+        Plane { width: chroma_width, height: chroma_height, samples: cr },
+    ]
+}
+
+// Basis of the 8-point DCT-II scaled as in A.3.3, so that the 2-D transform
+// is orthonormal and its coefficients divide straight by the table.
+fn dct_basis() -> [[f32; 8]; 8] {
+    let mut basis = [[0.0; 8]; 8];
+    for (u, row) in basis.iter_mut().enumerate() {
+        let scale = if u == 0 { std::f32::consts::FRAC_1_SQRT_2 } else { 1.0 } / 2.0;
+        for (x, value) in row.iter_mut().enumerate() {
+            *value = scale * ((2 * x + 1) as f32 * u as f32 * std::f32::consts::PI / 16.0).cos();
+        }
+    }
+    basis
+}
+
+// DCT coefficients of the block at (`bx`, `by`), in natural order.
+fn forward_dct(plane: &Plane, bx: usize, by: usize, basis: &[[f32; 8]; 8]) -> [f32; 64] {
+    let mut rows = [0.0f32; 64];
+    for y in 0..8 {
+        let line = &plane.samples[(by * 8 + y) * plane.width + bx * 8..][..8];
+        for u in 0..8 {
+            rows[y * 8 + u] = line.iter().zip(&basis[u]).map(|(&s, &b)| (s as f32 - 128.0) * b).sum();
+        }
+    }
+    let mut out = [0.0f32; 64];
+    for v in 0..8 {
+        for u in 0..8 {
+            out[v * 8 + u] = (0..8).map(|y| rows[y * 8 + u] * basis[v][y]).sum();
+        }
+    }
+    out
+}
+
+fn round(coefficients: &[f32; 64], table: &[u16; 64]) -> Block {
+    let mut block = [0i16; 64];
+    for ((level, &c), &q) in block.iter_mut().zip(coefficients).zip(table) {
+        *level = (c / q as f32).round().clamp(-MAX_LEVEL, MAX_LEVEL) as i16;
+    }
+    block
+}
+
+fn size(level: u32) -> u32 {
+    32 - level.leading_zeros()
+}
+
+// Replaces the rounded AC levels of `block` with those minimizing error
+// plus bits, given the AC table's code `lengths`. Each level is either its
+// rounded value or one less, since lowering it further rarely pays; the
+// search runs over the previous nonzero position, which fixes the run.
+fn trellis(coefficients: &[f32; 64], table: &[u16; 64], lengths: &[u8; 256], block: &mut Block) {
+    // A symbol the rounded levels never used gets a long code in the final
+    // tables too.
+    let bits = |symbol: u32| match lengths[symbol as usize] {
+        0 => 16.0,
+        length => length as f32,
+    };
+
+    // Coefficients and steps by zigzag position in units of the smallest
+    // step, and the cost of zeroing every position up to each one.
+    let unit = table[1..].iter().copied().min().unwrap_or(1) as f32;
+    let mut values = [0.0f32; 64];
+    let mut steps = [0.0f32; 64];
+    let mut zeroed = [0.0f32; 64];
+    for i in 1..64 {
+        values[i] = coefficients[ZIGZAG[i]] / unit;
+        steps[i] = table[ZIGZAG[i]] as f32 / unit;
+        zeroed[i] = zeroed[i - 1] + LAMBDA * values[i] * values[i];
+    }
+
+    // Cheapest coding of the positions up to each one, given a nonzero level
+    // there, with the previous nonzero position and the level. Position 0,
+    // the DC, starts every run.
+    let mut best = [f32::INFINITY; 64];
+    let mut choice = [(0usize, 0u32); 64];
+    best[0] = 0.0;
+    let mut starts = Vec::with_capacity(64);
+    starts.push(0);
+    for i in 1..64 {
+        let magnitude = values[i].abs();
+        let rounded = (magnitude / steps[i]).round().min(MAX_LEVEL) as u32;
+        if rounded == 0 {
+            continue;
+        }
+        for level in [rounded, rounded - 1].into_iter().filter(|&level| level > 0) {
+            let error = magnitude - level as f32 * steps[i];
+            let own = LAMBDA * error * error + size(level) as f32;
+            for &j in &starts {
+                let run = (i - j - 1) as u32;
+                let cost = best[j] + zeroed[i - 1] - zeroed[j] + own + (run / 16) as f32 * bits(0xF0) + bits((run % 16) << 4 | size(level));
+                if cost < best[i] {
+                    best[i] = cost;
+                    choice[i] = (j, level);
+                }
+            }
+        }
+        starts.push(i);
+    }
+
+    // The block ends after its last nonzero level, with an end-of-block code
+    // unless that is the last position.
+    let mut last = 0;
+    let mut total = f32::INFINITY;
+    for &j in &starts {
+        let cost = best[j] + zeroed[63] - zeroed[j] + if j < 63 { bits(0x00) } else { 0.0 };
+        if cost < total {
+            total = cost;
+            last = j;
+        }
+    }
+    for &natural in &ZIGZAG[1..] {
+        block[natural] = 0;
+    }
+    while last > 0 {
+        let (previous, level) = choice[last];
+        block[ZIGZAG[last]] = if values[last] < 0.0 { -(level as i16) } else { level as i16 };
+        last = previous;
+    }
+}
+
+type Quantize<'a> = dyn Fn(&[f32; 64], &[u16; 64], usize) -> Block + 'a;
+
+// A baseline JPEG of `img` at `quality`, with chroma subsampled by `h` x
+// `v` and the Annex K or, when `tuned_tables`, the Robidoux tables.
+pub(crate) fn encode(img: &RgbImage, quality: u8, h: usize, v: usize, tuned_tables: bool) -> Vec<u8> {
+    let (luma_table, chroma_table) = if tuned_tables { (&ROBIDOUX, &ROBIDOUX) } else { (&ANNEX_K_LUMA, &ANNEX_K_CHROMA) };
+    let tables = [scaled_table(luma_table, quality), scaled_table(chroma_table, quality)];
+    let planes = planes(img, h, v);
+    let basis = dct_basis();
+    let (width, height) = (img.width() as usize, img.height() as usize);
+
+    // Blocks of every component, with `quantize` turning each block's
+    // coefficients into levels given its table and AC table index.
+    let components = |quantize: &Quantize| {
+        let sampling = [(h, v), (1, 1), (1, 1)];
+        let components = planes.iter().zip(sampling).enumerate().map(|(index, (plane, (ch, cv)))| {
+            let table = &tables[index.min(1)];
+            let ac = if index == 0 { 1 } else { 3 };
+            let mut blocks = Vec::with_capacity(plane.width / 8 * (plane.height / 8));
+            for by in 0..plane.height / 8 {
+                for bx in 0..plane.width / 8 {
+                    blocks.push(quantize(&forward_dct(plane, bx, by, &basis), table, ac));
+                }
+            }
+            (ch, cv, blocks)
+        });
+        Coefficients::new(width, height, components.collect(), tables)
+    };
+
+    let lengths = components(&|coefficients, table, _| round(coefficients, table)).code_lengths();
+    components(&|coefficients, table, ac| {
+        let mut block = round(coefficients, table);
+        trellis(coefficients, table, &lengths[ac], &mut block);
+        block
+    })
+    .encode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    fn test_image(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| {
+            let wave = ((x as f32 * 0.3).sin() * (y as f32 * 0.2).cos() * 60.0) as i32;
+            Rgb([(x * 7 % 256) as u8, (128 + wave).clamp(0, 255) as u8, (y * 5 % 256) as u8])
+        })
+    }
+
+    fn psnr(a: &RgbImage, b: &RgbImage) -> f64 {
+        let sse: f64 = a.as_raw().iter().zip(b.as_raw()).map(|(&x, &y)| (x as f64 - y as f64).powi(2)).sum();
+        10.0 * (255.0f64.powi(2) * a.as_raw().len() as f64 / sse).log10()
+    }
+
+    fn decode(data: &[u8]) -> RgbImage {
+        image::load_from_memory_with_format(data, image::ImageFormat::Jpeg).unwrap().to_rgb8()
+    }
+
+    #[test]
+    fn dct_is_orthonormal() {
+        let plane = Plane { width: 8, height: 8, samples: (0..64).map(|i| (i * 37 % 256) as u8).collect() };
+        let coefficients = forward_dct(&plane, 0, 0, &dct_basis());
+        let energy: f32 = plane.samples.iter().map(|&s| (s as f32 - 128.0).powi(2)).sum();
+        let transformed: f32 = coefficients.iter().map(|c| c * c).sum();
+        assert!((energy - transformed).abs() / energy < 1e-4);
+    }
+
+    #[test]
+    fn trellis_keeps_levels_that_pay_for_themselves() {
+        let mut coefficients = [0.0f32; 64];
+        let table = [1u16; 64];
+        // One large coefficient is kept; a lone +1 at the end of the block
+        // costs more bits than its error is worth.
+        coefficients[ZIGZAG[1]] = 40.0;
+        coefficients[ZIGZAG[60]] = 0.6;
+        let lengths = [4u8; 256];
+        let mut block = round(&coefficients, &table);
+        assert_eq!(block[ZIGZAG[60]], 1);
+        trellis(&coefficients, &table, &lengths, &mut block);
+        assert_eq!(block[ZIGZAG[1]], 40);
+        assert_eq!(block[ZIGZAG[60]], 0);
+    }
+
+    #[test]
+    fn output_decodes_at_odd_sizes() {
+        for (h, v) in [(1, 1), (2, 1), (2, 2)] {
+            let img = test_image(37, 21);
+            let decoded = decode(&encode(&img, 90, h, v, true));
+            assert_eq!(decoded.dimensions(), (37, 21));
+            assert!(psnr(&img, &decoded) > 28.0);
+        }
+    }
+
+    #[test]
+    fn trellis_is_smaller_than_rounding() {
+        let img = test_image(96, 64);
+        let trellis = encode(&img, 75, 2, 2, true);
+        let mut rounded = Vec::new();
+        let mut encoder = jpeg_encoder::Encoder::new(&mut rounded, 75);
+        encoder.set_optimized_huffman_tables(true);
+        encoder.set_quantization_tables(jpeg_encoder::QuantizationTableType::ImageMagick, jpeg_encoder::QuantizationTableType::ImageMagick);
+        encoder.encode(img.as_raw(), 96, 64, jpeg_encoder::ColorType::Rgb).unwrap();
+        assert!(trellis.len() < rounded.len());
+        assert!(psnr(&img, &decode(&trellis)) > psnr(&img, &decode(&rounded)) - 1.0);
+    }
+}
@@ -0,0 +1,115 @@
+// JPEG XL support: decoding through jxl-oxide and lossless encoding through
+// zune-jpegxl, both pure Rust. Neither ships threading here, since the
+// module runs on a single WebAssembly thread. Neither crate can recompress
+// a JPEG losslessly, keeping its DCT coefficients; jxl_recompress writes
+// that, and reconstruct_jpeg below turns such files back into the JPEG.
+
+use crate::error;
+use image::{DynamicImage, ImageBuffer};
+use jxl_oxide::{JpegReconstructionStatus, JxlImage, PixelFormat};
+use std::io::Cursor;
+use wasm_bindgen::prelude::*;
+use zune_core::bit_depth::BitDepth;
+use zune_core::colorspace::ColorSpace;
+use zune_core::options::EncoderOptions;
+use zune_jpegxl::JxlSimpleEncoder;
+
+// Bare codestream or ISO BMFF container signature.
+pub(crate) fn is_jxl(data: &[u8]) -> bool {
+    data.starts_with(&[0xFF, 0x0A])
+        || data.starts_with(&[0, 0, 0, 0x0C, b'J', b'X', b'L', b' ', 0x0D, 0x0A, 0x87, 0x0A])
+}
+
+fn read(data: &[u8]) -> Result<JxlImage, JsValue> {
+    JxlImage::builder()
+        .read(Cursor::new(data))
+        .map_err(error::decode_failed)
+}
+
+// Pixel size from the header alone.
+pub(crate) fn dimensions(data: &[u8]) -> Result<(u32, u32), JsValue> {
+    let image = read(data)?;
+    Ok((image.width(), image.height()))
+}
+
+pub(crate) fn has_icc_profile(data: &[u8]) -> bool {
+    is_jxl(data) && read(data).is_ok_and(|image| image.original_icc().is_some())
+}
+
+// Decodes the first frame to 8-bit gray or RGB, with alpha when present.
+pub(crate) fn decode(data: &[u8]) -> Result<DynamicImage, JsValue> {
+    let image = read(data)?;
+    if matches!(image.pixel_format(), PixelFormat::Cmyk | PixelFormat::Cmyka) {
+        return Err(error::unsupported_format("CMYK JPEG XL is not supported"));
+    }
+    let render = image
+        .render_frame(0)
+        .map_err(error::decode_failed)?;
+
+    let mut stream = render.stream();
+    let (width, height, channels) = (stream.width(), stream.height(), stream.channels());
+    let len = buffer_len(width, height, channels)
+        .ok_or_else(|| error::size_limit("JPEG XL image is too large to decode"))?;
+    let mut pixels = vec![0u8; len];
+    stream.write_to_buffer(&mut pixels);
+
+    let img = match channels {
+        1 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        2 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8),
+        3 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        4 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8),
+        _ => None,
+    };
+    img.ok_or_else(|| error::decode_failed("unexpected JPEG XL channel layout"))
+}
+
+// Bytes in a `width` x `height` buffer of `channels` 8-bit samples, or None
+// when that does not fit in the address space.
+fn buffer_len(width: u32, height: u32, channels: u32) -> Option<usize> {
+    (width as usize).checked_mul(height as usize)?.checked_mul(channels as usize)
+}
+
+// The original JPEG, bit for bit, if `data` is a losslessly recompressed
+// JPEG carrying its reconstruction data.
+pub(crate) fn reconstruct_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+    let image = read(data).ok()?;
+    if image.jpeg_reconstruction_status() != JpegReconstructionStatus::Available {
+        return None;
+    }
+    let mut jpeg = Vec::new();
+    image.reconstruct_jpeg(&mut jpeg).ok()?;
+    Some(jpeg)
+}
+
+// Lossless 8-bit encode, keeping alpha when the image has it.
+pub(crate) fn encode(img: &DynamicImage) -> Result<Vec<u8>, JsValue> {
+    let (pixels, color_space) = if img.color().has_alpha() {
+        (img.to_rgba8().into_raw(), ColorSpace::RGBA)
+    } else {
+        (img.to_rgb8().into_raw(), ColorSpace::RGB)
+    };
+    let options = EncoderOptions::new(img.width() as usize, img.height() as usize, color_space, BitDepth::Eight);
+    let mut buffer = Vec::new();
+    JxlSimpleEncoder::new(&pixels, options)
+        .encode(&mut buffer)
+        .map_err(|e| error::encode_failed("JPEG XL", format!("{:?}", e)))?;
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffer_len_widens_before_multiplying() {
+        assert_eq!(buffer_len(640, 480, 3), Some(921_600));
+        assert_eq!(buffer_len(0, 480, 4), Some(0));
+        // 70000 x 70000 x 4 wraps in u32 arithmetic.
+        assert_eq!(buffer_len(70_000, 70_000, 4), 70_000usize.checked_mul(70_000 * 4));
+    }
+
+    #[test]
+    fn buffer_len_rejects_overflow() {
+        assert_eq!(buffer_len(u32::MAX, u32::MAX, 4), None);
+    }
+}
@@ -0,0 +1,1277 @@
+// Lossless recompression of a JPEG into JPEG XL, in the manner of cjxl's
+// lossless JPEG mode: the quantized DCT coefficients become a VarDCT frame
+// of 8x8 blocks, dequantized with the JPEG's own tables and kept in YCbCr
+// at the JPEG's chroma subsampling, and a jbrd box records the rest of the
+// file (marker order, Huffman tables, scan script, APP and COM segments) so
+// that a decoder can write the original JPEG back bit for bit. Exif and XMP
+// go into their own boxes, where JPEG XL readers look for them.
+//
+// Only what that needs is written: prefix codes rather than ANS, LZ77 only
+// in cluster maps, the natural coefficient order and a gradient-predicted
+// MA tree per modular image. Three-component JPEGs with 4:4:4, 4:2:2, 4:4:0
+// or 4:2:0 sampling are supported, baseline or progressive; progressive
+// scans must run EOBs together as far as they go, as libjpeg writes them,
+// since no reset points are recorded. Anything else, such as grayscale,
+// CMYK, RGB-coded files, ICC profiles or bytes between segments, returns
+// None and is encoded from pixels instead. Every result is checked by
+// reconstructing the JPEG from it, so a file this gets wrong falls back
+// rather than converting wrongly.
+
+use crate::jpeg_lossless::{self, Block, Coefficients, ZIGZAG};
+use crate::jxl;
+use crate::limits::Limits;
+use crate::metadata;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+// Context offsets of the coefficient entropy code by position in the
+// order and by the number of non-zero coefficients left.
+const COEFF_FREQ_CONTEXT: [u32; 63] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 15, 16, 16, 17, 17, 18, 18, 19, 19, 20, 20, 21, 21, 22, 22, 23,
+    23, 23, 23, 24, 24, 24, 24, 25, 25, 25, 25, 26, 26, 26, 26, 27, 27, 27, 27, 28, 28, 28, 28, 29, 29, 29, 29, 30, 30,
+    30, 30,
+];
+const COEFF_NUM_NONZERO_CONTEXT: [u32; 63] = [
+    0, 31, 62, 62, 93, 93, 93, 93, 123, 123, 123, 123, 152, 152, 152, 152, 152, 152, 152, 152, 180, 180, 180, 180, 180,
+    180, 180, 180, 180, 180, 180, 180, 206, 206, 206, 206, 206, 206, 206, 206, 206, 206, 206, 206, 206, 206, 206, 206,
+    206, 206, 206, 206, 206, 206, 206, 206, 206, 206, 206, 206, 206, 206, 206,
+];
+
+// Index in a JPEG block of each coefficient in the JPEG XL natural order
+// for 8x8 DCTs, which runs in zigzag over the transposed block.
+const NATURAL_ORDER: [usize; 64] = {
+    let mut order = [0; 64];
+    let mut z = 0;
+    while z < 64 {
+        order[z] = ZIGZAG[z] % 8 * 8 + ZIGZAG[z] / 8;
+        z += 1;
+    }
+    order
+};
+
+// Contexts per block context in the coefficient entropy code: 37 for the
+// non-zero counts and 458 for the coefficients.
+const HF_CONTEXTS: usize = 495;
+
+// Largest number of clusters contexts are merged into, and the fewest bits
+// a new cluster must save to be worth its prefix code.
+const MAX_CLUSTERS: usize = 32;
+const MIN_CLUSTER_GAIN: f64 = 128.0;
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+// U32 field distributions, as (offset, extra bits).
+type Distributions = [(u32, u32); 4];
+const IMAGE_SIZE: Distributions = [(1, 9), (1, 13), (1, 18), (1, 30)];
+const TOC_ENTRY: Distributions = [(0, 10), (1024, 14), (17408, 22), (4211712, 30)];
+
+// Bits written least significant first, as JPEG XL and brotli read them.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    used: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, bits: u32, value: u64) {
+        debug_assert!(bits <= 32 && (bits == 32 || value >> bits == 0));
+        self.buffer |= value << self.used;
+        self.used += bits;
+        while self.used >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.used -= 8;
+        }
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.write(1, value as u64);
+    }
+
+    fn len(&self) -> usize {
+        self.bytes.len() * 8 + self.used as usize
+    }
+
+    fn zero_pad(&mut self) {
+        if self.used > 0 {
+            self.write(8 - self.used, 0);
+        }
+    }
+
+    fn append(&mut self, other: &BitWriter) {
+        if self.used == 0 {
+            self.bytes.extend_from_slice(&other.bytes);
+        } else {
+            for &byte in &other.bytes {
+                self.write(8, byte as u64);
+            }
+        }
+        self.write(other.used, other.buffer);
+    }
+
+    // Whole bytes; the writer must be at a byte boundary.
+    fn bytes(&mut self, data: &[u8]) {
+        debug_assert_eq!(self.used, 0);
+        self.bytes.extend_from_slice(data);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.zero_pad();
+        self.bytes
+    }
+
+    // A U32 field: the first distribution that holds `value`, and the
+    // offset from it.
+    fn u32(&mut self, value: u32, distributions: Distributions) {
+        let selector = distributions
+            .iter()
+            .position(|&(offset, bits)| value >= offset && ((value - offset) as u64) < 1 << bits)
+            .expect("value fits the field");
+        let (offset, bits) = distributions[selector];
+        self.write(2, selector as u64);
+        self.write(bits, (value - offset) as u64);
+    }
+
+    // A U64 field, for the small values written here.
+    fn u64(&mut self, value: u64) {
+        match value {
+            0 => self.write(2, 0),
+            1..=16 => {
+                self.write(2, 1);
+                self.write(4, value - 1);
+            }
+            _ => {
+                debug_assert!(value <= 272);
+                self.write(2, 2);
+                self.write(8, value - 17);
+            }
+        }
+    }
+
+    fn f16(&mut self, value: f32) {
+        self.write(16, f16_bits(value) as u64);
+    }
+}
+
+// Half-precision bits of zero or a positive normal value, rounded to
+// nearest.
+fn f16_bits(value: f32) -> u16 {
+    if value == 0.0 {
+        return 0;
+    }
+    let bits = value.to_bits();
+    let exponent = ((bits >> 23) & 0xFF) as i32 - 127 + 15;
+    debug_assert!((1..31).contains(&exponent));
+    // A mantissa that rounds up carries into the exponent.
+    (((exponent as u32) << 10) + (((bits & 0x7F_FFFF) + 0x1000) >> 13)) as u16
+}
+
+fn pack_signed(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+// Bits needed to tell `count` values apart.
+fn ceil_log2(count: usize) -> u32 {
+    count.next_power_of_two().trailing_zeros()
+}
+
+// A value split into a prefix-coded symbol and raw bits, in the
+// hybrid integer configuration every distribution here uses: symbols
+// 0-15 are literal, and each larger value keeps two bits below its top
+// one in the symbol.
+#[derive(Clone, Copy)]
+struct Token {
+    context: u32,
+    symbol: u32,
+    extra_bits: u32,
+    extra: u32,
+}
+
+impl Token {
+    fn new(context: usize, value: u32) -> Token {
+        if value < 16 {
+            return Token { context: context as u32, symbol: value, extra_bits: 0, extra: 0 };
+        }
+        let top = 31 - value.leading_zeros();
+        let extra_bits = top - 2;
+        Token {
+            context: context as u32,
+            symbol: 16 + ((top - 4) << 2) + ((value >> extra_bits) & 3),
+            extra_bits,
+            extra: value & ((1 << extra_bits) - 1),
+        }
+    }
+
+    // An LZ77 copy of `length` values, to be followed by its distance.
+    fn copy(context: usize, length: u32) -> Token {
+        let mut token = Token::new(context, length - 3);
+        token.symbol += 224;
+        token
+    }
+}
+
+// Writes the hybrid integer configuration of `Token`: split exponent 4,
+// two most and no least significant bits in the token.
+fn write_config(w: &mut BitWriter) {
+    w.write(4, 4);
+    w.write(3, 2);
+    w.write(2, 0);
+}
+
+// Huffman code lengths for `counts`, none longer than `limit`. Counts are
+// halved until the code fits, which flattens it a little at a time.
+fn code_lengths(counts: &[u32], limit: u8) -> Vec<u8> {
+    let mut counts = counts.to_vec();
+    loop {
+        let lengths = huffman_lengths(&counts);
+        if lengths.iter().all(|&length| length <= limit) {
+            return lengths;
+        }
+        for count in counts.iter_mut().filter(|count| **count > 0) {
+            *count = count.div_ceil(2);
+        }
+    }
+}
+
+fn huffman_lengths(counts: &[u32]) -> Vec<u8> {
+    let mut parents = vec![usize::MAX; counts.len()];
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> =
+        counts.iter().enumerate().filter(|(_, &count)| count > 0).map(|(symbol, &count)| Reverse((count as u64, symbol))).collect();
+    while heap.len() > 1 {
+        let Reverse((a, i)) = heap.pop().unwrap();
+        let Reverse((b, j)) = heap.pop().unwrap();
+        let node = parents.len();
+        parents.push(usize::MAX);
+        (parents[i], parents[j]) = (node, node);
+        heap.push(Reverse((a + b, node)));
+    }
+    (0..counts.len())
+        .map(|symbol| {
+            let mut length = 0;
+            let mut node = symbol;
+            while parents[node] != usize::MAX {
+                node = parents[node];
+                length += 1;
+            }
+            length
+        })
+        .collect()
+}
+
+// Canonical codes for `lengths`, bit-reversed to be written least
+// significant bit first.
+fn canonical_codes(lengths: &[u8]) -> Vec<u32> {
+    let mut counts = [0u32; 16];
+    for &length in lengths.iter().filter(|&&length| length > 0) {
+        counts[length as usize] += 1;
+    }
+    let mut next = [0u32; 16];
+    let mut code = 0;
+    for length in 1..16 {
+        code = (code + counts[length - 1]) << 1;
+        next[length] = code;
+    }
+    lengths
+        .iter()
+        .map(|&length| {
+            if length == 0 {
+                return 0;
+            }
+            let code = next[length as usize];
+            next[length as usize] += 1;
+            code.reverse_bits() >> (32 - length as u32)
+        })
+        .collect()
+}
+
+// The order code length code lengths are written in.
+const CODE_LENGTH_ORDER: [usize; 18] = [1, 2, 3, 4, 0, 5, 17, 6, 16, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+// Code length code lengths as the writer sends them: two to four bits
+// each, in the order brotli assigns.
+fn write_code_length_length(w: &mut BitWriter, length: u8) {
+    match length {
+        0 => w.write(2, 0),
+        1 => w.write(4, 7),
+        2 => w.write(3, 3),
+        3 => w.write(2, 2),
+        4 => w.write(2, 1),
+        _ => w.write(4, 15),
+    }
+}
+
+struct PrefixCode {
+    lengths: Vec<u8>,
+    codes: Vec<u32>,
+}
+
+impl PrefixCode {
+    // A code for symbols with `counts` (the last nonzero), written as a
+    // brotli-style prefix code description.
+    fn write(w: &mut BitWriter, counts: &[u32]) -> PrefixCode {
+        let alphabet = counts.len();
+        let used: Vec<usize> = (0..alphabet).filter(|&symbol| counts[symbol] > 0).collect();
+        if alphabet == 1 {
+            return PrefixCode { lengths: vec![0], codes: vec![0] };
+        }
+        if used.len() == 1 {
+            // A single symbol takes no bits.
+            w.write(2, 1);
+            w.write(2, 0);
+            w.write(ceil_log2(alphabet), used[0] as u64);
+            return PrefixCode { lengths: vec![0; alphabet], codes: vec![0; alphabet] };
+        }
+
+        let lengths = code_lengths(counts, 15);
+        // Code lengths, with runs of three or more zeros as code 17.
+        let mut symbols: Vec<(u8, u32)> = Vec::new();
+        let mut i = 0;
+        while i < alphabet {
+            if lengths[i] != 0 {
+                symbols.push((lengths[i], 0));
+                i += 1;
+                continue;
+            }
+            let run = lengths[i..].iter().take_while(|&&length| length == 0).count();
+            if run < 3 {
+                symbols.extend(std::iter::repeat_n((0, 0), run));
+            } else {
+                let mut extras = Vec::new();
+                let mut rest = run - 3;
+                loop {
+                    extras.push((rest & 7) as u32);
+                    rest >>= 3;
+                    if rest == 0 {
+                        break;
+                    }
+                    rest -= 1;
+                }
+                symbols.extend(extras.into_iter().rev().map(|extra| (17, extra)));
+            }
+            i += run;
+        }
+
+        let mut length_counts = [0u32; 18];
+        for &(symbol, _) in &symbols {
+            length_counts[symbol as usize] += 1;
+        }
+        let single = length_counts.iter().filter(|&&count| count > 0).count() == 1;
+        let mut length_lengths = code_lengths(&length_counts, 5);
+        if single {
+            // A lone code length symbol is sent with any length and then
+            // takes no bits.
+            length_lengths = length_counts.iter().map(|&count| (count > 0) as u8).collect();
+        }
+        w.write(2, 0);
+        let mut space = 0;
+        let nonzero = length_lengths.iter().filter(|&&length| length > 0).count();
+        for &symbol in &CODE_LENGTH_ORDER {
+            let length = length_lengths[symbol];
+            write_code_length_length(w, length);
+            if length > 0 {
+                space += 32 >> length;
+                if nonzero > 1 && space == 32 {
+                    break;
+                }
+            }
+        }
+        let length_codes = canonical_codes(&length_lengths);
+        for &(symbol, extra) in &symbols {
+            if !single {
+                w.write(length_lengths[symbol as usize] as u32, length_codes[symbol as usize] as u64);
+            }
+            if symbol == 17 {
+                w.write(3, extra as u64);
+            }
+        }
+        let codes = canonical_codes(&lengths);
+        PrefixCode { lengths, codes }
+    }
+}
+
+// Bits to code a histogram on its own.
+fn entropy(histogram: &[u32]) -> f64 {
+    let total: u64 = histogram.iter().map(|&count| count as u64).sum();
+    histogram.iter().filter(|&&count| count > 0).map(|&count| count as f64 * (total as f64 / count as f64).log2()).sum()
+}
+
+// Bits lost by coding two histograms with one code.
+fn merge_cost(a: &[u32], b: &[u32]) -> f64 {
+    let merged: Vec<u32> = (0..a.len().max(b.len())).map(|i| a.get(i).unwrap_or(&0) + b.get(i).unwrap_or(&0)).collect();
+    entropy(&merged) - entropy(a) - entropy(b)
+}
+
+// Groups contexts with similar histograms. Cluster centres are picked
+// farthest first, starting from the busiest context, until no context
+// would save MIN_CLUSTER_GAIN bits with a code of its own; every context
+// then joins its nearest centre. Clusters are numbered in order of first
+// use, and empty contexts join their neighbour's.
+fn cluster(histograms: &[Vec<u32>]) -> Vec<usize> {
+    let used: Vec<usize> = (0..histograms.len()).filter(|&i| histograms[i].iter().any(|&count| count > 0)).collect();
+    let Some(&busiest) = used.iter().max_by_key(|&&i| histograms[i].iter().map(|&count| count as u64).sum::<u64>()) else {
+        return vec![0; histograms.len()];
+    };
+    let mut centres = vec![busiest];
+    let mut nearest: Vec<(f64, usize)> = used.iter().map(|&i| (merge_cost(&histograms[i], &histograms[busiest]), 0)).collect();
+    while centres.len() < MAX_CLUSTERS {
+        let (farthest, &(gain, _)) = nearest.iter().enumerate().max_by(|a, b| a.1 .0.total_cmp(&b.1 .0)).unwrap();
+        if gain < MIN_CLUSTER_GAIN {
+            break;
+        }
+        let centre = used[farthest];
+        for (k, &i) in used.iter().enumerate() {
+            let cost = merge_cost(&histograms[i], &histograms[centre]);
+            if cost < nearest[k].0 {
+                nearest[k] = (cost, centres.len());
+            }
+        }
+        nearest[farthest] = (0.0, centres.len());
+        centres.push(centre);
+    }
+
+    let mut clusters = vec![usize::MAX; histograms.len()];
+    for (k, &i) in used.iter().enumerate() {
+        clusters[i] = nearest[k].1;
+    }
+    let mut previous = clusters[used[0]];
+    for cluster in &mut clusters {
+        if *cluster == usize::MAX {
+            *cluster = previous;
+        }
+        previous = *cluster;
+    }
+    let mut numbers = vec![usize::MAX; centres.len()];
+    let mut next = 0;
+    for cluster in &mut clusters {
+        if numbers[*cluster] == usize::MAX {
+            numbers[*cluster] = next;
+            next += 1;
+        }
+        *cluster = numbers[*cluster];
+    }
+    clusters
+}
+
+// Writes a cluster map in the smaller of the two forms: fixed-width
+// indices, or runs coded with LZ77 through a nested entropy code, which
+// is not allowed for two contexts or fewer.
+fn write_clusters(w: &mut BitWriter, clusters: &[usize]) {
+    let count = clusters.iter().max().map_or(1, |&max| max + 1);
+    let bits = ceil_log2(count);
+    let simple = (bits <= 3).then(|| {
+        let mut simple = BitWriter::default();
+        simple.bool(true);
+        simple.write(2, bits as u64);
+        for &cluster in clusters {
+            simple.write(bits, cluster as u64);
+        }
+        simple
+    });
+    let complex = (clusters.len() > 2).then(|| {
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < clusters.len() {
+            let run = if i > 0 { clusters[i..].iter().take_while(|&&cluster| cluster == clusters[i - 1]).count() } else { 0 };
+            if run >= 3 {
+                tokens.push(Token::copy(0, run as u32));
+                tokens.push(Token::new(1, 0));
+                i += run;
+            } else {
+                tokens.push(Token::new(0, clusters[i] as u32));
+                i += 1;
+            }
+        }
+        let mut complex = BitWriter::default();
+        complex.bool(false);
+        complex.bool(false); // No move-to-front
+        let code = EntropyCode::write(&mut complex, 2, &tokens, true);
+        code.write_tokens(&mut complex, &tokens);
+        complex
+    });
+    let best = match (simple, complex) {
+        (Some(simple), Some(complex)) if complex.len() < simple.len() => complex,
+        (Some(simple), _) => simple,
+        (None, complex) => complex.expect("more than eight clusters need more than two contexts"),
+    };
+    w.append(&best);
+}
+
+// An entropy code fitted to a token stream: the context clustering and a
+// prefix code per cluster.
+struct EntropyCode {
+    clusters: Vec<usize>,
+    codes: Vec<PrefixCode>,
+}
+
+impl EntropyCode {
+    // Fits a code to `tokens` over `contexts` contexts and writes its
+    // description. With `lz77`, the last context holds copy distances and
+    // copies have symbols from 224, with lengths from 3.
+    fn write(w: &mut BitWriter, contexts: usize, tokens: &[Token], lz77: bool) -> EntropyCode {
+        let mut histograms = vec![Vec::new(); contexts];
+        for token in tokens {
+            let histogram: &mut Vec<u32> = &mut histograms[token.context as usize];
+            if histogram.len() <= token.symbol as usize {
+                histogram.resize(token.symbol as usize + 1, 0);
+            }
+            histogram[token.symbol as usize] += 1;
+        }
+
+        w.bool(lz77);
+        if lz77 {
+            w.write(2, 0); // Copies from symbol 224
+            w.write(2, 0); // Of at least 3 values
+            write_config(w);
+        }
+        let clusters = if contexts > 1 { cluster(&histograms) } else { vec![0] };
+        if contexts > 1 {
+            write_clusters(w, &clusters);
+        }
+        let count = clusters.iter().max().map_or(1, |&max| max + 1);
+        let mut merged = vec![Vec::new(); count];
+        for (histogram, &cluster) in histograms.iter().zip(&clusters) {
+            let merged: &mut Vec<u32> = &mut merged[cluster];
+            if merged.len() < histogram.len() {
+                merged.resize(histogram.len(), 0);
+            }
+            for (total, &count) in merged.iter_mut().zip(histogram) {
+                *total += count;
+            }
+        }
+        for histogram in &mut merged {
+            let alphabet = histogram.iter().rposition(|&count| count > 0).map_or(1, |last| last + 1);
+            histogram.resize(alphabet, 0);
+        }
+
+        w.bool(true); // Prefix codes
+        for _ in 0..count {
+            write_config(w);
+        }
+        for histogram in &merged {
+            if histogram.len() == 1 {
+                w.bool(false);
+            } else {
+                let top = 63 - (histogram.len() as u64 - 1).leading_zeros();
+                w.bool(true);
+                w.write(4, top as u64);
+                w.write(top, (histogram.len() - 1 - (1 << top)) as u64);
+            }
+        }
+        let codes = merged.iter().map(|histogram| PrefixCode::write(w, histogram)).collect();
+        EntropyCode { clusters, codes }
+    }
+
+    fn write_tokens(&self, w: &mut BitWriter, tokens: &[Token]) {
+        for token in tokens {
+            let code = &self.codes[self.clusters[token.context as usize]];
+            let symbol = token.symbol as usize;
+            w.write(code.lengths[symbol] as u32, code.codes[symbol] as u64);
+            w.write(token.extra_bits, token.extra as u64);
+        }
+    }
+}
+
+// One channel of a modular image, in rows of `width`.
+struct Channel {
+    width: usize,
+    samples: Vec<i32>,
+}
+
+impl Channel {
+    fn zeros(width: usize, height: usize) -> Channel {
+        Channel { width, samples: vec![0; width * height] }
+    }
+}
+
+// Writes `channels` as a modular image without transforms. Its MA tree
+// gives each channel a gradient-predicted leaf of its own, through a chain
+// of `channel > k` decisions; an image of zeros gets a single zero leaf.
+fn write_modular(w: &mut BitWriter, channels: &[Channel]) {
+    w.bool(false); // Local tree
+    w.bool(true); // Default weighted predictor parameters
+    w.write(2, 0); // No transforms
+
+    let zero = channels.iter().all(|channel| channel.samples.iter().all(|&sample| sample == 0));
+    let leaves = if zero { 1 } else { channels.len() };
+    let predictor = if zero { 0 } else { 5 };
+    let leaf = |tree: &mut Vec<Token>| {
+        tree.push(Token::new(1, 0));
+        tree.push(Token::new(2, predictor));
+        tree.extend([Token::new(3, 0), Token::new(4, 0), Token::new(5, 0)]);
+    };
+    // Breadth first, the chain is D0, D1, L0, D2, L1, ... L(n-1), L(n-2).
+    let mut tree = Vec::new();
+    for k in 0..leaves - 1 {
+        tree.push(Token::new(1, 1)); // Property 0, the channel
+        tree.push(Token::new(0, pack_signed(k as i32)));
+        if k > 0 {
+            leaf(&mut tree);
+        }
+    }
+    leaf(&mut tree);
+    if leaves > 1 {
+        leaf(&mut tree);
+    }
+    let code = EntropyCode::write(w, 6, &tree, false);
+    code.write_tokens(w, &tree);
+
+    // Leaves are numbered in the order the tree lists them.
+    let leaf_of = |c: usize| match leaves {
+        1 => 0,
+        n if c + 2 == n => n - 1,
+        n if c + 1 == n => n - 2,
+        _ => c,
+    };
+    let mut tokens = Vec::new();
+    for (c, channel) in channels.iter().enumerate() {
+        let width = channel.width;
+        for (i, &sample) in channel.samples.iter().enumerate() {
+            let (x, y) = (i % width, i / width);
+            let prediction = if zero {
+                0
+            } else if y == 0 {
+                if x == 0 { 0 } else { channel.samples[i - 1] }
+            } else if x == 0 {
+                channel.samples[i - width]
+            } else {
+                let (west, north, north_west) = (channel.samples[i - 1], channel.samples[i - width], channel.samples[i - width - 1]);
+                (west + north - north_west).clamp(west.min(north), west.max(north))
+            };
+            tokens.push(Token::new(leaf_of(c), pack_signed(sample - prediction)));
+        }
+    }
+    let code = EntropyCode::write(w, leaves, &tokens, false);
+    code.write_tokens(w, &tokens);
+}
+
+// Class, index, whether the last of its segment, counts and values.
+type HuffmanTable<'a> = (u8, u8, bool, &'a [u8], &'a [u8]);
+
+// Component indices and tables, then the spectral range and the high and
+// low successive approximation bits.
+type Scan = (Vec<(usize, u8, u8)>, u8, u8, u8, u8);
+
+// The JPEG's segments, as the jbrd box describes them.
+struct Layout<'a> {
+    markers: Vec<u8>,
+    // Kind (0 stored, 2 Exif, 3 XMP) and length, counting the marker byte.
+    app_markers: Vec<(u32, usize)>,
+    com_lengths: Vec<usize>,
+    // Precision, index and whether the last of its segment.
+    quant_tables: Vec<(u8, u8, bool)>,
+    component_ids: Vec<u8>,
+    component_tables: Vec<u8>,
+    huffman_tables: Vec<HuffmanTable<'a>>,
+    scans: Vec<Scan>,
+    restart_interval: Option<u16>,
+    // APP data, COM data and the bytes after EOI, brotli-compressed.
+    data: Vec<u8>,
+    tail: usize,
+    exif: Option<&'a [u8]>,
+    xmp: Option<&'a [u8]>,
+}
+
+// Walks the JPEG's segments, or returns None for what the jbrd box written
+// here cannot hold.
+fn layout(jpeg: &[u8]) -> Option<Layout<'_>> {
+    let mut layout = Layout {
+        markers: Vec::new(),
+        app_markers: Vec::new(),
+        com_lengths: Vec::new(),
+        quant_tables: Vec::new(),
+        component_ids: Vec::new(),
+        component_tables: Vec::new(),
+        huffman_tables: Vec::new(),
+        scans: Vec::new(),
+        restart_interval: None,
+        data: Vec::new(),
+        tail: 0,
+        exif: None,
+        xmp: None,
+    };
+    let mut com_data = Vec::new();
+    let mut pos = 2;
+    loop {
+        let marker = *jpeg.get(pos + 1).filter(|_| jpeg[pos] == 0xFF)?;
+        if marker == 0xD9 {
+            layout.markers.push(marker);
+            let tail = &jpeg[pos + 2..];
+            layout.tail = tail.len();
+            layout.data.extend_from_slice(&com_data);
+            layout.data.extend_from_slice(tail);
+            return Some(layout);
+        }
+        let length = jpeg.get(pos + 2..pos + 4).map(|b| u16::from_be_bytes([b[0], b[1]]) as usize).filter(|&length| length >= 2)?;
+        let segment = jpeg.get(pos + 4..pos + 2 + length)?;
+        match marker {
+            0xE0..=0xEF => {
+                if marker == 0xE2 && segment.starts_with(b"ICC_PROFILE\0") {
+                    return None;
+                }
+                if marker == 0xEE && segment.starts_with(b"Adobe") && segment.get(11) == Some(&0) {
+                    return None; // RGB or CMYK rather than YCbCr
+                }
+                let kind = if marker == 0xE1 && layout.exif.is_none() && segment.starts_with(EXIF_HEADER) {
+                    layout.exif = Some(&segment[EXIF_HEADER.len()..]);
+                    2
+                } else if marker == 0xE1 && layout.xmp.is_none() && segment.starts_with(XMP_HEADER) {
+                    layout.xmp = Some(&segment[XMP_HEADER.len()..]);
+                    3
+                } else {
+                    layout.data.extend_from_slice(&jpeg[pos + 1..pos + 2 + length]);
+                    0
+                };
+                layout.app_markers.push((kind, length + 1));
+            }
+            0xFE => {
+                layout.com_lengths.push(length);
+                com_data.extend_from_slice(&jpeg[pos + 2..pos + 2 + length]);
+            }
+            0xDB => {
+                let mut rest = segment;
+                while let Some(&info) = rest.first() {
+                    let (precision, index) = (info >> 4, info & 15);
+                    if precision > 1 || index > 3 {
+                        return None;
+                    }
+                    rest = rest.get(1 + (64 << precision)..)?;
+                    layout.quant_tables.push((precision, index, rest.is_empty()));
+                }
+            }
+            0xC4 => {
+                let mut rest = segment;
+                while let Some(&info) = rest.first() {
+                    let (class, index) = (info >> 4, info & 15);
+                    let counts = rest.get(1..17)?;
+                    let total: usize = counts.iter().map(|&count| count as usize).sum();
+                    let values = rest.get(17..17 + total)?;
+                    rest = &rest[17 + total..];
+                    if class > 1 || index > 3 || total == 0 {
+                        return None;
+                    }
+                    layout.huffman_tables.push((class, index, rest.is_empty(), counts, values));
+                }
+            }
+            0xC0..=0xC2 => {
+                if segment.get(5) != Some(&3) || segment.len() != 15 {
+                    return None;
+                }
+                for component in segment[6..].chunks(3) {
+                    layout.component_ids.push(component[0]);
+                    layout.component_tables.push(component[2]);
+                }
+            }
+            0xDD => layout.restart_interval = Some(u16::from_be_bytes(segment.try_into().ok()?)),
+            0xDA => {
+                let count = *segment.first()? as usize;
+                if !(1..=4).contains(&count) || segment.len() != 4 + 2 * count {
+                    return None;
+                }
+                let components = segment[1..1 + 2 * count]
+                    .chunks(2)
+                    .map(|c| {
+                        let index = layout.component_ids.iter().position(|&id| id == c[0])?;
+                        (c[1] >> 4 < 4 && c[1] & 15 < 4).then_some((index, c[1] & 15, c[1] >> 4))
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                let tail = &segment[1 + 2 * count..];
+                if tail[0] > 63 || tail[1] > 63 {
+                    return None;
+                }
+                layout.scans.push((components, tail[0], tail[1], tail[2] >> 4, tail[2] & 15));
+                layout.markers.push(marker);
+                pos = jpeg_lossless::entropy_end(jpeg, pos + 2 + length);
+                continue;
+            }
+            _ => return None,
+        }
+        layout.markers.push(marker);
+        pos += 2 + length;
+    }
+}
+
+impl Layout<'_> {
+    // The jbrd box: its header, then the stored data as brotli.
+    fn write(&self) -> Option<Vec<u8>> {
+        let mut w = BitWriter::default();
+        w.bool(false); // Not grayscale
+        for &marker in &self.markers {
+            w.write(6, (marker - 0xC0) as u64);
+        }
+        for &(kind, length) in &self.app_markers {
+            w.u32(kind, [(0, 0), (1, 0), (2, 1), (4, 2)]);
+            w.write(16, length as u64 - 1);
+        }
+        for &length in &self.com_lengths {
+            w.write(16, length as u64 - 1);
+        }
+        if !(1..=4).contains(&self.quant_tables.len()) {
+            return None;
+        }
+        w.write(2, self.quant_tables.len() as u64 - 1);
+        for &(precision, index, last) in &self.quant_tables {
+            w.write(1, precision as u64);
+            w.write(2, index as u64);
+            w.bool(last);
+        }
+        if self.component_ids == [1, 2, 3] {
+            w.write(2, 1);
+        } else {
+            w.write(2, 3);
+            w.write(2, 2); // Three components
+            for &id in &self.component_ids {
+                w.write(8, id as u64);
+            }
+        }
+        for &table in &self.component_tables {
+            w.write(2, table as u64 & 3);
+        }
+        if !(2..=89).contains(&self.huffman_tables.len()) {
+            return None;
+        }
+        w.u32(self.huffman_tables.len() as u32, [(4, 0), (2, 3), (10, 4), (26, 6)]);
+        for &(class, index, last, counts, values) in &self.huffman_tables {
+            w.bool(class == 1);
+            w.write(2, index as u64);
+            w.bool(last);
+            // The longest codes get one more, for the reserved all-ones
+            // code, which is given symbol 256.
+            let longest = counts.iter().rposition(|&count| count > 0)?;
+            let counts = std::iter::once(0).chain(counts.iter().enumerate().map(|(i, &count)| count as u32 + (i == longest) as u32));
+            for count in counts {
+                if count > 255 {
+                    return None;
+                }
+                w.u32(count, [(0, 0), (1, 0), (2, 3), (0, 8)]);
+            }
+            for value in values.iter().map(|&value| value as u32).chain([256]) {
+                w.u32(value, [(0, 2), (4, 2), (8, 4), (1, 8)]);
+            }
+        }
+        for (components, start, end, high, low) in &self.scans {
+            w.write(2, components.len() as u64 - 1);
+            w.write(6, *start as u64);
+            w.write(6, *end as u64);
+            w.write(4, *low as u64);
+            w.write(4, *high as u64);
+            for &(index, ac, dc) in components {
+                w.write(2, index as u64);
+                w.write(2, ac as u64);
+                w.write(2, dc as u64);
+            }
+            w.write(2, 0); // Last needed pass
+        }
+        if let Some(interval) = self.restart_interval {
+            w.write(16, interval as u64);
+        }
+        for _ in &self.scans {
+            w.write(2, 0); // No reset points
+            w.write(2, 0); // No extra zero runs
+        }
+        if self.tail >= 65793 + (1 << 22) {
+            return None;
+        }
+        w.u32(self.tail as u32, [(0, 0), (1, 8), (257, 16), (65793, 22)]);
+        w.bool(false); // No padding bits
+        let mut jbrd = w.finish();
+        jbrd.extend_from_slice(&brotli_stored(&self.data));
+        Some(jbrd)
+    }
+}
+
+// `data` as a brotli stream of uncompressed meta-blocks. There is no brotli
+// encoder among the dependencies, and the segments it holds are mostly
+// small or already compressed.
+fn brotli_stored(data: &[u8]) -> Vec<u8> {
+    let mut w = BitWriter::default();
+    w.write(1, 0); // 64 KiB window
+    for chunk in data.chunks(1 << 16) {
+        w.bool(false); // Not the last meta-block
+        w.write(2, 0); // Four length nibbles
+        w.write(16, chunk.len() as u64 - 1);
+        w.bool(true); // Uncompressed
+        w.zero_pad();
+        w.bytes(chunk);
+    }
+    w.bool(true); // Last
+    w.bool(true); // And empty
+    w.finish()
+}
+
+// Shift of each component against the largest sampling factors, and the
+// jpeg_upsampling code of its own factors.
+type Sampling = ([(usize, usize); 3], [u64; 3]);
+
+fn sampling(coefficients: &Coefficients) -> Option<Sampling> {
+    let components = coefficients.components();
+    let max_h = components.iter().map(|c| c.h).max()?;
+    let max_v = components.iter().map(|c| c.v).max()?;
+    let mut shifts = [(0, 0); 3];
+    let mut codes = [0; 3];
+    for (k, component) in components.iter().enumerate() {
+        codes[k] = match (component.h, component.v) {
+            (1, 1) => 0,
+            (2, 2) => 1,
+            (2, 1) => 2,
+            (1, 2) => 3,
+            _ => return None,
+        };
+        shifts[k] = (max_h / component.h - 1, max_v / component.v - 1);
+    }
+    Some((shifts, codes))
+}
+
+// Number of blocks along a side of `pixels`, rounded up to even when the
+// frame has subsampled chroma that way, and the part of it a component at
+// `shift` has.
+fn blocks(pixels: usize, subsampled: bool) -> usize {
+    let blocks = pixels.div_ceil(8);
+    if subsampled { blocks.div_ceil(2) * 2 } else { blocks }
+}
+
+fn component_blocks(blocks: usize, shift: usize) -> usize {
+    blocks.div_ceil(1 << shift)
+}
+
+// The JPEG XL codestream for `coefficients`: image header, frame header
+// and the frame's sections.
+fn codestream(coefficients: &Coefficients, orientation: u32) -> Option<Vec<u8>> {
+    let (width, height) = coefficients.dimensions();
+    let components = coefficients.components();
+    if components.len() != 3 {
+        return None;
+    }
+    let (shifts, codes) = sampling(coefficients)?;
+    let (h_sub, v_sub) = (shifts.iter().any(|s| s.0 > 0), shifts.iter().any(|s| s.1 > 0));
+    let quant_tables: Vec<&[u16; 64]> = components.iter().map(|c| coefficients.quant_table(c)).collect::<Option<_>>()?;
+    let block = |k: usize, bx: usize, by: usize| -> Option<&Block> {
+        let component = &components[k];
+        (bx < component.blocks_w).then(|| component.blocks.get(by * component.blocks_w + bx)).flatten()
+    };
+
+    let mut w = BitWriter::default();
+    w.write(16, 0x0AFF);
+    w.bool(false); // Not a small size
+    w.u32(height as u32, IMAGE_SIZE);
+    w.write(3, 0); // No aspect ratio
+    w.u32(width as u32, IMAGE_SIZE);
+    w.bool(false); // Metadata follow
+    w.bool(orientation != 1);
+    if orientation != 1 {
+        w.write(3, orientation as u64 - 1);
+        w.bool(false); // No intrinsic size
+        w.bool(false); // No preview
+        w.bool(false); // No animation
+    }
+    w.bool(false); // Integer samples
+    w.write(2, 0); // Of 8 bits
+    w.bool(true); // Fit 16-bit buffers
+    w.write(2, 0); // No extra channels
+    w.bool(false); // Not XYB
+    w.bool(true); // sRGB
+    if orientation != 1 {
+        w.bool(true); // Default tone mapping
+    }
+    w.u64(0); // No extensions
+    w.bool(true); // Default transform data
+    w.zero_pad();
+
+    w.bool(false); // Frame header follows
+    w.write(2, 0); // Regular frame
+    w.write(1, 0); // VarDCT
+    w.u64(0x80); // No adaptive LF smoothing
+    w.bool(true); // YCbCr
+    for k in [1, 0, 2] {
+        w.write(2, codes[k]);
+    }
+    w.write(2, 0); // No upsampling
+    w.write(2, 0); // One pass
+    w.bool(false); // No crop
+    w.write(2, 0); // Replace
+    w.bool(true); // Last frame
+    w.write(2, 0); // No name
+    w.bool(false); // Restoration filters follow
+    w.bool(false); // No Gabor-like filter
+    w.write(2, 0); // No edge-preserving filter
+    w.u64(0); // No filter extensions
+    w.u64(0); // No frame extensions
+
+    let (groups_x, groups_y) = (width.div_ceil(256), height.div_ceil(256));
+    let (lf_groups_x, lf_groups_y) = (width.div_ceil(2048), height.div_ceil(2048));
+    let num_groups = groups_x * groups_y;
+    let mut sections = Vec::new();
+
+    // LfGlobal: LF dequantization by each DC quantizer, unit quantizer
+    // scales, one block context per channel, and no chroma from luma.
+    let mut lf_global = BitWriter::default();
+    lf_global.bool(false);
+    for k in [1, 0, 2] {
+        lf_global.f16(quant_tables[k][0] as f32 / 2040.0 * 128.0);
+    }
+    lf_global.u32(65536, [(1, 11), (2049, 11), (4097, 12), (8193, 16)]);
+    lf_global.u32(1, [(16, 0), (1, 5), (1, 8), (1, 16)]);
+    lf_global.bool(false);
+    for _ in 0..4 {
+        lf_global.write(4, 0); // No LF or quantizer thresholds
+    }
+    write_clusters(&mut lf_global, &(0..39).map(|i| i / 13).collect::<Vec<_>>());
+    lf_global.bool(false);
+    lf_global.write(2, 0);
+    lf_global.f16(0.0);
+    lf_global.f16(0.0);
+    lf_global.write(8, 128);
+    lf_global.write(8, 128);
+    lf_global.bool(false); // No global MA tree
+    sections.push(lf_global);
+
+    // LfGroups: the DC coefficients, and metadata giving every block an
+    // 8x8 DCT.
+    let mut lf_block_widths = Vec::new();
+    let mut lf_block_heights = Vec::new();
+    for ly in 0..lf_groups_y {
+        for lx in 0..lf_groups_x {
+            let (lf_width, lf_height) = ((width - lx * 2048).min(2048), (height - ly * 2048).min(2048));
+            let (bw, bh) = (blocks(lf_width, h_sub), blocks(lf_height, v_sub));
+            let mut s = BitWriter::default();
+            s.write(2, 0); // No extra precision
+            let channels = (0..3)
+                .map(|k| {
+                    let (hs, vs) = shifts[k];
+                    let (cw, ch) = (component_blocks(bw, hs), component_blocks(bh, vs));
+                    let (x0, y0) = ((lx * 256) >> hs, (ly * 256) >> vs);
+                    let mut samples = Vec::with_capacity(cw * ch);
+                    for y in 0..ch {
+                        for x in 0..cw {
+                            samples.push(block(k, x0 + x, y0 + y)?[0] as i32);
+                        }
+                    }
+                    Some(Channel { width: cw, samples })
+                })
+                .collect::<Option<Vec<_>>>()?;
+            write_modular(&mut s, &channels);
+            s.write(ceil_log2(bw * bh), (bw * bh - 1) as u64);
+            let (cfl_width, cfl_height) = (lf_width.div_ceil(64), lf_height.div_ceil(64));
+            write_modular(
+                &mut s,
+                &[Channel::zeros(cfl_width, cfl_height), Channel::zeros(cfl_width, cfl_height), Channel::zeros(bw * bh, 2), Channel::zeros(bw, bh)],
+            );
+            sections.push(s);
+            lf_block_widths.push(bw);
+            lf_block_heights.push(bh);
+        }
+    }
+
+    // The AC coefficients of every pass group, in natural order, each
+    // block's count of non-zero ones first.
+    let mut group_tokens = Vec::with_capacity(num_groups);
+    for gy in 0..groups_y {
+        for gx in 0..groups_x {
+            let lf_group = (gy / 8) * lf_groups_x + gx / 8;
+            let gw = (lf_block_widths[lf_group] - (gx % 8) * 32).min(32);
+            let gh = (lf_block_heights[lf_group] - (gy % 8) * 32).min(32);
+            let mut tokens = Vec::new();
+            let mut non_zeros: Vec<Vec<u32>> = vec![vec![0; gw]; 3];
+            for y in 0..gh {
+                for x in 0..gw {
+                    for k in 0..3 {
+                        let (hs, vs) = shifts[k];
+                        let (sx, sy) = (x >> hs, y >> vs);
+                        if sx << hs != x || sy << vs != y {
+                            continue;
+                        }
+                        let coefficients = block(k, ((gx * 32) >> hs) + sx, ((gy * 32) >> vs) + sy)?;
+                        let row = &mut non_zeros[k];
+                        let predicted = match (sx, sy) {
+                            (0, 0) => 32,
+                            (_, 0) => row[sx - 1],
+                            (0, _) => row[sx],
+                            _ => (row[sx] + row[sx - 1] + 1) >> 1,
+                        };
+                        let count = (1..64).filter(|&z| coefficients[ZIGZAG[z]] != 0).count() as u32;
+                        let context = if predicted >= 8 { 4 + predicted / 2 } else { predicted };
+                        tokens.push(Token::new(k + 3 * context as usize, count));
+                        row[sx] = count;
+
+                        let base = k * 458 + 37 * 3;
+                        let mut remaining = count;
+                        let mut previous = (count <= 4) as u32;
+                        for z in 1..64 {
+                            if remaining == 0 {
+                                break;
+                            }
+                            let value = coefficients[NATURAL_ORDER[z]] as i32;
+                            let context = (COEFF_NUM_NONZERO_CONTEXT[remaining as usize - 1] + COEFF_FREQ_CONTEXT[z - 1]) * 2 + previous;
+                            tokens.push(Token::new(base + context as usize, pack_signed(value)));
+                            previous = (value != 0) as u32;
+                            remaining -= previous;
+                        }
+                    }
+                }
+            }
+            group_tokens.push(tokens);
+        }
+    }
+
+    // HfGlobal: the JPEG's quantization tables as raw matrices, transposed
+    // to the coefficient layout, and the entropy code of all pass groups.
+    let mut hf_global = BitWriter::default();
+    hf_global.bool(false);
+    hf_global.write(3, 7);
+    hf_global.f16(1.0 / 2040.0);
+    let matrices: Vec<Channel> = [1, 0, 2]
+        .iter()
+        .map(|&k| Channel { width: 8, samples: (0..64).map(|i| quant_tables[k][(i % 8) * 8 + i / 8] as i32).collect() })
+        .collect();
+    write_modular(&mut hf_global, &matrices);
+    for _ in 1..17 {
+        hf_global.write(3, 0); // Default for the other transforms
+    }
+    hf_global.write(ceil_log2(num_groups), 0); // One preset
+    hf_global.u32(0, [(0x5F, 0), (0x13, 0), (0, 0), (0, 13)]); // Natural orders
+    let all_tokens: Vec<Token> = group_tokens.concat();
+    let hf_code = EntropyCode::write(&mut hf_global, HF_CONTEXTS * 3, &all_tokens, false);
+    sections.push(hf_global);
+
+    for tokens in &group_tokens {
+        let mut s = BitWriter::default();
+        hf_code.write_tokens(&mut s, tokens);
+        sections.push(s);
+    }
+
+    // With a single group, the sections run together as one TOC entry.
+    if num_groups == 1 {
+        let mut all = BitWriter::default();
+        for section in &sections {
+            all.append(section);
+        }
+        sections = vec![all];
+    }
+    let sections: Vec<Vec<u8>> = sections.into_iter().map(BitWriter::finish).collect();
+    w.bool(false); // Not permuted
+    w.zero_pad();
+    for section in &sections {
+        w.u32(section.len() as u32, TOC_ENTRY);
+    }
+    w.zero_pad();
+    for section in &sections {
+        w.bytes(section);
+    }
+    Some(w.finish())
+}
+
+fn push_box(output: &mut Vec<u8>, kind: &[u8; 4], payload: &[u8]) {
+    output.extend_from_slice(&(8 + payload.len() as u32).to_be_bytes());
+    output.extend_from_slice(kind);
+    output.extend_from_slice(payload);
+}
+
+// A JPEG XL file that reconstructs `jpeg` exactly, or None when the JPEG is
+// outside what this supports or the result does not reconstruct it.
+pub(crate) fn recompress(jpeg: &[u8], limits: &Limits) -> Option<Vec<u8>> {
+    let layout = layout(jpeg)?;
+    let coefficients = jpeg_lossless::decode(jpeg, limits).ok()?;
+    let orientation = metadata::read_exif(jpeg).map_or(1, |exif| metadata::orientation(&exif));
+    let codestream = codestream(&coefficients, if (1..=8).contains(&orientation) { orientation } else { 1 })?;
+
+    let mut output = vec![0, 0, 0, 0x0C, b'J', b'X', b'L', b' ', 0x0D, 0x0A, 0x87, 0x0A];
+    push_box(&mut output, b"ftyp", b"jxl \0\0\0\0jxl ");
+    push_box(&mut output, b"jbrd", &layout.write()?);
+    if let Some(exif) = layout.exif {
+        push_box(&mut output, b"Exif", &[&[0, 0, 0, 0], exif].concat());
+    }
+    if let Some(xmp) = layout.xmp {
+        push_box(&mut output, b"xml ", xmp);
+    }
+    push_box(&mut output, b"jxlc", &codestream);
+    (jxl::reconstruct_jpeg(&output).as_deref() == Some(jpeg)).then_some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ImageProcessor;
+    use jpeg_encoder::{ColorType, Encoder, SamplingFactor};
+
+    fn test_jpeg(width: usize, height: usize, configure: impl FnOnce(&mut Encoder<&mut Vec<u8>>)) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.extend_from_slice(&[(x * 5) as u8, (y * 8) as u8, ((x * y) % 256) as u8]);
+            }
+        }
+        let mut buffer = Vec::new();
+        let mut encoder = Encoder::new(&mut buffer, 90);
+        configure(&mut encoder);
+        encoder.encode(&pixels, width as u16, height as u16, ColorType::Rgb).unwrap();
+        buffer
+    }
+
+    fn round_trips(jpeg: &[u8]) -> bool {
+        recompress(jpeg, &Limits::default()).is_some_and(|jxl| jxl::reconstruct_jpeg(&jxl).as_deref() == Some(jpeg))
+    }
+
+    #[test]
+    fn reconstructs_every_sampling() {
+        for sampling in [SamplingFactor::R_4_4_4, SamplingFactor::R_4_2_2, SamplingFactor::R_4_4_0, SamplingFactor::R_4_2_0] {
+            let jpeg = test_jpeg(37, 29, |encoder| encoder.set_sampling_factor(sampling));
+            assert!(round_trips(&jpeg), "{:?}", sampling);
+        }
+    }
+
+    #[test]
+    fn reconstructs_restart_intervals() {
+        assert!(round_trips(&test_jpeg(45, 29, |encoder| encoder.set_restart_interval(2))));
+    }
+
+    #[test]
+    fn falls_back_on_progressive_jpegs_without_eob_runs() {
+        // jpeg-encoder ends every band with its own EOB, which the jbrd box
+        // written here cannot describe.
+        let jpeg = test_jpeg(45, 29, |encoder| encoder.set_progressive(true));
+        assert!(recompress(&jpeg, &Limits::default()).is_none());
+        let jxl = ImageProcessor::new().convert_format(&jpeg, "jxl", 90).unwrap();
+        assert!(jxl::is_jxl(&jxl) && jxl::reconstruct_jpeg(&jxl).is_none());
+    }
+
+    #[test]
+    fn reconstructs_several_groups() {
+        // Three by two pass groups of 256 pixels.
+        let jpeg = test_jpeg(600, 300, |encoder| encoder.set_sampling_factor(SamplingFactor::R_4_2_0));
+        assert!(round_trips(&jpeg));
+        // Two LF groups of 2048 pixels.
+        let jpeg = test_jpeg(2100, 24, |encoder| encoder.set_sampling_factor(SamplingFactor::R_4_2_0));
+        assert!(round_trips(&jpeg));
+    }
+
+    #[test]
+    fn reconstructs_exif_and_keeps_orientation() {
+        // Big-endian TIFF with one IFD entry: orientation 6.
+        let tiff = [b"MM\0*\0\0\0\x08\0\x01".as_slice(), &[0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0], &[0; 4]].concat();
+        let jpeg = test_jpeg(45, 29, |encoder| encoder.add_exif_metadata(&tiff).unwrap());
+        let jxl = recompress(&jpeg, &Limits::default()).unwrap();
+        assert_eq!(jxl::reconstruct_jpeg(&jxl).unwrap(), jpeg);
+        assert_eq!(jxl::decode(&jxl).unwrap().width(), 29);
+    }
+
+    #[test]
+    fn decodes_close_to_the_jpeg() {
+        let jpeg = test_jpeg(64, 48, |encoder| encoder.set_sampling_factor(SamplingFactor::R_4_4_4));
+        let jxl = recompress(&jpeg, &Limits::default()).unwrap();
+        let expected = image::load_from_memory(&jpeg).unwrap().to_rgb8();
+        let actual = jxl::decode(&jxl).unwrap().to_rgb8();
+        assert_eq!(actual.dimensions(), (64, 48));
+        let difference: u64 = expected.as_raw().iter().zip(actual.as_raw()).map(|(&a, &b)| a.abs_diff(b) as u64).sum();
+        assert!(difference < expected.as_raw().len() as u64, "mean difference {}", difference as f64 / expected.as_raw().len() as f64);
+    }
+
+    #[test]
+    fn convert_format_recompresses_jpeg() {
+        let jpeg = test_jpeg(45, 29, |encoder| encoder.set_sampling_factor(SamplingFactor::R_4_2_0));
+        let processor = ImageProcessor::new();
+        let jxl = processor.convert_format(&jpeg, "jxl", 90).unwrap();
+        assert!(jxl::reconstruct_jpeg(&jxl).is_some());
+        assert_eq!(processor.convert_format(&jxl, "jpeg", 90).unwrap(), jxl::reconstruct_jpeg(&jxl).unwrap());
+    }
+}
@@ -1,8 +1,56 @@
+mod adjust;
+mod analysis;
+mod animation;
+mod barcode;
+mod collage;
+mod color_vision;
+mod composite;
+mod decorate;
+mod depth;
+mod enhance;
+mod error;
+mod favicon;
+mod filter;
+mod hash;
+mod heif;
+mod icc;
+mod jpeg;
+mod jpeg_lossless;
+mod jpeg_trellis;
+mod jxl;
+mod jxl_recompress;
+mod limits;
+mod mask;
+mod metadata;
+mod metrics;
+mod optimize;
+mod palette;
+mod pdf;
+mod pipeline;
+mod placeholder;
+mod qr;
+mod quantize;
+mod raw;
+mod redact;
+mod redeye;
+mod scan;
+mod sprites;
+mod styles;
+mod svg;
+mod text;
+mod transform;
+mod vp8;
+
+pub use animation::AnimatedImage;
+pub use error::ImageError;
+pub use pipeline::Pipeline;
+
 use wasm_bindgen::prelude::*;
-use image::{ImageFormat, ColorType};
-use image::codecs::jpeg::JpegEncoder;
-use image::codecs::png::PngEncoder;
+use image::{DynamicImage, ImageDecoder, ImageEncoder, ColorType, RgbImage};
+use image::codecs::jpeg::JpegDecoder;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
 use image::codecs::webp::WebPEncoder;
+use image::codecs::{bmp::BmpEncoder, ico::IcoEncoder, tga::TgaEncoder, tiff::TiffEncoder};
 use std::io::Cursor;
 use web_sys::console;
 
@@ -16,101 +64,1470 @@ pub fn main() {
     console::log_1(&"Rust Image Processor WASM module initialized".into());
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum OutputFormat {
+    Jpeg,
+    Png,
+    WebP,
+    Avif,
+    Jxl,
+    Tiff,
+    Bmp,
+    Ico,
+    Tga,
+}
+
+fn parse_format(format: &str) -> Result<OutputFormat, JsValue> {
+    match format.to_lowercase().as_str() {
+        "jpeg" | "jpg" => Ok(OutputFormat::Jpeg),
+        "png" => Ok(OutputFormat::Png),
+        "webp" => Ok(OutputFormat::WebP),
+        "avif" => Ok(OutputFormat::Avif),
+        "jxl" => Ok(OutputFormat::Jxl),
+        "tiff" | "tif" => Ok(OutputFormat::Tiff),
+        "bmp" => Ok(OutputFormat::Bmp),
+        "ico" => Ok(OutputFormat::Ico),
+        "tga" => Ok(OutputFormat::Tga),
+        _ => Err(error::unsupported_format("Unsupported format")),
+    }
+}
+
+// Whether `data` starts with a plausible TGA header. TGA has no signature,
+// so it is only tried for input no other format claims.
+fn looks_like_tga(data: &[u8]) -> bool {
+    data.len() >= 18
+        && data[1] <= 1
+        && matches!(data[2], 1 | 2 | 3 | 9 | 10 | 11)
+        && u16::from_le_bytes([data[12], data[13]]) > 0
+        && u16::from_le_bytes([data[14], data[15]]) > 0
+        && matches!(data[16], 8 | 15 | 16 | 24 | 32)
+}
+
+// A reader for `image_data` in whichever format the image crate recognizes,
+// TGA included.
+pub(crate) fn image_reader(image_data: &[u8]) -> Result<image::io::Reader<Cursor<&[u8]>>, JsValue> {
+    let mut reader = image::io::Reader::new(Cursor::new(image_data)).with_guessed_format().map_err(error::decode_failed)?;
+    match reader.format() {
+        Some(_) => Ok(reader),
+        None if looks_like_tga(image_data) => {
+            reader.set_format(image::ImageFormat::Tga);
+            Ok(reader)
+        }
+        None => Err(error::unsupported_format("Unsupported image format")),
+    }
+}
+
+// Decodes any supported input as stored, without applying orientation. JPEG
+// XL, HEIC and camera RAW are not known to the image crate and go through
+// their own decoders. The size in the header is checked against `limits`
+// before decoding. AVIF input is rejected: its AV1 decoders, dav1d and the
+// rav1d port, need C or libc and do not build for wasm32-unknown-unknown.
+// Decoding it is still open; see "AVIF Input" in SUGGESTIONS.md.
+fn decode(image_data: &[u8], limits: &limits::Limits) -> Result<DynamicImage, JsValue> {
+    limits.check_input(image_data)?;
+    if image::guess_format(image_data).ok() == Some(image::ImageFormat::Avif) {
+        return Err(error::unsupported_format("AVIF input is not supported"));
+    }
+    if jxl::is_jxl(image_data) {
+        let (width, height) = jxl::dimensions(image_data)?;
+        limits.check_pixels(width, height)?;
+        return jxl::decode(image_data);
+    }
+    if heif::is_heif(image_data) {
+        let (width, height) = heif::dimensions(image_data)?;
+        limits.check_pixels(width, height)?;
+        return heif::decode(image_data);
+    }
+    if raw::is_raw(image_data) {
+        let (width, height) = raw::dimensions(image_data)?;
+        limits.check_pixels(width, height)?;
+        return raw::decode(image_data);
+    }
+    let (width, height) = image_reader(image_data)?.into_dimensions().map_err(limits::decode_error)?;
+    limits.check_pixels(width, height)?;
+    let mut reader = image_reader(image_data)?;
+    reader.limits(limits.decoder_limits());
+    reader.decode().map_err(limits::decode_error)
+}
+
+// Decodes at least `max_side` pixels on the longer side as stored, but no
+// more than needed for JPEG, whose decoder can scale by 1/2, 1/4 or 1/8 as
+// it decodes and so skip most of the work for previews. Other formats are
+// decoded in full.
+fn decode_scaled(image_data: &[u8], max_side: u32, limits: &limits::Limits) -> Result<DynamicImage, JsValue> {
+    if image::guess_format(image_data).ok() != Some(image::ImageFormat::Jpeg) {
+        return decode(image_data, limits);
+    }
+    limits.check_input(image_data)?;
+    let mut decoder = JpegDecoder::new(Cursor::new(image_data)).map_err(limits::decode_error)?;
+    let (width, height) = decoder.dimensions();
+    limits.check_pixels(width, height)?;
+    let scale = (max_side as f64 / width.max(height) as f64).min(1.0);
+    let scaled = |side: u32| ((side as f64 * scale).ceil() as u32).clamp(1, u16::MAX as u32) as u16;
+    decoder.scale(scaled(width), scaled(height)).map_err(limits::decode_error)?;
+    decoder.set_limits(limits.decoder_limits()).map_err(limits::decode_error)?;
+    DynamicImage::from_decoder(decoder).map_err(limits::decode_error)
+}
+
+// JPEG has no alpha channel, so transparent pixels are composited onto white
+// instead of keeping whatever color happens to be stored under them.
+fn flatten_onto_white(img: &DynamicImage) -> RgbImage {
+    let rgba = img.to_rgba8();
+    RgbImage::from_fn(img.width(), img.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend = |c: u8| ((c as u32 * a as u32 + 255 * (255 - a as u32) + 127) / 255) as u8;
+        image::Rgb([blend(r), blend(g), blend(b)])
+    })
+}
+
+// Low bits dropped from the color channels by near-lossless encoding: 0 at
+// quality 100 or when it is not `enabled`, then one more for every 20
+// points down to a maximum of 4.
+fn near_lossless_bits(quality: u8, enabled: bool) -> u32 {
+    if !enabled {
+        return 0;
+    }
+    ((100 - quality.min(100) as u32).div_ceil(20)).min(4)
+}
+
+// Near-lossless preprocessing for the lossless encoders, as libwebp does it:
+// color values are rounded to multiples of 2^bits except where a pixel is
+// close to all four neighbors. Smooth areas already compress well, so
+// leaving them alone avoids banding while detailed and noisy areas, which
+// dominate the file size, lose their least significant bits. Alpha is
+// never touched.
+fn near_lossless(pixels: &[u8], width: u32, height: u32, channels: usize, bits: u32) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let limit = 1i32 << bits;
+    let half = 1u32 << (bits - 1);
+    let mut output = pixels.to_vec();
+    for y in 1..height.saturating_sub(1) {
+        for x in 1..width.saturating_sub(1) {
+            let i = (y * width + x) * channels;
+            let neighbors = [i - channels, i + channels, i - width * channels, i + width * channels];
+            let smooth = neighbors.iter().all(|&n| {
+                (0..3).all(|c| (pixels[i + c] as i32 - pixels[n + c] as i32).abs() <= limit)
+            });
+            if !smooth {
+                for c in 0..3 {
+                    output[i + c] = (((pixels[i + c] as u32 + half) >> bits) << bits).min(255) as u8;
+                }
+            }
+        }
+    }
+    output
+}
+
+// rav1e speed preset for AVIF, 1 (slowest) to 10. The module runs
+// single-threaded, so this trades some compression for usable latency.
+const AVIF_SPEED: u8 = 8;
+
+fn encode_avif(img: &DynamicImage, quality: u8) -> Result<Vec<u8>, ravif::Error> {
+    let encoder = ravif::Encoder::new()
+        .with_quality(quality.clamp(1, 100) as f32)
+        .with_alpha_quality(quality.clamp(1, 100) as f32)
+        .with_speed(AVIF_SPEED)
+        .with_bit_depth(ravif::BitDepth::Eight);
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let encoded = if img.color().has_alpha() {
+        let pixels: Vec<ravif::RGBA8> =
+            img.to_rgba8().pixels().map(|p| ravif::RGBA8::new(p[0], p[1], p[2], p[3])).collect();
+        encoder.encode_rgba(ravif::Img::new(&pixels, width, height))?
+    } else {
+        let pixels: Vec<ravif::RGB8> =
+            img.to_rgb8().pixels().map(|p| ravif::RGB8::new(p[0], p[1], p[2])).collect();
+        encoder.encode_rgb(ravif::Img::new(&pixels, width, height))?
+    };
+    Ok(encoded.avif_file)
+}
+
+// 8-bit pixels of `img`, RGBA when it has an alpha channel and RGB otherwise.
+fn pixels_keeping_alpha(img: &DynamicImage) -> (Vec<u8>, ColorType) {
+    if img.color().has_alpha() {
+        (img.to_rgba8().into_raw(), ColorType::Rgba8)
+    } else {
+        (img.to_rgb8().into_raw(), ColorType::Rgb8)
+    }
+}
+
+// A 16-bit RGB or RGBA PNG, compressed as `quality` selects, or TIFF.
+fn encode_16bit(img: &DynamicImage, format: OutputFormat, quality: u8) -> Result<Vec<u8>, JsValue> {
+    let (samples, color_type) = if img.color().has_alpha() {
+        (img.to_rgba16().into_raw(), ColorType::Rgba16)
+    } else {
+        (img.to_rgb16().into_raw(), ColorType::Rgb16)
+    };
+    // The encoder takes native-endian samples and writes them big-endian.
+    let bytes: Vec<u8> = samples.iter().flat_map(|sample| sample.to_ne_bytes()).collect();
+    let mut buffer = Vec::new();
+    let result = if format == OutputFormat::Tiff {
+        TiffEncoder::new(Cursor::new(&mut buffer)).write_image(&bytes, img.width(), img.height(), color_type)
+    } else {
+        let (compression, filter) = png_settings(quality, false);
+        PngEncoder::new_with_quality(&mut buffer, compression, filter).write_image(&bytes, img.width(), img.height(), color_type)
+    };
+    result.map_err(|e| error::encode_failed(&format!("{:?}", format), e))?;
+    Ok(buffer)
+}
+
+// PNG compression and row filtering for `quality`, since lossless output
+// has no quality to trade: 90 and above compress fastest, with the Sub
+// filter, below 50 hardest, and the range between with the defaults.
+// Near-lossless output always compresses hardest. Every setting but the
+// fastest picks each row's filter adaptively.
+fn png_settings(quality: u8, near_lossless: bool) -> (CompressionType, FilterType) {
+    match quality {
+        _ if near_lossless => (CompressionType::Best, FilterType::Adaptive),
+        90.. => (CompressionType::Fast, FilterType::Sub),
+        50..=89 => (CompressionType::Default, FilterType::Adaptive),
+        _ => (CompressionType::Best, FilterType::Adaptive),
+    }
+}
+
+// Largest width and height each format can store, where the encoder would
+// otherwise fail with a less specific error.
+fn max_dimension(format: OutputFormat) -> Option<u32> {
+    match format {
+        OutputFormat::Jpeg => Some(65535),
+        OutputFormat::WebP => Some(16384),
+        OutputFormat::Ico => Some(256),
+        OutputFormat::Tga => Some(65535),
+        _ => None,
+    }
+}
+
+// Encodes `img` as `format`. PNG, WebP and AVIF keep the alpha channel when
+// the image has one; JPEG is written as 8-bit RGB with `jpeg_options`. JPEG
+// and AVIF use `quality` directly, and so does WebP below 100, which is
+// lossy VP8 there and lossless VP8L at 100. With `allow_near_lossless`,
+// WebP below 100 is near-lossless VP8L instead, as is PNG; otherwise PNG
+// stays lossless and `quality` picks its compression effort (see
+// png_settings). AVIF is output only; see decode. JPEG XL is always
+// lossless, and so are TIFF, BMP, ICO (at most 256 pixels per side, as PNG
+// inside) and TGA, which ignore `quality`. 16-bit images stay 16-bit as
+// TIFF, and as PNG unless it is near-lossless, if `depth` keeps them, and
+// are reduced to 8 bits as `depth` describes otherwise.
+fn encode(
+    img: &DynamicImage,
+    format: OutputFormat,
+    quality: u8,
+    allow_near_lossless: bool,
+    jpeg_options: &jpeg::JpegOptions,
+    depth: &depth::DepthOptions,
+) -> Result<Vec<u8>, JsValue> {
+    let (width, height) = (img.width(), img.height());
+    if let Some(limit) = max_dimension(format) {
+        if width > limit || height > limit {
+            return Err(error::size_limit(format!("{:?} images are limited to {} pixels per side", format, limit)));
+        }
+    }
+    let bits = near_lossless_bits(quality, allow_near_lossless);
+    if depth::is_16bit(img) {
+        if (format == OutputFormat::Tiff || (format == OutputFormat::Png && bits == 0)) && depth.keep_16bit() {
+            return encode_16bit(img, format, quality);
+        }
+        return encode(&depth::to_8bit(img, depth.dither()), format, quality, allow_near_lossless, jpeg_options, depth);
+    }
+    let has_alpha = img.color().has_alpha();
+    let mut buffer = Vec::with_capacity(width as usize * height as usize * if has_alpha { 4 } else { 3 });
+
+    match format {
+        OutputFormat::Jpeg => {
+            let rgb_img = if has_alpha { flatten_onto_white(img) } else { img.to_rgb8() };
+            buffer = jpeg::encode(&rgb_img, quality, jpeg_options)?;
+        }
+        OutputFormat::WebP if quality < 100 && !allow_near_lossless => {
+            let (pixels, _) = pixels_keeping_alpha(img);
+            buffer = vp8::encode(&pixels, width, height, if has_alpha { 4 } else { 3 }, quality)?;
+        }
+        OutputFormat::Png | OutputFormat::WebP => {
+            let (mut pixels, color_type) = pixels_keeping_alpha(img);
+            if bits > 0 {
+                let channels = if has_alpha { 4 } else { 3 };
+                pixels = near_lossless(&pixels, width, height, channels, bits);
+            }
+            let result = if format == OutputFormat::Png {
+                let (compression, filter) = png_settings(quality, bits > 0);
+                PngEncoder::new_with_quality(&mut buffer, compression, filter)
+                    .write_image(&pixels, width, height, color_type)
+            } else {
+                WebPEncoder::new_lossless(&mut buffer).encode(&pixels, width, height, color_type)
+            };
+            result.map_err(|e| error::encode_failed(&format!("{:?}", format), e))?;
+        }
+        OutputFormat::Avif => {
+            buffer = encode_avif(img, quality)
+                .map_err(|e| error::encode_failed("AVIF", e))?;
+        }
+        OutputFormat::Jxl => buffer = jxl::encode(img)?,
+        OutputFormat::Tiff | OutputFormat::Bmp | OutputFormat::Ico | OutputFormat::Tga => {
+            let (pixels, color_type) = pixels_keeping_alpha(img);
+            let result = match format {
+                OutputFormat::Tiff => TiffEncoder::new(Cursor::new(&mut buffer)).write_image(&pixels, width, height, color_type),
+                OutputFormat::Bmp => BmpEncoder::new(&mut buffer).write_image(&pixels, width, height, color_type),
+                OutputFormat::Ico => IcoEncoder::new(&mut buffer).write_image(&pixels, width, height, color_type),
+                _ => TgaEncoder::new(&mut buffer).write_image(&pixels, width, height, color_type),
+            };
+            result.map_err(|e| error::encode_failed(&format!("{:?}", format), e))?;
+        }
+    }
+
+    Ok(buffer)
+}
+
+// Longest side encode_to_target_size scales an image down to before giving
+// up on the target.
+const MIN_TARGET_SIDE: u32 = 16;
+
 #[wasm_bindgen]
-pub struct ImageProcessor;
+#[derive(Clone, Default)]
+pub struct ImageProcessor {
+    keep_orientation: bool,
+    preserved_metadata: Vec<String>,
+    written_metadata: metadata::WrittenMetadata,
+    limits: limits::Limits,
+    near_lossless: bool,
+    jpeg_options: jpeg::JpegOptions,
+    color: icc::ColorManagement,
+    depth: depth::DepthOptions,
+}
+
+impl ImageProcessor {
+    // Decodes the input, tone maps floating point images as set with
+    // depth_options, converts it from its ICC profile as set with
+    // color_management and, unless disabled, rotates and flips it upright
+    // according to its EXIF orientation. JPEG XL output from jxl-oxide and
+    // HEIC output, with the container's rotation and mirroring applied, are
+    // already upright.
+    fn load(&self, image_data: &[u8]) -> Result<DynamicImage, JsValue> {
+        let img = depth::tone_map(decode(image_data, &self.limits)?, &self.depth);
+        Ok(self.orient(image_data, self.color.convert(metadata::icc_profile(image_data).as_deref(), img)))
+    }
+
+    // Like load, but only decodes JPEGs at the smallest scale that keeps
+    // `max_side` pixels on the longer side.
+    fn load_scaled(&self, image_data: &[u8], max_side: u32) -> Result<DynamicImage, JsValue> {
+        let img = depth::tone_map(decode_scaled(image_data, max_side, &self.limits)?, &self.depth);
+        Ok(self.orient(image_data, self.color.convert(metadata::icc_profile(image_data).as_deref(), img)))
+    }
+
+    // Turns `img`, decoded from `image_data`, upright as load describes.
+    fn orient(&self, image_data: &[u8], img: DynamicImage) -> DynamicImage {
+        if self.keep_orientation || jxl::is_jxl(image_data) || heif::is_heif(image_data) {
+            return img;
+        }
+        match metadata::read_exif(image_data) {
+            Some(exif) => metadata::apply_orientation(img, metadata::orientation(&exif)),
+            None => img,
+        }
+    }
+
+    // Decodes, applies `transform` and encodes as `format`.
+    fn transformed(
+        &self,
+        image_data: &[u8],
+        format: &str,
+        quality: u8,
+        transform: impl FnOnce(&DynamicImage) -> Result<DynamicImage, JsValue>,
+    ) -> Result<Vec<u8>, JsValue> {
+        let format = parse_format(format)?;
+        let img = self.load(image_data)?;
+        self.encode(image_data, &transform(&img)?, format, quality)
+    }
+
+    // Whether load would leave the pixels of `jpeg` as stored, neither
+    // turning them upright nor converting their colors, and encode would
+    // embed no profile, so the JPEG data itself can be passed on.
+    fn loads_unchanged(&self, jpeg: &[u8]) -> bool {
+        let upright = self.keep_orientation || metadata::read_exif(jpeg).is_none_or(|exif| metadata::orientation(&exif) == 1);
+        upright && !self.color.converts(metadata::icc_profile(jpeg).as_deref()) && self.color.output_profile().is_none()
+    }
+
+    // Transforms a JPEG's DCT coefficients with `transform`, after orienting
+    // them, and writes them back without re-encoding.
+    fn lossless_jpeg(
+        &self,
+        image_data: &[u8],
+        transform: impl FnOnce(&mut jpeg_lossless::Coefficients) -> Result<(), JsValue>,
+    ) -> Result<Vec<u8>, JsValue> {
+        self.limits.check_input(image_data)?;
+        let mut coefficients = jpeg_lossless::decode(image_data, &self.limits)?;
+        if !self.keep_orientation {
+            if let Some(exif) = metadata::read_exif(image_data) {
+                coefficients.orient(metadata::orientation(&exif))?;
+            }
+        }
+        transform(&mut coefficients)?;
+        let (exif, xmp) = self.output_metadata(image_data, !self.keep_orientation);
+        Ok(metadata::embed_jpeg(&coefficients.encode(), exif.as_deref(), xmp.as_deref()))
+    }
+
+    fn output_metadata(&self, source: &[u8], upright: bool) -> (Option<Vec<u8>>, Option<String>) {
+        metadata::output_metadata(source, &self.preserved_metadata, upright, &self.written_metadata)
+    }
+
+    // Encodes `img`, decoded from `source`, with the EXIF tags selected with
+    // preserve_metadata, the fields set with write_metadata and the profile
+    // chosen with color_management.
+    fn encode(&self, source: &[u8], img: &DynamicImage, format: OutputFormat, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.limits.check_output(img.width(), img.height())?;
+        let encoded = encode(img, format, quality, self.near_lossless, &self.jpeg_options, &self.depth)?;
+        let (exif, xmp) = self.output_metadata(source, !self.keep_orientation);
+        let icc = self.color.output_profile();
+        Ok(metadata::embed(encoded, format, exif.as_deref(), xmp.as_deref(), icc.as_deref(), img))
+    }
+
+    // Encodes at the highest quality whose output, metadata included, fits
+    // in `max_bytes`, found by binary search. With `allow_resize`, an image
+    // that is too large even at the lowest quality is scaled down by the
+    // estimated excess, since the size grows roughly with the area, and
+    // searched again.
+    fn encode_within(
+        &self,
+        source: &[u8],
+        img: &DynamicImage,
+        format: OutputFormat,
+        max_bytes: usize,
+        allow_resize: bool,
+    ) -> Result<Vec<u8>, JsValue> {
+        // JPEG XL ignores quality, so only its size can change. Lossless PNG
+        // only compresses harder at lower quality, which still helps.
+        let lossless = format == OutputFormat::Jxl;
+        let lowest = if lossless { 100 } else { 1 };
+        let mut img = std::borrow::Cow::Borrowed(img);
+        loop {
+            let best = self.encode(source, &img, format, 100)?;
+            if best.len() <= max_bytes {
+                return Ok(best);
+            }
+            let smallest = if lowest == 100 { best } else { self.encode(source, &img, format, lowest)? };
+            if smallest.len() <= max_bytes {
+                // `low` always fits and `high` is the largest candidate left.
+                let (mut low, mut high, mut best) = (lowest, 99, smallest);
+                while low < high {
+                    let quality = (low + high).div_ceil(2);
+                    let encoded = self.encode(source, &img, format, quality)?;
+                    if encoded.len() <= max_bytes {
+                        (low, best) = (quality, encoded);
+                    } else {
+                        high = quality - 1;
+                    }
+                }
+                return Ok(best);
+            }
+            if !allow_resize || img.width().max(img.height()) <= MIN_TARGET_SIDE {
+                return Err(error::size_limit(format!("Cannot encode the image within {} bytes", max_bytes)));
+            }
+            let scale = ((max_bytes as f64 / smallest.len() as f64).sqrt() * 0.9).min(0.9);
+            let scaled = |side: u32| ((side as f64 * scale).round() as u32).max(1);
+            img = std::borrow::Cow::Owned(img.resize(scaled(img.width()), scaled(img.height()), image::imageops::FilterType::Lanczos3));
+        }
+    }
+}
 
 #[wasm_bindgen]
 impl ImageProcessor {
     #[wasm_bindgen(constructor)]
     pub fn new() -> ImageProcessor {
-        ImageProcessor
+        ImageProcessor::default()
+    }
+
+    // Whether inputs are turned upright from their EXIF orientation when
+    // loaded, before any resizing. On by default.
+    #[wasm_bindgen(getter)]
+    pub fn auto_orient(&self) -> bool {
+        !self.keep_orientation
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_auto_orient(&mut self, auto_orient: bool) {
+        self.keep_orientation = !auto_orient;
+    }
+
+    // Resource limits, each 0 for none. Inputs with more than `max_pixels`
+    // pixels (64 million by default) or more than `max_input_bytes` bytes
+    // (64 MiB) are rejected from their header, before decoding, as are
+    // operations whose result would exceed `max_pixels` or be wider or
+    // taller than `max_dimension` (32768). Violations throw an ImageError
+    // with code "size_limit".
+    #[wasm_bindgen(getter)]
+    pub fn max_pixels(&self) -> u32 {
+        self.limits.max_pixels.min(u32::MAX as u64) as u32
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_max_pixels(&mut self, max_pixels: u32) {
+        self.limits.max_pixels = max_pixels as u64;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn max_input_bytes(&self) -> u32 {
+        self.limits.max_input_bytes.min(u32::MAX as u64) as u32
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_max_input_bytes(&mut self, max_input_bytes: u32) {
+        self.limits.max_input_bytes = max_input_bytes as u64;
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn max_dimension(&self) -> u32 {
+        self.limits.max_dimension
     }
 
+    #[wasm_bindgen(setter)]
+    pub fn set_max_dimension(&mut self, max_dimension: u32) {
+        self.limits.max_dimension = max_dimension;
+    }
+
+    // Whether PNG and WebP output below quality 100 is near-lossless: color
+    // values in detailed areas are rounded to coarser steps, as libwebp's
+    // near-lossless mode does, which makes files smaller but is lossy, and
+    // WebP stays VP8L. Off by default, when WebP below 100 is lossy VP8 and
+    // PNG is lossless, with `quality` only choosing its compression effort.
+    #[wasm_bindgen(getter)]
+    pub fn near_lossless(&self) -> bool {
+        self.near_lossless
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_near_lossless(&mut self, near_lossless: bool) {
+        self.near_lossless = near_lossless;
+    }
+
+    // Options for all following JPEG output, process_image included, as
+    // `{ progressive?, subsampling?, optimizeHuffman? }`: progressive scans
+    // (off by default), chroma subsampling "4:2:0" (the default), "4:2:2" or
+    // "4:4:4", and optimized Huffman tables (on). Pass null for the defaults.
     #[wasm_bindgen]
-    pub fn resize_image(&self, image_data: &[u8], width: u32, height: u32, quality: u8) -> Result<Vec<u8>, JsValue> {
-        let img = image::load_from_memory(image_data)
-            .map_err(|e| JsValue::from_str(&format!("Failed to load image: {}", e)))?;
+    pub fn jpeg_options(&mut self, options: &JsValue) -> Result<(), JsValue> {
+        self.jpeg_options = jpeg::JpegOptions::from_js(options)?;
+        Ok(())
+    }
 
-        let resized = img.resize(width, height, image::imageops::FilterType::Lanczos3);
+    // High bit depth handling for all following decoding and output, as
+    // `{ toneMapping?, exposure?, dither?, keep16Bit? }`. Linear floating
+    // point images, from OpenEXR, Radiance HDR or float TIFF, are tone
+    // mapped on decoding with "aces" (the default, filmic), "reinhard" or
+    // "clip", after scaling by `exposure` stops (0), into 16-bit sRGB.
+    // 16-bit images, 16-bit PNG and TIFF among them, stay 16-bit in TIFF
+    // output and lossless PNG output unless `keep16Bit` is false, and
+    // are reduced to 8 bits for every other output, with an ordered dither
+    // against banding unless `dither` is false. Pass null for the defaults.
+    #[wasm_bindgen]
+    pub fn depth_options(&mut self, options: &JsValue) -> Result<(), JsValue> {
+        self.depth = depth::DepthOptions::from_js(options)?;
+        Ok(())
+    }
 
-        let mut buffer = Cursor::new(Vec::new());
-        resized.write_to(&mut buffer, ImageFormat::Jpeg)
-            .map_err(|e| JsValue::from_str(&format!("Failed to save image: {}", e)))?;
+    // Color management for all following decoding and output, as
+    // `{ target?, embedProfile? }`. Images with an embedded RGB or gray
+    // matrix/TRC ICC profile, such as Adobe RGB or Display P3, are converted
+    // to `target` on decoding: "srgb" (the default), "display-p3",
+    // "adobe-rgb", or "none" to leave pixels as decoded. `embedProfile`
+    // writes the target's profile into JPEG, PNG and WebP output; it is on
+    // by default for targets other than sRGB, which viewers otherwise
+    // assume. Other profiles, CMYK and lookup table ones among them, are
+    // left unconverted. Lossless JPEG operations keep the source profile.
+    // Pass null for the defaults.
+    #[wasm_bindgen]
+    pub fn color_management(&mut self, options: &JsValue) -> Result<(), JsValue> {
+        self.color = icc::ColorManagement::from_js(options)?;
+        Ok(())
+    }
 
-        Ok(buffer.into_inner())
+    // EXIF fields to keep when re-encoding to JPEG, PNG or WebP, from
+    // "orientation", "copyright" (with artist), "description", "datetime",
+    // "camera" and "gps". Everything else, XMP and IPTC included, is always
+    // stripped; the default empty list strips all metadata. The orientation
+    // is written as upright while auto_orient is on.
+    #[wasm_bindgen]
+    pub fn preserve_metadata(&mut self, fields: Vec<String>) -> Result<(), JsValue> {
+        metadata::check_preservable(&fields)?;
+        self.preserved_metadata = fields;
+        Ok(())
     }
 
+    // Metadata to write into every following JPEG, PNG and WebP output, as
+    // `{ copyright?, artist?, xmp?, xmpNamespace? }`. Copyright and artist
+    // go into both EXIF and XMP (dc:rights and dc:creator), replacing
+    // preserved values. `xmp` maps element names to string values written
+    // under the `xmpNamespace` URI. Pass null to stop writing metadata.
     #[wasm_bindgen]
-    pub fn convert_format(&self, image_data: &[u8], format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
-        let img = image::load_from_memory(image_data)
-            .map_err(|e| JsValue::from_str(&format!("Failed to load image: {}", e)))?;
-
-        let image_format = match format.to_lowercase().as_str() {
-            "jpeg" | "jpg" => ImageFormat::Jpeg,
-            "png" => ImageFormat::Png,
-            "webp" => ImageFormat::WebP,
-            _ => return Err(JsValue::from_str("Unsupported format")),
-        };
+    pub fn write_metadata(&mut self, metadata: &JsValue) -> Result<(), JsValue> {
+        self.written_metadata = metadata::WrittenMetadata::from_js(metadata)?;
+        Ok(())
+    }
+
+    // Reads photo information without decoding the pixels: `width` and
+    // `height` as stored, EXIF `orientation`, `camera` (make, model, lens,
+    // exposure settings), `timestamp` as ISO 8601, `gps` (latitude and
+    // longitude in degrees, altitude in meters), `hasIccProfile` with the
+    // profile's `iccDescription`, the raw `xmp` packet and JPEG `iptc`
+    // fields. Missing entries are null.
+    #[wasm_bindgen]
+    pub fn read_metadata(&self, image_data: &[u8]) -> Result<js_sys::Object, JsValue> {
+        metadata::read(image_data)
+    }
+
+    // Decodes `image_data` once into a Pipeline for chained operations,
+    // encoded at the end with this processor's current settings.
+    #[wasm_bindgen]
+    pub fn load_image(&self, image_data: &[u8]) -> Result<Pipeline, JsValue> {
+        Pipeline::new(self, image_data)
+    }
 
-        let mut buffer = Cursor::new(Vec::new());
-        img.write_to(&mut buffer, image_format)
-            .map_err(|e| JsValue::from_str(&format!("Failed to save image: {}", e)))?;
+    // Starts a Pipeline from raw RGBA pixels, such as canvas ImageData or a
+    // WebGL readback, without a PNG round trip. `pixels` holds width ×
+    // height × 4 bytes, not premultiplied, and carries no metadata.
+    #[wasm_bindgen]
+    pub fn from_rgba(&self, pixels: &[u8], width: u32, height: u32) -> Result<Pipeline, JsValue> {
+        Pipeline::from_rgba(self, pixels, width, height)
+    }
 
-        Ok(buffer.into_inner())
+    // Encodes as `format` at the highest quality that keeps the file within
+    // `max_bytes`, for upload size limits. With `allow_resize` the image is
+    // also scaled down when the lowest quality is not small enough; without
+    // it, or when even a tiny image does not fit, an ImageError with code
+    // "size_limit" is thrown. PNG searches its compression efforts, or its
+    // near-lossless levels when near_lossless is set, and WebP its lossy or
+    // near-lossless qualities; JPEG XL can only shrink by resizing.
+    #[wasm_bindgen]
+    pub fn encode_to_target_size(&self, image_data: &[u8], format: &str, max_bytes: u32, allow_resize: bool) -> Result<Vec<u8>, JsValue> {
+        let format = parse_format(format)?;
+        let img = self.load(image_data)?;
+        self.encode_within(image_data, &img, format, max_bytes as usize, allow_resize)
     }
 
+    // Encodes one `format` output per entry of `widths`, each resized from a
+    // single decode with the aspect ratio kept, for srcset breakpoints.
+    // Widths beyond the image's own are not upscaled but encoded at its
+    // size. Returns an array of `{ width, height, data }`, in the order of
+    // `widths`, with `data` a Uint8Array.
     #[wasm_bindgen]
-    pub fn optimize_image(&self, image_data: &[u8], quality: u8) -> Result<Vec<u8>, JsValue> {
-        let img = image::load_from_memory(image_data)
-            .map_err(|e| JsValue::from_str(&format!("Failed to save image: {}", e)))?;
+    pub fn generate_variants(&self, image_data: &[u8], widths: Vec<u32>, format: &str, quality: u8) -> Result<js_sys::Array, JsValue> {
+        let format = parse_format(format)?;
+        if widths.contains(&0) {
+            return Err(error::invalid_argument("Variant widths must not be zero"));
+        }
+        let img = self.load(image_data)?;
+        let variants = js_sys::Array::new();
+        for width in widths {
+            let width = width.min(img.width());
+            let height = ((width as f64 * img.height() as f64 / img.width() as f64).round() as u32).max(1);
+            let resized = if width == img.width() {
+                std::borrow::Cow::Borrowed(&img)
+            } else {
+                std::borrow::Cow::Owned(img.resize_exact(width, height, image::imageops::FilterType::Lanczos3))
+            };
+            let encoded = self.encode(image_data, &resized, format, quality)?;
+            let variant = js_sys::Object::new();
+            metadata::set(&variant, "width", resized.width());
+            metadata::set(&variant, "height", resized.height());
+            metadata::set(&variant, "data", js_sys::Uint8Array::from(&encoded[..]));
+            variants.push(&variant);
+        }
+        Ok(variants)
+    }
 
-        let mut buffer = Cursor::new(Vec::new());
-        img.write_to(&mut buffer, ImageFormat::Jpeg)
-            .map_err(|e| JsValue::from_str(&format!("Failed to save image: {}", e)))?;
+    // Makes a favicon set, each size the image fitted into a square on
+    // transparent. Returns `{ ico, png }`: `ico` is one .ico holding every
+    // size of `sizes` (1–256, with 16, 32 and 48 when empty), and `png` maps
+    // "180", "192" and "512" to PNGs for the Apple touch icon and web app
+    // manifests. All are Uint8Arrays.
+    #[wasm_bindgen]
+    pub fn generate_favicon(&self, image_data: &[u8], sizes: Vec<u32>) -> Result<js_sys::Object, JsValue> {
+        let sizes = if sizes.is_empty() { favicon::DEFAULT_ICO_SIZES.to_vec() } else { sizes };
+        let img = self.load(image_data)?;
+        let result = js_sys::Object::new();
+        metadata::set(&result, "ico", js_sys::Uint8Array::from(&favicon::ico(&img, &sizes)?[..]));
+        let pngs = js_sys::Object::new();
+        for (size, data) in favicon::pngs(&img)? {
+            metadata::set(&pngs, &size.to_string(), js_sys::Uint8Array::from(&data[..]));
+        }
+        metadata::set(&result, "png", pngs);
+        Ok(result)
+    }
 
-        Ok(buffer.into_inner())
+    // The BlurHash of the image with `x_components` × `y_components` (each
+    // 1–9) frequencies, such as 4 × 3, for a blurred placeholder.
+    #[wasm_bindgen]
+    pub fn blurhash(&self, image_data: &[u8], x_components: u32, y_components: u32) -> Result<String, JsValue> {
+        placeholder::blurhash(&self.load(image_data)?, x_components, y_components)
     }
 
+    // The ThumbHash of the image, about 25 bytes that also keep its aspect
+    // ratio and alpha; base64 them to store alongside the image.
     #[wasm_bindgen]
-    pub fn process_image(&self, image_data: &[u8], width: u32, height: u32, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
-        console::log_1(&format!("process_image called with width: {}, height: {}, format: {}, quality: {}", width, height, format, quality).into());
-        let mut img = image::load_from_memory(image_data)
-            .map_err(|e| JsValue::from_str(&format!("Failed to load image: {}", e)))?;
+    pub fn thumbhash(&self, image_data: &[u8]) -> Result<Vec<u8>, JsValue> {
+        Ok(placeholder::thumbhash(&self.load(image_data)?))
+    }
 
-        // Resize if dimensions provided
-        if width > 0 && height > 0 {
-            img = img.resize(width, height, image::imageops::FilterType::CatmullRom);
+    // Up to `count` (1–32) dominant colors, most common first, as an array
+    // of `{ color, r, g, b, percentage }` with `color` as "#rrggbb" and the
+    // percentages of the opaque pixels summing to 100. Pixels under half
+    // opacity are ignored, so a fully transparent image gives none.
+    #[wasm_bindgen]
+    pub fn extract_palette(&self, image_data: &[u8], count: u32) -> Result<js_sys::Array, JsValue> {
+        let swatches = palette::extract(&self.load(image_data)?, count)?;
+        let colors = js_sys::Array::new();
+        for swatch in swatches {
+            let [r, g, b] = swatch.rgb;
+            let color = js_sys::Object::new();
+            metadata::set(&color, "color", format!("#{:02x}{:02x}{:02x}", r, g, b));
+            metadata::set(&color, "r", r);
+            metadata::set(&color, "g", g);
+            metadata::set(&color, "b", b);
+            metadata::set(&color, "percentage", swatch.share * 100.0);
+            colors.push(&color);
+        }
+        Ok(colors)
+    }
+
+    // Reads every QR code, 2D symbol and linear barcode in the image, after
+    // orienting, as an array of `{ format, rawValue, boundingBox, points }`
+    // like BarcodeDetector.detect: `format` as that API names it (such as
+    // "qr_code" or "ean_13"), `boundingBox` as `{ x, y, width, height }` and
+    // `points` the `{ x, y }` locations the detector found. An image without
+    // barcodes gives an empty array.
+    #[wasm_bindgen]
+    pub fn decode_barcodes(&self, image_data: &[u8]) -> Result<js_sys::Array, JsValue> {
+        let barcodes = js_sys::Array::new();
+        for barcode in barcode::decode(&self.load(image_data)?) {
+            let (x, y, width, height) = barcode.bounds();
+            let bounds = js_sys::Object::new();
+            metadata::set(&bounds, "x", x);
+            metadata::set(&bounds, "y", y);
+            metadata::set(&bounds, "width", width);
+            metadata::set(&bounds, "height", height);
+            let points = js_sys::Array::new();
+            for (px, py) in &barcode.points {
+                let point = js_sys::Object::new();
+                metadata::set(&point, "x", *px);
+                metadata::set(&point, "y", *py);
+                points.push(&point);
+            }
+            let result = js_sys::Object::new();
+            metadata::set(&result, "format", barcode.format);
+            metadata::set(&result, "rawValue", barcode.value);
+            metadata::set(&result, "boundingBox", bounds);
+            metadata::set(&result, "points", points);
+            barcodes.push(&result);
         }
+        Ok(barcodes)
+    }
 
-        let image_format = match format.to_lowercase().as_str() {
-            "jpeg" | "jpg" => ImageFormat::Jpeg,
-            "png" => ImageFormat::Png,
-            "webp" => ImageFormat::WebP,
-            _ => return Err(JsValue::from_str("Unsupported format")),
+    // Finds the page in a photo of a document, after orienting, as its four
+    // `{ x, y }` corners in pixels: top-left, top-right, bottom-right, then
+    // bottom-left. Gives null when no page covering at least a fifth of the
+    // frame stands out from the background.
+    #[wasm_bindgen]
+    pub fn detect_document(&self, image_data: &[u8]) -> Result<JsValue, JsValue> {
+        let Some(quad) = scan::detect(&self.load(image_data)?)? else {
+            return Ok(JsValue::NULL);
         };
+        let corners = js_sys::Array::new();
+        for (x, y) in quad {
+            let corner = js_sys::Object::new();
+            metadata::set(&corner, "x", x);
+            metadata::set(&corner, "y", y);
+            corners.push(&corner);
+        }
+        Ok(corners.into())
+    }
+
+    // The mean color, weighted by alpha, as `{ color, r, g, b, a }` with
+    // `color` as "#rrggbb" and `a` the mean opacity (0–255). JPEGs are only
+    // decoded at 1/8 scale, which averages the same pixels.
+    #[wasm_bindgen]
+    pub fn get_average_color(&self, image_data: &[u8]) -> Result<js_sys::Object, JsValue> {
+        let [r, g, b, a] = placeholder::average_color(&decode_scaled(image_data, 1, &self.limits)?);
+        let color = js_sys::Object::new();
+        metadata::set(&color, "color", format!("#{:02x}{:02x}{:02x}", r, g, b));
+        metadata::set(&color, "r", r);
+        metadata::set(&color, "g", g);
+        metadata::set(&color, "b", b);
+        metadata::set(&color, "a", a);
+        Ok(color)
+    }
+
+    // A tiny blurred WebP preview, at most `max_dim` pixels on its longer
+    // side (about 32 works well), to inline while the real image loads. It
+    // carries no metadata, and JPEGs are decoded at the smallest scale that
+    // still covers `max_dim`.
+    #[wasm_bindgen]
+    pub fn make_lqip(&self, image_data: &[u8], max_dim: u32) -> Result<Vec<u8>, JsValue> {
+        if max_dim == 0 {
+            return Err(error::invalid_argument("Preview size must not be zero"));
+        }
+        self.limits.check_output(max_dim, max_dim)?;
+        let preview = placeholder::lqip(&self.load_scaled(image_data, max_dim)?, max_dim);
+        encode(&preview, OutputFormat::WebP, placeholder::LQIP_QUALITY, false, &self.jpeg_options, &self.depth)
+    }
+
+    // A 64-bit perceptual hash, as a BigInt, for spotting duplicates:
+    // `algorithm` "ahash" (average), "dhash" (difference, the most robust to
+    // recompression for its cost) or "phash" (DCT, the most robust to edits).
+    // Compare hashes of the same algorithm with hamming_distance; a distance
+    // of about 10 or less out of 64 usually means the same picture.
+    #[wasm_bindgen]
+    pub fn perceptual_hash(&self, image_data: &[u8], algorithm: &str) -> Result<u64, JsValue> {
+        let algorithm = hash::parse_algorithm(algorithm)?;
+        Ok(hash::hash(&self.load_scaled(image_data, hash::DECODE_SIDE)?, algorithm))
+    }
+
+    // The number of bits that differ between two hashes.
+    #[wasm_bindgen]
+    pub fn hamming_distance(a: u64, b: u64) -> u32 {
+        (a ^ b).count_ones()
+    }
+
+    // Compares a re-encoded image `b` against the original `a`, both of the
+    // same size after orienting, as `{ mse, psnr, ssim }`: the mean squared
+    // error and PSNR in dB over RGB (Infinity when identical), and SSIM over
+    // luma from 0 to 1. Alpha is ignored. As rough thresholds, PSNR above 40
+    // dB or SSIM above 0.98 is hard to tell from the original.
+    #[wasm_bindgen]
+    pub fn compare(&self, a: &[u8], b: &[u8]) -> Result<js_sys::Object, JsValue> {
+        let comparison = metrics::compare(&self.load(a)?, &self.load(b)?)?;
+        let result = js_sys::Object::new();
+        metadata::set(&result, "mse", comparison.mse);
+        metadata::set(&result, "psnr", comparison.psnr);
+        metadata::set(&result, "ssim", comparison.ssim);
+        Ok(result)
+    }
+
+    // 256-bin histograms of the `red`, `green`, `blue` and `luminance`
+    // (BT.709) channels, plus `alpha` for images that have one, each as a
+    // Uint32Array of pixel counts, with `meanLuminance` (0–255) and the
+    // percentages of pixels clipped to black, `shadowsClipped`, and to
+    // white, `highlightsClipped`.
+    #[wasm_bindgen]
+    pub fn histogram(&self, image_data: &[u8]) -> Result<js_sys::Object, JsValue> {
+        let histogram = analysis::histogram(&self.load(image_data)?);
+        let result = js_sys::Object::new();
+        metadata::set(&result, "red", js_sys::Uint32Array::from(&histogram.red[..]));
+        metadata::set(&result, "green", js_sys::Uint32Array::from(&histogram.green[..]));
+        metadata::set(&result, "blue", js_sys::Uint32Array::from(&histogram.blue[..]));
+        if let Some(alpha) = &histogram.alpha {
+            metadata::set(&result, "alpha", js_sys::Uint32Array::from(&alpha[..]));
+        }
+        metadata::set(&result, "luminance", js_sys::Uint32Array::from(&histogram.luminance[..]));
+        metadata::set(&result, "meanLuminance", histogram.mean_luminance);
+        metadata::set(&result, "shadowsClipped", histogram.shadows_clipped);
+        metadata::set(&result, "highlightsClipped", histogram.highlights_clipped);
+        Ok(result)
+    }
 
-        let rgb_img = img.to_rgb8();
-        let mut buffer = Vec::with_capacity((img.width() * img.height() * 3) as usize);
+    // A sharpness score, the variance of the Laplacian of the luma with the
+    // image fitted within 1024 pixels so scores compare across sizes. Higher
+    // is sharper; photos scoring under about 100 usually look blurry, though
+    // the threshold depends on the content.
+    #[wasm_bindgen]
+    pub fn sharpness(&self, image_data: &[u8]) -> Result<f64, JsValue> {
+        Ok(analysis::sharpness(&self.load_scaled(image_data, analysis::SHARPNESS_SIDE)?))
+    }
+
+    #[wasm_bindgen]
+    pub fn resize_image(&self, image_data: &[u8], width: u32, height: u32, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.limits.check_output(width, height)?;
+        let img = self.load(image_data)?;
 
-        match image_format {
-            ImageFormat::Jpeg => {
-                let mut encoder = JpegEncoder::new_with_quality(&mut buffer, quality);
-                encoder.encode(rgb_img.as_raw(), img.width(), img.height(), ColorType::Rgb8)
-                    .map_err(|e| JsValue::from_str(&format!("Failed to encode JPEG: {}", e)))?;
+        let resized = img.resize(width, height, image::imageops::FilterType::Lanczos3);
+
+        self.encode(image_data, &resized, OutputFormat::Jpeg, quality)
+    }
+
+    #[wasm_bindgen]
+    pub fn convert_format(&self, image_data: &[u8], format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        let format = parse_format(format)?;
+        self.limits.check_input(image_data)?;
+
+        // A JPEG that was recompressed into JPEG XL losslessly converts back
+        // to the original image data instead of being re-encoded, with only
+        // its metadata segments rewritten. One that auto-orient would turn or
+        // color management convert is decoded and encoded as usual instead.
+        if format == OutputFormat::Jpeg && jxl::is_jxl(image_data) {
+            let (width, height) = jxl::dimensions(image_data)?;
+            self.limits.check_pixels(width, height)?;
+            if let Some(jpeg) = jxl::reconstruct_jpeg(image_data).filter(|jpeg| self.loads_unchanged(jpeg)) {
+                let (exif, xmp) = self.output_metadata(&jpeg, false);
+                return Ok(metadata::embed_jpeg(&jpeg, exif.as_deref(), xmp.as_deref()));
             }
-            ImageFormat::Png => {
-                let encoder = PngEncoder::new(&mut buffer);
-                encoder.encode(rgb_img.as_raw(), img.width(), img.height(), ColorType::Rgb8)
-                    .map_err(|e| JsValue::from_str(&format!("Failed to encode PNG: {}", e)))?;
+        }
+
+        // The other way round, a JPEG that would load unchanged is
+        // recompressed into JPEG XL losslessly, keeping its DCT coefficients,
+        // when jxl_recompress supports it, and encoded from pixels otherwise.
+        if format == OutputFormat::Jxl && image_data.starts_with(&[0xFF, 0xD8]) && self.loads_unchanged(image_data) {
+            let (exif, xmp) = self.output_metadata(image_data, false);
+            let jpeg = metadata::embed_jpeg(image_data, exif.as_deref(), xmp.as_deref());
+            if let Some(jxl) = jxl_recompress::recompress(&jpeg, &self.limits) {
+                return Ok(jxl);
             }
-            ImageFormat::WebP => {
-                let encoder = WebPEncoder::new_lossless(&mut buffer);
-                encoder.encode(rgb_img.as_raw(), img.width(), img.height(), ColorType::Rgb8)
-                    .map_err(|e| JsValue::from_str(&format!("Failed to encode WebP: {}", e)))?;
+        }
+
+        let img = self.load(image_data)?;
+        self.encode(image_data, &img, format, quality)
+    }
+
+    // Re-encodes a JPEG, or any other non-PNG input, as JPEG at `quality`.
+    // `effort` 0 uses jpeg_options as set; 1 switches to mozjpeg's
+    // quantization tables with optimized Huffman tables, which saves about
+    // 5-10% at equal PSNR, and 2 or more adds trellis quantization for
+    // another 11-26%, unless jpeg_options asks for progressive output.
+    //
+    // PNG input stays PNG and lossless, ignoring `quality`: it is rewritten
+    // with the smallest color type and bit depth that hold its pixels, a
+    // palette for up to 256 colors, and the best filters and compression
+    // for `effort`: 0 is adaptive filtering at zlib level 6, 1 tries every
+    // filter strategy at level 9, and 2 or more uses zopfli, which is much
+    // slower. Only the first frame of an APNG is kept.
+    #[wasm_bindgen]
+    pub fn optimize_image(&self, image_data: &[u8], quality: u8, effort: u8) -> Result<Vec<u8>, JsValue> {
+        let img = self.load(image_data)?;
+
+        if image_data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            self.limits.check_output(img.width(), img.height())?;
+            let optimized = optimize::optimize_png(&img, effort)?;
+            let (exif, xmp) = self.output_metadata(image_data, !self.keep_orientation);
+            let icc = self.color.output_profile();
+            return Ok(metadata::embed(optimized, OutputFormat::Png, exif.as_deref(), xmp.as_deref(), icc.as_deref(), &img));
+        }
+
+        let processor = ImageProcessor { jpeg_options: self.jpeg_options.with_effort(effort), ..self.clone() };
+        processor.encode(image_data, &img, OutputFormat::Jpeg, quality)
+    }
+
+    // Reduces the image to at most `colors` (2–256) colors and writes it as
+    // an indexed "png" or "gif". Images with that few colors already keep
+    // them exactly; others get a learned palette, mapped with `dither`
+    // "none", "floyd-steinberg" or "ordered" (Bayer 8 × 8). GIF keeps one
+    // transparent color, for every pixel under half opacity.
+    #[wasm_bindgen]
+    pub fn quantize(&self, image_data: &[u8], colors: u32, dither: &str, format: &str) -> Result<Vec<u8>, JsValue> {
+        let dither = quantize::parse_dither(dither)?;
+        let img = self.load(image_data)?;
+        self.limits.check_output(img.width(), img.height())?;
+        let (palette, indices) = quantize::quantize(&img, colors, dither)?;
+        match format.to_lowercase().as_str() {
+            "png" => {
+                let quantized = DynamicImage::ImageRgba8(quantize::to_rgba(img.width(), img.height(), &palette, &indices));
+                let encoded = optimize::optimize_png(&quantized, 1)?;
+                let (exif, xmp) = self.output_metadata(image_data, !self.keep_orientation);
+                let icc = self.color.output_profile();
+                Ok(metadata::embed(encoded, OutputFormat::Png, exif.as_deref(), xmp.as_deref(), icc.as_deref(), &quantized))
             }
-            _ => return Err(JsValue::from_str("Unsupported format")),
+            "gif" => quantize::encode_gif(img.width(), img.height(), &palette, &indices),
+            _ => Err(error::unsupported_format("Quantized output must be PNG or GIF")),
+        }
+    }
+
+    // Resizes into a `width` × `height` box with a CSS object-fit `fit`:
+    // "cover" (fill and crop the center), "contain" (letterbox onto
+    // `background`), "fill" (stretch), "inside" (fit within, like
+    // resize_image) or "outside" (cover without cropping). `background` is
+    // "#rrggbb", "#rrggbbaa" or "transparent", with "" for transparent.
+    #[wasm_bindgen]
+    #[allow(clippy::too_many_arguments)]
+    pub fn resize_fit(
+        &self,
+        image_data: &[u8],
+        width: u32,
+        height: u32,
+        fit: &str,
+        background: &str,
+        format: &str,
+        quality: u8,
+    ) -> Result<Vec<u8>, JsValue> {
+        self.limits.check_output(width, height)?;
+        let background = if background.is_empty() { image::Rgba([0, 0, 0, 0]) } else { transform::parse_color(background)? };
+        self.transformed(image_data, format, quality, |img| transform::fit(img, width, height, fit, background))
+    }
+
+    // Pads the image, centered, out to `width` × `height` with `background`
+    // ("#rrggbb", "#rrggbbaa" or "transparent"). Neither side may be smaller
+    // than the image; resize first for uniform thumbnails of any input.
+    #[wasm_bindgen]
+    pub fn extend(&self, image_data: &[u8], width: u32, height: u32, background: &str, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.limits.check_output(width, height)?;
+        let background = transform::parse_color(background)?;
+        self.transformed(image_data, format, quality, |img| transform::extend(img, width, height, background))
+    }
+
+    // Draws `overlay` over `base` with its top-left corner at `x`, `y`
+    // (negative values clip), at `opacity` (0–1) with `blend_mode`:
+    // "normal", "multiply", "screen", "overlay", "darken", "lighten",
+    // "difference" or "add".
+    #[wasm_bindgen]
+    #[allow(clippy::too_many_arguments)]
+    pub fn composite(
+        &self,
+        base: &[u8],
+        overlay: &[u8],
+        x: i32,
+        y: i32,
+        opacity: f32,
+        blend_mode: &str,
+        format: &str,
+        quality: u8,
+    ) -> Result<Vec<u8>, JsValue> {
+        let overlay = self.load(overlay)?;
+        self.transformed(base, format, quality, |img| composite::composite(img, &overlay, x as i64, y as i64, opacity, blend_mode))
+    }
+
+    // composite with the overlay placed by `position`: "top-left", "top",
+    // "top-right", "left", "center", "right", "bottom-left", "bottom" or
+    // "bottom-right", `margin` pixels in from the edges it touches.
+    #[wasm_bindgen]
+    #[allow(clippy::too_many_arguments)]
+    pub fn watermark(
+        &self,
+        base: &[u8],
+        overlay: &[u8],
+        position: &str,
+        margin: u32,
+        opacity: f32,
+        blend_mode: &str,
+        format: &str,
+        quality: u8,
+    ) -> Result<Vec<u8>, JsValue> {
+        let overlay = self.load(overlay)?;
+        self.transformed(base, format, quality, |img| {
+            let (x, y) = composite::preset_position(position, margin, (img.width(), img.height()), (overlay.width(), overlay.height()))?;
+            composite::composite(img, &overlay, x, y, opacity, blend_mode)
+        })
+    }
+
+    // Lays `images` out in a collage `columns` cells wide, row by row, with
+    // `gap` pixels between cells on `background` ("#rrggbb", "#rrggbbaa" or
+    // "transparent", with "" for transparent). Each cell is `cell_width` ×
+    // `cell_height`, with 0 taking the first image's size, and each image is
+    // fitted into it as by resize_fit with `fit`. `images` is an Array of
+    // Uint8Arrays, or of `{ data, fit? }` objects to give a cell its own fit
+    // mode. The collage carries no metadata.
+    #[wasm_bindgen]
+    #[allow(clippy::too_many_arguments)]
+    pub fn compose_grid(
+        &self,
+        images: &js_sys::Array,
+        columns: u32,
+        cell_width: u32,
+        cell_height: u32,
+        gap: u32,
+        background: &str,
+        fit: &str,
+        format: &str,
+        quality: u8,
+    ) -> Result<Vec<u8>, JsValue> {
+        let format = parse_format(format)?;
+        let background = if background.is_empty() { image::Rgba([0, 0, 0, 0]) } else { transform::parse_color(background)? };
+        let entries = collage::parse_entries(images)?;
+        let images = entries
+            .into_iter()
+            .map(|entry| Ok((self.load(&entry.data)?, entry.fit)))
+            .collect::<Result<Vec<_>, JsValue>>()?;
+        let first = &images[0].0;
+        let cell = (
+            if cell_width == 0 { first.width() } else { cell_width },
+            if cell_height == 0 { first.height() } else { cell_height },
+        );
+        let (width, height) = collage::grid_size(images.len(), columns, cell, gap)?;
+        self.limits.check_output(width, height)?;
+        let grid = collage::compose(&images, columns, cell, gap, background, fit)?;
+        encode(&grid, format, quality, self.near_lossless, &self.jpeg_options, &self.depth)
+    }
+
+    // Packs `images` into one sprite sheet with `padding` pixels between
+    // sprites, returning `{ data, width, height, sprites, json }`: the
+    // encoded sheet as a Uint8Array, its size, an object mapping each name to
+    // `{ x, y, width, height }`, and that map as a JSON string. `images` is
+    // an Array of Uint8Arrays, named "0", "1", and so on, or of
+    // `{ name, data }` objects. The sheet is transparent between sprites, so
+    // PNG or WebP suit it best.
+    #[wasm_bindgen]
+    pub fn pack_sprites(&self, images: &js_sys::Array, padding: u32, format: &str, quality: u8) -> Result<js_sys::Object, JsValue> {
+        let format = parse_format(format)?;
+        let entries = sprites::parse_entries(images)?;
+        let images = entries.iter().map(|entry| self.load(&entry.data)).collect::<Result<Vec<_>, JsValue>>()?;
+        let sizes: Vec<(u32, u32)> = images.iter().map(|img| (img.width(), img.height())).collect();
+        let ((width, height), placements) = sprites::pack(&sizes, padding);
+        self.limits.check_output(width, height)?;
+        let sheet = encode(&sprites::draw(&images, (width, height), &placements), format, quality, self.near_lossless, &self.jpeg_options, &self.depth)?;
+
+        let map = js_sys::Object::new();
+        for (entry, placement) in entries.iter().zip(&placements) {
+            let frame = js_sys::Object::new();
+            metadata::set(&frame, "x", placement.x);
+            metadata::set(&frame, "y", placement.y);
+            metadata::set(&frame, "width", placement.width);
+            metadata::set(&frame, "height", placement.height);
+            metadata::set(&map, &entry.name, frame);
+        }
+        let result = js_sys::Object::new();
+        metadata::set(&result, "data", js_sys::Uint8Array::from(&sheet[..]));
+        metadata::set(&result, "width", width);
+        metadata::set(&result, "height", height);
+        metadata::set(&result, "json", js_sys::JSON::stringify(&map)?);
+        metadata::set(&result, "sprites", map);
+        Ok(result)
+    }
+
+    // Renders the SVG document `svg_bytes` at `width` × `height`, scaled to
+    // fit and centered, on `background` ("#rrggbb", "#rrggbbaa" or
+    // "transparent", with "" for transparent). A side of 0 follows the SVG's
+    // aspect ratio, and 0 for both keeps its own size. Text uses the embedded
+    // DejaVu Sans whatever font the SVG names.
+    #[wasm_bindgen]
+    pub fn rasterize_svg(
+        &self,
+        svg_bytes: &[u8],
+        width: u32,
+        height: u32,
+        background: &str,
+        format: &str,
+        quality: u8,
+    ) -> Result<Vec<u8>, JsValue> {
+        let format = parse_format(format)?;
+        let background = if background.is_empty() { image::Rgba([0, 0, 0, 0]) } else { transform::parse_color(background)? };
+        self.limits.check_input(svg_bytes)?;
+        let tree = svg::parse(svg_bytes)?;
+        let (width, height) = svg::output_size(&tree, width, height);
+        self.limits.check_pixels(width, height)?;
+        self.limits.check_output(width, height)?;
+        let img = svg::rasterize(&tree, width, height, background)?;
+        encode(&img, format, quality, self.near_lossless, &self.jpeg_options, &self.depth)
+    }
+
+    // Renders page `page` of a PDF document, counting from 0, at `dpi` (72
+    // is one pixel per point) on white, for document previews.
+    #[wasm_bindgen]
+    pub fn render_pdf_page(&self, pdf_bytes: &[u8], page: u32, dpi: f32, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        let format = parse_format(format)?;
+        let img = pdf::render_page(pdf_bytes, page, dpi, &self.limits)?;
+        encode(&img, format, quality, self.near_lossless, &self.jpeg_options, &self.depth)
+    }
+
+    // Encodes `text` as a QR code `size` pixels square, quiet zone included,
+    // with `error_correction` "L", "M" (the default for ""), "Q" or "H".
+    // `colors` is `{ foreground?, background? }` as "#rrggbb", "#rrggbbaa"
+    // or "transparent", black on white by default. `format` is "svg" for an
+    // SVG document, or any output format, written losslessly where it can
+    // be.
+    #[wasm_bindgen]
+    pub fn generate_qr(&self, text: &str, size: u32, error_correction: &str, colors: &JsValue, format: &str) -> Result<Vec<u8>, JsValue> {
+        let colors = qr::Colors::from_js(colors)?;
+        let code = qr::encode(text, error_correction)?;
+        if format.eq_ignore_ascii_case("svg") {
+            return Ok(qr::svg(&code, size, &colors)?.into_bytes());
+        }
+        let format = parse_format(format)?;
+        self.limits.check_pixels(size, size)?;
+        self.limits.check_output(size, size)?;
+        encode(&qr::render(&code, size, &colors)?, format, 100, false, &self.jpeg_options, &self.depth)
+    }
+
+    // Draws `text` onto the image. `options` is `{ font?, size?, color?, x?,
+    // y?, maxWidth?, align?, lineHeight?, strokeWidth?, strokeColor? }`:
+    // `font` is TTF or OTF data (the embedded DejaVu Sans by default), `size`
+    // is in pixels (32), `x` and `y` place the top-left of the text block
+    // (0, 0), and lines wrap at spaces within `maxWidth` (the rest of the
+    // image width) and are aligned "left", "center" or "right" there.
+    // `lineHeight` is a multiple of the size (1.2). Colors are "#rrggbb" or
+    // "#rrggbbaa", black for the fill and white for the stroke, which is
+    // `strokeWidth` pixels wide (0, none).
+    #[wasm_bindgen]
+    pub fn draw_text(&self, image_data: &[u8], text: &str, options: &JsValue, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        let options = text::TextOptions::from_js(options)?;
+        self.transformed(image_data, format, quality, |img| Ok(text::draw_text(img, text, &options)))
+    }
+
+    // Cuts out the `width` × `height` rectangle at `x`, `y`, after orienting.
+    #[wasm_bindgen]
+    #[allow(clippy::too_many_arguments)]
+    pub fn crop(&self, image_data: &[u8], x: u32, y: u32, width: u32, height: u32, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.transformed(image_data, format, quality, |img| transform::crop(img, x, y, width, height))
+    }
+
+    // Crops to the largest region with aspect `ratio` (width / height).
+    // `gravity` picks the part to keep: "center", "top", "bottom", "left",
+    // "right", or "entropy" for the region with the most detail.
+    #[wasm_bindgen]
+    pub fn crop_to_aspect(&self, image_data: &[u8], ratio: f64, gravity: &str, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.transformed(image_data, format, quality, |img| transform::crop_to_aspect(img, ratio, gravity))
+    }
+
+    // Quarter turns clockwise and mirroring, applied after orienting.
+    #[wasm_bindgen]
+    pub fn rotate90(&self, image_data: &[u8], format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.transformed(image_data, format, quality, |img| Ok(img.rotate90()))
+    }
+
+    #[wasm_bindgen]
+    pub fn rotate180(&self, image_data: &[u8], format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.transformed(image_data, format, quality, |img| Ok(img.rotate180()))
+    }
+
+    #[wasm_bindgen]
+    pub fn rotate270(&self, image_data: &[u8], format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.transformed(image_data, format, quality, |img| Ok(img.rotate270()))
+    }
+
+    #[wasm_bindgen]
+    pub fn flip_horizontal(&self, image_data: &[u8], format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.transformed(image_data, format, quality, |img| Ok(img.fliph()))
+    }
+
+    #[wasm_bindgen]
+    pub fn flip_vertical(&self, image_data: &[u8], format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.transformed(image_data, format, quality, |img| Ok(img.flipv()))
+    }
+
+    // Brightness, contrast, saturation, hue and gamma, from `options` as
+    // `{ brightness?, contrast?, saturation?, hue?, gamma? }`: the first
+    // three from -1 to 1 with 0 unchanged (saturation -1 is grayscale), hue
+    // in degrees, and gamma above 0 with 1 unchanged. They apply in that
+    // order, to the color channels only.
+    #[wasm_bindgen]
+    pub fn adjust(&self, image_data: &[u8], options: &JsValue, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        let adjustments = adjust::Adjustments::from_js(options)?;
+        self.transformed(image_data, format, quality, |img| Ok(adjustments.apply(img)))
+    }
+
+    // One-shot enhancement, for an "Enhance" button: gray-world white
+    // balance, then a levels stretch that ignores the darkest and brightest
+    // few pixels. `options` is `{ whiteBalance?, stretch?, clip? }`: white
+    // balance on or off (true), the stretch as "contrast" (the default, one
+    // range for all channels, keeping hues), "levels" (a range per channel,
+    // which also evens out color casts) or "none", and the percentage of
+    // pixels clipped at each end (0.5, up to 10).
+    #[wasm_bindgen]
+    pub fn auto_enhance(&self, image_data: &[u8], options: &JsValue, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        let options = enhance::EnhanceOptions::from_js(options)?;
+        self.transformed(image_data, format, quality, |img| Ok(enhance::auto_enhance(img, &options)))
+    }
+
+    // Gaussian blur with standard deviation `sigma` pixels (above 0, up to
+    // 100).
+    #[wasm_bindgen]
+    pub fn blur(&self, image_data: &[u8], sigma: f32, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.transformed(image_data, format, quality, |img| filter::gaussian_blur(img, sigma))
+    }
+
+    // Mean over a square of `2 × radius + 1` pixels, cheaper than blur for
+    // large radii.
+    #[wasm_bindgen]
+    pub fn box_blur(&self, image_data: &[u8], radius: u32, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.transformed(image_data, format, quality, |img| filter::box_blur(img, radius))
+    }
+
+    // A light fixed sharpen; unsharp_mask gives control over it.
+    #[wasm_bindgen]
+    pub fn sharpen(&self, image_data: &[u8], format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.transformed(image_data, format, quality, |img| Ok(filter::sharpen(img)))
+    }
+
+    // Sharpens by adding back `amount` (0–10, typically 0.5–2) times the
+    // detail a Gaussian blur of `sigma` removes, only where it differs by at
+    // least `threshold` levels (0–255) so flat areas keep their smoothness.
+    #[wasm_bindgen]
+    pub fn unsharp_mask(&self, image_data: &[u8], sigma: f32, amount: f32, threshold: u8, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.transformed(image_data, format, quality, |img| filter::unsharp_mask(img, sigma, amount, threshold))
+    }
+
+    // Reduces noise while keeping edges, with a bilateral filter, at
+    // `strength` from 0 (unchanged) to 1. Run before compressing high-ISO
+    // photos, whose grain otherwise costs bytes and turns blocky.
+    #[wasm_bindgen]
+    pub fn denoise(&self, image_data: &[u8], strength: f32, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.transformed(image_data, format, quality, |img| filter::denoise(img, strength))
+    }
+
+    // Sepia toning at `amount` (0–1), as the CSS sepia() filter.
+    #[wasm_bindgen]
+    pub fn sepia(&self, image_data: &[u8], amount: f32, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.transformed(image_data, format, quality, |img| styles::sepia(img, amount))
+    }
+
+    // Recolors by brightness from `shadow` to `highlight` ("#rrggbb").
+    #[wasm_bindgen]
+    pub fn duotone(&self, image_data: &[u8], shadow: &str, highlight: &str, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        let (shadow, highlight) = (transform::parse_color(shadow)?, transform::parse_color(highlight)?);
+        self.transformed(image_data, format, quality, |img| Ok(styles::duotone(img, shadow, highlight)))
+    }
+
+    // Reduces each color channel to `levels` (2–255) values.
+    #[wasm_bindgen]
+    pub fn posterize(&self, image_data: &[u8], levels: u32, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.transformed(image_data, format, quality, |img| styles::posterize(img, levels))
+    }
+
+    #[wasm_bindgen]
+    pub fn invert(&self, image_data: &[u8], format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.transformed(image_data, format, quality, |img| Ok(styles::invert(img)))
+    }
+
+    // Darkens the edges, by up to `strength` (0–1) in the corners.
+    #[wasm_bindgen]
+    pub fn vignette(&self, image_data: &[u8], strength: f32, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.transformed(image_data, format, quality, |img| styles::vignette(img, strength))
+    }
+
+    // Simulates how the image looks with the color vision deficiency
+    // `deficiency`, "protanopia", "deuteranopia" or "tritanopia", at
+    // `severity` from 0 (normal vision) to 1 (the full dichromacy).
+    #[wasm_bindgen]
+    pub fn simulate_color_blindness(&self, image_data: &[u8], deficiency: &str, severity: f32, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.transformed(image_data, format, quality, |img| color_vision::simulate(img, deficiency, severity))
+    }
+
+    // Hides the rectangles `rects`, an array of `{ x, y, width, height }`
+    // clipped to the image, by `mode` "pixelate" or "blur". Blocks and blur
+    // scale with each rectangle, a tenth of its longer side and at least 12
+    // pixels, and only its own pixels are used. Pixelation is the safer
+    // choice for text.
+    #[wasm_bindgen]
+    pub fn redact(&self, image_data: &[u8], rects: &JsValue, mode: &str, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        let (rects, mode) = (redact::parse_rects(rects)?, redact::parse_mode(mode)?);
+        self.transformed(image_data, format, quality, |img| redact::redact(img, &rects, mode))
+    }
+
+    // Corrects red pupils inside `regions`, an array of `{ x, y, width,
+    // height }` rectangles around each eye: within the ellipse a rectangle
+    // bounds, red is pulled down to the level of green and blue where it
+    // clearly dominates them, fading out towards the ellipse's edge.
+    #[wasm_bindgen]
+    pub fn remove_red_eye(&self, image_data: &[u8], regions: &JsValue, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        let regions = redact::parse_rects(regions)?;
+        self.transformed(image_data, format, quality, |img| Ok(redeye::remove_red_eye(img, &regions)))
+    }
+
+    // Masks the image to transparency with anti-aliased edges; encode as
+    // "png", "webp" or "avif" to keep it, as JPEG composites onto white.
+    // round_corners uses `radius` pixels, at most half the shorter side.
+    #[wasm_bindgen]
+    pub fn round_corners(&self, image_data: &[u8], radius: u32, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.transformed(image_data, format, quality, |img| Ok(mask::round_corners(img, radius)))
+    }
+
+    // Crops the centered square and masks it to a circle, for avatars.
+    #[wasm_bindgen]
+    pub fn circle_mask(&self, image_data: &[u8], format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.transformed(image_data, format, quality, |img| Ok(mask::circle(img)))
+    }
+
+    // Masks with the image `mask_data`, stretched to fit: by its alpha if it
+    // has an alpha channel, otherwise by its brightness, with white opaque.
+    #[wasm_bindgen]
+    pub fn apply_mask(&self, image_data: &[u8], mask_data: &[u8], format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        let mask = self.load(mask_data)?;
+        self.transformed(image_data, format, quality, |img| mask::apply_mask(img, &mask))
+    }
+
+    // Surrounds the image with a border, from `options` as `{ width?, top?,
+    // right?, bottom?, left?, color?, gradientTo?, gradientAngle? }`: each
+    // side in pixels, defaulting to `width` (20), filled with `color`
+    // ("#ffffff") or, with `gradientTo`, a linear gradient to it at the CSS
+    // angle `gradientAngle` (180, top to bottom). A thicker bottom gives the
+    // polaroid look.
+    #[wasm_bindgen]
+    pub fn add_border(&self, image_data: &[u8], options: &JsValue, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        let options = decorate::BorderOptions::from_js(options)?;
+        self.transformed(image_data, format, quality, |img| {
+            let bordered = decorate::border(img, &options);
+            self.limits.check_output(bordered.width(), bordered.height())?;
+            Ok(bordered)
+        })
+    }
+
+    // Adds a soft drop shadow behind the image, from `options` as
+    // `{ offsetX?, offsetY?, blur?, color?, background? }`: the offset in
+    // pixels (0, 8), the blur's sigma (8), the shadow color ("#00000080")
+    // and the canvas background ("transparent"). The canvas grows to fit the
+    // shadow.
+    #[wasm_bindgen]
+    pub fn drop_shadow(&self, image_data: &[u8], options: &JsValue, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        let options = decorate::ShadowOptions::from_js(options)?;
+        self.transformed(image_data, format, quality, |img| {
+            let shadowed = decorate::drop_shadow(img, &options)?;
+            self.limits.check_output(shadowed.width(), shadowed.height())?;
+            Ok(shadowed)
+        })
+    }
+
+    // Cleans up a photo of a document: the page is found as detect_document
+    // does, or taken from `options.corners`, and warped flat to a rectangle
+    // as wide and tall as its longer edges. `options` is `{ corners?,
+    // binarize?, blockSize?, offset? }`; with `binarize` (false) the page
+    // becomes black on white, pixels turning black when more than `offset`
+    // levels (10) darker than the mean of the `blockSize` pixel square around
+    // them (0 for a twentieth of the shorter side), which copes with shadows
+    // and uneven light. Without a page the whole image is kept.
+    #[wasm_bindgen]
+    pub fn scan_document(&self, image_data: &[u8], options: &JsValue, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        let options = scan::ScanOptions::from_js(options)?;
+        self.transformed(image_data, format, quality, |img| {
+            let page = scan::clean_up(img, &options, &self.limits)?;
+            self.limits.check_output(page.width(), page.height())?;
+            Ok(page)
+        })
+    }
+
+    // Rotates or mirrors a JPEG without re-encoding it, so no quality is
+    // lost: `transform` is "rotate90", "rotate180" or "rotate270"
+    // (clockwise), "flip-horizontal", "flip-vertical", "transpose",
+    // "transverse", or "none" just to apply the EXIF orientation. Mirroring
+    // an axis needs whole 8 or 16 pixel blocks along it, so a partial block
+    // at the right or bottom edge is trimmed off first.
+    #[wasm_bindgen]
+    pub fn jpeg_transform(&self, image_data: &[u8], transform: &str) -> Result<Vec<u8>, JsValue> {
+        let orientation = match transform.to_lowercase().as_str() {
+            "none" => 1,
+            "flip-horizontal" => 2,
+            "rotate180" => 3,
+            "flip-vertical" => 4,
+            "transpose" => 5,
+            "rotate90" => 6,
+            "transverse" => 7,
+            "rotate270" => 8,
+            _ => return Err(error::invalid_argument("Unsupported lossless transform")),
+        };
+        self.lossless_jpeg(image_data, |coefficients| coefficients.orient(orientation))
+    }
+
+    // Crops a JPEG without re-encoding it. The top-left corner moves up and
+    // left to the nearest 8 or 16 pixel block boundary, keeping the
+    // requested rectangle inside the result.
+    #[wasm_bindgen]
+    pub fn jpeg_crop(&self, image_data: &[u8], x: u32, y: u32, width: u32, height: u32) -> Result<Vec<u8>, JsValue> {
+        self.lossless_jpeg(image_data, |coefficients| coefficients.crop(x, y, width, height))
+    }
+
+    // Rotates clockwise by any angle in degrees. The canvas grows to fit the
+    // rotated image and the uncovered corners are filled with `background`
+    // ("#rrggbb", "#rrggbbaa" or "transparent"); formats without alpha
+    // composite a transparent background onto white.
+    #[wasm_bindgen]
+    pub fn rotate(&self, image_data: &[u8], degrees: f64, background: &str, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        let background = transform::parse_color(background)?;
+        self.transformed(image_data, format, quality, |img| {
+            let (width, height) = transform::rotated_size(img, degrees);
+            self.limits.check_output(width, height)?;
+            transform::rotate(img, degrees, background)
+        })
+    }
+
+    #[wasm_bindgen]
+    pub fn process_image(&self, image_data: &[u8], width: u32, height: u32, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        console::log_1(&format!("process_image called with width: {}, height: {}, format: {}, quality: {}", width, height, format, quality).into());
+        let mut img = self.load(image_data)?;
+
+        // Resize if dimensions provided
+        if width > 0 && height > 0 {
+            self.limits.check_output(width, height)?;
+            img = img.resize(width, height, image::imageops::FilterType::CatmullRom);
         }
 
-        Ok(buffer)
+        self.encode(image_data, &img, parse_format(format)?, quality)
     }
 }
@@ -0,0 +1,79 @@
+// Resource limits against decompression bombs and oversized requests. The
+// WebAssembly heap cannot grow past 4 GiB and the module aborts when an
+// allocation fails, so sizes are checked from headers before any pixel
+// buffer is allocated.
+
+use crate::error;
+use wasm_bindgen::prelude::*;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Limits {
+    // Largest width × height decoded or produced, 0 for no limit.
+    pub(crate) max_pixels: u64,
+    // Largest encoded input accepted, 0 for no limit.
+    pub(crate) max_input_bytes: u64,
+    // Largest width or height produced by an operation, 0 for no limit.
+    pub(crate) max_dimension: u32,
+}
+
+impl Default for Limits {
+    // 64 megapixels is 256 MiB as RGBA, leaving room for a working copy.
+    fn default() -> Limits {
+        Limits { max_pixels: 64_000_000, max_input_bytes: 64 * 1024 * 1024, max_dimension: 32768 }
+    }
+}
+
+impl Limits {
+    pub(crate) fn check_input(&self, data: &[u8]) -> Result<(), JsValue> {
+        if self.max_input_bytes > 0 && data.len() as u64 > self.max_input_bytes {
+            return Err(error::size_limit(format!("Input exceeds the limit of {} bytes", self.max_input_bytes)));
+        }
+        Ok(())
+    }
+
+    // For decoded images, whose size is set by the input.
+    pub(crate) fn check_pixels(&self, width: u32, height: u32) -> Result<(), JsValue> {
+        if !self.allows_pixels(width, height) {
+            return Err(error::size_limit(format!(
+                "Image of {} × {} exceeds the limit of {} pixels",
+                width, height, self.max_pixels
+            )));
+        }
+        Ok(())
+    }
+
+    // For images about to be created by an operation.
+    pub(crate) fn check_output(&self, width: u32, height: u32) -> Result<(), JsValue> {
+        if !self.allows_dimensions(width, height) {
+            return Err(error::size_limit(format!(
+                "Output of {} × {} exceeds the limit of {} pixels per side",
+                width, height, self.max_dimension
+            )));
+        }
+        self.check_pixels(width, height)
+    }
+
+    pub(crate) fn allows_dimensions(&self, width: u32, height: u32) -> bool {
+        self.max_dimension == 0 || (width <= self.max_dimension && height <= self.max_dimension)
+    }
+
+    pub(crate) fn allows_pixels(&self, width: u32, height: u32) -> bool {
+        self.max_pixels == 0 || width as u64 * height as u64 <= self.max_pixels
+    }
+
+    // Allocation cap handed to the image crate's decoders, which enforce it
+    // while decoding: the pixel limit at up to 16 bytes per pixel.
+    pub(crate) fn decoder_limits(&self) -> image::io::Limits {
+        let mut limits = image::io::Limits::default();
+        limits.max_alloc = (self.max_pixels > 0).then(|| self.max_pixels.saturating_mul(16));
+        limits
+    }
+}
+
+// Decode errors, reporting the decoders' own limit checks as size_limit.
+pub(crate) fn decode_error(e: image::ImageError) -> JsValue {
+    match e {
+        image::ImageError::Limits(e) => error::size_limit(format!("Image exceeds the memory limit: {}", e)),
+        e => error::decode_failed(e),
+    }
+}
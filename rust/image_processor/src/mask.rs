@@ -0,0 +1,66 @@
+// Alpha masks: rounded corners, circles for avatars, and an image used as a
+// mask. Shape edges are anti-aliased by pixel coverage, estimated from the
+// distance between the pixel center and the edge.
+
+use crate::error;
+use image::imageops::FilterType;
+use image::{DynamicImage, RgbaImage};
+use wasm_bindgen::prelude::*;
+
+// Multiplies each pixel's alpha by `coverage` (0–1) at its position.
+fn masked(img: &DynamicImage, coverage: impl Fn(u32, u32) -> f32) -> DynamicImage {
+    let mut rgba = img.to_rgba8();
+    for (x, y, pixel) in rgba.enumerate_pixels_mut() {
+        pixel[3] = (pixel[3] as f32 * coverage(x, y)).round() as u8;
+    }
+    DynamicImage::ImageRgba8(rgba)
+}
+
+// Coverage of a pixel whose center lies `distance` from the center of a
+// circle of `radius`.
+fn circle_coverage(distance: f32, radius: f32) -> f32 {
+    (radius - distance + 0.5).clamp(0.0, 1.0)
+}
+
+// Rounds the corners with `radius` pixels, at most half the shorter side.
+pub(crate) fn round_corners(img: &DynamicImage, radius: u32) -> DynamicImage {
+    let (width, height) = (img.width() as f32, img.height() as f32);
+    let radius = (radius as f32).min(width.min(height) / 2.0);
+    masked(img, |x, y| {
+        let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+        // Distance past the straight edges into a corner's square.
+        let dx = (radius - px).max(px - (width - radius)).max(0.0);
+        let dy = (radius - py).max(py - (height - radius)).max(0.0);
+        if dx == 0.0 || dy == 0.0 {
+            return 1.0;
+        }
+        circle_coverage((dx * dx + dy * dy).sqrt(), radius)
+    })
+}
+
+// Crops the centered square and keeps the circle inscribed in it.
+pub(crate) fn circle(img: &DynamicImage) -> DynamicImage {
+    let side = img.width().min(img.height());
+    let square = img.crop_imm((img.width() - side) / 2, (img.height() - side) / 2, side, side);
+    let radius = side as f32 / 2.0;
+    masked(&square, |x, y| {
+        let (dx, dy) = (x as f32 + 0.5 - radius, y as f32 + 0.5 - radius);
+        circle_coverage((dx * dx + dy * dy).sqrt(), radius)
+    })
+}
+
+// Uses `mask`, stretched to the image's size, as coverage: its alpha when it
+// has an alpha channel, otherwise its brightness, white keeping the pixel.
+pub(crate) fn apply_mask(img: &DynamicImage, mask: &DynamicImage) -> Result<DynamicImage, JsValue> {
+    if mask.width() == 0 || mask.height() == 0 {
+        return Err(error::invalid_argument("Mask image is empty"));
+    }
+    let scaled = mask.resize_exact(img.width(), img.height(), FilterType::Triangle);
+    let values: RgbaImage = scaled.to_rgba8();
+    let alpha = mask.color().has_alpha();
+    let luma = scaled.to_luma8();
+    Ok(masked(img, |x, y| {
+        let value = if alpha { values.get_pixel(x, y)[3] } else { luma.get_pixel(x, y)[0] };
+        value as f32 / 255.0
+    }))
+}
@@ -0,0 +1,657 @@
+// Metadata embedded in the input file: EXIF through kamadak-exif, and XMP,
+// IPTC and ICC profiles found by walking the JPEG, PNG or WebP container.
+
+use crate::error;
+use image::DynamicImage;
+use js_sys::{Array, Object, Reflect};
+use std::io::Cursor;
+use wasm_bindgen::prelude::*;
+
+pub(crate) fn read_exif(image_data: &[u8]) -> Option<exif::Exif> {
+    exif::Reader::new().read_from_container(&mut Cursor::new(image_data)).ok()
+}
+
+// EXIF orientation, 1 (upright) to 8, or 1 when the file has none.
+pub(crate) fn orientation(exif: &exif::Exif) -> u32 {
+    exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+        .and_then(|field| field.value.get_uint(0))
+        .filter(|value| (1..=8).contains(value))
+        .unwrap_or(1)
+}
+
+// Turns pixels stored in `orientation` upright.
+pub(crate) fn apply_orientation(img: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+// JPEG marker segments before the image data, as (marker, payload).
+pub(crate) fn jpeg_segments(data: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut segments = Vec::new();
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return segments;
+    }
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xFF {
+        let marker = data[pos + 1];
+        if marker == 0xDA || marker == 0xD9 {
+            break; // Start of scan or end of image
+        }
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        if length < 2 || pos + 2 + length > data.len() {
+            break;
+        }
+        segments.push((marker, &data[pos + 4..pos + 2 + length]));
+        pos += 2 + length;
+    }
+    segments
+}
+
+// PNG chunks, as (type, payload).
+pub(crate) fn png_chunks(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut chunks = Vec::new();
+    if !data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return chunks;
+    }
+    let mut pos = 8;
+    while pos + 12 <= data.len() {
+        let length = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+        let kind: [u8; 4] = data[pos + 4..pos + 8].try_into().unwrap();
+        if pos + 12 + length > data.len() {
+            break;
+        }
+        chunks.push((kind, &data[pos + 8..pos + 8 + length]));
+        pos += 12 + length;
+    }
+    chunks
+}
+
+// Top-level WebP chunks, as (fourcc, payload).
+pub(crate) fn webp_chunks(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut chunks = Vec::new();
+    if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+        return chunks;
+    }
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let kind: [u8; 4] = data[pos..pos + 4].try_into().unwrap();
+        let length = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        if pos + 8 + length > data.len() {
+            break;
+        }
+        chunks.push((kind, &data[pos + 8..pos + 8 + length]));
+        pos += 8 + length + length % 2;
+    }
+    chunks
+}
+
+const JPEG_XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const PNG_XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp\0";
+const JPEG_ICC_HEADER: &[u8] = b"ICC_PROFILE\0";
+// Largest decompressed PNG profile accepted.
+const MAX_ICC_BYTES: usize = 16 << 20;
+const PNG_ICC_NAME: &[u8] = b"ICC profile";
+
+// The XMP packet, if the file carries one.
+fn xmp(data: &[u8]) -> Option<String> {
+    let packet = jpeg_segments(data)
+        .into_iter()
+        .find_map(|(marker, payload)| (marker == 0xE1).then(|| payload.strip_prefix(JPEG_XMP_HEADER)).flatten())
+        .or_else(|| {
+            // iTXt: keyword, compression flag and method, language tag and
+            // translated keyword, then the text. XMP is never compressed.
+            png_chunks(data).into_iter().find_map(|(kind, payload)| {
+                let rest = payload.strip_prefix(PNG_XMP_KEYWORD).filter(|_| &kind == b"iTXt")?;
+                let rest = rest.strip_prefix(&[0, 0])?;
+                let after_language = &rest[rest.iter().position(|&b| b == 0)? + 1..];
+                Some(&after_language[after_language.iter().position(|&b| b == 0)? + 1..])
+            })
+        })
+        .or_else(|| webp_chunks(data).into_iter().find_map(|(kind, payload)| (&kind == b"XMP ").then_some(payload)))?;
+    Some(String::from_utf8_lossy(packet).trim_end_matches('\0').to_string())
+}
+
+// The embedded ICC profile: JPEG APP2 segments joined in sequence order, or
+// a PNG iCCP or WebP ICCP chunk.
+pub(crate) fn icc_profile(data: &[u8]) -> Option<Vec<u8>> {
+    let mut parts: Vec<(u8, &[u8])> = jpeg_segments(data)
+        .into_iter()
+        .filter_map(|(marker, payload)| (marker == 0xE2).then(|| payload.strip_prefix(JPEG_ICC_HEADER)).flatten())
+        .filter_map(|rest| Some((*rest.first()?, rest.get(2..)?)))
+        .collect();
+    if !parts.is_empty() {
+        parts.sort_by_key(|(sequence, _)| *sequence);
+        return Some(parts.into_iter().flat_map(|(_, part)| part.to_vec()).collect());
+    }
+    // iCCP: profile name, compression method 0, then the zlib stream.
+    let iccp = png_chunks(data).into_iter().find_map(|(kind, payload)| {
+        let compressed = payload.get(payload.iter().position(|&b| b == 0).filter(|_| &kind == b"iCCP")? + 2..)?;
+        miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(compressed, MAX_ICC_BYTES).ok()
+    });
+    iccp.or_else(|| webp_chunks(data).into_iter().find_map(|(kind, payload)| (&kind == b"ICCP").then(|| payload.to_vec())))
+        .or_else(|| crate::heif::is_heif(data).then(|| crate::heif::icc_profile(data)).flatten())
+}
+
+fn has_icc_profile(data: &[u8]) -> bool {
+    jpeg_segments(data).iter().any(|(marker, payload)| *marker == 0xE2 && payload.starts_with(JPEG_ICC_HEADER))
+        || png_chunks(data).iter().any(|(kind, _)| kind == b"iCCP")
+        || webp_chunks(data).iter().any(|(kind, _)| kind == b"ICCP")
+        || (crate::heif::is_heif(data) && crate::heif::icc_profile(data).is_some())
+}
+
+// IPTC-NAA records from the Photoshop resource block (APP13) of a JPEG, as
+// (dataset, value) for the application record.
+fn iptc_records(data: &[u8]) -> Vec<(u8, String)> {
+    let mut records = Vec::new();
+    for (_, payload) in jpeg_segments(data).into_iter().filter(|(marker, _)| *marker == 0xED) {
+        let Some(mut resources) = payload.strip_prefix(b"Photoshop 3.0\0") else { continue };
+        while resources.len() >= 12 && resources.starts_with(b"8BIM") {
+            let id = u16::from_be_bytes([resources[4], resources[5]]);
+            // Pascal-string name, padded to an even length.
+            let name_length = resources[6] as usize;
+            let name_end = 7 + name_length + (name_length + 1) % 2;
+            if name_end + 4 > resources.len() {
+                break;
+            }
+            let size = u32::from_be_bytes(resources[name_end..name_end + 4].try_into().unwrap()) as usize;
+            let start = name_end + 4;
+            if start + size > resources.len() {
+                break;
+            }
+            if id == 0x0404 {
+                let mut block = &resources[start..start + size];
+                while block.len() >= 5 && block[0] == 0x1C {
+                    let (record, dataset) = (block[1], block[2]);
+                    let length = u16::from_be_bytes([block[3], block[4]]) as usize;
+                    if 5 + length > block.len() {
+                        break;
+                    }
+                    if record == 2 {
+                        records.push((dataset, String::from_utf8_lossy(&block[5..5 + length]).into_owned()));
+                    }
+                    block = &block[5 + length..];
+                }
+            }
+            resources = &resources[(start + size + size % 2).min(resources.len())..];
+        }
+    }
+    records
+}
+
+pub(crate) fn set(object: &Object, key: &str, value: impl Into<JsValue>) {
+    // Setting a property on a plain object cannot fail.
+    let _ = Reflect::set(object, &key.into(), &value.into());
+}
+
+fn ascii(exif: &exif::Exif, tag: exif::Tag) -> Option<String> {
+    match &exif.get_field(tag, exif::In::PRIMARY)?.value {
+        exif::Value::Ascii(values) => {
+            let text = String::from_utf8_lossy(values.first()?).trim().to_string();
+            (!text.is_empty()).then_some(text)
+        }
+        _ => None,
+    }
+}
+
+fn rationals(exif: &exif::Exif, tag: exif::Tag) -> Option<Vec<f64>> {
+    match &exif.get_field(tag, exif::In::PRIMARY)?.value {
+        exif::Value::Rational(values) => Some(values.iter().map(|r| r.to_f64()).collect()),
+        _ => None,
+    }
+}
+
+// EXIF "YYYY:MM:DD HH:MM:SS" as ISO 8601, with the UTC offset when known.
+fn timestamp(exif: &exif::Exif) -> Option<String> {
+    let (value, offset) = match ascii(exif, exif::Tag::DateTimeOriginal) {
+        Some(value) => (value, ascii(exif, exif::Tag::OffsetTimeOriginal)),
+        None => (ascii(exif, exif::Tag::DateTime)?, ascii(exif, exif::Tag::OffsetTime)),
+    };
+    let (date, time) = value.split_once(' ')?;
+    Some(format!("{}T{}{}", date.replace(':', "-"), time, offset.unwrap_or_default()))
+}
+
+fn gps(exif: &exif::Exif) -> Option<Object> {
+    let coordinate = |value: exif::Tag, reference: exif::Tag, negative: &str| -> Option<f64> {
+        let parts = rationals(exif, value)?;
+        let degrees = parts.iter().zip([1.0, 60.0, 3600.0]).map(|(part, scale)| part / scale).sum::<f64>();
+        Some(if ascii(exif, reference).as_deref() == Some(negative) { -degrees } else { degrees })
+    };
+    let latitude = coordinate(exif::Tag::GPSLatitude, exif::Tag::GPSLatitudeRef, "S")?;
+    let longitude = coordinate(exif::Tag::GPSLongitude, exif::Tag::GPSLongitudeRef, "W")?;
+    let gps = Object::new();
+    set(&gps, "latitude", latitude);
+    set(&gps, "longitude", longitude);
+    if let Some(altitude) = rationals(exif, exif::Tag::GPSAltitude).and_then(|v| v.first().copied()) {
+        let below_sea_level = exif
+            .get_field(exif::Tag::GPSAltitudeRef, exif::In::PRIMARY)
+            .and_then(|field| field.value.get_uint(0))
+            == Some(1);
+        set(&gps, "altitude", if below_sea_level { -altitude } else { altitude });
+    }
+    Some(gps)
+}
+
+fn camera(exif: &exif::Exif) -> Option<Object> {
+    let make = ascii(exif, exif::Tag::Make);
+    let model = ascii(exif, exif::Tag::Model);
+    if make.is_none() && model.is_none() {
+        return None;
+    }
+    let camera = Object::new();
+    set(&camera, "make", make);
+    set(&camera, "model", model);
+    set(&camera, "lens", ascii(exif, exif::Tag::LensModel));
+    set(&camera, "exposureTime", rationals(exif, exif::Tag::ExposureTime).and_then(|v| v.first().copied()));
+    set(&camera, "fNumber", rationals(exif, exif::Tag::FNumber).and_then(|v| v.first().copied()));
+    set(&camera, "focalLength", rationals(exif, exif::Tag::FocalLength).and_then(|v| v.first().copied()));
+    set(
+        &camera,
+        "iso",
+        exif.get_field(exif::Tag::PhotographicSensitivity, exif::In::PRIMARY).and_then(|f| f.value.get_uint(0)),
+    );
+    Some(camera)
+}
+
+fn iptc(data: &[u8]) -> Option<Object> {
+    let records = iptc_records(data);
+    if records.is_empty() {
+        return None;
+    }
+    let first = |dataset: u8| records.iter().find(|(d, _)| *d == dataset).map(|(_, value)| value.clone());
+    let iptc = Object::new();
+    set(&iptc, "title", first(5));
+    set(&iptc, "caption", first(120));
+    set(&iptc, "byline", first(80));
+    set(&iptc, "copyright", first(116));
+    let keywords: Array = records.iter().filter(|(d, _)| *d == 25).map(|(_, value)| JsValue::from_str(value)).collect();
+    set(&iptc, "keywords", keywords);
+    Some(iptc)
+}
+
+// Stored (not oriented) pixel size, read from the header only.
+fn dimensions(data: &[u8]) -> Result<(u32, u32), JsValue> {
+    if crate::jxl::is_jxl(data) {
+        return crate::jxl::dimensions(data);
+    }
+    if crate::heif::is_heif(data) {
+        return crate::heif::dimensions(data);
+    }
+    if crate::raw::is_raw(data) {
+        return crate::raw::dimensions(data);
+    }
+    crate::image_reader(data)?.into_dimensions().map_err(error::decode_failed)
+}
+
+// The object returned by ImageProcessor.read_metadata.
+pub(crate) fn read(data: &[u8]) -> Result<Object, JsValue> {
+    let (width, height) = dimensions(data)?;
+    let exif = read_exif(data);
+    let metadata = Object::new();
+    set(&metadata, "width", width);
+    set(&metadata, "height", height);
+    set(&metadata, "orientation", exif.as_ref().map_or(1, orientation));
+    set(&metadata, "camera", exif.as_ref().and_then(camera));
+    set(&metadata, "timestamp", exif.as_ref().and_then(timestamp));
+    set(&metadata, "gps", exif.as_ref().and_then(gps));
+    set(&metadata, "hasIccProfile", has_icc_profile(data) || crate::jxl::has_icc_profile(data));
+    let profile = icc_profile(data).and_then(|icc| crate::icc::Profile::parse(&icc));
+    set(&metadata, "iccDescription", profile.as_ref().and_then(|p| p.description()).map(str::to_string));
+    set(&metadata, "xmp", xmp(data));
+    set(&metadata, "iptc", iptc(data));
+    Ok(metadata)
+}
+
+// EXIF tags kept by each name accepted by ImageProcessor.preserve_metadata.
+// "gps" keeps every GPS tag.
+const PRESERVABLE: &[(&str, &[exif::Tag])] = &[
+    ("orientation", &[exif::Tag::Orientation]),
+    ("copyright", &[exif::Tag::Copyright, exif::Tag::Artist]),
+    ("description", &[exif::Tag::ImageDescription, exif::Tag::UserComment]),
+    (
+        "datetime",
+        &[
+            exif::Tag::DateTime,
+            exif::Tag::DateTimeOriginal,
+            exif::Tag::DateTimeDigitized,
+            exif::Tag::OffsetTime,
+            exif::Tag::OffsetTimeOriginal,
+            exif::Tag::OffsetTimeDigitized,
+        ],
+    ),
+    (
+        "camera",
+        &[
+            exif::Tag::Make,
+            exif::Tag::Model,
+            exif::Tag::LensMake,
+            exif::Tag::LensModel,
+            exif::Tag::ExposureTime,
+            exif::Tag::FNumber,
+            exif::Tag::PhotographicSensitivity,
+            exif::Tag::FocalLength,
+            exif::Tag::FocalLengthIn35mmFilm,
+        ],
+    ),
+    ("gps", &[]),
+];
+
+pub(crate) fn check_preservable(names: &[String]) -> Result<(), JsValue> {
+    match names.iter().find(|name| !PRESERVABLE.iter().any(|(known, _)| known == name)) {
+        Some(name) => Err(error::invalid_argument(format!("Unknown metadata field: {}", name))),
+        None => Ok(()),
+    }
+}
+
+// Metadata set with ImageProcessor.write_metadata, added to every output.
+#[derive(Clone, Default)]
+pub(crate) struct WrittenMetadata {
+    copyright: Option<String>,
+    artist: Option<String>,
+    xmp_namespace: Option<String>,
+    xmp: Vec<(String, String)>,
+}
+
+fn string_property(object: &JsValue, key: &str) -> Result<Option<String>, JsValue> {
+    let value = Reflect::get(object, &key.into())?;
+    if value.is_undefined() || value.is_null() {
+        return Ok(None);
+    }
+    value.as_string().map(Some).ok_or_else(|| error::invalid_argument(format!("Metadata `{}` must be a string", key)))
+}
+
+// XML names usable as element names in the custom namespace.
+fn is_xml_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+impl WrittenMetadata {
+    // Reads `{ copyright?, artist?, xmp?, xmpNamespace? }`; null or
+    // undefined clears everything.
+    pub(crate) fn from_js(value: &JsValue) -> Result<WrittenMetadata, JsValue> {
+        if value.is_undefined() || value.is_null() {
+            return Ok(WrittenMetadata::default());
+        }
+        let mut written = WrittenMetadata {
+            copyright: string_property(value, "copyright")?,
+            artist: string_property(value, "artist")?,
+            xmp_namespace: string_property(value, "xmpNamespace")?,
+            xmp: Vec::new(),
+        };
+        let xmp = Reflect::get(value, &"xmp".into())?;
+        if !xmp.is_undefined() && !xmp.is_null() {
+            for entry in Object::entries(xmp.dyn_ref::<Object>().ok_or_else(|| error::invalid_argument("Metadata `xmp` must be an object"))?) {
+                let entry = Array::from(&entry);
+                let name = entry.get(0).as_string().unwrap_or_default();
+                if !is_xml_name(&name) {
+                    return Err(error::invalid_argument(format!("Invalid XMP field name: {}", name)));
+                }
+                let value = entry.get(1).as_string().ok_or_else(|| error::invalid_argument(format!("XMP field `{}` must be a string", name)))?;
+                written.xmp.push((name, value));
+            }
+        }
+        if !written.xmp.is_empty() && written.xmp_namespace.is_none() {
+            return Err(error::invalid_argument("Metadata `xmpNamespace` is required for custom XMP fields"));
+        }
+        Ok(written)
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// An XMP packet with the copyright as dc:rights, the artist as dc:creator
+// and the custom fields under `xmpNamespace`, or None when there is
+// nothing to write.
+fn xmp_packet(written: &WrittenMetadata) -> Option<String> {
+    if written.copyright.is_none() && written.artist.is_none() && written.xmp.is_empty() {
+        return None;
+    }
+    let mut properties = String::new();
+    if let Some(copyright) = &written.copyright {
+        properties += &format!(
+            "   <dc:rights><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:rights>\n",
+            escape_xml(copyright)
+        );
+    }
+    if let Some(artist) = &written.artist {
+        properties += &format!("   <dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>\n", escape_xml(artist));
+    }
+    for (name, value) in &written.xmp {
+        properties += &format!("   <custom:{0}>{1}</custom:{0}>\n", name, escape_xml(value));
+    }
+    let namespace = written
+        .xmp_namespace
+        .as_ref()
+        .map_or(String::new(), |uri| format!(" xmlns:custom=\"{}\"", escape_xml(uri)));
+    Some(format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+         <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n\
+         \x20<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n\
+         \x20 <rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\"{}>\n\
+         {}\
+         \x20 </rdf:Description>\n\
+         \x20</rdf:RDF>\n\
+         </x:xmpmeta>\n\
+         <?xpacket end=\"w\"?>",
+        namespace, properties
+    ))
+}
+
+// The metadata to embed in output encoded from `source`: a TIFF-structured
+// EXIF block with the tags named by `preserved` and the written copyright
+// and artist, and the XMP packet. `upright` records that the pixels were
+// rotated for the orientation, which is then written as 1.
+pub(crate) fn output_metadata(
+    source: &[u8],
+    preserved: &[String],
+    upright: bool,
+    written: &WrittenMetadata,
+) -> (Option<Vec<u8>>, Option<String>) {
+    let keep = |tag: exif::Tag| {
+        PRESERVABLE.iter().filter(|(name, _)| preserved.iter().any(|p| p == name)).any(|(name, tags)| {
+            tags.contains(&tag) || (*name == "gps" && tag.context() == exif::Context::Gps)
+        })
+    };
+    let source_exif = if preserved.is_empty() { None } else { read_exif(source) };
+    let mut fields: Vec<exif::Field> = source_exif
+        .iter()
+        .flat_map(|exif| exif.fields())
+        .filter(|field| field.ifd_num == exif::In::PRIMARY && keep(field.tag))
+        .map(|field| match field.tag {
+            exif::Tag::Orientation if upright => exif::Field { value: exif::Value::Short(vec![1]), ..field.clone() },
+            _ => field.clone(),
+        })
+        .collect();
+    for (tag, value) in [(exif::Tag::Copyright, &written.copyright), (exif::Tag::Artist, &written.artist)] {
+        if let Some(value) = value {
+            fields.retain(|field| field.tag != tag);
+            let value = exif::Value::Ascii(vec![value.as_bytes().to_vec()]);
+            fields.push(exif::Field { tag, ifd_num: exif::In::PRIMARY, value });
+        }
+    }
+
+    let mut exif = None;
+    if !fields.is_empty() {
+        let mut writer = exif::experimental::Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut tiff = Cursor::new(Vec::new());
+        let little_endian = source_exif.as_ref().is_some_and(|exif| exif.little_endian());
+        if writer.write(&mut tiff, little_endian).is_ok() {
+            exif = Some(tiff.into_inner());
+        }
+    }
+    (exif, xmp_packet(written))
+}
+
+fn is_jpeg_metadata(marker: u8, payload: &[u8]) -> bool {
+    match marker {
+        0xE1 => payload.starts_with(b"Exif\0\0") || payload.starts_with(JPEG_XMP_HEADER),
+        0xED => true, // Photoshop resources, including IPTC
+        _ => false,
+    }
+}
+
+fn push_jpeg_app1(output: &mut Vec<u8>, header: &[u8], payload: &[u8]) {
+    // A segment holds at most 65533 bytes after its length.
+    if header.len() + payload.len() > 65533 {
+        return;
+    }
+    output.extend_from_slice(&[0xFF, 0xE1]);
+    output.extend_from_slice(&((header.len() + payload.len() + 2) as u16).to_be_bytes());
+    output.extend_from_slice(header);
+    output.extend_from_slice(payload);
+}
+
+// Rewrites a JPEG without its EXIF, XMP and IPTC segments, then adds `exif`
+// and `xmp` straight after SOI. ICC profiles and JFIF headers stay.
+pub(crate) fn embed_jpeg(data: &[u8], exif: Option<&[u8]>, xmp: Option<&str>) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(&data[..2]);
+    if let Some(exif) = exif {
+        push_jpeg_app1(&mut output, b"Exif\0\0", exif);
+    }
+    if let Some(xmp) = xmp {
+        push_jpeg_app1(&mut output, JPEG_XMP_HEADER, xmp.as_bytes());
+    }
+    let mut pos = 2;
+    for (marker, payload) in jpeg_segments(data) {
+        let end = pos + 4 + payload.len();
+        if !is_jpeg_metadata(marker, payload) {
+            output.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+    }
+    output.extend_from_slice(&data[pos..]);
+    output
+}
+
+pub(crate) fn push_png_chunk(output: &mut Vec<u8>, kind: &[u8; 4], payload: &[u8]) {
+    let mut chunk = kind.to_vec();
+    chunk.extend_from_slice(payload);
+    output.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    output.extend_from_slice(&chunk);
+    output.extend_from_slice(&crc32fast::hash(&chunk).to_be_bytes());
+}
+
+// Adds iCCP, eXIf and XMP iTXt chunks after IHDR.
+fn embed_png(data: &[u8], exif: Option<&[u8]>, xmp: Option<&str>, icc: Option<&[u8]>) -> Vec<u8> {
+    let ihdr_end = 8 + 12 + 13;
+    let mut output = Vec::with_capacity(data.len());
+    output.extend_from_slice(&data[..ihdr_end]);
+    if let Some(icc) = icc {
+        // Profile name, then compression method 0 (zlib).
+        let mut iccp = PNG_ICC_NAME.to_vec();
+        iccp.extend_from_slice(&[0, 0]);
+        iccp.extend_from_slice(&miniz_oxide::deflate::compress_to_vec_zlib(icc, 9));
+        push_png_chunk(&mut output, b"iCCP", &iccp);
+    }
+    if let Some(exif) = exif {
+        push_png_chunk(&mut output, b"eXIf", exif);
+    }
+    if let Some(xmp) = xmp {
+        // Uncompressed, with empty language tag and translated keyword.
+        let mut text = PNG_XMP_KEYWORD.to_vec();
+        text.extend_from_slice(&[0, 0, 0, 0]);
+        text.extend_from_slice(xmp.as_bytes());
+        push_png_chunk(&mut output, b"iTXt", &text);
+    }
+    output.extend_from_slice(&data[ihdr_end..]);
+    output
+}
+
+// Moves a WebP into the extended format, which is the only one that can
+// carry ICC, EXIF and XMP chunks. Its image chunks, VP8L or ALPH and VP8,
+// are kept as they are; a VP8X header already there is replaced.
+fn embed_webp(data: &[u8], exif: Option<&[u8]>, xmp: Option<&str>, icc: Option<&[u8]>, img: &DynamicImage) -> Vec<u8> {
+    let flags = if icc.is_some() { 0x20 } else { 0 }
+        | if img.color().has_alpha() { 0x10 } else { 0 }
+        | if exif.is_some() { 0x08 } else { 0 }
+        | if xmp.is_some() { 0x04 } else { 0 };
+    let mut vp8x = vec![flags, 0, 0, 0];
+    vp8x.extend_from_slice(&(img.width() - 1).to_le_bytes()[..3]);
+    vp8x.extend_from_slice(&(img.height() - 1).to_le_bytes()[..3]);
+    let mut chunks: Vec<([u8; 4], &[u8])> = vec![(*b"VP8X", &vp8x)];
+    if let Some(icc) = icc {
+        chunks.push((*b"ICCP", icc));
+    }
+    chunks.extend(webp_chunks(data).into_iter().filter(|(kind, _)| kind != b"VP8X"));
+    if let Some(exif) = exif {
+        chunks.push((*b"EXIF", exif));
+    }
+    if let Some(xmp) = xmp {
+        chunks.push((*b"XMP ", xmp.as_bytes()));
+    }
+    let mut body = b"WEBP".to_vec();
+    for (kind, payload) in chunks {
+        body.extend_from_slice(&kind);
+        body.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        body.extend_from_slice(payload);
+        if payload.len() % 2 == 1 {
+            body.push(0);
+        }
+    }
+    let mut output = b"RIFF".to_vec();
+    output.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    output.extend_from_slice(&body);
+    output
+}
+
+// Inserts `icc` after SOI as APP2 segments, split to fit.
+fn embed_jpeg_icc(data: &[u8], icc: &[u8]) -> Vec<u8> {
+    // A segment holds 65533 bytes after its length, less the header and
+    // the sequence number and count.
+    let parts: Vec<&[u8]> = icc.chunks(65533 - JPEG_ICC_HEADER.len() - 2).collect();
+    let mut output = Vec::with_capacity(data.len() + icc.len() + parts.len() * 18);
+    output.extend_from_slice(&data[..2]);
+    for (index, part) in parts.iter().enumerate() {
+        output.extend_from_slice(&[0xFF, 0xE2]);
+        output.extend_from_slice(&((JPEG_ICC_HEADER.len() + 2 + part.len() + 2) as u16).to_be_bytes());
+        output.extend_from_slice(JPEG_ICC_HEADER);
+        output.extend_from_slice(&[index as u8 + 1, parts.len() as u8]);
+        output.extend_from_slice(part);
+    }
+    output.extend_from_slice(&data[2..]);
+    output
+}
+
+// Applies the metadata policy to encoded output: JPEG loses any metadata
+// segments it was carrying, and JPEG, PNG and WebP get `exif`, `xmp` and the
+// `icc` profile when there are any. Other formats are written without
+// metadata.
+pub(crate) fn embed(
+    encoded: Vec<u8>,
+    format: crate::OutputFormat,
+    exif: Option<&[u8]>,
+    xmp: Option<&str>,
+    icc: Option<&[u8]>,
+    img: &DynamicImage,
+) -> Vec<u8> {
+    if format == crate::OutputFormat::Jpeg {
+        let output = embed_jpeg(&encoded, exif, xmp);
+        return match icc {
+            Some(icc) => embed_jpeg_icc(&output, icc),
+            None => output,
+        };
+    }
+    if exif.is_none() && xmp.is_none() && icc.is_none() {
+        return encoded;
+    }
+    match format {
+        crate::OutputFormat::Png => embed_png(&encoded, exif, xmp, icc),
+        crate::OutputFormat::WebP => embed_webp(&encoded, exif, xmp, icc, img),
+        _ => encoded,
+    }
+}
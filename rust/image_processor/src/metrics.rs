@@ -0,0 +1,119 @@
+// Full-reference quality metrics between an original and a re-encoded
+// image of the same size: PSNR over the RGB channels and SSIM over luma, the
+// latter as Wang et al. define it, with an 11 × 11 Gaussian window (σ 1.5)
+// after their suggested downsampling of large images.
+
+use crate::error;
+use image::DynamicImage;
+use wasm_bindgen::prelude::*;
+
+const WINDOW: usize = 11;
+const SIGMA: f64 = 1.5;
+const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+pub(crate) struct Comparison {
+    pub(crate) mse: f64,
+    pub(crate) psnr: f64,
+    pub(crate) ssim: f64,
+}
+
+// Mean squared error and PSNR in dB, infinite for identical images.
+fn psnr(a: &DynamicImage, b: &DynamicImage) -> (f64, f64) {
+    let (a, b) = (a.to_rgb8(), b.to_rgb8());
+    let total: f64 = a.as_raw().iter().zip(b.as_raw()).map(|(&x, &y)| (x as f64 - y as f64).powi(2)).sum();
+    let mse = total / a.as_raw().len() as f64;
+    (mse, 10.0 * (255.0 * 255.0 / mse).log10())
+}
+
+// BT.601 luma, averaged over `factor` × `factor` blocks.
+fn luma(img: &DynamicImage, factor: usize) -> (usize, usize, Vec<f64>) {
+    let rgb = img.to_rgb8();
+    let (width, height) = (rgb.width() as usize / factor, rgb.height() as usize / factor);
+    let mut out = vec![0.0; width * height];
+    for (x, y, pixel) in rgb.enumerate_pixels() {
+        let (x, y) = (x as usize / factor, y as usize / factor);
+        if x < width && y < height {
+            out[y * width + x] += 0.299 * pixel[0] as f64 + 0.587 * pixel[1] as f64 + 0.114 * pixel[2] as f64;
+        }
+    }
+    let area = (factor * factor) as f64;
+    out.iter_mut().for_each(|v| *v /= area);
+    (width, height, out)
+}
+
+// Separable Gaussian filtering over the positions where the whole window
+// fits, as MATLAB's filter2 with 'valid'.
+fn filter(values: &[f64], width: usize, height: usize, kernel: &[f64; WINDOW]) -> Vec<f64> {
+    let (out_w, out_h) = (width + 1 - WINDOW, height + 1 - WINDOW);
+    let mut rows = vec![0.0; out_w * height];
+    for y in 0..height {
+        for x in 0..out_w {
+            rows[y * out_w + x] = (0..WINDOW).map(|k| values[y * width + x + k] * kernel[k]).sum();
+        }
+    }
+    let mut out = vec![0.0; out_w * out_h];
+    for y in 0..out_h {
+        for x in 0..out_w {
+            out[y * out_w + x] = (0..WINDOW).map(|k| rows[(y + k) * out_w + x] * kernel[k]).sum();
+        }
+    }
+    out
+}
+
+fn ssim(a: &DynamicImage, b: &DynamicImage) -> f64 {
+    let factor = ((a.width().min(a.height()) as f64 / 256.0).round() as usize).max(1);
+    let (width, height, x) = luma(a, factor);
+    let (_, _, y) = luma(b, factor);
+    if width < WINDOW || height < WINDOW {
+        // Too small for the window: compare the whole image as one.
+        return ssim_of(&[mean(&x)], &[mean(&y)], &[variance(&x)], &[variance(&y)], &[covariance(&x, &y)]);
+    }
+    let weights: [f64; WINDOW] = std::array::from_fn(|i| (-((i as f64 - 5.0).powi(2)) / (2.0 * SIGMA * SIGMA)).exp());
+    let total: f64 = weights.iter().sum();
+    let kernel = weights.map(|w| w / total);
+
+    let product = |p: &[f64], q: &[f64]| p.iter().zip(q).map(|(a, b)| a * b).collect::<Vec<f64>>();
+    let mu_x = filter(&x, width, height, &kernel);
+    let mu_y = filter(&y, width, height, &kernel);
+    let sigma = |p: &[f64], q: &[f64], mu_p: &[f64], mu_q: &[f64]| {
+        let moment = filter(&product(p, q), width, height, &kernel);
+        moment.iter().zip(mu_p.iter().zip(mu_q)).map(|(m, (a, b))| m - a * b).collect::<Vec<f64>>()
+    };
+    let sigma_x = sigma(&x, &x, &mu_x, &mu_x);
+    let sigma_y = sigma(&y, &y, &mu_y, &mu_y);
+    let sigma_xy = sigma(&x, &y, &mu_x, &mu_y);
+    ssim_of(&mu_x, &mu_y, &sigma_x, &sigma_y, &sigma_xy)
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len().max(1) as f64
+}
+
+fn variance(values: &[f64]) -> f64 {
+    covariance(values, values)
+}
+
+fn covariance(p: &[f64], q: &[f64]) -> f64 {
+    let (mean_p, mean_q) = (mean(p), mean(q));
+    p.iter().zip(q).map(|(a, b)| (a - mean_p) * (b - mean_q)).sum::<f64>() / p.len().max(1) as f64
+}
+
+// Mean of the SSIM map from local means, variances and covariances.
+fn ssim_of(mu_x: &[f64], mu_y: &[f64], sigma_x: &[f64], sigma_y: &[f64], sigma_xy: &[f64]) -> f64 {
+    let values: Vec<f64> = (0..mu_x.len())
+        .map(|i| {
+            let (mx, my) = (mu_x[i], mu_y[i]);
+            ((2.0 * mx * my + C1) * (2.0 * sigma_xy[i] + C2)) / ((mx * mx + my * my + C1) * (sigma_x[i] + sigma_y[i] + C2))
+        })
+        .collect();
+    mean(&values)
+}
+
+pub(crate) fn compare(a: &DynamicImage, b: &DynamicImage) -> Result<Comparison, JsValue> {
+    if a.width() != b.width() || a.height() != b.height() {
+        return Err(error::invalid_argument("Images to compare must have the same size"));
+    }
+    let (mse, psnr) = psnr(a, b);
+    Ok(Comparison { mse, psnr, ssim: ssim(a, b) })
+}
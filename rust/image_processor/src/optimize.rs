@@ -0,0 +1,271 @@
+// Lossless PNG optimization: the smallest color type and bit depth that hold
+// the pixels exactly, a palette for up to 256 colors, the best of the
+// standard filter strategies, and zlib or zopfli compression.
+
+use crate::error;
+use crate::metadata::push_png_chunk;
+use image::DynamicImage;
+use std::collections::{HashMap, HashSet};
+use wasm_bindgen::prelude::*;
+
+// Unfiltered scanlines ready for filtering, with what IHDR, PLTE and tRNS
+// need to describe them.
+struct Raster {
+    width: u32,
+    height: u32,
+    color_type: u8,
+    bit_depth: u8,
+    // Bytes per complete pixel, at least 1, as the filters count them.
+    bpp: usize,
+    stride: usize,
+    data: Vec<u8>,
+    palette: Vec<[u8; 4]>,
+}
+
+impl Raster {
+    fn new(width: u32, height: u32, color_type: u8, bit_depth: u8, channels: usize) -> Raster {
+        let bits = width as usize * channels * bit_depth as usize;
+        Raster {
+            width,
+            height,
+            color_type,
+            bit_depth,
+            bpp: (channels * bit_depth as usize).div_ceil(8),
+            stride: bits.div_ceil(8),
+            data: Vec::with_capacity(bits.div_ceil(8) * height as usize),
+            palette: Vec::new(),
+        }
+    }
+}
+
+// Lowest of 1, 2, 4 and 8 bits that stores every 8-bit gray `value` exactly.
+fn gray_depth(values: impl Iterator<Item = u8>) -> u8 {
+    let mut depth = 1;
+    for value in values {
+        while depth < 8 && value % (255 / ((1u16 << depth) - 1) as u8) != 0 {
+            depth *= 2;
+        }
+        if depth == 8 {
+            break;
+        }
+    }
+    depth
+}
+
+fn palette_depth(colors: usize) -> u8 {
+    match colors {
+        0..=2 => 1,
+        3..=4 => 2,
+        5..=16 => 4,
+        _ => 8,
+    }
+}
+
+// Packs samples of `depth` bits, most significant first, one row at a time.
+fn pack_rows(raster: &mut Raster, samples: impl Iterator<Item = u8>) {
+    let per_row = raster.width as usize;
+    let depth = raster.bit_depth as usize;
+    let mut row = vec![0u8; raster.stride];
+    for (index, sample) in samples.enumerate() {
+        let x = index % per_row;
+        let bit = x * depth;
+        row[bit / 8] |= sample << (8 - depth - bit % 8);
+        if x + 1 == per_row {
+            raster.data.extend_from_slice(&row);
+            row.fill(0);
+        }
+    }
+}
+
+fn reduce_8bit(img: &DynamicImage) -> Raster {
+    let rgba = img.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    let pixels: Vec<[u8; 4]> = rgba.pixels().map(|p| p.0).collect();
+    let opaque = pixels.iter().all(|p| p[3] == 255);
+    let gray = pixels.iter().all(|p| p[0] == p[1] && p[1] == p[2]);
+
+    let mut colors = HashSet::new();
+    for pixel in &pixels {
+        if colors.len() > 256 {
+            break;
+        }
+        colors.insert(*pixel);
+    }
+    let paletted = colors.len() <= 256;
+
+    let gray_bits = if gray && opaque { gray_depth(pixels.iter().map(|p| p[0])) } else { 8 };
+    if gray && opaque && (!paletted || gray_bits <= palette_depth(colors.len())) {
+        let mut raster = Raster::new(width, height, 0, gray_bits, 1);
+        let scale = 255 / ((1u16 << gray_bits) - 1) as u8;
+        pack_rows(&mut raster, pixels.iter().map(|p| p[0] / scale));
+        return raster;
+    }
+    if paletted {
+        // Translucent entries first, so tRNS can stop at the last of them.
+        let mut palette: Vec<[u8; 4]> = colors.into_iter().collect();
+        palette.sort_by_key(|color| (color[3] == 255, *color));
+        let index: HashMap<[u8; 4], u8> = palette.iter().enumerate().map(|(i, color)| (*color, i as u8)).collect();
+        let mut raster = Raster::new(width, height, 3, palette_depth(palette.len()), 1);
+        pack_rows(&mut raster, pixels.iter().map(|p| index[p]));
+        raster.palette = palette;
+        return raster;
+    }
+    let (color_type, channels): (u8, usize) = match (gray, opaque) {
+        (true, true) => (0, 1),
+        (true, false) => (4, 2),
+        (false, true) => (2, 3),
+        (false, false) => (6, 4),
+    };
+    let mut raster = Raster::new(width, height, color_type, 8, channels);
+    for pixel in &pixels {
+        match channels {
+            1 => raster.data.push(pixel[0]),
+            2 => raster.data.extend_from_slice(&[pixel[0], pixel[3]]),
+            3 => raster.data.extend_from_slice(&pixel[..3]),
+            _ => raster.data.extend_from_slice(pixel),
+        }
+    }
+    raster
+}
+
+// 16-bit images stay 16-bit unless every sample is a repeated byte, which 8
+// bits store exactly.
+fn reduce(img: &DynamicImage) -> Raster {
+    let deep = matches!(
+        img,
+        DynamicImage::ImageLuma16(_) | DynamicImage::ImageLumaA16(_) | DynamicImage::ImageRgb16(_) | DynamicImage::ImageRgba16(_)
+    );
+    if !deep {
+        return reduce_8bit(img);
+    }
+    let rgba = img.to_rgba16();
+    if rgba.as_raw().iter().all(|&v| v >> 8 == v & 0xFF) {
+        return reduce_8bit(img);
+    }
+    let (width, height) = rgba.dimensions();
+    let opaque = rgba.pixels().all(|p| p[3] == u16::MAX);
+    let gray = rgba.pixels().all(|p| p[0] == p[1] && p[1] == p[2]);
+    let (color_type, channels): (u8, &[usize]) = match (gray, opaque) {
+        (true, true) => (0, &[0]),
+        (true, false) => (4, &[0, 3]),
+        (false, true) => (2, &[0, 1, 2]),
+        (false, false) => (6, &[0, 1, 2, 3]),
+    };
+    let mut raster = Raster::new(width, height, color_type, 16, channels.len());
+    for pixel in rgba.pixels() {
+        for &channel in channels {
+            raster.data.extend_from_slice(&pixel[channel].to_be_bytes());
+        }
+    }
+    raster
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// Appends `row` filtered with `filter` (0 None, 1 Sub, 2 Up, 3 Average,
+// 4 Paeth), its type byte first.
+fn filter_row(out: &mut Vec<u8>, filter: u8, row: &[u8], previous: &[u8], bpp: usize) {
+    out.push(filter);
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let b = previous[i];
+        let c = if i >= bpp { previous[i - bpp] } else { 0 };
+        out.push(row[i].wrapping_sub(match filter {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            _ => paeth(a, b, c),
+        }));
+    }
+}
+
+// A filter strategy: one filter for every row, or per row the filter with
+// the smallest sum of absolute differences, the usual heuristic.
+#[derive(Clone, Copy)]
+enum Strategy {
+    Fixed(u8),
+    Adaptive,
+}
+
+fn filtered(raster: &Raster, strategy: Strategy) -> Vec<u8> {
+    let mut out = Vec::with_capacity((raster.stride + 1) * raster.height as usize);
+    let zero = vec![0u8; raster.stride];
+    let mut candidate = Vec::with_capacity(raster.stride + 1);
+    for (y, row) in raster.data.chunks(raster.stride.max(1)).enumerate() {
+        let previous = if y == 0 { &zero[..] } else { &raster.data[(y - 1) * raster.stride..y * raster.stride] };
+        match strategy {
+            Strategy::Fixed(filter) => filter_row(&mut out, filter, row, previous, raster.bpp),
+            Strategy::Adaptive => {
+                let mut best: Option<(u64, Vec<u8>)> = None;
+                for filter in 0..5 {
+                    candidate.clear();
+                    filter_row(&mut candidate, filter, row, previous, raster.bpp);
+                    let cost = candidate[1..].iter().map(|&v| (v as i8).unsigned_abs() as u64).sum();
+                    if best.as_ref().is_none_or(|(lowest, _)| cost < *lowest) {
+                        best = Some((cost, candidate.clone()));
+                    }
+                }
+                out.extend_from_slice(&best.map(|(_, row)| row).unwrap_or_default());
+            }
+        }
+    }
+    out
+}
+
+fn zlib(data: &[u8], effort: u8) -> Result<Vec<u8>, JsValue> {
+    if effort < 2 {
+        let level = if effort == 0 { 6 } else { 9 };
+        return Ok(miniz_oxide::deflate::compress_to_vec_zlib(data, level));
+    }
+    let mut out = Vec::new();
+    zopfli::compress(zopfli::Options::default(), zopfli::Format::Zlib, data, &mut out)
+        .map_err(|e| error::encode_failed("PNG", e))?;
+    Ok(out)
+}
+
+// Encodes `img` losslessly as the smallest PNG found with `effort`: 0 uses
+// adaptive filtering at zlib level 6, 1 tries every filter strategy and
+// keeps the smallest at level 9, and 2 or more compresses that with zopfli,
+// which is several times slower again.
+pub(crate) fn optimize_png(img: &DynamicImage, effort: u8) -> Result<Vec<u8>, JsValue> {
+    let raster = reduce(img);
+    let strategy = if effort == 0 {
+        if raster.color_type == 3 || raster.bit_depth < 8 { Strategy::Fixed(0) } else { Strategy::Adaptive }
+    } else {
+        let strategies = [0, 1, 2, 3, 4].map(Strategy::Fixed).into_iter().chain([Strategy::Adaptive]);
+        strategies
+            .map(|strategy| (miniz_oxide::deflate::compress_to_vec_zlib(&filtered(&raster, strategy), 6).len(), strategy))
+            .min_by_key(|(size, _)| *size)
+            .map_or(Strategy::Adaptive, |(_, strategy)| strategy)
+    };
+    let idat = zlib(&filtered(&raster, strategy), effort)?;
+
+    let mut output = b"\x89PNG\r\n\x1a\n".to_vec();
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&raster.width.to_be_bytes());
+    ihdr.extend_from_slice(&raster.height.to_be_bytes());
+    ihdr.extend_from_slice(&[raster.bit_depth, raster.color_type, 0, 0, 0]);
+    push_png_chunk(&mut output, b"IHDR", &ihdr);
+    if !raster.palette.is_empty() {
+        let plte: Vec<u8> = raster.palette.iter().flat_map(|color| [color[0], color[1], color[2]]).collect();
+        push_png_chunk(&mut output, b"PLTE", &plte);
+        let trns: Vec<u8> = raster.palette.iter().map(|color| color[3]).take_while(|&alpha| alpha < 255).collect();
+        if !trns.is_empty() {
+            push_png_chunk(&mut output, b"tRNS", &trns);
+        }
+    }
+    push_png_chunk(&mut output, b"IDAT", &idat);
+    push_png_chunk(&mut output, b"IEND", &[]);
+    Ok(output)
+}
@@ -0,0 +1,102 @@
+// Dominant colors: median cut over a thumbnail, refined with a few rounds of
+// k-means so each color is the mean of the pixels nearest it. Deterministic,
+// so the same image always gives the same palette.
+
+use crate::error;
+use image::DynamicImage;
+use wasm_bindgen::prelude::*;
+
+// Enough pixels for stable proportions while keeping k-means cheap.
+const THUMBNAIL_SIDE: u32 = 128;
+const KMEANS_ROUNDS: usize = 8;
+
+// A color and the fraction of the (opaque enough) pixels it stands for.
+pub(crate) struct Swatch {
+    pub(crate) rgb: [u8; 3],
+    pub(crate) share: f64,
+}
+
+fn mean(pixels: &[[u8; 3]]) -> [f64; 3] {
+    let mut sum = [0.0; 3];
+    for pixel in pixels {
+        for c in 0..3 {
+            sum[c] += pixel[c] as f64;
+        }
+    }
+    sum.map(|s| s / pixels.len().max(1) as f64)
+}
+
+// Splits the box with the widest channel range at its median until there
+// are `count` boxes, or none can be split.
+fn median_cut(pixels: &mut [[u8; 3]], count: usize) -> Vec<[f64; 3]> {
+    let range = |pixels: &[[u8; 3]], c: usize| {
+        let (low, high) = pixels.iter().fold((255, 0), |(low, high), p| (p[c].min(low), p[c].max(high)));
+        high.saturating_sub(low)
+    };
+    let mut boxes: Vec<&mut [[u8; 3]]> = vec![pixels];
+    while boxes.len() < count {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, pixels)| (i, (0..3).map(|c| (range(pixels, c), c)).max().unwrap_or((0, 0))))
+            .max_by_key(|(_, (width, _))| *width);
+        let Some((index, (width, channel))) = widest else { break };
+        if width == 0 {
+            break;
+        }
+        let pixels = boxes.swap_remove(index);
+        pixels.sort_unstable_by_key(|p| p[channel]);
+        let (low, high) = pixels.split_at_mut(pixels.len() / 2);
+        boxes.push(low);
+        boxes.push(high);
+    }
+    boxes.iter().map(|pixels| mean(pixels)).collect()
+}
+
+fn nearest(centers: &[[f64; 3]], pixel: &[u8; 3]) -> usize {
+    let distance = |center: &[f64; 3]| (0..3).map(|c| (center[c] - pixel[c] as f64).powi(2)).sum::<f64>();
+    (0..centers.len()).min_by(|&a, &b| distance(&centers[a]).total_cmp(&distance(&centers[b]))).unwrap_or(0)
+}
+
+// Up to `count` (1–32) dominant colors, most common first. Pixels under half
+// opacity are left out.
+pub(crate) fn extract(img: &DynamicImage, count: u32) -> Result<Vec<Swatch>, JsValue> {
+    if !(1..=32).contains(&count) {
+        return Err(error::invalid_argument("Palette size must be between 1 and 32"));
+    }
+    let thumbnail = img.thumbnail(THUMBNAIL_SIDE, THUMBNAIL_SIDE).to_rgba8();
+    let mut pixels: Vec<[u8; 3]> = thumbnail.pixels().filter(|p| p[3] >= 128).map(|p| [p[0], p[1], p[2]]).collect();
+    if pixels.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut centers = median_cut(&mut pixels, count as usize);
+    let mut assigned = vec![0; pixels.len()];
+    for _ in 0..KMEANS_ROUNDS {
+        for (slot, pixel) in assigned.iter_mut().zip(&pixels) {
+            *slot = nearest(&centers, pixel);
+        }
+        for (index, center) in centers.iter_mut().enumerate() {
+            let members: Vec<[u8; 3]> = pixels.iter().zip(&assigned).filter(|(_, &a)| a == index).map(|(p, _)| *p).collect();
+            if !members.is_empty() {
+                *center = mean(&members);
+            }
+        }
+    }
+
+    let mut populations = vec![0usize; centers.len()];
+    for pixel in &pixels {
+        populations[nearest(&centers, pixel)] += 1;
+    }
+    let mut swatches: Vec<Swatch> = centers
+        .iter()
+        .zip(&populations)
+        .filter(|(_, &population)| population > 0)
+        .map(|(center, &population)| Swatch {
+            rgb: center.map(|c| c.round().clamp(0.0, 255.0) as u8),
+            share: population as f64 / pixels.len() as f64,
+        })
+        .collect();
+    swatches.sort_by(|a, b| b.share.total_cmp(&a.share));
+    Ok(swatches)
+}
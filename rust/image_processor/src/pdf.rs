@@ -0,0 +1,52 @@
+// PDF page rendering with hayro, pure Rust. Fonts that a document names but
+// does not embed are replaced by hayro's bundled standard fonts. Documents
+// encrypted with a user password cannot be opened.
+
+use crate::error;
+use crate::limits::Limits;
+use hayro::hayro_interpret::InterpreterSettings;
+use hayro::hayro_syntax::{LoadPdfError, Pdf};
+use hayro::vello_cpu::color::palette::css::WHITE;
+use hayro::vello_cpu::peniko::ImageAlphaType;
+use hayro::{PixmapSettings, RenderCache, RenderSettings};
+use image::{DynamicImage, RgbaImage};
+use wasm_bindgen::prelude::*;
+
+// PDF user space units per inch.
+const POINTS_PER_INCH: f32 = 72.0;
+
+// Renders page `page`, counting from 0, at `dpi` on white, after checking
+// the rendered size against `limits`.
+pub(crate) fn render_page(data: &[u8], page: u32, dpi: f32, limits: &Limits) -> Result<DynamicImage, JsValue> {
+    if !(dpi.is_finite() && dpi > 0.0) {
+        return Err(error::invalid_argument("DPI must be above 0"));
+    }
+    limits.check_input(data)?;
+    let pdf = Pdf::new(data.to_vec()).map_err(|e| match e {
+        LoadPdfError::Decryption(_) => error::unsupported_format("Encrypted PDFs are not supported"),
+        LoadPdfError::Invalid => error::decode_failed("Invalid PDF"),
+    })?;
+    let pages = pdf.pages();
+    let page = pages
+        .get(page as usize)
+        .ok_or_else(|| error::invalid_argument(format!("Page {} is out of range for a PDF of {} pages", page, pages.len())))?;
+
+    let scale = dpi / POINTS_PER_INCH;
+    let (width, height) = page.render_dimensions();
+    let (width, height) = ((width * scale) as u32, (height * scale) as u32);
+    if width == 0 || height == 0 {
+        return Err(error::decode_failed("PDF page has no area"));
+    }
+    if width > u16::MAX as u32 || height > u16::MAX as u32 {
+        return Err(error::size_limit("PDF pages render at most 65535 pixels per side"));
+    }
+    limits.check_pixels(width, height)?;
+    limits.check_output(width, height)?;
+
+    let settings = PixmapSettings { x_scale: scale, y_scale: scale, bg_color: WHITE };
+    let pixmap = hayro::render(page, &RenderCache::new(), &InterpreterSettings::default(), &RenderSettings::default(), &settings);
+    let (width, height) = (pixmap.width() as u32, pixmap.height() as u32);
+    let rgba = RgbaImage::from_raw(width, height, pixmap.take_rgba8(ImageAlphaType::Alpha)).expect("four bytes per pixel");
+    // The white background leaves every pixel opaque.
+    Ok(DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rgba).to_rgb8()))
+}
@@ -0,0 +1,288 @@
+// A decoded image kept in the module across calls, so chained operations
+// decode once and encode once instead of recompressing at every step.
+// Each operation consumes the handle and returns the updated one, which in
+// JS reads as `processor.load_image(data).resize(800, 600).encode("webp", 80)`.
+
+use crate::{adjust, color_vision, composite, decorate, enhance, error, filter, mask, parse_format, redact, redeye, scan, styles, text, transform, ImageProcessor};
+use image::DynamicImage;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::Clamped;
+
+#[wasm_bindgen]
+pub struct Pipeline {
+    // Settings of the processor that loaded the image, for encoding.
+    processor: ImageProcessor,
+    // Original file, for the metadata carried into the output.
+    source: Vec<u8>,
+    img: DynamicImage,
+}
+
+impl Pipeline {
+    pub(crate) fn new(processor: &ImageProcessor, image_data: &[u8]) -> Result<Pipeline, JsValue> {
+        let img = processor.load(image_data)?;
+        Ok(Pipeline { processor: processor.clone(), source: image_data.to_vec(), img })
+    }
+
+    pub(crate) fn from_rgba(processor: &ImageProcessor, pixels: &[u8], width: u32, height: u32) -> Result<Pipeline, JsValue> {
+        processor.limits.check_pixels(width, height)?;
+        let img = image::RgbaImage::from_raw(width, height, pixels.to_vec())
+            .filter(|_| pixels.len() as u64 == width as u64 * height as u64 * 4)
+            .ok_or_else(|| error::invalid_argument("Pixel data does not match the image size"))?;
+        Ok(Pipeline { processor: processor.clone(), source: Vec::new(), img: DynamicImage::ImageRgba8(img) })
+    }
+
+    fn apply(mut self, transform: impl FnOnce(&DynamicImage) -> Result<DynamicImage, JsValue>) -> Result<Pipeline, JsValue> {
+        self.img = transform(&self.img)?;
+        Ok(self)
+    }
+}
+
+#[wasm_bindgen]
+impl Pipeline {
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> u32 {
+        self.img.width()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> u32 {
+        self.img.height()
+    }
+
+    // The current image as non-premultiplied RGBA, width × height × 4 bytes,
+    // ready for `new ImageData(pixels, width, height)` or a WebGL texture
+    // upload.
+    #[wasm_bindgen]
+    pub fn to_rgba(&self) -> Clamped<Vec<u8>> {
+        Clamped(self.img.to_rgba8().into_raw())
+    }
+
+    // Encodes the current image. The pipeline stays usable, so one result
+    // can be written in several formats.
+    #[wasm_bindgen]
+    pub fn encode(&self, format: &str, quality: u8) -> Result<Vec<u8>, JsValue> {
+        self.processor.encode(&self.source, &self.img, parse_format(format)?, quality)
+    }
+
+    // encode_to_target_size for the current image.
+    #[wasm_bindgen]
+    pub fn encode_to_target_size(&self, format: &str, max_bytes: u32, allow_resize: bool) -> Result<Vec<u8>, JsValue> {
+        self.processor.encode_within(&self.source, &self.img, parse_format(format)?, max_bytes as usize, allow_resize)
+    }
+
+    // The operations below match the ImageProcessor methods of the same
+    // name.
+
+    #[wasm_bindgen]
+    pub fn resize(self, width: u32, height: u32) -> Result<Pipeline, JsValue> {
+        self.processor.limits.check_output(width, height)?;
+        self.apply(|img| Ok(img.resize(width, height, image::imageops::FilterType::Lanczos3)))
+    }
+
+    #[wasm_bindgen]
+    pub fn resize_fit(self, width: u32, height: u32, fit: &str, background: &str) -> Result<Pipeline, JsValue> {
+        self.processor.limits.check_output(width, height)?;
+        let background = if background.is_empty() { image::Rgba([0, 0, 0, 0]) } else { transform::parse_color(background)? };
+        self.apply(|img| transform::fit(img, width, height, fit, background))
+    }
+
+    #[wasm_bindgen]
+    pub fn extend(self, width: u32, height: u32, background: &str) -> Result<Pipeline, JsValue> {
+        self.processor.limits.check_output(width, height)?;
+        let background = transform::parse_color(background)?;
+        self.apply(|img| transform::extend(img, width, height, background))
+    }
+
+    #[wasm_bindgen]
+    pub fn crop(self, x: u32, y: u32, width: u32, height: u32) -> Result<Pipeline, JsValue> {
+        self.apply(|img| transform::crop(img, x, y, width, height))
+    }
+
+    #[wasm_bindgen]
+    pub fn crop_to_aspect(self, ratio: f64, gravity: &str) -> Result<Pipeline, JsValue> {
+        self.apply(|img| transform::crop_to_aspect(img, ratio, gravity))
+    }
+
+    #[wasm_bindgen]
+    pub fn rotate90(self) -> Result<Pipeline, JsValue> {
+        self.apply(|img| Ok(img.rotate90()))
+    }
+
+    #[wasm_bindgen]
+    pub fn rotate180(self) -> Result<Pipeline, JsValue> {
+        self.apply(|img| Ok(img.rotate180()))
+    }
+
+    #[wasm_bindgen]
+    pub fn rotate270(self) -> Result<Pipeline, JsValue> {
+        self.apply(|img| Ok(img.rotate270()))
+    }
+
+    #[wasm_bindgen]
+    pub fn flip_horizontal(self) -> Result<Pipeline, JsValue> {
+        self.apply(|img| Ok(img.fliph()))
+    }
+
+    #[wasm_bindgen]
+    pub fn flip_vertical(self) -> Result<Pipeline, JsValue> {
+        self.apply(|img| Ok(img.flipv()))
+    }
+
+    #[wasm_bindgen]
+    pub fn rotate(self, degrees: f64, background: &str) -> Result<Pipeline, JsValue> {
+        let background = transform::parse_color(background)?;
+        let (width, height) = transform::rotated_size(&self.img, degrees);
+        self.processor.limits.check_output(width, height)?;
+        self.apply(|img| transform::rotate(img, degrees, background))
+    }
+
+    #[wasm_bindgen]
+    pub fn composite(self, overlay: &[u8], x: i32, y: i32, opacity: f32, blend_mode: &str) -> Result<Pipeline, JsValue> {
+        let overlay = self.processor.load(overlay)?;
+        self.apply(|img| composite::composite(img, &overlay, x as i64, y as i64, opacity, blend_mode))
+    }
+
+    #[wasm_bindgen]
+    pub fn watermark(self, overlay: &[u8], position: &str, margin: u32, opacity: f32, blend_mode: &str) -> Result<Pipeline, JsValue> {
+        let overlay = self.processor.load(overlay)?;
+        self.apply(|img| {
+            let (x, y) = composite::preset_position(position, margin, (img.width(), img.height()), (overlay.width(), overlay.height()))?;
+            composite::composite(img, &overlay, x, y, opacity, blend_mode)
+        })
+    }
+
+    #[wasm_bindgen]
+    pub fn adjust(self, options: &JsValue) -> Result<Pipeline, JsValue> {
+        let adjustments = adjust::Adjustments::from_js(options)?;
+        self.apply(|img| Ok(adjustments.apply(img)))
+    }
+
+    #[wasm_bindgen]
+    pub fn auto_enhance(self, options: &JsValue) -> Result<Pipeline, JsValue> {
+        let options = enhance::EnhanceOptions::from_js(options)?;
+        self.apply(|img| Ok(enhance::auto_enhance(img, &options)))
+    }
+
+    #[wasm_bindgen]
+    pub fn blur(self, sigma: f32) -> Result<Pipeline, JsValue> {
+        self.apply(|img| filter::gaussian_blur(img, sigma))
+    }
+
+    #[wasm_bindgen]
+    pub fn box_blur(self, radius: u32) -> Result<Pipeline, JsValue> {
+        self.apply(|img| filter::box_blur(img, radius))
+    }
+
+    #[wasm_bindgen]
+    pub fn sharpen(self) -> Result<Pipeline, JsValue> {
+        self.apply(|img| Ok(filter::sharpen(img)))
+    }
+
+    #[wasm_bindgen]
+    pub fn unsharp_mask(self, sigma: f32, amount: f32, threshold: u8) -> Result<Pipeline, JsValue> {
+        self.apply(|img| filter::unsharp_mask(img, sigma, amount, threshold))
+    }
+
+    #[wasm_bindgen]
+    pub fn denoise(self, strength: f32) -> Result<Pipeline, JsValue> {
+        self.apply(|img| filter::denoise(img, strength))
+    }
+
+    #[wasm_bindgen]
+    pub fn sepia(self, amount: f32) -> Result<Pipeline, JsValue> {
+        self.apply(|img| styles::sepia(img, amount))
+    }
+
+    #[wasm_bindgen]
+    pub fn duotone(self, shadow: &str, highlight: &str) -> Result<Pipeline, JsValue> {
+        let (shadow, highlight) = (transform::parse_color(shadow)?, transform::parse_color(highlight)?);
+        self.apply(|img| Ok(styles::duotone(img, shadow, highlight)))
+    }
+
+    #[wasm_bindgen]
+    pub fn posterize(self, levels: u32) -> Result<Pipeline, JsValue> {
+        self.apply(|img| styles::posterize(img, levels))
+    }
+
+    #[wasm_bindgen]
+    pub fn invert(self) -> Result<Pipeline, JsValue> {
+        self.apply(|img| Ok(styles::invert(img)))
+    }
+
+    #[wasm_bindgen]
+    pub fn vignette(self, strength: f32) -> Result<Pipeline, JsValue> {
+        self.apply(|img| styles::vignette(img, strength))
+    }
+
+    #[wasm_bindgen]
+    pub fn simulate_color_blindness(self, deficiency: &str, severity: f32) -> Result<Pipeline, JsValue> {
+        self.apply(|img| color_vision::simulate(img, deficiency, severity))
+    }
+
+    #[wasm_bindgen]
+    pub fn redact(self, rects: &JsValue, mode: &str) -> Result<Pipeline, JsValue> {
+        let (rects, mode) = (redact::parse_rects(rects)?, redact::parse_mode(mode)?);
+        self.apply(|img| redact::redact(img, &rects, mode))
+    }
+
+    #[wasm_bindgen]
+    pub fn remove_red_eye(self, regions: &JsValue) -> Result<Pipeline, JsValue> {
+        let regions = redact::parse_rects(regions)?;
+        self.apply(|img| Ok(redeye::remove_red_eye(img, &regions)))
+    }
+
+    #[wasm_bindgen]
+    pub fn round_corners(self, radius: u32) -> Result<Pipeline, JsValue> {
+        self.apply(|img| Ok(mask::round_corners(img, radius)))
+    }
+
+    #[wasm_bindgen]
+    pub fn circle_mask(self) -> Result<Pipeline, JsValue> {
+        self.apply(|img| Ok(mask::circle(img)))
+    }
+
+    #[wasm_bindgen]
+    pub fn apply_mask(self, mask_data: &[u8]) -> Result<Pipeline, JsValue> {
+        let mask = self.processor.load(mask_data)?;
+        self.apply(|img| mask::apply_mask(img, &mask))
+    }
+
+    #[wasm_bindgen]
+    pub fn add_border(self, options: &JsValue) -> Result<Pipeline, JsValue> {
+        let options = decorate::BorderOptions::from_js(options)?;
+        let limits = self.processor.limits;
+        self.apply(|img| {
+            let bordered = decorate::border(img, &options);
+            limits.check_output(bordered.width(), bordered.height())?;
+            Ok(bordered)
+        })
+    }
+
+    #[wasm_bindgen]
+    pub fn drop_shadow(self, options: &JsValue) -> Result<Pipeline, JsValue> {
+        let options = decorate::ShadowOptions::from_js(options)?;
+        let limits = self.processor.limits;
+        self.apply(|img| {
+            let shadowed = decorate::drop_shadow(img, &options)?;
+            limits.check_output(shadowed.width(), shadowed.height())?;
+            Ok(shadowed)
+        })
+    }
+
+    #[wasm_bindgen]
+    pub fn scan_document(self, options: &JsValue) -> Result<Pipeline, JsValue> {
+        let options = scan::ScanOptions::from_js(options)?;
+        let limits = self.processor.limits;
+        self.apply(|img| {
+            let page = scan::clean_up(img, &options, &limits)?;
+            limits.check_output(page.width(), page.height())?;
+            Ok(page)
+        })
+    }
+
+    #[wasm_bindgen]
+    pub fn draw_text(self, text: &str, options: &JsValue) -> Result<Pipeline, JsValue> {
+        let options = text::TextOptions::from_js(options)?;
+        self.apply(|img| Ok(text::draw_text(img, text, &options)))
+    }
+}
@@ -0,0 +1,51 @@
+// Compact placeholders to show while an image loads: BlurHash strings and
+// ThumbHash bytes, both computed from a small thumbnail since they only keep
+// a few low frequencies, the average color and tiny blurred previews.
+
+use crate::error;
+use image::DynamicImage;
+use wasm_bindgen::prelude::*;
+
+// ThumbHash accepts at most 100 × 100 pixels.
+const THUMBNAIL_SIDE: u32 = 100;
+
+// Lossy WebP, since the blur leaves little detail to lose.
+pub(crate) const LQIP_QUALITY: u8 = 80;
+
+pub(crate) fn blurhash(img: &DynamicImage, x_components: u32, y_components: u32) -> Result<String, JsValue> {
+    if !(1..=9).contains(&x_components) || !(1..=9).contains(&y_components) {
+        return Err(error::invalid_argument("BlurHash components must be between 1 and 9"));
+    }
+    let thumbnail = img.thumbnail(THUMBNAIL_SIDE, THUMBNAIL_SIDE).to_rgba8();
+    blurhash::encode(x_components, y_components, thumbnail.width(), thumbnail.height(), thumbnail.as_raw())
+        .map_err(|e| error::invalid_argument(e.to_string()))
+}
+
+pub(crate) fn thumbhash(img: &DynamicImage) -> Vec<u8> {
+    let thumbnail = img.thumbnail(THUMBNAIL_SIDE, THUMBNAIL_SIDE).to_rgba8();
+    thumbhash::rgba_to_thumb_hash(thumbnail.width() as usize, thumbnail.height() as usize, thumbnail.as_raw())
+}
+
+// The alpha-weighted mean RGB and the mean alpha.
+pub(crate) fn average_color(img: &DynamicImage) -> [u8; 4] {
+    let rgba = img.to_rgba8();
+    let (mut sum, mut alpha) = ([0u64; 3], 0u64);
+    for pixel in rgba.pixels() {
+        let a = pixel[3] as u64;
+        for c in 0..3 {
+            sum[c] += pixel[c] as u64 * a;
+        }
+        alpha += a;
+    }
+    let mean = |total: u64, count: u64| (total + count / 2).checked_div(count).unwrap_or(0) as u8;
+    let pixels = rgba.pixels().len() as u64;
+    [mean(sum[0], alpha), mean(sum[1], alpha), mean(sum[2], alpha), mean(alpha, pixels)]
+}
+
+// `img` fitted within `max_dim` × `max_dim` and blurred by about a pixel
+// per 32 of its size.
+pub(crate) fn lqip(img: &DynamicImage, max_dim: u32) -> DynamicImage {
+    let thumbnail = img.thumbnail(max_dim, max_dim);
+    let sigma = thumbnail.width().max(thumbnail.height()) as f32 / 32.0;
+    thumbnail.blur(sigma)
+}
@@ -0,0 +1,112 @@
+// QR code generation with qrcodegen, as a raster image or as SVG. Modules
+// are whole pixels, centered in the requested size with at least the
+// standard four-module quiet zone around them.
+
+use crate::{error, transform};
+use image::{DynamicImage, Rgba, RgbaImage};
+use js_sys::Reflect;
+use qrcodegen::{QrCode, QrCodeEcc};
+use wasm_bindgen::prelude::*;
+
+// Light modules around the symbol, as the standard requires.
+const QUIET_ZONE: u32 = 4;
+
+fn color(options: &JsValue, key: &str, default: &str) -> Result<Rgba<u8>, JsValue> {
+    if options.is_undefined() || options.is_null() {
+        return transform::parse_color(default);
+    }
+    let value = Reflect::get(options, &key.into())?;
+    if value.is_undefined() || value.is_null() {
+        return transform::parse_color(default);
+    }
+    let value = value.as_string().ok_or_else(|| error::invalid_argument(format!("Option `{}` must be a color string", key)))?;
+    transform::parse_color(&value)
+}
+
+pub(crate) struct Colors {
+    foreground: Rgba<u8>,
+    background: Rgba<u8>,
+}
+
+impl Colors {
+    // Reads `{ foreground?, background? }`, black on white by default.
+    pub(crate) fn from_js(options: &JsValue) -> Result<Colors, JsValue> {
+        Ok(Colors { foreground: color(options, "foreground", "#000000")?, background: color(options, "background", "#ffffff")? })
+    }
+}
+
+// Error correction "L" (7% of the symbol can be restored), "M" (15%, and
+// the default for ""), "Q" (25%) or "H" (30%).
+fn parse_error_correction(level: &str) -> Result<QrCodeEcc, JsValue> {
+    match level.to_uppercase().as_str() {
+        "L" => Ok(QrCodeEcc::Low),
+        "M" | "" => Ok(QrCodeEcc::Medium),
+        "Q" => Ok(QrCodeEcc::Quartile),
+        "H" => Ok(QrCodeEcc::High),
+        _ => Err(error::invalid_argument("Error correction must be \"L\", \"M\", \"Q\" or \"H\"")),
+    }
+}
+
+// The smallest symbol that holds `text`, with the error correction raised
+// as far as fits in that same symbol.
+pub(crate) fn encode(text: &str, error_correction: &str) -> Result<QrCode, JsValue> {
+    QrCode::encode_text(text, parse_error_correction(error_correction)?).map_err(|e| error::invalid_argument(e.to_string()))
+}
+
+// Pixels per module and the offset of the symbol in a `size` square.
+fn layout(qr: &QrCode, size: u32) -> Result<(u32, u32), JsValue> {
+    let modules = qr.size() as u32;
+    let needed = modules + 2 * QUIET_ZONE;
+    if size < needed {
+        return Err(error::invalid_argument(format!("This QR code needs a size of at least {} pixels", needed)));
+    }
+    let scale = size / needed;
+    Ok((scale, (size - modules * scale) / 2))
+}
+
+pub(crate) fn render(qr: &QrCode, size: u32, colors: &Colors) -> Result<DynamicImage, JsValue> {
+    let (scale, offset) = layout(qr, size)?;
+    let end = offset + qr.size() as u32 * scale;
+    let rgba = RgbaImage::from_fn(size, size, |x, y| {
+        let inside = (offset..end).contains(&x) && (offset..end).contains(&y);
+        if inside && qr.get_module(((x - offset) / scale) as i32, ((y - offset) / scale) as i32) {
+            colors.foreground
+        } else {
+            colors.background
+        }
+    });
+    let img = DynamicImage::ImageRgba8(rgba);
+    Ok(if colors.foreground[3] == 255 && colors.background[3] == 255 { DynamicImage::ImageRgb8(img.to_rgb8()) } else { img })
+}
+
+fn svg_fill(color: Rgba<u8>) -> String {
+    let [r, g, b, a] = color.0;
+    let fill = format!("fill=\"#{:02x}{:02x}{:02x}\"", r, g, b);
+    if a == 255 { fill } else { format!("{} fill-opacity=\"{:.3}\"", fill, a as f32 / 255.0) }
+}
+
+// An SVG document `size` pixels square, laid out as render does, with the
+// dark modules as one path.
+pub(crate) fn svg(qr: &QrCode, size: u32, colors: &Colors) -> Result<String, JsValue> {
+    let (scale, offset) = layout(qr, size)?;
+    let mut path = String::new();
+    for y in 0..qr.size() {
+        for x in 0..qr.size() {
+            if qr.get_module(x, y) {
+                let (px, py) = (offset + x as u32 * scale, offset + y as u32 * scale);
+                path += &format!("M{},{}h{}v{}h-{}z", px, py, scale, scale, scale);
+            }
+        }
+    }
+    let background = if colors.background[3] == 0 {
+        String::new()
+    } else {
+        format!("<rect width=\"{}\" height=\"{}\" {}/>", size, size, svg_fill(colors.background))
+    };
+    Ok(format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{size}\" height=\"{size}\" viewBox=\"0 0 {size} {size}\" shape-rendering=\"crispEdges\">{}<path d=\"{}\" {}/></svg>",
+        background,
+        path,
+        svg_fill(colors.foreground),
+    ))
+}
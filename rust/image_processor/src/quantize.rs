@@ -0,0 +1,170 @@
+// Palette quantization for indexed PNG and GIF output. Images that already
+// have few enough colors keep them exactly; others get a NeuQuant palette,
+// mapped with optional Floyd–Steinberg or ordered dithering.
+
+use crate::error;
+use color_quant::NeuQuant;
+use image::{DynamicImage, RgbaImage};
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+
+// NeuQuant sampling factor, 1 (best) to 30, as for GIF frames.
+const NEUQUANT_SPEED: i32 = 10;
+
+#[derive(Clone, Copy)]
+pub(crate) enum Dither {
+    None,
+    FloydSteinberg,
+    Ordered,
+}
+
+pub(crate) fn parse_dither(dither: &str) -> Result<Dither, JsValue> {
+    match dither.to_lowercase().as_str() {
+        "none" | "" => Ok(Dither::None),
+        "floyd-steinberg" | "floyd_steinberg" => Ok(Dither::FloydSteinberg),
+        "ordered" => Ok(Dither::Ordered),
+        _ => Err(error::invalid_argument("Unsupported dither mode")),
+    }
+}
+
+enum Palette {
+    Exact(HashMap<[u8; 4], u8>),
+    Learned(NeuQuant),
+}
+
+impl Palette {
+    fn index_of(&self, color: [u8; 4]) -> u8 {
+        match self {
+            // Exact palettes are only used undithered, on their own colors.
+            Palette::Exact(index) => index[&color],
+            Palette::Learned(quant) => quant.index_of(&color) as u8,
+        }
+    }
+}
+
+// 8 × 8 Bayer matrix, thresholds 0–63.
+pub(crate) const BAYER: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+// Reduces `img` to at most `colors` (2–256) RGBA colors, as the palette and
+// one index per pixel.
+pub(crate) fn quantize(img: &DynamicImage, colors: u32, dither: Dither) -> Result<(Vec<[u8; 4]>, Vec<u8>), JsValue> {
+    if !(2..=256).contains(&colors) {
+        return Err(error::invalid_argument("Palette size must be between 2 and 256"));
+    }
+    let rgba = img.to_rgba8();
+
+    let mut exact = HashMap::new();
+    for pixel in rgba.pixels() {
+        if exact.len() > colors as usize {
+            break;
+        }
+        let next = exact.len() as u8;
+        exact.entry(pixel.0).or_insert(next);
+    }
+    if exact.len() <= colors as usize {
+        let mut palette = vec![[0u8; 4]; exact.len()];
+        for (color, &index) in &exact {
+            palette[index as usize] = *color;
+        }
+        let palette_map = Palette::Exact(exact);
+        let indices = rgba.pixels().map(|p| palette_map.index_of(p.0)).collect();
+        return Ok((palette, indices));
+    }
+
+    let quant = NeuQuant::new(NEUQUANT_SPEED, colors as usize, rgba.as_raw());
+    let palette: Vec<[u8; 4]> = quant.color_map_rgba().chunks(4).map(|c| [c[0], c[1], c[2], c[3]]).collect();
+    let palette_map = Palette::Learned(quant);
+    let indices = match dither {
+        Dither::None => rgba.pixels().map(|p| palette_map.index_of(p.0)).collect(),
+        Dither::Ordered => {
+            // Spread the thresholds over about one palette step per channel.
+            let spread = 256.0 / (colors as f32).cbrt();
+            rgba.enumerate_pixels()
+                .map(|(x, y, p)| {
+                    let offset = (BAYER[y as usize % 8][x as usize % 8] as f32 + 0.5) / 64.0 - 0.5;
+                    let shifted = |c: u8| (c as f32 + offset * spread).round().clamp(0.0, 255.0) as u8;
+                    palette_map.index_of([shifted(p[0]), shifted(p[1]), shifted(p[2]), p[3]])
+                })
+                .collect()
+        }
+        Dither::FloydSteinberg => floyd_steinberg(&rgba, &palette, &palette_map),
+    };
+    Ok((palette, indices))
+}
+
+// Error diffusion over all four channels, scanning left to right.
+fn floyd_steinberg(rgba: &RgbaImage, palette: &[[u8; 4]], palette_map: &Palette) -> Vec<u8> {
+    let (width, height) = (rgba.width() as usize, rgba.height() as usize);
+    let mut values: Vec<[f32; 4]> = rgba.pixels().map(|p| p.0.map(|c| c as f32)).collect();
+    let mut indices = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let value = values[y * width + x];
+            let index = palette_map.index_of(value.map(|c| c.round().clamp(0.0, 255.0) as u8));
+            indices.push(index);
+            let chosen = palette[index as usize];
+            let error: [f32; 4] = std::array::from_fn(|c| value[c] - chosen[c] as f32);
+            let mut spread = |dx: isize, dy: usize, weight: f32| {
+                let nx = x as isize + dx;
+                if nx >= 0 && (nx as usize) < width && y + dy < height {
+                    let target = &mut values[(y + dy) * width + nx as usize];
+                    for c in 0..4 {
+                        target[c] += error[c] * weight;
+                    }
+                }
+            };
+            spread(1, 0, 7.0 / 16.0);
+            spread(-1, 1, 3.0 / 16.0);
+            spread(0, 1, 5.0 / 16.0);
+            spread(1, 1, 1.0 / 16.0);
+        }
+    }
+    indices
+}
+
+// The quantized image as RGBA, for writing as an indexed PNG.
+pub(crate) fn to_rgba(width: u32, height: u32, palette: &[[u8; 4]], indices: &[u8]) -> RgbaImage {
+    let pixels = indices.iter().flat_map(|&index| palette[index as usize]).collect();
+    RgbaImage::from_raw(width, height, pixels).expect("one index per pixel")
+}
+
+// A still GIF with the palette as its global color table. GIF has a single
+// transparent index, so entries under half opacity all become it and the
+// others are written opaque.
+pub(crate) fn encode_gif(width: u32, height: u32, palette: &[[u8; 4]], indices: &[u8]) -> Result<Vec<u8>, JsValue> {
+    if width > u16::MAX as u32 || height > u16::MAX as u32 {
+        return Err(error::size_limit("GIF images are limited to 65535 pixels per side"));
+    }
+    let transparent = palette.iter().position(|color| color[3] < 128);
+    let remap: Vec<u8> = palette
+        .iter()
+        .enumerate()
+        .map(|(index, color)| match transparent {
+            Some(t) if color[3] < 128 => t as u8,
+            _ => index as u8,
+        })
+        .collect();
+    let table: Vec<u8> = palette.iter().flat_map(|color| [color[0], color[1], color[2]]).collect();
+    let frame = gif::Frame {
+        width: width as u16,
+        height: height as u16,
+        transparent: transparent.map(|t| t as u8),
+        buffer: indices.iter().map(|&index| remap[index as usize]).collect::<Vec<u8>>().into(),
+        ..gif::Frame::default()
+    };
+    let mut buffer = Vec::new();
+    let encode_error = |e: gif::EncodingError| error::encode_failed("GIF", e);
+    let mut encoder = gif::Encoder::new(&mut buffer, width as u16, height as u16, &table).map_err(encode_error)?;
+    encoder.write_frame(&frame).map_err(encode_error)?;
+    drop(encoder);
+    Ok(buffer)
+}
@@ -0,0 +1,229 @@
+// Camera RAW decoding for web previews: rawloader reads the sensor data of
+// CR2, NEF, ARW and DNG files, which is then bilinearly demosaiced, white
+// balanced with the camera's as-shot coefficients and converted from the
+// camera's color space to sRGB. There is no noise reduction, sharpening or
+// highlight recovery. Orientation is left to the EXIF tag in IFD0.
+
+use crate::depth;
+use crate::error;
+use image::{DynamicImage, ImageBuffer};
+use rawloader::{RawImage, RawImageData};
+use std::io::Cursor;
+use wasm_bindgen::prelude::*;
+
+// Tags looked up in IFD0.
+const TAG_MAKE: u16 = 0x010F;
+const TAG_DNG_VERSION: u16 = 0xC612;
+
+// Makers whose TIFF-based RAW files have no signature of their own.
+const RAW_MAKES: &[&[u8]] = &[b"NIKON", b"SONY"];
+
+// Linear sRGB to XYZ (D65).
+const SRGB_TO_XYZ: [[f32; 3]; 3] = [
+    [0.4124564, 0.3575761, 0.1804375],
+    [0.2126729, 0.7151522, 0.072175],
+    [0.0193339, 0.119192, 0.9503041],
+];
+
+// IFD0 entries as (tag, field type, count, value or offset field).
+fn ifd0(data: &[u8]) -> Option<Vec<(u16, u16, u32, u32)>> {
+    let little = match data.get(..4)? {
+        [b'I', b'I', 42, 0] => true,
+        [b'M', b'M', 0, 42] => false,
+        _ => return None,
+    };
+    let u16_at = |pos: usize| {
+        let bytes = [*data.get(pos)?, *data.get(pos + 1)?];
+        Some(if little { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    };
+    let u32_at = |pos: usize| {
+        let bytes: [u8; 4] = data.get(pos..pos + 4)?.try_into().ok()?;
+        Some(if little { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    };
+    let offset = u32_at(4)? as usize;
+    let count = u16_at(offset)? as usize;
+    let entries = (0..count)
+        .map_while(|i| {
+            let entry = offset + 2 + i * 12;
+            Some((u16_at(entry)?, u16_at(entry + 2)?, u32_at(entry + 4)?, u32_at(entry + 8)?))
+        })
+        .collect();
+    Some(entries)
+}
+
+// CR2 (a TIFF with "CR" after the header), DNG (a DNGVersion tag) or a
+// TIFF made by Nikon or Sony (NEF, ARW). Ordinary TIFFs are left to the
+// image crate.
+pub(crate) fn is_raw(data: &[u8]) -> bool {
+    let Some(entries) = ifd0(data) else {
+        return false;
+    };
+    if data.get(8..10) == Some(b"CR") || entries.iter().any(|&(tag, ..)| tag == TAG_DNG_VERSION) {
+        return true;
+    }
+    // ASCII values longer than four bytes are stored at the offset.
+    let make = entries.iter().find(|&&(tag, kind, ..)| tag == TAG_MAKE && kind == 2).and_then(|&(_, _, count, value)| {
+        let start = value as usize;
+        if count <= 4 { None } else { data.get(start..start.checked_add(count as usize)?) }
+    });
+    make.is_some_and(|make| RAW_MAKES.iter().any(|prefix| make.starts_with(prefix)))
+}
+
+// Usable pixel size after the sensor's masked borders are cropped off.
+// Reads the metadata without decoding the sensor data.
+pub(crate) fn dimensions(data: &[u8]) -> Result<(u32, u32), JsValue> {
+    let raw = rawloader::decode_dummy(&mut Cursor::new(data)).map_err(error::decode_failed)?;
+    let (_, _, width, height) = cropped_window(&raw)?;
+    Ok((width as u32, height as u32))
+}
+
+// The left, top, width and height of the sensor area inside `crops`, which
+// are stored as top, right, bottom, left. None when the crops leave nothing
+// or reach past the sensor.
+fn crop_window(raw_width: usize, raw_height: usize, crops: [usize; 4]) -> Option<(usize, usize, usize, usize)> {
+    let [top, right, bottom, left] = crops;
+    let width = raw_width.checked_sub(left.checked_add(right)?)?;
+    let height = raw_height.checked_sub(top.checked_add(bottom)?)?;
+    (width > 0 && height > 0).then_some((left, top, width, height))
+}
+
+fn cropped_window(raw: &RawImage) -> Result<(usize, usize, usize, usize), JsValue> {
+    crop_window(raw.width, raw.height, raw.crops).ok_or_else(|| error::decode_failed("RAW crop lies outside the sensor"))
+}
+
+// Whether every color the CFA pattern names has a black and white level.
+// The pattern repeats every 48 pixels at most.
+fn cfa_colors_valid(raw: &RawImage) -> bool {
+    (0..48).all(|row| (0..48).all(|col| raw.cfa.color_at(row, col) < 4))
+}
+
+// Decodes to 16-bit sRGB, or 16-bit gray for monochrome sensors.
+pub(crate) fn decode(data: &[u8]) -> Result<DynamicImage, JsValue> {
+    let raw = rawloader::decode(&mut Cursor::new(data)).map_err(error::decode_failed)?;
+    if raw.cpp != 1 && raw.cpp != 3 {
+        return Err(error::unsupported_format("Unsupported RAW sample layout"));
+    }
+    let (left, top, width, height) = cropped_window(&raw)?;
+    if raw.width.checked_mul(raw.height).and_then(|n| n.checked_mul(raw.cpp)).is_none_or(|n| n > sample_count(&raw.data)) {
+        return Err(error::decode_failed("RAW data is shorter than its size"));
+    }
+    if raw.cpp == 1 && !raw.is_monochrome() && !cfa_colors_valid(&raw) {
+        return Err(error::decode_failed("RAW color filter pattern names an unknown color"));
+    }
+
+    // Sample at (x, y) of the uncropped image, scaled from the black to the
+    // white level of its color.
+    let levels: [(f32, f32); 4] = std::array::from_fn(|c| {
+        let black = raw.blacklevels[c] as f32;
+        (black, (raw.whitelevels[c] as f32 - black).max(1.0))
+    });
+    let sample = |x: usize, y: usize, channel: usize, color: usize| {
+        let index = (y * raw.width + x) * raw.cpp + channel;
+        match &raw.data {
+            RawImageData::Integer(samples) => (samples[index] as f32 - levels[color].0) / levels[color].1,
+            RawImageData::Float(samples) => samples[index],
+        }
+    };
+
+    let to_u16 = |value: f32| (value * 65535.0).round() as u16;
+    let encode = |linear: f32| depth::srgb_encode(linear.clamp(0.0, 1.0));
+    if raw.cpp == 1 && raw.is_monochrome() {
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| to_u16(encode(sample(x + left, y + top, 0, 0))))
+            .collect();
+        return ImageBuffer::from_raw(width as u32, height as u32, pixels)
+            .map(DynamicImage::ImageLuma16)
+            .ok_or_else(|| error::decode_failed("unexpected RAW pixel layout"));
+    }
+
+    let matrix = color_matrix(&raw);
+    let mut pixels = Vec::with_capacity(width * height * 3);
+    for y in top..top + height {
+        for x in left..left + width {
+            let camera = if raw.cpp == 3 {
+                [sample(x, y, 0, 0), sample(x, y, 1, 1), sample(x, y, 2, 2), 0.0]
+            } else {
+                demosaic(&raw, x, y, |sx, sy, color| sample(sx, sy, 0, color))
+            };
+            for row in &matrix {
+                let linear: f32 = row.iter().zip(&camera).map(|(m, v)| m * v).sum();
+                pixels.push(to_u16(encode(linear)));
+            }
+        }
+    }
+    ImageBuffer::from_raw(width as u32, height as u32, pixels)
+        .map(DynamicImage::ImageRgb16)
+        .ok_or_else(|| error::decode_failed("unexpected RAW pixel layout"))
+}
+
+fn sample_count(data: &RawImageData) -> usize {
+    match data {
+        RawImageData::Integer(samples) => samples.len(),
+        RawImageData::Float(samples) => samples.len(),
+    }
+}
+
+// Bilinear demosaic: the pixel's own color as sampled, and each other color
+// as the mean of its neighbors of that color in the surrounding 3 × 3 block.
+fn demosaic(raw: &RawImage, x: usize, y: usize, sample: impl Fn(usize, usize, usize) -> f32) -> [f32; 4] {
+    let own = raw.cfa.color_at(y, x);
+    let mut sums = [0.0f32; 4];
+    let mut counts = [0u32; 4];
+    for sy in y.saturating_sub(1)..(y + 2).min(raw.height) {
+        for sx in x.saturating_sub(1)..(x + 2).min(raw.width) {
+            let color = raw.cfa.color_at(sy, sx);
+            if color < 4 && color != own {
+                sums[color] += sample(sx, sy, color);
+                counts[color] += 1;
+            }
+        }
+    }
+    std::array::from_fn(|c| match (c == own, counts[c]) {
+        (true, _) => sample(x, y, own),
+        (false, 0) => 0.0,
+        (false, count) => sums[c] / count as f32,
+    })
+}
+
+// Camera values to linear sRGB: white balance, then the inverse of the
+// camera's XYZ matrix composed with sRGB, normalized so that white stays
+// white, as dcraw does. Cameras missing from rawloader's database have no
+// matrix and are passed through as RGB.
+fn color_matrix(raw: &RawImage) -> [[f32; 4]; 3] {
+    let mut balance = raw.wb_coeffs;
+    if !balance[..3].iter().all(|c| c.is_finite() && *c > 0.0) {
+        balance = raw.neutralwb();
+    }
+    let green = balance[1];
+    let balance = balance.map(|c| if c.is_finite() { c / green } else { 0.0 });
+
+    let camera_to_srgb = if raw.xyz_to_cam.iter().flatten().all(|&v| v == 0.0) {
+        [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0]]
+    } else {
+        let srgb_to_camera: [[f32; 3]; 4] = std::array::from_fn(|row| {
+            std::array::from_fn(|col| (0..3).map(|k| raw.xyz_to_cam[row][k] * SRGB_TO_XYZ[k][col]).sum())
+        });
+        RawImage::normalized_pseudoinverse(srgb_to_camera)
+    };
+    camera_to_srgb.map(|row| std::array::from_fn(|col| if row[col].is_finite() { row[col] * balance[col] } else { 0.0 }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crop_window_inside_sensor() {
+        assert_eq!(crop_window(6000, 4000, [10, 20, 30, 40]), Some((40, 10, 5940, 3960)));
+        assert_eq!(crop_window(6000, 4000, [0; 4]), Some((0, 0, 6000, 4000)));
+    }
+
+    #[test]
+    fn crop_window_rejects_crops_past_sensor() {
+        assert_eq!(crop_window(6000, 4000, [0, 3000, 0, 3000]), None);
+        assert_eq!(crop_window(6000, 4000, [0, 0, 4001, 0]), None);
+        assert_eq!(crop_window(6000, 4000, [0, usize::MAX, 0, 1]), None);
+        assert_eq!(crop_window(6000, 4000, [usize::MAX, 0, usize::MAX, 0]), None);
+    }
+}
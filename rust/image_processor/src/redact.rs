@@ -0,0 +1,100 @@
+// Redaction of rectangles by pixelation or heavy blur. Each rectangle is
+// processed on its own pixels only, so nothing outside it is mixed in and
+// nothing inside it leaks out.
+
+use crate::{error, filter};
+use image::{DynamicImage, GenericImage, GenericImageView, Rgba};
+use js_sys::{Array, Reflect};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+#[derive(Clone, Copy)]
+pub(crate) enum Mode {
+    Pixelate,
+    Blur,
+}
+
+pub(crate) fn parse_mode(mode: &str) -> Result<Mode, JsValue> {
+    match mode.to_lowercase().as_str() {
+        "pixelate" => Ok(Mode::Pixelate),
+        "blur" => Ok(Mode::Blur),
+        _ => Err(error::invalid_argument("Redaction mode must be \"pixelate\" or \"blur\"")),
+    }
+}
+
+// A rectangle in pixels, clipped to the image later.
+#[derive(Clone, Copy)]
+pub(crate) struct Rect {
+    pub(crate) x: i64,
+    pub(crate) y: i64,
+    pub(crate) width: i64,
+    pub(crate) height: i64,
+}
+
+// Reads an array of `{ x, y, width, height }`.
+pub(crate) fn parse_rects(rects: &JsValue) -> Result<Vec<Rect>, JsValue> {
+    let invalid = || error::invalid_argument("Rectangles must be an array of { x, y, width, height }");
+    let rects = rects.dyn_ref::<Array>().ok_or_else(invalid)?;
+    rects
+        .iter()
+        .map(|rect| {
+            let field = |key: &str| -> Result<i64, JsValue> {
+                let value = Reflect::get(&rect, &key.into()).map_err(|_| invalid())?;
+                value.as_f64().filter(|v| v.is_finite()).map(|v| v.round() as i64).ok_or_else(invalid)
+            };
+            Ok(Rect { x: field("x")?, y: field("y")?, width: field("width")?, height: field("height")? })
+        })
+        .collect()
+}
+
+// Block size for pixelation, and the blur's sigma: a tenth of the longer
+// side, and at least 12 pixels, so text inside cannot be read back.
+fn strength(width: u32, height: u32) -> u32 {
+    (width.max(height) / 10).max(12)
+}
+
+fn pixelate(region: &mut DynamicImage) {
+    let block = strength(region.width(), region.height());
+    for top in (0..region.height()).step_by(block as usize) {
+        for left in (0..region.width()).step_by(block as usize) {
+            let (w, h) = (block.min(region.width() - left), block.min(region.height() - top));
+            let mut sum = [0u64; 4];
+            for (_, _, pixel) in region.view(left, top, w, h).pixels() {
+                for c in 0..4 {
+                    sum[c] += pixel[c] as u64;
+                }
+            }
+            let count = (w * h) as u64;
+            let mean = Rgba(sum.map(|s| ((s + count / 2) / count) as u8));
+            for y in top..top + h {
+                for x in left..left + w {
+                    region.put_pixel(x, y, mean);
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn redact(img: &DynamicImage, rects: &[Rect], mode: Mode) -> Result<DynamicImage, JsValue> {
+    let mut out = img.clone();
+    for rect in rects {
+        let left = rect.x.clamp(0, img.width() as i64);
+        let top = rect.y.clamp(0, img.height() as i64);
+        let right = (rect.x + rect.width.max(0)).clamp(0, img.width() as i64);
+        let bottom = (rect.y + rect.height.max(0)).clamp(0, img.height() as i64);
+        if right <= left || bottom <= top {
+            continue;
+        }
+        let (left, top) = (left as u32, top as u32);
+        let mut region = out.crop_imm(left, top, right as u32 - left, bottom as u32 - top);
+        match mode {
+            Mode::Pixelate => pixelate(&mut region),
+            Mode::Blur => {
+                let sigma = strength(region.width(), region.height()).min(100) as f32;
+                region = filter::gaussian_blur(&region, sigma)?;
+            }
+        }
+        out.copy_from(&region, left, top).map_err(|e| error::invalid_argument(e.to_string()))?;
+    }
+    Ok(out)
+}
@@ -0,0 +1,45 @@
+// Red-eye removal inside rectangles the caller places around each eye, by
+// hand or from a face detector. Within the ellipse each rectangle bounds,
+// pixels are corrected by how much red dominates green and blue, so the
+// pupil changes and the skin and iris around it do not.
+
+use crate::redact::Rect;
+use crate::styles;
+use image::DynamicImage;
+
+// Red over the mean of green and blue where correction starts, and where
+// it is complete.
+const REDNESS_START: f32 = 1.6;
+const REDNESS_FULL: f32 = 2.2;
+
+// Share of the ellipse's radius from which correction fades to nothing at
+// its edge.
+const FEATHER: f32 = 0.8;
+
+// How far `(x, y)` lies inside the ellipse of `rect`: 1 up to FEATHER of
+// the way out, falling to 0 at the edge and beyond.
+fn inside(rect: &Rect, x: f32, y: f32) -> f32 {
+    if rect.width <= 0 || rect.height <= 0 {
+        return 0.0;
+    }
+    let (rx, ry) = (rect.width as f32 / 2.0, rect.height as f32 / 2.0);
+    let (dx, dy) = ((x - rect.x as f32 - rx) / rx, (y - rect.y as f32 - ry) / ry);
+    let distance = (dx * dx + dy * dy).sqrt();
+    ((1.0 - distance) / (1.0 - FEATHER)).clamp(0.0, 1.0)
+}
+
+// Replaces red with the mean of green and blue where it dominates them,
+// which leaves the pupil dark and keeps its highlight.
+pub(crate) fn remove_red_eye(img: &DynamicImage, regions: &[Rect]) -> DynamicImage {
+    styles::map_rgb(img, |x, y, [r, g, b]| {
+        let (cx, cy) = (x as f32 + 0.5, y as f32 + 0.5);
+        let region = regions.iter().map(|rect| inside(rect, cx, cy)).fold(0.0, f32::max);
+        if region == 0.0 {
+            return [r, g, b];
+        }
+        let others = (g + b) / 2.0;
+        let redness = if others > 0.0 { r / others } else if r > 0.0 { REDNESS_FULL } else { 0.0 };
+        let weight = region * ((redness - REDNESS_START) / (REDNESS_FULL - REDNESS_START)).clamp(0.0, 1.0);
+        [r + (others - r) * weight, g, b]
+    })
+}
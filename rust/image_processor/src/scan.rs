@@ -0,0 +1,499 @@
+// Document scan cleanup: finding the page in a photo, warping it flat with
+// a perspective transform, and optionally binarizing it with a local mean
+// threshold so that uneven lighting does not blacken whole regions.
+//
+// The page is looked for on a copy at most DETECT_SIDE pixels long: first
+// as the area enclosed by strong edges, then, for pages touching the frame
+// or on a busy background, as the largest region brighter than the Otsu
+// threshold. Its convex hull is reduced to the inscribed quadrilateral of
+// largest area.
+
+use crate::limits::Limits;
+use crate::{error, filter};
+use image::{DynamicImage, GrayImage, Luma, Rgba, RgbaImage};
+use js_sys::{Array, Reflect};
+use std::collections::VecDeque;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+const DETECT_SIDE: u32 = 512;
+
+// Share of the frame a page must cover, and not quite fill, to count.
+const MIN_PAGE_AREA: f64 = 0.2;
+const MAX_PAGE_AREA: f64 = 0.98;
+
+type Point = (f64, f64);
+
+// Corners as top-left, top-right, bottom-right, bottom-left.
+pub(crate) type Quad = [Point; 4];
+
+fn property(options: &JsValue, key: &str) -> Result<Option<JsValue>, JsValue> {
+    if options.is_undefined() || options.is_null() {
+        return Ok(None);
+    }
+    let value = Reflect::get(options, &key.into())?;
+    Ok((!value.is_undefined() && !value.is_null()).then_some(value))
+}
+
+fn number(value: &JsValue, key: &str) -> Result<f64, JsValue> {
+    value.as_f64().filter(|v| v.is_finite()).ok_or_else(|| error::invalid_argument(format!("Option `{}` must be a number", key)))
+}
+
+pub(crate) struct ScanOptions {
+    corners: Option<Quad>,
+    binarize: bool,
+    block_size: u32,
+    offset: f64,
+}
+
+impl ScanOptions {
+    // Reads `{ corners?, binarize?, blockSize?, offset? }`. `corners` is
+    // four `{ x, y }` points, top-left, top-right, bottom-right then
+    // bottom-left, replacing detection. `binarize` (false) turns pixels
+    // black when they are more than `offset` levels (10) darker than the
+    // mean of the `blockSize` square around them (0, a twentieth of the
+    // shorter side).
+    pub(crate) fn from_js(options: &JsValue) -> Result<ScanOptions, JsValue> {
+        let corners = match property(options, "corners")? {
+            Some(value) => {
+                let points = value.dyn_into::<Array>().map_err(|_| error::invalid_argument("Option `corners` must be an array"))?;
+                if points.length() != 4 {
+                    return Err(error::invalid_argument("Option `corners` must hold four points"));
+                }
+                let mut quad = [(0.0, 0.0); 4];
+                for (corner, point) in quad.iter_mut().zip(points.iter()) {
+                    let x = Reflect::get(&point, &"x".into())?;
+                    let y = Reflect::get(&point, &"y".into())?;
+                    *corner = (number(&x, "corners")?, number(&y, "corners")?);
+                }
+                Some(quad)
+            }
+            None => None,
+        };
+        let binarize = match property(options, "binarize")? {
+            Some(value) => value.as_bool().ok_or_else(|| error::invalid_argument("Option `binarize` must be a boolean"))?,
+            None => false,
+        };
+        let block_size = match property(options, "blockSize")? {
+            Some(value) => number(&value, "blockSize")?,
+            None => 0.0,
+        };
+        if !(0.0..=1000.0).contains(&block_size) {
+            return Err(error::invalid_argument("Option `blockSize` must be between 0 and 1000"));
+        }
+        let offset = match property(options, "offset")? {
+            Some(value) => number(&value, "offset")?,
+            None => 10.0,
+        };
+        if !(-255.0..=255.0).contains(&offset) {
+            return Err(error::invalid_argument("Option `offset` must be between -255 and 255"));
+        }
+        Ok(ScanOptions { corners, binarize, block_size: block_size.round() as u32, offset })
+    }
+}
+
+// Otsu's threshold: the level that best separates the histogram into two
+// classes, by between-class variance.
+fn otsu(histogram: &[u64; 256]) -> u8 {
+    let total: u64 = histogram.iter().sum();
+    let weighted: f64 = histogram.iter().enumerate().map(|(level, &count)| level as f64 * count as f64).sum();
+    let (mut below, mut below_sum, mut best, mut best_variance) = (0u64, 0.0, 0u8, -1.0);
+    for (level, &count) in histogram.iter().enumerate() {
+        below += count;
+        below_sum += level as f64 * count as f64;
+        if below == 0 || below == total {
+            continue;
+        }
+        let (w0, w1) = (below as f64, (total - below) as f64);
+        let (m0, m1) = (below_sum / w0, (weighted - below_sum) / w1);
+        let variance = w0 * w1 * (m0 - m1) * (m0 - m1);
+        if variance > best_variance {
+            best_variance = variance;
+            best = level as u8;
+        }
+    }
+    best
+}
+
+fn histogram(values: impl Iterator<Item = u8>) -> [u64; 256] {
+    let mut histogram = [0u64; 256];
+    for value in values {
+        histogram[value as usize] += 1;
+    }
+    histogram
+}
+
+// Sobel gradient magnitude, scaled to 0–255 by the largest.
+fn edges(luma: &GrayImage) -> GrayImage {
+    let (width, height) = luma.dimensions();
+    let at = |x: i64, y: i64| luma.get_pixel(x.clamp(0, width as i64 - 1) as u32, y.clamp(0, height as i64 - 1) as u32)[0] as f32;
+    let magnitudes: Vec<f32> = (0..height as i64)
+        .flat_map(|y| (0..width as i64).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let gx = at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1) - at(x - 1, y - 1) - 2.0 * at(x - 1, y) - at(x - 1, y + 1);
+            let gy = at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1) - at(x - 1, y - 1) - 2.0 * at(x, y - 1) - at(x + 1, y - 1);
+            (gx * gx + gy * gy).sqrt()
+        })
+        .collect();
+    let max = magnitudes.iter().cloned().fold(0.0, f32::max).max(1.0);
+    GrayImage::from_raw(width, height, magnitudes.iter().map(|m| (m * 255.0 / max) as u8).collect()).expect("one value per pixel")
+}
+
+// Grows set pixels by one in each of the eight directions.
+fn dilate(mask: &[bool], width: usize, height: usize) -> Vec<bool> {
+    let mut grown = mask.to_vec();
+    for y in 0..height {
+        for x in 0..width {
+            if mask[y * width + x] {
+                for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                    for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                        grown[ny * width + nx] = true;
+                    }
+                }
+            }
+        }
+    }
+    grown
+}
+
+// The 4-connected regions of `mask`, as pixel indices, reached from `seeds`.
+fn flood(mask: &[bool], width: usize, height: usize, seeds: impl Iterator<Item = usize>, visited: &mut [bool]) -> Vec<usize> {
+    let mut region = Vec::new();
+    let mut queue: VecDeque<usize> = seeds.filter(|&i| mask[i] && !visited[i]).collect();
+    for &i in &queue {
+        visited[i] = true;
+    }
+    while let Some(i) = queue.pop_front() {
+        region.push(i);
+        let (x, y) = (i % width, i / width);
+        let neighbors = [(x > 0).then(|| i - 1), (x + 1 < width).then(|| i + 1), (y > 0).then(|| i - width), (y + 1 < height).then(|| i + width)];
+        for n in neighbors.into_iter().flatten() {
+            if mask[n] && !visited[n] {
+                visited[n] = true;
+                queue.push_back(n);
+            }
+        }
+    }
+    region
+}
+
+fn largest_region(mask: &[bool], width: usize, height: usize) -> Vec<usize> {
+    let mut visited = vec![false; mask.len()];
+    let mut largest = Vec::new();
+    for i in 0..mask.len() {
+        if mask[i] && !visited[i] {
+            let region = flood(mask, width, height, std::iter::once(i), &mut visited);
+            if region.len() > largest.len() {
+                largest = region;
+            }
+        }
+    }
+    largest
+}
+
+fn cross(o: Point, a: Point, b: Point) -> f64 {
+    (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+}
+
+// Convex hull by the monotone chain, clockwise on screen (y down).
+fn hull(mut points: Vec<Point>) -> Vec<Point> {
+    points.sort_by(|a, b| a.partial_cmp(b).expect("finite points"));
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+    let mut lower: Vec<Point> = Vec::new();
+    for &p in &points {
+        while lower.len() >= 2 && cross(lower[lower.len() - 2], lower[lower.len() - 1], p) <= 0.0 {
+            lower.pop();
+        }
+        lower.push(p);
+    }
+    let mut upper: Vec<Point> = Vec::new();
+    for &p in points.iter().rev() {
+        while upper.len() >= 2 && cross(upper[upper.len() - 2], upper[upper.len() - 1], p) <= 0.0 {
+            upper.pop();
+        }
+        upper.push(p);
+    }
+    lower.pop();
+    upper.pop();
+    lower.extend(upper);
+    lower
+}
+
+fn area(quad: &Quad) -> f64 {
+    (0..4).map(|i| cross((0.0, 0.0), quad[i], quad[(i + 1) % 4])).sum::<f64>().abs() / 2.0
+}
+
+// The largest quadrilateral on the hull's vertices, found by moving one
+// corner at a time to the vertex that most enlarges it, starting from the
+// extremes along both diagonals.
+fn largest_quad(hull: &[Point]) -> Option<Quad> {
+    if hull.len() < 4 {
+        return None;
+    }
+    let extreme = |key: &dyn Fn(&Point) -> f64| (0..hull.len()).max_by(|&a, &b| key(&hull[a]).total_cmp(&key(&hull[b]))).expect("non-empty hull");
+    let mut corners = [extreme(&|p| -p.0 - p.1), extreme(&|p| p.0 - p.1), extreme(&|p| p.0 + p.1), extreme(&|p| p.1 - p.0)];
+    let quad = |corners: &[usize; 4]| corners.map(|i| hull[i]);
+    let mut best = area(&quad(&corners));
+    let mut improved = true;
+    while improved {
+        improved = false;
+        for corner in 0..4 {
+            for candidate in 0..hull.len() {
+                let mut trial = corners;
+                trial[corner] = candidate;
+                let trial_area = area(&quad(&trial));
+                if trial_area > best + 1e-9 {
+                    best = trial_area;
+                    corners = trial;
+                    improved = true;
+                }
+            }
+        }
+    }
+    let mut quad = quad(&corners);
+    // Put the corner nearest the top-left first, keeping the order clockwise.
+    let signed: f64 = (0..4).map(|i| cross((0.0, 0.0), quad[i], quad[(i + 1) % 4])).sum();
+    if signed < 0.0 {
+        quad.reverse();
+    }
+    let first = (0..4).min_by(|&a, &b| (quad[a].0 + quad[a].1).total_cmp(&(quad[b].0 + quad[b].1))).expect("four corners");
+    quad.rotate_left(first);
+    Some(quad)
+}
+
+// The page outline in `region`, when the region is large enough to be one.
+fn region_quad(region: &[usize], width: usize, height: usize) -> Option<Quad> {
+    let frame = (width * height) as f64;
+    if (region.len() as f64) < frame * MIN_PAGE_AREA {
+        return None;
+    }
+    // The leftmost and rightmost pixel of each row bound the hull.
+    let mut rows: Vec<Option<(usize, usize)>> = vec![None; height];
+    for &i in region {
+        let (x, y) = (i % width, i / width);
+        rows[y] = Some(rows[y].map_or((x, x), |(left, right)| (left.min(x), right.max(x))));
+    }
+    let points = rows
+        .iter()
+        .enumerate()
+        .filter_map(|(y, row)| row.map(|(left, right)| [(left as f64, y as f64), (right as f64 + 1.0, y as f64), (left as f64, y as f64 + 1.0), (right as f64 + 1.0, y as f64 + 1.0)]))
+        .flatten()
+        .collect();
+    let quad = largest_quad(&hull(points))?;
+    (area(&quad) >= frame * MIN_PAGE_AREA && area(&quad) <= frame * MAX_PAGE_AREA).then_some(quad)
+}
+
+// The page corners in `img` pixel coordinates, if a page is found.
+pub(crate) fn detect(img: &DynamicImage) -> Result<Option<Quad>, JsValue> {
+    let scale = (DETECT_SIDE as f64 / img.width().max(img.height()) as f64).min(1.0);
+    let small = if scale < 1.0 {
+        let side = |v: u32| ((v as f64 * scale).round() as u32).max(1);
+        img.resize_exact(side(img.width()), side(img.height()), image::imageops::FilterType::Triangle)
+    } else {
+        img.clone()
+    };
+    let luma = filter::gaussian_blur(&DynamicImage::ImageLuma8(small.to_luma8()), 1.5)?.to_luma8();
+    let (width, height) = (luma.width() as usize, luma.height() as usize);
+
+    // Pixels that cannot be reached from the frame without crossing an edge.
+    let gradient = edges(&luma);
+    let threshold = otsu(&histogram(gradient.pixels().map(|p| p[0])));
+    let edge: Vec<bool> = gradient.pixels().map(|p| p[0] > threshold).collect();
+    let edge = dilate(&dilate(&edge, width, height), width, height);
+    let open: Vec<bool> = edge.iter().map(|&e| !e).collect();
+    let border = (0..width).flat_map(|x| [x, (height - 1) * width + x]).chain((0..height).flat_map(|y| [y * width, y * width + width - 1]));
+    let mut outside = vec![false; open.len()];
+    flood(&open, width, height, border, &mut outside);
+    // Undo the dilation, which pushed the outline outwards.
+    let outside = dilate(&dilate(&outside, width, height), width, height);
+    let enclosed: Vec<bool> = outside.iter().map(|&o| !o).collect();
+
+    let threshold = otsu(&histogram(luma.pixels().map(|p| p[0])));
+    let bright: Vec<bool> = luma.pixels().map(|p| p[0] > threshold).collect();
+
+    let quad = region_quad(&largest_region(&enclosed, width, height), width, height)
+        .or_else(|| region_quad(&largest_region(&bright, width, height), width, height));
+    let (sx, sy) = (img.width() as f64 / width as f64, img.height() as f64 / height as f64);
+    Ok(quad.map(|quad| quad.map(|(x, y)| (x * sx, y * sy))))
+}
+
+// Solves the 8 × 8 system for the homography taking the corners of a
+// `width` × `height` rectangle to `quad`, by Gaussian elimination.
+fn homography(quad: &Quad, width: f64, height: f64) -> Result<[f64; 8], JsValue> {
+    let rect = [(0.0, 0.0), (width, 0.0), (width, height), (0.0, height)];
+    let mut rows = [[0.0f64; 9]; 8];
+    for (i, (&(u, v), &(x, y))) in rect.iter().zip(quad).enumerate() {
+        rows[2 * i] = [u, v, 1.0, 0.0, 0.0, 0.0, -u * x, -v * x, x];
+        rows[2 * i + 1] = [0.0, 0.0, 0.0, u, v, 1.0, -u * y, -v * y, y];
+    }
+    for col in 0..8 {
+        let pivot = (col..8).max_by(|&a, &b| rows[a][col].abs().total_cmp(&rows[b][col].abs())).expect("rows left");
+        if rows[pivot][col].abs() < 1e-12 {
+            return Err(error::invalid_argument("Document corners must form a quadrilateral"));
+        }
+        rows.swap(col, pivot);
+        for row in 0..8 {
+            if row != col {
+                let (factor, pivot_row) = (rows[row][col] / rows[col][col], rows[col]);
+                for (value, pivot_value) in rows[row][col..].iter_mut().zip(&pivot_row[col..]) {
+                    *value -= factor * pivot_value;
+                }
+            }
+        }
+    }
+    Ok(std::array::from_fn(|i| rows[i][8] / rows[i][i]))
+}
+
+fn distance(a: Point, b: Point) -> f64 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+// Size of the rectangle warp maps `quad` onto: as wide and tall as its
+// longer opposite sides. Sides too long for u32 saturate.
+pub(crate) fn warped_size(quad: &Quad) -> (u32, u32) {
+    let [tl, tr, br, bl] = *quad;
+    let side = |length: f64| length.round().max(1.0) as u32;
+    (side(distance(tl, tr).max(distance(bl, br))), side(distance(tl, bl).max(distance(tr, br))))
+}
+
+// Whether an RGBA buffer of `width` × `height` fits in the address space.
+fn fits_in_memory(width: u32, height: u32) -> bool {
+    (width as usize).checked_mul(height as usize).and_then(|n| n.checked_mul(4)).is_some_and(|n| n <= isize::MAX as usize)
+}
+
+// Maps `quad` onto an upright rectangle of warped_size, sampling
+// bilinearly.
+pub(crate) fn warp(img: &DynamicImage, quad: &Quad) -> Result<DynamicImage, JsValue> {
+    let (width, height) = warped_size(quad);
+    if !fits_in_memory(width, height) {
+        return Err(error::size_limit(format!("Flattened page of {} × {} is too large", width, height)));
+    }
+    let (width, height) = (width as f64, height as f64);
+    let h = homography(quad, width, height)?;
+    let source = img.to_rgba8();
+    let (sw, sh) = (source.width() as i64, source.height() as i64);
+    let texel = |x: i64, y: i64| {
+        let p = source.get_pixel(x.clamp(0, sw - 1) as u32, y.clamp(0, sh - 1) as u32);
+        let a = p[3] as f64 / 255.0;
+        [p[0] as f64 * a, p[1] as f64 * a, p[2] as f64 * a, p[3] as f64]
+    };
+    let warped = RgbaImage::from_fn(width as u32, height as u32, |u, v| {
+        let (u, v) = (u as f64 + 0.5, v as f64 + 0.5);
+        let w = h[6] * u + h[7] * v + 1.0;
+        let sx = (h[0] * u + h[1] * v + h[2]) / w - 0.5;
+        let sy = (h[3] * u + h[4] * v + h[5]) / w - 0.5;
+        let (x0, y0) = (sx.floor(), sy.floor());
+        let (fx, fy) = (sx - x0, sy - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let mut value = [0.0; 4];
+        for (tx, ty, weight) in [(x0, y0, (1.0 - fx) * (1.0 - fy)), (x0 + 1, y0, fx * (1.0 - fy)), (x0, y0 + 1, (1.0 - fx) * fy), (x0 + 1, y0 + 1, fx * fy)] {
+            for (sum, channel) in value.iter_mut().zip(texel(tx, ty)) {
+                *sum += channel * weight;
+            }
+        }
+        let alpha = value[3];
+        let unpremultiply = |c: f64| if alpha > 0.0 { (c * 255.0 / alpha).round().clamp(0.0, 255.0) as u8 } else { 0 };
+        Rgba([unpremultiply(value[0]), unpremultiply(value[1]), unpremultiply(value[2]), alpha.round() as u8])
+    });
+    let warped = DynamicImage::ImageRgba8(warped);
+    Ok(if img.color().has_alpha() { warped } else { DynamicImage::ImageRgb8(warped.to_rgb8()) })
+}
+
+// Black where a pixel is more than `offset` levels below the mean of the
+// `block_size` square around it, white elsewhere.
+fn binarize(img: &DynamicImage, block_size: u32, offset: f64) -> Result<DynamicImage, JsValue> {
+    let luma = img.to_luma8();
+    let block = if block_size == 0 { (luma.width().min(luma.height()) / 20).max(15) } else { block_size };
+    let mean = filter::box_blur(&DynamicImage::ImageLuma8(luma.clone()), (block / 2).clamp(1, 1000))?.to_luma8();
+    let binary = GrayImage::from_fn(luma.width(), luma.height(), |x, y| {
+        let dark = (luma.get_pixel(x, y)[0] as f64) < mean.get_pixel(x, y)[0] as f64 - offset;
+        Luma([if dark { 0 } else { 255 }])
+    });
+    Ok(DynamicImage::ImageLuma8(binary))
+}
+
+// Flattens the page given by `options.corners` or, without them, the one
+// detected, then binarizes it if asked. Without corners or a detected page
+// the whole image is kept. The flattened size is checked against `limits`
+// before anything is allocated for it.
+pub(crate) fn clean_up(img: &DynamicImage, options: &ScanOptions, limits: &Limits) -> Result<DynamicImage, JsValue> {
+    let quad = match options.corners {
+        Some(quad) => Some(quad),
+        None => detect(img)?,
+    };
+    let page = match quad {
+        Some(quad) => {
+            let (width, height) = warped_size(&quad);
+            limits.check_output(width, height)?;
+            warp(img, &quad)?
+        }
+        None => img.clone(),
+    };
+    if options.binarize {
+        binarize(&page, options.block_size, options.offset)
+    } else {
+        Ok(page)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(h: &[f64; 8], (u, v): Point) -> Point {
+        let w = h[6] * u + h[7] * v + 1.0;
+        ((h[0] * u + h[1] * v + h[2]) / w, (h[3] * u + h[4] * v + h[5]) / w)
+    }
+
+    #[test]
+    fn warped_size_of_far_apart_corners_is_over_the_limits() {
+        let quad = [(0.0, 0.0), (40_000.0, 0.0), (40_000.0, 40_000.0), (0.0, 40_000.0)];
+        assert_eq!(warped_size(&quad), (40_000, 40_000));
+        assert!(!Limits::default().allows_dimensions(40_000, 40_000));
+        assert!(!Limits::default().allows_pixels(40_000, 40_000));
+        // 40000 × 40000 × 4 overflows a 32-bit length.
+        assert_eq!(fits_in_memory(40_000, 40_000), cfg!(target_pointer_width = "64"));
+
+        let quad = [(0.0, 0.0), (1e300, 0.0), (1e300, 1e300), (0.0, 1e300)];
+        assert_eq!(warped_size(&quad), (u32::MAX, u32::MAX));
+        assert!(!Limits::default().allows_pixels(u32::MAX, u32::MAX));
+        assert!(!fits_in_memory(u32::MAX, u32::MAX));
+    }
+
+    #[test]
+    fn warped_size_uses_the_longer_sides() {
+        let quad = [(10.0, 10.0), (110.0, 10.0), (130.0, 210.0), (0.0, 200.0)];
+        assert_eq!(warped_size(&quad), (130, 201));
+    }
+
+    #[test]
+    fn homography_of_a_scaled_rectangle_is_affine() {
+        let quad = [(10.0, 20.0), (210.0, 20.0), (210.0, 120.0), (10.0, 120.0)];
+        let h = homography(&quad, 100.0, 50.0).unwrap();
+        let expected = [2.0, 0.0, 10.0, 0.0, 2.0, 20.0, 0.0, 0.0];
+        assert!(h.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-9), "{:?}", h);
+    }
+
+    #[test]
+    fn homography_maps_corners_onto_the_quad() {
+        // A page photographed at an angle: the far edge is shorter.
+        let quad = [(120.0, 80.0), (420.0, 95.0), (500.0, 460.0), (40.0, 430.0)];
+        let (width, height) = (300.0, 400.0);
+        let h = homography(&quad, width, height).unwrap();
+        let rect = [(0.0, 0.0), (width, 0.0), (width, height), (0.0, height)];
+        for (corner, target) in rect.into_iter().zip(quad) {
+            let (x, y) = project(&h, corner);
+            assert!((x - target.0).abs() < 1e-6 && (y - target.1).abs() < 1e-6, "{:?} -> {:?}", corner, (x, y));
+        }
+        // Straight lines stay straight: the rectangle's center lands where
+        // the quad's diagonals cross.
+        let (x, y) = project(&h, (width / 2.0, height / 2.0));
+        let [a, b, c, d] = quad;
+        let cross = (c.0 - a.0) * (y - a.1) - (c.1 - a.1) * (x - a.0);
+        let cross2 = (d.0 - b.0) * (y - b.1) - (d.1 - b.1) * (x - b.0);
+        assert!(cross.abs() < 1e-6 && cross2.abs() < 1e-6);
+    }
+}
@@ -0,0 +1,153 @@
+// Sprite sheets: images bin-packed onto one canvas with a skyline
+// bottom-left packer, tallest first.
+
+use crate::error;
+use image::{imageops, DynamicImage, RgbaImage};
+use js_sys::{Reflect, Uint8Array};
+use std::collections::HashSet;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+// One sprite: its name in the coordinate map and its encoded data.
+pub(crate) struct Entry {
+    pub(crate) name: String,
+    pub(crate) data: Vec<u8>,
+}
+
+// Reads `images` as an Array of Uint8Arrays, named by their index, or of
+// `{ name, data }` objects. Names must be unique.
+pub(crate) fn parse_entries(images: &js_sys::Array) -> Result<Vec<Entry>, JsValue> {
+    if images.length() == 0 {
+        return Err(error::invalid_argument("A sprite sheet needs at least one image"));
+    }
+    let mut names = HashSet::new();
+    images
+        .iter()
+        .enumerate()
+        .map(|(index, value)| {
+            let invalid = || error::invalid_argument("Sprites must be Uint8Arrays or { name, data } objects");
+            let entry = if let Some(data) = value.dyn_ref::<Uint8Array>() {
+                Entry { name: index.to_string(), data: data.to_vec() }
+            } else if value.is_object() {
+                let name = Reflect::get(&value, &"name".into())?.as_string().ok_or_else(invalid)?;
+                let data = Reflect::get(&value, &"data".into())?.dyn_into::<Uint8Array>().map_err(|_| invalid())?;
+                Entry { name, data: data.to_vec() }
+            } else {
+                return Err(invalid());
+            };
+            if !names.insert(entry.name.clone()) {
+                return Err(error::invalid_argument(format!("Duplicate sprite name: {}", entry.name)));
+            }
+            Ok(entry)
+        })
+        .collect()
+}
+
+pub(crate) struct Placement {
+    pub(crate) x: u32,
+    pub(crate) y: u32,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+// A run of the skyline: the sheet is filled below `y` from `x` for `width`.
+struct Segment {
+    x: u64,
+    y: u64,
+    width: u64,
+}
+
+// The lowest, then leftmost, spot for a box `width` wide on the skyline
+// within `sheet_width`, as (segment index, x, y).
+fn lowest_fit(skyline: &[Segment], width: u64, sheet_width: u64) -> Option<(usize, u64, u64)> {
+    let mut best: Option<(usize, u64, u64)> = None;
+    for start in 0..skyline.len() {
+        let x = skyline[start].x;
+        if x + width > sheet_width {
+            break;
+        }
+        let mut y = 0;
+        let mut covered = 0;
+        for segment in &skyline[start..] {
+            y = y.max(segment.y);
+            covered += segment.width;
+            if covered >= width {
+                break;
+            }
+        }
+        if best.is_none_or(|(_, bx, by)| y < by || (y == by && x < bx)) {
+            best = Some((start, x, y));
+        }
+    }
+    best
+}
+
+// Raises the skyline to `top` over a box `width` wide placed at `x`, the
+// start of segment `start`.
+fn raise(skyline: &mut Vec<Segment>, start: usize, x: u64, top: u64, width: u64) {
+    let end = x + width;
+    let index = start;
+    while index < skyline.len() && skyline[index].x < end {
+        let segment_end = skyline[index].x + skyline[index].width;
+        if segment_end <= end {
+            skyline.remove(index);
+        } else {
+            skyline[index].width = segment_end - end;
+            skyline[index].x = end;
+            break;
+        }
+    }
+    skyline.insert(start, Segment { x, y: top, width });
+    // Merge neighbours at the same height.
+    let mut index = 0;
+    while index + 1 < skyline.len() {
+        if skyline[index].y == skyline[index + 1].y {
+            skyline[index].width += skyline[index + 1].width;
+            skyline.remove(index + 1);
+        } else {
+            index += 1;
+        }
+    }
+}
+
+// Packs boxes of `sizes` with `padding` pixels between them, returning the
+// sheet size and one placement per box in their original order. The sheet
+// is about square: as wide as the square root of the padded area, and at
+// least as wide as the widest box.
+pub(crate) fn pack(sizes: &[(u32, u32)], padding: u32) -> ((u32, u32), Vec<Placement>) {
+    let padding = padding as u64;
+    let padded: Vec<(u64, u64)> = sizes.iter().map(|&(w, h)| (w as u64 + padding, h as u64 + padding)).collect();
+    let area: u64 = padded.iter().map(|(w, h)| w * h).sum();
+    let widest = padded.iter().map(|&(w, _)| w).max().unwrap_or(0);
+    let sheet_width = widest.max((area as f64).sqrt().ceil() as u64);
+
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&index| (std::cmp::Reverse(padded[index].1), std::cmp::Reverse(padded[index].0)));
+
+    let mut skyline = vec![Segment { x: 0, y: 0, width: sheet_width }];
+    let mut positions = vec![(0, 0); sizes.len()];
+    for index in order {
+        let (width, height) = padded[index];
+        let (start, x, y) = lowest_fit(&skyline, width, sheet_width).expect("the sheet fits the widest box");
+        raise(&mut skyline, start, x, y + height, width);
+        positions[index] = (x, y);
+    }
+
+    let placements: Vec<Placement> = sizes
+        .iter()
+        .zip(&positions)
+        .map(|(&(width, height), &(x, y))| Placement { x: x as u32, y: y as u32, width, height })
+        .collect();
+    let used_width = placements.iter().map(|p| p.x + p.width).max().unwrap_or(0);
+    let used_height = placements.iter().map(|p| p.y + p.height).max().unwrap_or(0);
+    ((used_width, used_height), placements)
+}
+
+// Draws each image at its placement on a transparent sheet.
+pub(crate) fn draw(images: &[DynamicImage], size: (u32, u32), placements: &[Placement]) -> DynamicImage {
+    let mut sheet = RgbaImage::new(size.0, size.1);
+    for (img, placement) in images.iter().zip(placements) {
+        imageops::replace(&mut sheet, &img.to_rgba8(), placement.x as i64, placement.y as i64);
+    }
+    DynamicImage::ImageRgba8(sheet)
+}
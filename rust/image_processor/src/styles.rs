@@ -0,0 +1,80 @@
+// Quick styles: sepia, duotone, posterize, invert and vignette. They change
+// only the color channels, at 8 bits, so the editor preview and the export
+// run the same code and match exactly.
+
+use crate::error;
+use image::{DynamicImage, Rgba};
+use wasm_bindgen::prelude::*;
+
+// Applies `f` to the RGB of every pixel, given its position, keeping alpha
+// and, for images without it, leaving it out of the result.
+pub(crate) fn map_rgb(img: &DynamicImage, f: impl Fn(u32, u32, [f32; 3]) -> [f32; 3]) -> DynamicImage {
+    let mut rgba = img.to_rgba8();
+    for (x, y, pixel) in rgba.enumerate_pixels_mut() {
+        let rgb = f(x, y, [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32]);
+        for c in 0..3 {
+            pixel[c] = rgb[c].round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    let out = DynamicImage::ImageRgba8(rgba);
+    if img.color().has_alpha() { out } else { DynamicImage::ImageRgb8(out.to_rgb8()) }
+}
+
+fn check_amount(amount: f32, name: &str) -> Result<(), JsValue> {
+    if !(0.0..=1.0).contains(&amount) {
+        return Err(error::invalid_argument(format!("{} must be between 0 and 1", name)));
+    }
+    Ok(())
+}
+
+fn luma(rgb: [f32; 3]) -> f32 {
+    0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]
+}
+
+// The CSS sepia() matrix at `amount` (0–1).
+pub(crate) fn sepia(img: &DynamicImage, amount: f32) -> Result<DynamicImage, JsValue> {
+    check_amount(amount, "Sepia amount")?;
+    let keep = 1.0 - amount;
+    let matrix = [
+        [0.393 + 0.607 * keep, 0.769 - 0.769 * keep, 0.189 - 0.189 * keep],
+        [0.349 - 0.349 * keep, 0.686 + 0.314 * keep, 0.168 - 0.168 * keep],
+        [0.272 - 0.272 * keep, 0.534 - 0.534 * keep, 0.131 + 0.869 * keep],
+    ];
+    Ok(map_rgb(img, |_, _, rgb| std::array::from_fn(|row| (0..3).map(|k| matrix[row][k] * rgb[k]).sum())))
+}
+
+// Maps luma from black to white onto the gradient from `shadow` to
+// `highlight`.
+pub(crate) fn duotone(img: &DynamicImage, shadow: Rgba<u8>, highlight: Rgba<u8>) -> DynamicImage {
+    map_rgb(img, |_, _, rgb| {
+        let t = luma(rgb) / 255.0;
+        std::array::from_fn(|c| shadow[c] as f32 + (highlight[c] as f32 - shadow[c] as f32) * t)
+    })
+}
+
+// Rounds each channel to `levels` (2–255) evenly spaced values.
+pub(crate) fn posterize(img: &DynamicImage, levels: u32) -> Result<DynamicImage, JsValue> {
+    if !(2..=255).contains(&levels) {
+        return Err(error::invalid_argument("Posterize levels must be between 2 and 255"));
+    }
+    let steps = (levels - 1) as f32;
+    Ok(map_rgb(img, |_, _, rgb| rgb.map(|v| (v * steps / 255.0).round() * 255.0 / steps)))
+}
+
+pub(crate) fn invert(img: &DynamicImage) -> DynamicImage {
+    map_rgb(img, |_, _, rgb| rgb.map(|v| 255.0 - v))
+}
+
+// Darkens towards the corners by up to `strength` (0–1), starting halfway
+// from the center and easing in smoothly.
+pub(crate) fn vignette(img: &DynamicImage, strength: f32) -> Result<DynamicImage, JsValue> {
+    check_amount(strength, "Vignette strength")?;
+    let (cx, cy) = (img.width() as f32 / 2.0, img.height() as f32 / 2.0);
+    let corner = (cx * cx + cy * cy).sqrt().max(1.0);
+    Ok(map_rgb(img, |x, y, rgb| {
+        let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+        let t = (((dx * dx + dy * dy).sqrt() / corner - 0.5) / 0.5).clamp(0.0, 1.0);
+        let falloff = t * t * (3.0 - 2.0 * t);
+        rgb.map(|v| v * (1.0 - strength * falloff))
+    }))
+}
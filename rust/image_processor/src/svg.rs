@@ -0,0 +1,73 @@
+// SVG rasterization with resvg, pure Rust. Text is set in the embedded font
+// for every generic and named family, since the module sees no system fonts;
+// without the embedded-font feature, text is left out. Embedded raster
+// images are drawn, external files and URLs are not fetched.
+
+use crate::error;
+use image::{DynamicImage, Rgba, RgbaImage};
+use resvg::tiny_skia::{Color, Pixmap, Transform};
+use resvg::usvg::{Options, Tree};
+use wasm_bindgen::prelude::*;
+
+#[cfg(feature = "embedded-font")]
+fn options() -> Options<'static> {
+    let family = "DejaVu Sans";
+    let mut fonts = resvg::usvg::fontdb::Database::new();
+    fonts.load_font_data(crate::text::EMBEDDED_FONT.to_vec());
+    fonts.set_serif_family(family);
+    fonts.set_sans_serif_family(family);
+    fonts.set_monospace_family(family);
+    fonts.set_cursive_family(family);
+    fonts.set_fantasy_family(family);
+    Options { font_family: family.to_string(), fontdb: std::sync::Arc::new(fonts), ..Options::default() }
+}
+
+#[cfg(not(feature = "embedded-font"))]
+fn options() -> Options<'static> {
+    Options::default()
+}
+
+pub(crate) fn parse(svg: &[u8]) -> Result<Tree, JsValue> {
+    Tree::from_data(svg, &options()).map_err(error::decode_failed)
+}
+
+// The output size for `width` × `height`, with 0 for either side following
+// the SVG's aspect ratio and 0 for both taking its own size.
+pub(crate) fn output_size(tree: &Tree, width: u32, height: u32) -> (u32, u32) {
+    let size = tree.size();
+    let aspect = size.width() / size.height();
+    let side = |value: f32| (value.round() as u32).max(1);
+    match (width, height) {
+        (0, 0) => (side(size.width()), side(size.height())),
+        (0, height) => (side(height as f32 * aspect), height),
+        (width, 0) => (width, side(width as f32 / aspect)),
+        size => size,
+    }
+}
+
+// Draws `tree` as large as fits in `width` × `height`, centered, on
+// `background`. The result is RGB when it is fully opaque.
+pub(crate) fn rasterize(tree: &Tree, width: u32, height: u32, background: Rgba<u8>) -> Result<DynamicImage, JsValue> {
+    let mut pixmap = Pixmap::new(width, height).ok_or_else(|| error::invalid_argument("Invalid SVG output size"))?;
+    let [r, g, b, a] = background.0;
+    pixmap.fill(Color::from_rgba8(r, g, b, a));
+
+    let size = tree.size();
+    let scale = (width as f32 / size.width()).min(height as f32 / size.height());
+    let (dx, dy) = ((width as f32 - size.width() * scale) / 2.0, (height as f32 - size.height() * scale) / 2.0);
+    resvg::render(tree, Transform::from_row(scale, 0.0, 0.0, scale, dx, dy), &mut pixmap.as_mut());
+
+    // tiny-skia keeps premultiplied alpha.
+    let pixels = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect();
+    let rgba = RgbaImage::from_raw(width, height, pixels).expect("four bytes per pixel");
+    let opaque = rgba.pixels().all(|pixel| pixel[3] == 255);
+    let img = DynamicImage::ImageRgba8(rgba);
+    Ok(if opaque { DynamicImage::ImageRgb8(img.to_rgb8()) } else { img })
+}
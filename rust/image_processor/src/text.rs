@@ -0,0 +1,238 @@
+// Text rendering with ab_glyph: greedy word wrapping, alignment, and a
+// stroke made by dilating the glyph coverage.
+
+use crate::error;
+use ab_glyph::{point, Font, FontArc, PxScale, ScaleFont};
+use image::{DynamicImage, Rgba, RgbaImage};
+use js_sys::{Reflect, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+// DejaVu Sans, see fonts/DejaVuSans-LICENSE.txt.
+#[cfg(feature = "embedded-font")]
+pub(crate) const EMBEDDED_FONT: &[u8] = include_bytes!("../fonts/DejaVuSans.ttf");
+
+#[derive(Clone, Copy)]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+pub(crate) struct TextOptions {
+    font: FontArc,
+    size: f32,
+    color: Rgba<u8>,
+    x: f32,
+    y: f32,
+    max_width: Option<f32>,
+    align: Align,
+    line_height: f32,
+    stroke_width: u32,
+    stroke_color: Rgba<u8>,
+}
+
+fn property(options: &JsValue, key: &str) -> Result<Option<JsValue>, JsValue> {
+    if options.is_undefined() || options.is_null() {
+        return Ok(None);
+    }
+    let value = Reflect::get(options, &key.into())?;
+    Ok((!value.is_undefined() && !value.is_null()).then_some(value))
+}
+
+fn number(options: &JsValue, key: &str, default: f64) -> Result<f64, JsValue> {
+    match property(options, key)? {
+        Some(value) => value.as_f64().ok_or_else(|| error::invalid_argument(format!("Text option `{}` must be a number", key))),
+        None => Ok(default),
+    }
+}
+
+fn string(options: &JsValue, key: &str, default: &str) -> Result<String, JsValue> {
+    match property(options, key)? {
+        Some(value) => value.as_string().ok_or_else(|| error::invalid_argument(format!("Text option `{}` must be a string", key))),
+        None => Ok(default.to_string()),
+    }
+}
+
+fn font(options: &JsValue) -> Result<FontArc, JsValue> {
+    let invalid = |_| error::invalid_argument("Failed to load font");
+    match property(options, "font")? {
+        Some(value) => {
+            let bytes = value.dyn_into::<Uint8Array>().map_err(|_| error::invalid_argument("Text option `font` must be a Uint8Array"))?;
+            FontArc::try_from_vec(bytes.to_vec()).map_err(invalid)
+        }
+        #[cfg(feature = "embedded-font")]
+        None => FontArc::try_from_slice(EMBEDDED_FONT).map_err(invalid),
+        #[cfg(not(feature = "embedded-font"))]
+        None => Err(error::invalid_argument("Text option `font` is required without the embedded font")),
+    }
+}
+
+impl TextOptions {
+    // Reads `{ font?, size?, color?, x?, y?, maxWidth?, align?, lineHeight?,
+    // strokeWidth?, strokeColor? }`.
+    pub(crate) fn from_js(options: &JsValue) -> Result<TextOptions, JsValue> {
+        let size = number(options, "size", 32.0)?;
+        if !(size > 0.0 && size.is_finite()) {
+            return Err(error::invalid_argument("Text size must be positive"));
+        }
+        let max_width = match property(options, "maxWidth")? {
+            Some(_) => Some(number(options, "maxWidth", 0.0)?.max(0.0) as f32),
+            None => None,
+        };
+        let align = match string(options, "align", "left")?.as_str() {
+            "left" => Align::Left,
+            "center" | "centre" => Align::Center,
+            "right" => Align::Right,
+            _ => return Err(error::invalid_argument("Unsupported text alignment")),
+        };
+        let line_height = number(options, "lineHeight", 1.2)?;
+        if !(line_height > 0.0 && line_height.is_finite()) {
+            return Err(error::invalid_argument("Line height must be positive"));
+        }
+        let stroke_width = number(options, "strokeWidth", 0.0)?;
+        if !(0.0..=64.0).contains(&stroke_width) {
+            return Err(error::invalid_argument("Stroke width must be between 0 and 64"));
+        }
+        Ok(TextOptions {
+            font: font(options)?,
+            size: size as f32,
+            color: crate::transform::parse_color(&string(options, "color", "#000000")?)?,
+            x: number(options, "x", 0.0)? as f32,
+            y: number(options, "y", 0.0)? as f32,
+            max_width,
+            align,
+            line_height: line_height as f32,
+            stroke_width: stroke_width.round() as u32,
+            stroke_color: crate::transform::parse_color(&string(options, "strokeColor", "#ffffff")?)?,
+        })
+    }
+}
+
+fn text_width<F: Font>(font: &ab_glyph::PxScaleFont<F>, text: &str) -> f32 {
+    let mut width = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let glyph = font.glyph_id(c);
+        if let Some(previous) = previous {
+            width += font.kern(previous, glyph);
+        }
+        width += font.h_advance(glyph);
+        previous = Some(glyph);
+    }
+    width
+}
+
+// Lines of `text` no wider than `max_width` where possible, breaking at
+// spaces and at explicit newlines. Words wider than the limit get a line of
+// their own.
+fn wrap<F: Font>(font: &ab_glyph::PxScaleFont<F>, text: &str, max_width: f32) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let candidate = if line.is_empty() { word.to_string() } else { format!("{} {}", line, word) };
+            if !line.is_empty() && text_width(font, &candidate) > max_width {
+                lines.push(std::mem::replace(&mut line, word.to_string()));
+            } else {
+                line = candidate;
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+fn blend_over(pixel: &mut Rgba<u8>, color: Rgba<u8>, coverage: f32) {
+    let alpha_s = color[3] as f32 / 255.0 * coverage.min(1.0);
+    if alpha_s <= 0.0 {
+        return;
+    }
+    let alpha_b = pixel[3] as f32 / 255.0;
+    let alpha = alpha_s + alpha_b * (1.0 - alpha_s);
+    for c in 0..3 {
+        let value = (color[c] as f32 * alpha_s + pixel[c] as f32 * alpha_b * (1.0 - alpha_s)) / alpha;
+        pixel[c] = value.round().clamp(0.0, 255.0) as u8;
+    }
+    pixel[3] = (alpha * 255.0).round() as u8;
+}
+
+// Draws `text` onto `img` with its block's top-left corner at the options'
+// `x`, `y`. Lines wrap at `maxWidth`, by default the rest of the image
+// width, and are aligned within it. A stroke is drawn under the fill.
+pub(crate) fn draw_text(img: &DynamicImage, text: &str, options: &TextOptions) -> DynamicImage {
+    let mut canvas: RgbaImage = img.to_rgba8();
+    let (width, height) = canvas.dimensions();
+    let font = options.font.as_scaled(PxScale::from(options.size));
+    let max_width = options.max_width.unwrap_or((width as f32 - options.x).max(0.0));
+    let lines = wrap(&font, text, max_width);
+
+    // Fill coverage over the whole canvas, 0–1.
+    let mut coverage = vec![0f32; (width * height) as usize];
+    // Inked area, as min and max x and y.
+    let mut ink = (u32::MAX, u32::MAX, 0, 0);
+    let line_advance = options.size * options.line_height;
+    for (index, line) in lines.iter().enumerate() {
+        let line_width = text_width(&font, line);
+        let mut caret = options.x
+            + match options.align {
+                Align::Left => 0.0,
+                Align::Center => (max_width - line_width) / 2.0,
+                Align::Right => max_width - line_width,
+            };
+        let baseline = options.y + font.ascent() + index as f32 * line_advance;
+        let mut previous = None;
+        for c in line.chars() {
+            let id = font.glyph_id(c);
+            if let Some(previous) = previous {
+                caret += font.kern(previous, id);
+            }
+            let glyph = id.with_scale_and_position(options.size, point(caret, baseline));
+            caret += font.h_advance(id);
+            previous = Some(id);
+            let Some(outline) = font.outline_glyph(glyph) else { continue };
+            let bounds = outline.px_bounds();
+            outline.draw(|gx, gy, value| {
+                let (px, py) = (bounds.min.x as i64 + gx as i64, bounds.min.y as i64 + gy as i64);
+                if px >= 0 && py >= 0 && (px as u32) < width && (py as u32) < height {
+                    let (px, py) = (px as u32, py as u32);
+                    let slot = &mut coverage[(py * width + px) as usize];
+                    *slot = (*slot + value).min(1.0);
+                    ink = (ink.0.min(px), ink.1.min(py), ink.2.max(px), ink.3.max(py));
+                }
+            });
+        }
+    }
+
+    if options.stroke_width > 0 && ink.0 <= ink.2 {
+        // Dilate the coverage by a disk of the stroke width, around the ink.
+        let radius = options.stroke_width as i64;
+        let offsets: Vec<(i64, i64)> = (-radius..=radius)
+            .flat_map(|dy| (-radius..=radius).map(move |dx| (dx, dy)))
+            .filter(|(dx, dy)| dx * dx + dy * dy <= radius * radius)
+            .collect();
+        let (w, h) = (width as i64, height as i64);
+        for y in (ink.1 as i64 - radius).max(0)..(ink.3 as i64 + radius + 1).min(h) {
+            for x in (ink.0 as i64 - radius).max(0)..(ink.2 as i64 + radius + 1).min(w) {
+                let stroke = offsets
+                    .iter()
+                    .filter_map(|(dx, dy)| {
+                        let (sx, sy) = (x + dx, y + dy);
+                        (sx >= 0 && sy >= 0 && sx < w && sy < h).then(|| coverage[(sy * w + sx) as usize])
+                    })
+                    .fold(0.0, f32::max);
+                if stroke > 0.0 {
+                    blend_over(canvas.get_pixel_mut(x as u32, y as u32), options.stroke_color, stroke);
+                }
+            }
+        }
+    }
+    for (pixel, &value) in canvas.pixels_mut().zip(&coverage) {
+        if value > 0.0 {
+            blend_over(pixel, options.color, value);
+        }
+    }
+
+    let canvas = DynamicImage::ImageRgba8(canvas);
+    if img.color().has_alpha() { canvas } else { DynamicImage::ImageRgb8(canvas.to_rgb8()) }
+}
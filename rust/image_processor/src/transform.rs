@@ -0,0 +1,269 @@
+// Geometric operations applied between decoding and encoding.
+
+use crate::error;
+use image::{DynamicImage, GenericImageView};
+use wasm_bindgen::prelude::*;
+
+pub(crate) fn crop(img: &DynamicImage, x: u32, y: u32, width: u32, height: u32) -> Result<DynamicImage, JsValue> {
+    if width == 0 || height == 0 {
+        return Err(error::invalid_argument("Crop size must not be zero"));
+    }
+    let fits = |start: u32, size: u32, limit: u32| start.checked_add(size).is_some_and(|end| end <= limit);
+    if !fits(x, width, img.width()) || !fits(y, height, img.height()) {
+        return Err(error::invalid_argument("Crop rectangle is outside the image"));
+    }
+    Ok(img.crop_imm(x, y, width, height))
+}
+
+#[derive(Clone, Copy)]
+enum Gravity {
+    Center,
+    Top,
+    Bottom,
+    Left,
+    Right,
+    Entropy,
+}
+
+fn parse_gravity(gravity: &str) -> Result<Gravity, JsValue> {
+    match gravity.to_lowercase().as_str() {
+        "center" | "centre" => Ok(Gravity::Center),
+        "top" => Ok(Gravity::Top),
+        "bottom" => Ok(Gravity::Bottom),
+        "left" => Ok(Gravity::Left),
+        "right" => Ok(Gravity::Right),
+        "entropy" => Ok(Gravity::Entropy),
+        _ => Err(error::invalid_argument("Unsupported gravity")),
+    }
+}
+
+// Shannon entropy of a luma histogram, in bits.
+fn entropy(histogram: &[u32; 256]) -> f64 {
+    let total: u32 = histogram.iter().sum();
+    histogram
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+// Side of the working copy used to score entropy windows.
+const ENTROPY_SAMPLE_SIZE: u32 = 256;
+
+// Offset along the cropped axis of the window of `length` (out of `extent`)
+// with the most detail, scored on a small grayscale copy.
+fn entropy_offset(img: &DynamicImage, horizontal: bool, length: u32, extent: u32) -> u32 {
+    let sample = img.thumbnail(ENTROPY_SAMPLE_SIZE, ENTROPY_SAMPLE_SIZE).into_luma8();
+    let (width, height) = sample.dimensions();
+    let sample_extent = if horizontal { width } else { height };
+    let window = ((length as f64 / extent as f64) * sample_extent as f64).round().clamp(1.0, sample_extent as f64) as u32;
+
+    let mut best = (f64::MIN, 0);
+    for start in 0..=sample_extent - window {
+        let mut histogram = [0u32; 256];
+        let (x0, y0, w, h) = if horizontal { (start, 0, window, height) } else { (0, start, width, window) };
+        for y in y0..y0 + h {
+            for x in x0..x0 + w {
+                histogram[sample.get_pixel(x, y)[0] as usize] += 1;
+            }
+        }
+        let score = entropy(&histogram);
+        if score > best.0 {
+            best = (score, start);
+        }
+    }
+    let offset = (best.1 as f64 / sample_extent as f64 * extent as f64).round() as u32;
+    offset.min(extent - length)
+}
+
+// The largest `ratio` (width / height) region of `img`, placed by `gravity`.
+pub(crate) fn crop_to_aspect(img: &DynamicImage, ratio: f64, gravity: &str) -> Result<DynamicImage, JsValue> {
+    if !ratio.is_finite() || ratio <= 0.0 {
+        return Err(error::invalid_argument("Aspect ratio must be positive"));
+    }
+    let gravity = parse_gravity(gravity)?;
+    let (width, height) = img.dimensions();
+    // Crop whichever axis is too long for the ratio.
+    let horizontal = width as f64 / height as f64 > ratio;
+    let (length, extent) = if horizontal {
+        (((height as f64 * ratio).round() as u32).clamp(1, width), width)
+    } else {
+        (((width as f64 / ratio).round() as u32).clamp(1, height), height)
+    };
+
+    let slack = extent - length;
+    let offset = match (gravity, horizontal) {
+        (Gravity::Entropy, _) => entropy_offset(img, horizontal, length, extent),
+        (Gravity::Top, false) | (Gravity::Left, true) => 0,
+        (Gravity::Bottom, false) | (Gravity::Right, true) => slack,
+        _ => slack / 2,
+    };
+    Ok(if horizontal {
+        img.crop_imm(offset, 0, length, height)
+    } else {
+        img.crop_imm(0, offset, width, length)
+    })
+}
+
+// Parses "#rgb", "#rrggbb", "#rrggbbaa" or "transparent".
+pub(crate) fn parse_color(color: &str) -> Result<image::Rgba<u8>, JsValue> {
+    let invalid = || error::invalid_argument(format!("Invalid color: {}", color));
+    if color.eq_ignore_ascii_case("transparent") {
+        return Ok(image::Rgba([0, 0, 0, 0]));
+    }
+    let hex = color.strip_prefix('#').ok_or_else(invalid)?;
+    let digits: Vec<u8> = hex.chars().map(|c| c.to_digit(16).map(|d| d as u8)).collect::<Option<_>>().ok_or_else(invalid)?;
+    match digits.len() {
+        3 => Ok(image::Rgba([digits[0] * 17, digits[1] * 17, digits[2] * 17, 255])),
+        6 | 8 => {
+            let mut rgba = [255u8; 4];
+            for (channel, pair) in rgba.iter_mut().zip(digits.chunks(2)) {
+                *channel = pair[0] * 16 + pair[1];
+            }
+            Ok(image::Rgba(rgba))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+// Size of the canvas rotate produces for `img`.
+pub(crate) fn rotated_size(img: &DynamicImage, degrees: f64) -> (u32, u32) {
+    let (width, height) = (img.width() as f64, img.height() as f64);
+    let (sin, cos) = degrees.rem_euclid(360.0).to_radians().sin_cos();
+    let (sin, cos) = (sin.abs(), cos.abs());
+    let side = |value: f64| value.round().max(1.0) as u32;
+    (side(width * cos + height * sin), side(width * sin + height * cos))
+}
+
+// Rotates clockwise by `degrees` onto a canvas that fits the whole result,
+// filling the corners with `background`. Right angles are exact; other
+// angles are sampled bilinearly with premultiplied alpha, so edges blend
+// smoothly into the background.
+pub(crate) fn rotate(img: &DynamicImage, degrees: f64, background: image::Rgba<u8>) -> Result<DynamicImage, JsValue> {
+    if !degrees.is_finite() {
+        return Err(error::invalid_argument("Rotation angle must be finite"));
+    }
+    let turn = degrees.rem_euclid(360.0);
+    if turn == 0.0 {
+        return Ok(img.clone());
+    } else if turn == 90.0 {
+        return Ok(img.rotate90());
+    } else if turn == 180.0 {
+        return Ok(img.rotate180());
+    } else if turn == 270.0 {
+        return Ok(img.rotate270());
+    }
+
+    let source = img.to_rgba8();
+    let (width, height) = source.dimensions();
+    let (sin, cos) = turn.to_radians().sin_cos();
+    let (out_width, out_height) = rotated_size(img, degrees);
+    let (cx, cy) = (width as f64 / 2.0, height as f64 / 2.0);
+    let (ox, oy) = (out_width as f64 / 2.0, out_height as f64 / 2.0);
+
+    let premultiplied = |p: image::Rgba<u8>| {
+        let a = p[3] as f64 / 255.0;
+        [p[0] as f64 * a, p[1] as f64 * a, p[2] as f64 * a, p[3] as f64]
+    };
+    let fill = premultiplied(background);
+    let texel = |x: i64, y: i64| {
+        if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+            fill
+        } else {
+            premultiplied(*source.get_pixel(x as u32, y as u32))
+        }
+    };
+
+    let rotated = image::RgbaImage::from_fn(out_width, out_height, |x, y| {
+        // Inverse mapping from the output pixel center into the source.
+        let (dx, dy) = (x as f64 + 0.5 - ox, y as f64 + 0.5 - oy);
+        let sx = dx * cos + dy * sin + cx - 0.5;
+        let sy = -dx * sin + dy * cos + cy - 0.5;
+        let (x0, y0) = (sx.floor(), sy.floor());
+        let (fx, fy) = (sx - x0, sy - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let mut value = [0.0; 4];
+        for (tx, ty, weight) in [
+            (x0, y0, (1.0 - fx) * (1.0 - fy)),
+            (x0 + 1, y0, fx * (1.0 - fy)),
+            (x0, y0 + 1, (1.0 - fx) * fy),
+            (x0 + 1, y0 + 1, fx * fy),
+        ] {
+            for (sum, channel) in value.iter_mut().zip(texel(tx, ty)) {
+                *sum += channel * weight;
+            }
+        }
+        let alpha = value[3];
+        let unpremultiply = |c: f64| if alpha > 0.0 { (c * 255.0 / alpha).round().clamp(0.0, 255.0) as u8 } else { 0 };
+        image::Rgba([unpremultiply(value[0]), unpremultiply(value[1]), unpremultiply(value[2]), alpha.round() as u8])
+    });
+
+    let rotated = DynamicImage::ImageRgba8(rotated);
+    Ok(if img.color().has_alpha() || background[3] < 255 { rotated } else { DynamicImage::ImageRgb8(rotated.to_rgb8()) })
+}
+
+#[derive(Clone, Copy)]
+enum Fit {
+    Cover,
+    Contain,
+    Fill,
+    Inside,
+    Outside,
+}
+
+fn parse_fit(fit: &str) -> Result<Fit, JsValue> {
+    match fit.to_lowercase().as_str() {
+        "cover" => Ok(Fit::Cover),
+        "contain" => Ok(Fit::Contain),
+        "fill" => Ok(Fit::Fill),
+        "inside" => Ok(Fit::Inside),
+        "outside" => Ok(Fit::Outside),
+        _ => Err(error::invalid_argument("Unsupported fit mode")),
+    }
+}
+
+// Resizes into a `width` × `height` box following CSS object-fit: "cover"
+// fills the box and crops the overflow around the center, "contain" fits
+// inside and letterboxes onto `background`, "fill" stretches to the box.
+// "inside" and "outside" keep the aspect ratio with the image no larger,
+// respectively no smaller, than the box, and return that size uncropped.
+pub(crate) fn fit(
+    img: &DynamicImage,
+    width: u32,
+    height: u32,
+    fit: &str,
+    background: image::Rgba<u8>,
+) -> Result<DynamicImage, JsValue> {
+    if width == 0 || height == 0 {
+        return Err(error::invalid_argument("Target size must not be zero"));
+    }
+    let filter = image::imageops::FilterType::Lanczos3;
+    Ok(match parse_fit(fit)? {
+        Fit::Fill => img.resize_exact(width, height, filter),
+        Fit::Inside => img.resize(width, height, filter),
+        Fit::Outside => {
+            let scale = (width as f64 / img.width() as f64).max(height as f64 / img.height() as f64);
+            let scaled = |side: u32| ((side as f64 * scale).round() as u32).max(1);
+            img.resize_exact(scaled(img.width()), scaled(img.height()), filter)
+        }
+        Fit::Cover => img.resize_to_fill(width, height, filter),
+        Fit::Contain => extend(&img.resize(width, height, filter), width, height, background)?,
+    })
+}
+
+// Centers `img` on a `width` × `height` canvas of `background`. The canvas
+// must be at least as large as the image on both sides.
+pub(crate) fn extend(img: &DynamicImage, width: u32, height: u32, background: image::Rgba<u8>) -> Result<DynamicImage, JsValue> {
+    if width < img.width() || height < img.height() {
+        return Err(error::invalid_argument("Target size is smaller than the image"));
+    }
+    let mut canvas = image::RgbaImage::from_pixel(width, height, background);
+    let x = (width - img.width()) / 2;
+    let y = (height - img.height()) / 2;
+    image::imageops::overlay(&mut canvas, &img.to_rgba8(), x as i64, y as i64);
+    let canvas = DynamicImage::ImageRgba8(canvas);
+    Ok(if img.color().has_alpha() || background[3] < 255 { canvas } else { DynamicImage::ImageRgb8(canvas.to_rgb8()) })
+}
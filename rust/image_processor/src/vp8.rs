@@ -0,0 +1,1268 @@
+// Lossy WebP: a VP8 key frame encoder (RFC 6386), since the image crate
+// and the permissively licensed Rust codecs only write lossless WebP. Each
+// macroblock is predicted whole, with the best of the DC, vertical,
+// horizontal and TrueMotion modes for luma and chroma alike, and the DC
+// terms of its luma blocks go through the second-order Walsh-Hadamard
+// transform. The 4 × 4 subblock predictions, which help on fine detail,
+// are not tried. Token probabilities are adapted to the frame from a
+// counting pass over the same tokens, and the normal loop filter is set
+// from the quantizer. Alpha is kept losslessly in an ALPH chunk.
+
+use crate::animation::{push_chunk, push_u24};
+use crate::error;
+use image::codecs::webp::WebPEncoder;
+use image::ColorType;
+use wasm_bindgen::prelude::*;
+
+// Largest width and height a VP8 frame header can hold.
+pub(crate) const MAX_DIMENSION: u32 = 16383;
+
+// Largest size of the first partition, which holds the modes.
+const MAX_FIRST_PARTITION: usize = (1 << 19) - 1;
+
+// Largest coefficient level the tokens can code.
+const MAX_LEVEL: i32 = 2047;
+
+// Token planes, the first index of the coefficient probabilities.
+const PLANE_Y_AFTER_Y2: usize = 0;
+const PLANE_Y2: usize = 1;
+const PLANE_CHROMA: usize = 2;
+
+type Block = [i16; 16];
+type TokenProbs = [[[[u8; 11]; 3]; 8]; 4];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Dc,
+    Vertical,
+    Horizontal,
+    TrueMotion,
+}
+
+const MODES: [Mode; 4] = [Mode::Dc, Mode::Vertical, Mode::Horizontal, Mode::TrueMotion];
+
+// Step sizes of the DC and AC coefficients of one block type, and the
+// rounding offsets added before dividing by them, in 256ths of a step.
+#[derive(Clone, Copy)]
+struct Quantizer {
+    dc: i32,
+    ac: i32,
+    dc_bias: i32,
+    ac_bias: i32,
+}
+
+struct Quantizers {
+    y: Quantizer,
+    y2: Quantizer,
+    uv: Quantizer,
+}
+
+impl Quantizers {
+    // The decoder's step sizes for quantizer `index` (RFC 6386, 14.1), with
+    // libwebp's rounding offsets.
+    fn new(index: usize) -> Self {
+        let (dc, ac) = (DC_QUANT[index] as i32, AC_QUANT[index] as i32);
+        Quantizers {
+            y: Quantizer { dc, ac, dc_bias: 96, ac_bias: 110 },
+            y2: Quantizer { dc: dc * 2, ac: (ac * 155 / 100).max(8), dc_bias: 96, ac_bias: 108 },
+            uv: Quantizer { dc: dc.min(132), ac, dc_bias: 110, ac_bias: 115 },
+        }
+    }
+}
+
+// Quantizer index for `quality`, on libwebp's curve, which spends the top
+// quarter of the scale on the finest steps.
+fn quantizer_index(quality: u8) -> usize {
+    let quality = quality.min(100) as f64 / 100.0;
+    let linear = if quality < 0.75 { quality * 2.0 / 3.0 } else { 2.0 * quality - 1.0 };
+    (127.0 * (1.0 - linear.cbrt())).round() as usize
+}
+
+// Loop filter level for quantizer `index`: coarser steps leave stronger
+// block edges to smooth.
+fn filter_level(index: usize) -> u32 {
+    (index as u32 * 3 / 8).min(63)
+}
+
+// One plane, padded to whole macroblocks.
+struct Plane {
+    data: Vec<u8>,
+    stride: usize,
+}
+
+impl Plane {
+    fn new(width: usize, height: usize) -> Self {
+        Plane { data: vec![0; width * height], stride: width }
+    }
+
+    fn at(&self, x: usize, y: usize) -> u8 {
+        self.data[y * self.stride + x]
+    }
+}
+
+// BT.601 studio-range Y'CbCr with 2 × 2 averaged chroma, with libwebp's
+// integer coefficients, padded to `mb_width` × `mb_height` macroblocks by
+// repeating the last row and column.
+fn to_yuv(pixels: &[u8], width: usize, height: usize, channels: usize, mb_width: usize, mb_height: usize) -> [Plane; 3] {
+    let pixel = |x: usize, y: usize| {
+        let i = (y.min(height - 1) * width + x.min(width - 1)) * channels;
+        (pixels[i] as i32, pixels[i + 1] as i32, pixels[i + 2] as i32)
+    };
+    let mut luma = Plane::new(mb_width * 16, mb_height * 16);
+    for y in 0..mb_height * 16 {
+        for x in 0..mb_width * 16 {
+            let (r, g, b) = pixel(x, y);
+            luma.data[y * luma.stride + x] = ((16839 * r + 33059 * g + 6420 * b + (16 << 16) + (1 << 15)) >> 16) as u8;
+        }
+    }
+    let (mut u, mut v) = (Plane::new(mb_width * 8, mb_height * 8), Plane::new(mb_width * 8, mb_height * 8));
+    for y in 0..mb_height * 8 {
+        for x in 0..mb_width * 8 {
+            let (mut r, mut g, mut b) = (0, 0, 0);
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let p = pixel(x * 2 + dx, y * 2 + dy);
+                (r, g, b) = (r + p.0, g + p.1, b + p.2);
+            }
+            let i = y * u.stride + x;
+            u.data[i] = ((-9719 * r - 19081 * g + 28800 * b + (128 << 18) + (1 << 17)) >> 18).clamp(0, 255) as u8;
+            v.data[i] = ((28800 * r - 24116 * g - 4684 * b + (128 << 18) + (1 << 17)) >> 18).clamp(0, 255) as u8;
+        }
+    }
+    [luma, u, v]
+}
+
+// The `mode` prediction of the `size`-pixel square block at `x`, `y` from
+// the reconstructed pixels around it. Outside the frame, the row above
+// reads 127 and the column to the left 129, and DC prediction averages only
+// the edges inside it, as the decoder does.
+fn predict(plane: &Plane, x: usize, y: usize, size: usize, mode: Mode) -> [u8; 256] {
+    let above = |i: usize| if y == 0 { 127 } else { plane.at(x + i, y - 1) as i32 };
+    let left = |j: usize| if x == 0 { 129 } else { plane.at(x - 1, y + j) as i32 };
+    let corner = if y == 0 {
+        127
+    } else if x == 0 {
+        129
+    } else {
+        plane.at(x - 1, y - 1) as i32
+    };
+    let shift = size.trailing_zeros();
+    let half = size as i32 / 2;
+    let dc = match (x > 0, y > 0) {
+        (true, true) => ((0..size).map(above).sum::<i32>() + (0..size).map(left).sum::<i32>() + size as i32) >> (shift + 1),
+        (false, true) => ((0..size).map(above).sum::<i32>() + half) >> shift,
+        (true, false) => ((0..size).map(left).sum::<i32>() + half) >> shift,
+        (false, false) => 128,
+    };
+    let mut prediction = [0; 256];
+    for j in 0..size {
+        for i in 0..size {
+            prediction[j * size + i] = match mode {
+                Mode::Dc => dc,
+                Mode::Vertical => above(i),
+                Mode::Horizontal => left(j),
+                Mode::TrueMotion => (left(j) + above(i) - corner).clamp(0, 255),
+            } as u8;
+        }
+    }
+    prediction
+}
+
+// The mode whose predictions are closest to the source, in squared error
+// summed over the planes of `pairs`, each a source and its reconstruction.
+fn best_mode(pairs: &[(&Plane, &Plane)], x: usize, y: usize, size: usize) -> Mode {
+    let error = |mode: Mode| -> u64 {
+        pairs
+            .iter()
+            .map(|(source, recon)| {
+                let prediction = predict(recon, x, y, size, mode);
+                (0..size * size)
+                    .map(|k| (source.at(x + k % size, y + k / size) as i64 - prediction[k] as i64).pow(2) as u64)
+                    .sum::<u64>()
+            })
+            .sum()
+    };
+    MODES.into_iter().min_by_key(|&mode| error(mode)).unwrap()
+}
+
+// Forward 4 × 4 DCT of a raster-order residual, as libvpx computes it.
+fn fdct(input: &[i32; 16]) -> [i32; 16] {
+    let mut temp = [0; 16];
+    for i in 0..4 {
+        let row = &input[i * 4..i * 4 + 4];
+        let a = (row[0] + row[3]) * 8;
+        let b = (row[1] + row[2]) * 8;
+        let c = (row[1] - row[2]) * 8;
+        let d = (row[0] - row[3]) * 8;
+        temp[i * 4] = a + b;
+        temp[i * 4 + 2] = a - b;
+        temp[i * 4 + 1] = (c * 2217 + d * 5352 + 14500) >> 12;
+        temp[i * 4 + 3] = (d * 2217 - c * 5352 + 7500) >> 12;
+    }
+    let mut output = [0; 16];
+    for i in 0..4 {
+        let a = temp[i] + temp[12 + i];
+        let b = temp[4 + i] + temp[8 + i];
+        let c = temp[4 + i] - temp[8 + i];
+        let d = temp[i] - temp[12 + i];
+        output[i] = (a + b + 7) >> 4;
+        output[8 + i] = (a - b + 7) >> 4;
+        output[4 + i] = ((c * 2217 + d * 5352 + 12000) >> 16) + (d != 0) as i32;
+        output[12 + i] = (d * 2217 - c * 5352 + 51000) >> 16;
+    }
+    output
+}
+
+// Forward Walsh-Hadamard transform of the 16 luma DC terms, as libvpx
+// computes it.
+fn fwht(input: &[i32; 16]) -> [i32; 16] {
+    let mut temp = [0; 16];
+    for i in 0..4 {
+        let row = &input[i * 4..i * 4 + 4];
+        let a = (row[0] + row[2]) * 4;
+        let d = (row[1] + row[3]) * 4;
+        let c = (row[1] - row[3]) * 4;
+        let b = (row[0] - row[2]) * 4;
+        temp[i * 4] = a + d + (a != 0) as i32;
+        temp[i * 4 + 1] = b + c;
+        temp[i * 4 + 2] = b - c;
+        temp[i * 4 + 3] = a - d;
+    }
+    let mut output = [0; 16];
+    for i in 0..4 {
+        let a = temp[i] + temp[8 + i];
+        let d = temp[4 + i] + temp[12 + i];
+        let c = temp[4 + i] - temp[12 + i];
+        let b = temp[i] - temp[8 + i];
+        for (row, value) in [a + d, b + c, b - c, a - d].into_iter().enumerate() {
+            let value = value + (value < 0) as i32;
+            output[row * 4 + i] = (value + 3) >> 3;
+        }
+    }
+    output
+}
+
+// The decoder's inverse DCT (RFC 6386, 14.3), exactly, so that prediction
+// works from the pixels it will see.
+fn idct(input: &[i32; 16]) -> [i32; 16] {
+    const C1: i64 = 20091;
+    const C2: i64 = 35468;
+    let mut block = input.map(i64::from);
+    for i in 0..4 {
+        let a = block[i] + block[8 + i];
+        let b = block[i] - block[8 + i];
+        let c = ((block[4 + i] * C2) >> 16) - (block[12 + i] + ((block[12 + i] * C1) >> 16));
+        let d = (block[4 + i] + ((block[4 + i] * C1) >> 16)) + ((block[12 + i] * C2) >> 16);
+        block[i] = a + d;
+        block[4 + i] = b + c;
+        block[8 + i] = b - c;
+        block[12 + i] = a - d;
+    }
+    let mut output = [0; 16];
+    for i in 0..4 {
+        let row = &block[i * 4..i * 4 + 4];
+        let a = row[0] + row[2];
+        let b = row[0] - row[2];
+        let c = ((row[1] * C2) >> 16) - (row[3] + ((row[3] * C1) >> 16));
+        let d = (row[1] + ((row[1] * C1) >> 16)) + ((row[3] * C2) >> 16);
+        output[i * 4] = ((a + d + 4) >> 3) as i32;
+        output[i * 4 + 1] = ((b + c + 4) >> 3) as i32;
+        output[i * 4 + 2] = ((b - c + 4) >> 3) as i32;
+        output[i * 4 + 3] = ((a - d + 4) >> 3) as i32;
+    }
+    output
+}
+
+// The decoder's inverse Walsh-Hadamard transform (RFC 6386, 14.3).
+fn iwht(input: &[i32; 16]) -> [i32; 16] {
+    let mut block = *input;
+    for i in 0..4 {
+        let a = block[i] + block[12 + i];
+        let b = block[4 + i] + block[8 + i];
+        let c = block[4 + i] - block[8 + i];
+        let d = block[i] - block[12 + i];
+        block[i] = a + b;
+        block[4 + i] = c + d;
+        block[8 + i] = a - b;
+        block[12 + i] = d - c;
+    }
+    let mut output = [0; 16];
+    for i in 0..4 {
+        let row = &block[i * 4..i * 4 + 4];
+        let a = row[0] + row[3];
+        let b = row[1] + row[2];
+        let c = row[1] - row[2];
+        let d = row[0] - row[3];
+        output[i * 4] = (a + b + 3) >> 3;
+        output[i * 4 + 1] = (c + d + 3) >> 3;
+        output[i * 4 + 2] = (a - b + 3) >> 3;
+        output[i * 4 + 3] = (d - c + 3) >> 3;
+    }
+    output
+}
+
+// Quantizes the raster-order `coefficients` from zigzag position `first`
+// on and returns the levels in zigzag order. The coefficients are replaced
+// with the values the decoder will reconstruct.
+fn quantize(coefficients: &mut [i32; 16], quantizer: Quantizer, first: usize) -> Block {
+    let mut levels = [0; 16];
+    for (n, &i) in ZIGZAG.iter().enumerate().skip(first) {
+        let (step, bias) = if n == 0 { (quantizer.dc, quantizer.dc_bias) } else { (quantizer.ac, quantizer.ac_bias) };
+        let level = ((coefficients[i].abs() * 256 + step * bias) / (step * 256)).min(MAX_LEVEL);
+        let level = if coefficients[i] < 0 { -level } else { level };
+        levels[n] = level as i16;
+        coefficients[i] = level * step;
+    }
+    levels
+}
+
+// Adds `residual` to the 4 × 4 block of `prediction`, `size` wide, at `bx`,
+// `by`, and stores it at `x` + `bx`, `y` + `by` of `recon`.
+#[allow(clippy::too_many_arguments)]
+fn reconstruct(recon: &mut Plane, x: usize, y: usize, prediction: &[u8; 256], size: usize, bx: usize, by: usize, residual: &[i32; 16]) {
+    for j in 0..4 {
+        for i in 0..4 {
+            let value = prediction[(by + j) * size + bx + i] as i32 + residual[j * 4 + i];
+            recon.data[(y + by + j) * recon.stride + x + bx + i] = value.clamp(0, 255) as u8;
+        }
+    }
+}
+
+// Residual of the 4 × 4 block at `bx`, `by` of the `size`-pixel block at
+// `x`, `y`.
+fn residual(source: &Plane, x: usize, y: usize, prediction: &[u8; 256], size: usize, bx: usize, by: usize) -> [i32; 16] {
+    let mut residual = [0; 16];
+    for (k, value) in residual.iter_mut().enumerate() {
+        let (i, j) = (bx + k % 4, by + k / 4);
+        *value = source.at(x + i, y + j) as i32 - prediction[j * size + i] as i32;
+    }
+    residual
+}
+
+struct Macroblock {
+    luma: Mode,
+    chroma: Mode,
+    // Quantized levels in zigzag order: the Y2 block, the 16 luma blocks in
+    // raster order, then the four U and the four V blocks.
+    blocks: [Block; 25],
+}
+
+impl Macroblock {
+    fn is_empty(&self) -> bool {
+        self.blocks.iter().all(|block| block.iter().all(|&level| level == 0))
+    }
+}
+
+// Predicts, transforms and quantizes the macroblock at `mb_x`, `mb_y`, and
+// reconstructs it into `recon` as the decoder will.
+fn encode_macroblock(source: &[Plane; 3], recon: &mut [Plane; 3], mb_x: usize, mb_y: usize, quantizers: &Quantizers) -> Macroblock {
+    let mut blocks = [[0; 16]; 25];
+
+    let (x, y) = (mb_x * 16, mb_y * 16);
+    let luma = best_mode(&[(&source[0], &recon[0])], x, y, 16);
+    let prediction = predict(&recon[0], x, y, 16, luma);
+    let mut coefficients = [[0; 16]; 16];
+    let mut dc = [0; 16];
+    for (k, block) in coefficients.iter_mut().enumerate() {
+        *block = fdct(&residual(&source[0], x, y, &prediction, 16, k % 4 * 4, k / 4 * 4));
+        dc[k] = block[0];
+    }
+    let mut y2 = fwht(&dc);
+    blocks[0] = quantize(&mut y2, quantizers.y2, 0);
+    let dc = iwht(&y2);
+    for (k, block) in coefficients.iter_mut().enumerate() {
+        blocks[1 + k] = quantize(block, quantizers.y, 1);
+        block[0] = dc[k];
+        reconstruct(&mut recon[0], x, y, &prediction, 16, k % 4 * 4, k / 4 * 4, &idct(block));
+    }
+
+    let (x, y) = (mb_x * 8, mb_y * 8);
+    let chroma = best_mode(&[(&source[1], &recon[1]), (&source[2], &recon[2])], x, y, 8);
+    for plane in 1..3 {
+        let prediction = predict(&recon[plane], x, y, 8, chroma);
+        for k in 0..4 {
+            let (bx, by) = (k % 2 * 4, k / 2 * 4);
+            let mut block = fdct(&residual(&source[plane], x, y, &prediction, 8, bx, by));
+            blocks[13 + plane * 4 + k] = quantize(&mut block, quantizers.uv, 0);
+            reconstruct(&mut recon[plane], x, y, &prediction, 8, bx, by, &idct(&block));
+        }
+    }
+
+    Macroblock { luma, chroma, blocks }
+}
+
+// Boolean entropy coder (RFC 6386, 7.3).
+struct BoolEncoder {
+    out: Vec<u8>,
+    range: u32,
+    bottom: u32,
+    bit_count: i32,
+}
+
+impl BoolEncoder {
+    fn new() -> Self {
+        BoolEncoder { out: Vec::new(), range: 255, bottom: 0, bit_count: 24 }
+    }
+
+    // Propagates a carry into the bytes already written.
+    fn carry(&mut self) {
+        for byte in self.out.iter_mut().rev() {
+            if *byte == 255 {
+                *byte = 0;
+            } else {
+                *byte += 1;
+                break;
+            }
+        }
+    }
+
+    // Codes `bit`, which is false with probability `prob` / 256.
+    fn put(&mut self, bit: bool, prob: u8) {
+        let split = 1 + (((self.range - 1) * prob as u32) >> 8);
+        if bit {
+            self.bottom += split;
+            self.range -= split;
+        } else {
+            self.range = split;
+        }
+        while self.range < 128 {
+            self.range <<= 1;
+            if self.bottom & (1 << 31) != 0 {
+                self.carry();
+            }
+            self.bottom <<= 1;
+            self.bit_count -= 1;
+            if self.bit_count == 0 {
+                self.out.push((self.bottom >> 24) as u8);
+                self.bottom &= (1 << 24) - 1;
+                self.bit_count = 8;
+            }
+        }
+    }
+
+    // `value` as `bits` even-odds bits, most significant first.
+    fn literal(&mut self, value: u32, bits: u32) {
+        for bit in (0..bits).rev() {
+            self.put((value >> bit) & 1 == 1, 128);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let mut value = self.bottom;
+        let mut count = self.bit_count;
+        if value & (1 << (32 - count)) != 0 {
+            self.carry();
+        }
+        value <<= count & 7;
+        count >>= 3;
+        while count > 0 {
+            value <<= 8;
+            count -= 1;
+        }
+        for _ in 0..4 {
+            self.out.push((value >> 24) as u8);
+            value <<= 8;
+        }
+        self.out
+    }
+}
+
+// Token output: the same walk over the coefficients first counts the
+// branches taken, to adapt the probabilities, then codes them.
+trait Emit {
+    // A token tree branch, coded with the probability at `plane`, `band`,
+    // `context` and `node`.
+    fn branch(&mut self, plane: usize, band: usize, context: usize, node: usize, bit: bool);
+    // A sign or extra bit with a fixed probability.
+    fn bit(&mut self, bit: bool, prob: u8);
+}
+
+struct Counter {
+    counts: [[[[[u32; 2]; 11]; 3]; 8]; 4],
+}
+
+impl Emit for Counter {
+    fn branch(&mut self, plane: usize, band: usize, context: usize, node: usize, bit: bool) {
+        self.counts[plane][band][context][node][bit as usize] += 1;
+    }
+
+    fn bit(&mut self, _: bool, _: u8) {}
+}
+
+struct Writer {
+    encoder: BoolEncoder,
+    probs: TokenProbs,
+}
+
+impl Emit for Writer {
+    fn branch(&mut self, plane: usize, band: usize, context: usize, node: usize, bit: bool) {
+        self.encoder.put(bit, self.probs[plane][band][context][node]);
+    }
+
+    fn bit(&mut self, bit: bool, prob: u8) {
+        self.encoder.put(bit, prob);
+    }
+}
+
+// Writes the tokens of one block from zigzag position `first`; `context`
+// counts the neighbors above and to the left with nonzero levels. Returns
+// whether this block has any.
+fn write_block(out: &mut impl Emit, plane: usize, first: usize, context: usize, levels: &Block) -> bool {
+    let Some(last) = (first..16).rev().find(|&n| levels[n] != 0) else {
+        out.branch(plane, COEFF_BANDS[first], context, 0, false);
+        return false;
+    };
+    let mut context = context;
+    let mut after_zero = false;
+    for (n, &level) in levels.iter().enumerate().take(last + 1).skip(first) {
+        let band = COEFF_BANDS[n];
+        let mut branch = |node: usize, bit: bool| out.branch(plane, band, context, node, bit);
+        // No end of block can follow a zero.
+        if !after_zero {
+            branch(0, true);
+        }
+        if level == 0 {
+            branch(1, false);
+            (after_zero, context) = (true, 0);
+            continue;
+        }
+        branch(1, true);
+        let value = level.unsigned_abs() as u32;
+        if value == 1 {
+            branch(2, false);
+        } else {
+            branch(2, true);
+            if value <= 4 {
+                branch(3, false);
+                branch(4, value != 2);
+                if value != 2 {
+                    branch(5, value == 4);
+                }
+            } else {
+                branch(3, true);
+                let category = match value {
+                    5..=6 => 0,
+                    7..=10 => 1,
+                    11..=18 => 2,
+                    19..=34 => 3,
+                    35..=66 => 4,
+                    _ => 5,
+                };
+                if category < 2 {
+                    branch(6, false);
+                    branch(7, category == 1);
+                } else {
+                    branch(6, true);
+                    if category < 4 {
+                        branch(8, false);
+                        branch(9, category == 3);
+                    } else {
+                        branch(8, true);
+                        branch(10, category == 5);
+                    }
+                }
+                let probs = &PROB_DCT_CAT[category];
+                let bits = probs.iter().take_while(|&&prob| prob > 0).count();
+                let extra = value - DCT_CAT_BASE[category];
+                for (i, &prob) in probs[..bits].iter().enumerate() {
+                    out.bit((extra >> (bits - 1 - i)) & 1 == 1, prob);
+                }
+            }
+        }
+        out.bit(level < 0, 128);
+        (after_zero, context) = (false, if value == 1 { 1 } else { 2 });
+    }
+    if last < 15 {
+        out.branch(plane, COEFF_BANDS[last + 1], context, 0, false);
+    }
+    true
+}
+
+// Writes the tokens of every macroblock, skipping empty ones when `skip`
+// is set. The contexts track, for the blocks above and to the left,
+// whether they had nonzero levels: four luma columns or rows, two for U,
+// two for V, then Y2.
+fn write_tokens(out: &mut impl Emit, macroblocks: &[Macroblock], mb_width: usize, skip: bool) {
+    let mut above = vec![[false; 9]; mb_width];
+    for row in macroblocks.chunks(mb_width) {
+        let mut left = [false; 9];
+        for (mb, above) in row.iter().zip(above.iter_mut()) {
+            if skip && mb.is_empty() {
+                (*above, left) = ([false; 9], [false; 9]);
+                continue;
+            }
+            let nonzero = write_block(out, PLANE_Y2, 0, above[8] as usize + left[8] as usize, &mb.blocks[0]);
+            (above[8], left[8]) = (nonzero, nonzero);
+            for k in 0..16 {
+                let (i, j) = (k % 4, k / 4);
+                let nonzero = write_block(out, PLANE_Y_AFTER_Y2, 1, above[i] as usize + left[j] as usize, &mb.blocks[1 + k]);
+                (above[i], left[j]) = (nonzero, nonzero);
+            }
+            for k in 0..8 {
+                let (i, j) = (4 + k / 4 * 2 + k % 2, 4 + k / 4 * 2 + k % 4 / 2);
+                let nonzero = write_block(out, PLANE_CHROMA, 0, above[i] as usize + left[j] as usize, &mb.blocks[17 + k]);
+                (above[i], left[j]) = (nonzero, nonzero);
+            }
+        }
+    }
+}
+
+// Bits to code `bit` when it is false with probability `prob` / 256.
+fn cost(bit: bool, prob: u8) -> f64 {
+    let p = if bit { 256 - prob as u32 } else { prob as u32 };
+    -(p as f64 / 256.0).log2()
+}
+
+// Token probabilities for the frame: each default is replaced by the one
+// its branch counts call for when that saves more than the update costs.
+fn adapted_probs(counter: &Counter) -> TokenProbs {
+    let mut probs = COEFF_PROBS;
+    for (i, planes) in counter.counts.iter().enumerate() {
+        for (j, bands) in planes.iter().enumerate() {
+            for (k, contexts) in bands.iter().enumerate() {
+                for (l, &[zeros, ones]) in contexts.iter().enumerate() {
+                    let total = zeros as u64 + ones as u64;
+                    if total == 0 {
+                        continue;
+                    }
+                    let old = probs[i][j][k][l];
+                    let new = ((zeros as u64 * 256 + total / 2) / total).clamp(1, 255) as u8;
+                    let update = COEFF_UPDATE_PROBS[i][j][k][l];
+                    let saving = zeros as f64 * (cost(false, old) - cost(false, new))
+                        + ones as f64 * (cost(true, old) - cost(true, new))
+                        - (8.0 + cost(true, update) - cost(false, update));
+                    if saving > 0.0 {
+                        probs[i][j][k][l] = new;
+                    }
+                }
+            }
+        }
+    }
+    probs
+}
+
+// The prediction modes of a macroblock, on the key frame trees.
+fn write_modes(encoder: &mut BoolEncoder, mb: &Macroblock) {
+    encoder.put(true, 145); // Not subblock prediction
+    let (first, second) = match mb.luma {
+        Mode::Dc => (false, false),
+        Mode::Vertical => (false, true),
+        Mode::Horizontal => (true, false),
+        Mode::TrueMotion => (true, true),
+    };
+    encoder.put(first, 156);
+    encoder.put(second, if first { 128 } else { 163 });
+    encoder.put(mb.chroma != Mode::Dc, 142);
+    if mb.chroma != Mode::Dc {
+        encoder.put(mb.chroma != Mode::Vertical, 114);
+        if mb.chroma != Mode::Vertical {
+            encoder.put(mb.chroma == Mode::TrueMotion, 183);
+        }
+    }
+}
+
+// A VP8 key frame of `pixels` at quantizer `index`.
+fn frame(pixels: &[u8], width: usize, height: usize, channels: usize, index: usize) -> Result<Vec<u8>, JsValue> {
+    let (mb_width, mb_height) = (width.div_ceil(16), height.div_ceil(16));
+    let source = to_yuv(pixels, width, height, channels, mb_width, mb_height);
+    let mut recon = [
+        Plane::new(mb_width * 16, mb_height * 16),
+        Plane::new(mb_width * 8, mb_height * 8),
+        Plane::new(mb_width * 8, mb_height * 8),
+    ];
+    let quantizers = Quantizers::new(index);
+    let mut macroblocks = Vec::with_capacity(mb_width * mb_height);
+    for mb_y in 0..mb_height {
+        for mb_x in 0..mb_width {
+            macroblocks.push(encode_macroblock(&source, &mut recon, mb_x, mb_y, &quantizers));
+        }
+    }
+
+    // The skip flag is only coded when some macroblock can use it.
+    let empty = macroblocks.iter().filter(|mb| mb.is_empty()).count();
+    let skip_prob = (empty > 0).then(|| {
+        let coded = (macroblocks.len() - empty) as u64;
+        ((coded * 256 + macroblocks.len() as u64 / 2) / macroblocks.len() as u64).clamp(1, 255) as u8
+    });
+    let mut counter = Counter { counts: [[[[[0; 2]; 11]; 3]; 8]; 4] };
+    write_tokens(&mut counter, &macroblocks, mb_width, skip_prob.is_some());
+    let probs = adapted_probs(&counter);
+
+    let mut header = BoolEncoder::new();
+    header.literal(0, 1); // Color space
+    header.literal(0, 1); // Clamping required
+    // One segment, with zero deltas, rather than no segmentation, which the
+    // image crate's decoder reads with quantizer 0 whatever the header says.
+    header.literal(1, 1); // Segmentation
+    header.literal(0, 1); // No segment map
+    header.literal(1, 1); // Segment data follows
+    header.literal(0, 1); // Deltas
+    header.literal(0, 8); // No quantizer or loop filter deltas
+    header.literal(0, 1); // Normal loop filter
+    header.literal(filter_level(index), 6);
+    header.literal(0, 3); // Sharpness
+    header.literal(0, 1); // No loop filter deltas
+    header.literal(0, 2); // One token partition
+    header.literal(index as u32, 7);
+    header.literal(0, 5); // No quantizer deltas
+    header.literal(0, 1); // Probabilities are not kept
+    for (i, planes) in probs.iter().enumerate() {
+        for (j, bands) in planes.iter().enumerate() {
+            for (k, contexts) in bands.iter().enumerate() {
+                for (l, &prob) in contexts.iter().enumerate() {
+                    let update = prob != COEFF_PROBS[i][j][k][l];
+                    header.put(update, COEFF_UPDATE_PROBS[i][j][k][l]);
+                    if update {
+                        header.literal(prob as u32, 8);
+                    }
+                }
+            }
+        }
+    }
+    header.literal(skip_prob.is_some() as u32, 1);
+    if let Some(prob) = skip_prob {
+        header.literal(prob as u32, 8);
+    }
+    for mb in &macroblocks {
+        if let Some(prob) = skip_prob {
+            header.put(mb.is_empty(), prob);
+        }
+        write_modes(&mut header, mb);
+    }
+    let header = header.finish();
+    if header.len() > MAX_FIRST_PARTITION {
+        return Err(error::size_limit("The image has too many macroblocks for a VP8 frame"));
+    }
+
+    let mut writer = Writer { encoder: BoolEncoder::new(), probs };
+    write_tokens(&mut writer, &macroblocks, mb_width, skip_prob.is_some());
+    let tokens = writer.encoder.finish();
+
+    let mut frame = Vec::with_capacity(10 + header.len() + tokens.len());
+    push_u24(&mut frame, (header.len() as u32) << 5 | 0x10); // Key frame, version 0, shown
+    frame.extend_from_slice(&[0x9D, 0x01, 0x2A]);
+    frame.extend_from_slice(&(width as u16).to_le_bytes());
+    frame.extend_from_slice(&(height as u16).to_le_bytes());
+    frame.extend_from_slice(&header);
+    frame.extend_from_slice(&tokens);
+    Ok(frame)
+}
+
+// An ALPH chunk payload: the alpha plane as a VP8L image stream without
+// its header, read from the green channel, or raw when that is smaller.
+fn alpha_payload(alpha: &[u8], width: u32, height: u32) -> Result<Vec<u8>, JsValue> {
+    let mut file = Vec::new();
+    WebPEncoder::new_lossless(&mut file)
+        .encode(alpha, width, height, ColorType::L8)
+        .map_err(|e| error::encode_failed("WebP", e))?;
+    // The encoder writes a simple file: the RIFF header, then the VP8L
+    // chunk header, then the 5-byte image header.
+    let length = u32::from_le_bytes(file[16..20].try_into().unwrap()) as usize;
+    let stream = &file[25..20 + length];
+    let mut payload = Vec::with_capacity(1 + stream.len().min(alpha.len()));
+    if stream.len() < alpha.len() {
+        payload.push(1); // Lossless compression, no filtering
+        payload.extend_from_slice(stream);
+    } else {
+        payload.push(0);
+        payload.extend_from_slice(alpha);
+    }
+    Ok(payload)
+}
+
+// The chunks of a lossy WebP image of `pixels`, RGB or RGBA, at `quality`:
+// ALPH when any pixel is not opaque, then VP8. Returns them and whether
+// ALPH was written.
+pub(crate) fn encode_chunks(pixels: &[u8], width: u32, height: u32, channels: usize, quality: u8) -> Result<(Vec<u8>, bool), JsValue> {
+    if !(1..=MAX_DIMENSION).contains(&width) || !(1..=MAX_DIMENSION).contains(&height) {
+        return Err(error::size_limit(format!("Lossy WebP images are limited to {} pixels per side", MAX_DIMENSION)));
+    }
+    let mut chunks = Vec::new();
+    let has_alpha = channels == 4 && pixels.chunks_exact(4).any(|p| p[3] < 255);
+    if has_alpha {
+        let alpha: Vec<u8> = pixels.chunks_exact(4).map(|p| p[3]).collect();
+        push_chunk(&mut chunks, b"ALPH", &alpha_payload(&alpha, width, height)?);
+    }
+    let frame = frame(pixels, width as usize, height as usize, channels, quantizer_index(quality))?;
+    push_chunk(&mut chunks, b"VP8 ", &frame);
+    Ok((chunks, has_alpha))
+}
+
+// A lossy WebP file, in the extended format when it has alpha.
+pub(crate) fn encode(pixels: &[u8], width: u32, height: u32, channels: usize, quality: u8) -> Result<Vec<u8>, JsValue> {
+    let (chunks, has_alpha) = encode_chunks(pixels, width, height, channels, quality)?;
+    let mut body = b"WEBP".to_vec();
+    if has_alpha {
+        let mut vp8x = vec![0x10, 0, 0, 0]; // Alpha
+        push_u24(&mut vp8x, width - 1);
+        push_u24(&mut vp8x, height - 1);
+        push_chunk(&mut body, b"VP8X", &vp8x);
+    }
+    body.extend_from_slice(&chunks);
+    let mut file = Vec::with_capacity(8 + body.len());
+    push_chunk(&mut file, b"RIFF", &body);
+    Ok(file)
+}
+
+// Zigzag position to raster index.
+const ZIGZAG: [usize; 16] = [0, 1, 4, 8, 5, 2, 3, 6, 9, 12, 13, 10, 7, 11, 14, 15];
+
+// Probability band of each zigzag position.
+const COEFF_BANDS: [usize; 16] = [0, 1, 2, 3, 6, 4, 5, 6, 6, 6, 6, 6, 6, 6, 6, 7];
+
+// Extra bit probabilities and smallest value of each token category.
+const PROB_DCT_CAT: [[u8; 12]; 6] = [
+    [159, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [165, 145, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [173, 148, 140, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+    [176, 155, 140, 135, 0, 0, 0, 0, 0, 0, 0, 0],
+    [180, 157, 141, 134, 130, 0, 0, 0, 0, 0, 0, 0],
+    [254, 254, 243, 230, 196, 177, 153, 140, 133, 130, 129, 0],
+];
+const DCT_CAT_BASE: [u32; 6] = [5, 7, 11, 19, 35, 67];
+
+// Quantizer step sizes by index (RFC 6386, 14.1).
+const DC_QUANT: [i16; 128] = [
+    4, 5, 6, 7, 8, 9, 10, 10, 11, 12, 13, 14, 15, 16, 17, 17,
+    18, 19, 20, 20, 21, 21, 22, 22, 23, 23, 24, 25, 25, 26, 27, 28,
+    29, 30, 31, 32, 33, 34, 35, 36, 37, 37, 38, 39, 40, 41, 42, 43,
+    44, 45, 46, 46, 47, 48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58,
+    59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 69, 70, 71, 72, 73, 74,
+    75, 76, 76, 77, 78, 79, 80, 81, 82, 83, 84, 85, 86, 87, 88, 89,
+    91, 93, 95, 96, 98, 100, 101, 102, 104, 106, 108, 110, 112, 114, 116, 118,
+    122, 124, 126, 128, 130, 132, 134, 136, 138, 140, 143, 145, 148, 151, 154, 157,
+];
+const AC_QUANT: [i16; 128] = [
+    4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19,
+    20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35,
+    36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47, 48, 49, 50, 51,
+    52, 53, 54, 55, 56, 57, 58, 60, 62, 64, 66, 68, 70, 72, 74, 76,
+    78, 80, 82, 84, 86, 88, 90, 92, 94, 96, 98, 100, 102, 104, 106, 108,
+    110, 112, 114, 116, 119, 122, 125, 128, 131, 134, 137, 140, 143, 146, 149, 152,
+    155, 158, 161, 164, 167, 170, 173, 177, 181, 185, 189, 193, 197, 201, 205, 209,
+    213, 217, 221, 225, 229, 234, 239, 245, 249, 254, 259, 264, 269, 274, 279, 284,
+];
+
+// Probabilities that each token probability is updated (RFC 6386, 13.4).
+const COEFF_UPDATE_PROBS: TokenProbs = [
+    [
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [176, 246, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [223, 241, 252, 255, 255, 255, 255, 255, 255, 255, 255],
+            [249, 253, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 244, 252, 255, 255, 255, 255, 255, 255, 255, 255],
+            [234, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [253, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 246, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [239, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 248, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [251, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [251, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 253, 255, 254, 255, 255, 255, 255, 255, 255],
+            [250, 255, 254, 255, 254, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+    ],
+    [
+        [
+            [217, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [225, 252, 241, 253, 255, 255, 254, 255, 255, 255, 255],
+            [234, 250, 241, 250, 253, 255, 253, 254, 255, 255, 255],
+        ],
+        [
+            [255, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [223, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [238, 253, 254, 254, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 248, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [249, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [247, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [252, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [253, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [250, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+    ],
+    [
+        [
+            [186, 251, 250, 255, 255, 255, 255, 255, 255, 255, 255],
+            [234, 251, 244, 254, 255, 255, 255, 255, 255, 255, 255],
+            [251, 251, 243, 253, 254, 255, 254, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [236, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [251, 253, 253, 254, 254, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+    ],
+    [
+        [
+            [248, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [250, 254, 252, 254, 255, 255, 255, 255, 255, 255, 255],
+            [248, 254, 249, 253, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [246, 253, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [252, 254, 251, 254, 254, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 252, 255, 255, 255, 255, 255, 255, 255, 255],
+            [248, 254, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [253, 255, 254, 254, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 251, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [245, 251, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [253, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 251, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [252, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 252, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [249, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [250, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+    ],
+];
+
+// Default token probabilities (RFC 6386, 13.5).
+const COEFF_PROBS: TokenProbs = [
+    [
+        [
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [253, 136, 254, 255, 228, 219, 128, 128, 128, 128, 128],
+            [189, 129, 242, 255, 227, 213, 255, 219, 128, 128, 128],
+            [106, 126, 227, 252, 214, 209, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 98, 248, 255, 236, 226, 255, 255, 128, 128, 128],
+            [181, 133, 238, 254, 221, 234, 255, 154, 128, 128, 128],
+            [78, 134, 202, 247, 198, 180, 255, 219, 128, 128, 128],
+        ],
+        [
+            [1, 185, 249, 255, 243, 255, 128, 128, 128, 128, 128],
+            [184, 150, 247, 255, 236, 224, 128, 128, 128, 128, 128],
+            [77, 110, 216, 255, 236, 230, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 101, 251, 255, 241, 255, 128, 128, 128, 128, 128],
+            [170, 139, 241, 252, 236, 209, 255, 255, 128, 128, 128],
+            [37, 116, 196, 243, 228, 255, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 204, 254, 255, 245, 255, 128, 128, 128, 128, 128],
+            [207, 160, 250, 255, 238, 128, 128, 128, 128, 128, 128],
+            [102, 103, 231, 255, 211, 171, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 152, 252, 255, 240, 255, 128, 128, 128, 128, 128],
+            [177, 135, 243, 255, 234, 225, 128, 128, 128, 128, 128],
+            [80, 129, 211, 255, 194, 224, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [246, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [255, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+    ],
+    [
+        [
+            [198, 35, 237, 223, 193, 187, 162, 160, 145, 155, 62],
+            [131, 45, 198, 221, 172, 176, 220, 157, 252, 221, 1],
+            [68, 47, 146, 208, 149, 167, 221, 162, 255, 223, 128],
+        ],
+        [
+            [1, 149, 241, 255, 221, 224, 255, 255, 128, 128, 128],
+            [184, 141, 234, 253, 222, 220, 255, 199, 128, 128, 128],
+            [81, 99, 181, 242, 176, 190, 249, 202, 255, 255, 128],
+        ],
+        [
+            [1, 129, 232, 253, 214, 197, 242, 196, 255, 255, 128],
+            [99, 121, 210, 250, 201, 198, 255, 202, 128, 128, 128],
+            [23, 91, 163, 242, 170, 187, 247, 210, 255, 255, 128],
+        ],
+        [
+            [1, 200, 246, 255, 234, 255, 128, 128, 128, 128, 128],
+            [109, 178, 241, 255, 231, 245, 255, 255, 128, 128, 128],
+            [44, 130, 201, 253, 205, 192, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 132, 239, 251, 219, 209, 255, 165, 128, 128, 128],
+            [94, 136, 225, 251, 218, 190, 255, 255, 128, 128, 128],
+            [22, 100, 174, 245, 186, 161, 255, 199, 128, 128, 128],
+        ],
+        [
+            [1, 182, 249, 255, 232, 235, 128, 128, 128, 128, 128],
+            [124, 143, 241, 255, 227, 234, 128, 128, 128, 128, 128],
+            [35, 77, 181, 251, 193, 211, 255, 205, 128, 128, 128],
+        ],
+        [
+            [1, 157, 247, 255, 236, 231, 255, 255, 128, 128, 128],
+            [121, 141, 235, 255, 225, 227, 255, 255, 128, 128, 128],
+            [45, 99, 188, 251, 195, 217, 255, 224, 128, 128, 128],
+        ],
+        [
+            [1, 1, 251, 255, 213, 255, 128, 128, 128, 128, 128],
+            [203, 1, 248, 255, 255, 128, 128, 128, 128, 128, 128],
+            [137, 1, 177, 255, 224, 255, 128, 128, 128, 128, 128],
+        ],
+    ],
+    [
+        [
+            [253, 9, 248, 251, 207, 208, 255, 192, 128, 128, 128],
+            [175, 13, 224, 243, 193, 185, 249, 198, 255, 255, 128],
+            [73, 17, 171, 221, 161, 179, 236, 167, 255, 234, 128],
+        ],
+        [
+            [1, 95, 247, 253, 212, 183, 255, 255, 128, 128, 128],
+            [239, 90, 244, 250, 211, 209, 255, 255, 128, 128, 128],
+            [155, 77, 195, 248, 188, 195, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 24, 239, 251, 218, 219, 255, 205, 128, 128, 128],
+            [201, 51, 219, 255, 196, 186, 128, 128, 128, 128, 128],
+            [69, 46, 190, 239, 201, 218, 255, 228, 128, 128, 128],
+        ],
+        [
+            [1, 191, 251, 255, 255, 128, 128, 128, 128, 128, 128],
+            [223, 165, 249, 255, 213, 255, 128, 128, 128, 128, 128],
+            [141, 124, 248, 255, 255, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 16, 248, 255, 255, 128, 128, 128, 128, 128, 128],
+            [190, 36, 230, 255, 236, 255, 128, 128, 128, 128, 128],
+            [149, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 226, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [247, 192, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [240, 128, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 134, 252, 255, 255, 128, 128, 128, 128, 128, 128],
+            [213, 62, 250, 255, 255, 128, 128, 128, 128, 128, 128],
+            [55, 93, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+    ],
+    [
+        [
+            [202, 24, 213, 235, 186, 191, 220, 160, 240, 175, 255],
+            [126, 38, 182, 232, 169, 184, 228, 174, 255, 187, 128],
+            [61, 46, 138, 219, 151, 178, 240, 170, 255, 216, 128],
+        ],
+        [
+            [1, 112, 230, 250, 199, 191, 247, 159, 255, 255, 128],
+            [166, 109, 228, 252, 211, 215, 255, 174, 128, 128, 128],
+            [39, 77, 162, 232, 172, 180, 245, 178, 255, 255, 128],
+        ],
+        [
+            [1, 52, 220, 246, 198, 199, 249, 220, 255, 255, 128],
+            [124, 74, 191, 243, 183, 193, 250, 221, 255, 255, 128],
+            [24, 71, 130, 219, 154, 170, 243, 182, 255, 255, 128],
+        ],
+        [
+            [1, 182, 225, 249, 219, 240, 255, 224, 128, 128, 128],
+            [149, 150, 226, 252, 216, 205, 255, 171, 128, 128, 128],
+            [28, 108, 170, 242, 183, 194, 254, 223, 255, 255, 128],
+        ],
+        [
+            [1, 81, 230, 252, 204, 203, 255, 192, 128, 128, 128],
+            [123, 102, 209, 247, 188, 196, 255, 233, 128, 128, 128],
+            [20, 95, 153, 243, 164, 173, 255, 203, 128, 128, 128],
+        ],
+        [
+            [1, 222, 248, 255, 216, 213, 128, 128, 128, 128, 128],
+            [168, 175, 246, 252, 235, 205, 255, 255, 128, 128, 128],
+            [47, 116, 215, 255, 211, 212, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 121, 236, 253, 212, 214, 255, 255, 128, 128, 128],
+            [141, 84, 213, 252, 201, 202, 255, 219, 128, 128, 128],
+            [42, 80, 160, 240, 162, 185, 255, 205, 128, 128, 128],
+        ],
+        [
+            [1, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [244, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [238, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+    ],
+];
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixels(width: u32, height: u32, channels: usize) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * channels);
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = [(x * 5) as u8, (y * 8) as u8, ((x + y) * 3) as u8, (x * 255 / width) as u8];
+                pixels.extend_from_slice(&pixel[..channels]);
+            }
+        }
+        pixels
+    }
+
+    fn max_difference(a: &[u8], b: &[u8]) -> u8 {
+        a.iter().zip(b).map(|(&a, &b)| a.abs_diff(b)).max().unwrap()
+    }
+
+    #[test]
+    fn quality_maps_onto_the_quantizer_range() {
+        assert_eq!(quantizer_index(100), 0);
+        assert_eq!(quantizer_index(0), 127);
+        assert!((1..=100).all(|quality| quantizer_index(quality) <= quantizer_index(quality - 1)));
+    }
+
+    #[test]
+    fn flat_color_survives_exactly() {
+        let pixels: Vec<u8> = [100, 100, 100].repeat(32 * 16);
+        let decoded = image::load_from_memory(&encode(&pixels, 32, 16, 3, 75).unwrap()).unwrap().to_rgb8();
+        assert_eq!(decoded.dimensions(), (32, 16));
+        assert_eq!(max_difference(decoded.as_raw(), &pixels), 0);
+    }
+
+    #[test]
+    fn gradients_decode_close_to_the_source() {
+        // Odd sizes leave partial macroblocks on the right and bottom.
+        for (width, height) in [(48, 32), (45, 29), (17, 9)] {
+            let source = pixels(width, height, 3);
+            let decoded = image::load_from_memory(&encode(&source, width, height, 3, 90).unwrap()).unwrap().to_rgb8();
+            assert_eq!(decoded.dimensions(), (width, height));
+            assert!(max_difference(decoded.as_raw(), &source) <= 16);
+        }
+    }
+
+    #[test]
+    fn lower_quality_makes_smaller_files() {
+        let source = pixels(64, 64, 3);
+        let sizes: Vec<usize> = [95, 75, 30].iter().map(|&quality| encode(&source, 64, 64, 3, quality).unwrap().len()).collect();
+        assert!(sizes[0] > sizes[1] && sizes[1] > sizes[2]);
+    }
+
+    #[test]
+    fn alpha_is_kept_losslessly() {
+        let source = pixels(40, 24, 4);
+        let file = encode(&source, 40, 24, 4, 60).unwrap();
+        assert_eq!(&file[12..16], b"VP8X");
+        let decoded = image::load_from_memory(&file).unwrap().to_rgba8();
+        let alpha = |pixels: &[u8]| pixels.chunks_exact(4).map(|p| p[3]).collect::<Vec<_>>();
+        assert_eq!(alpha(decoded.as_raw()), alpha(&source));
+    }
+
+    #[test]
+    fn opaque_rgba_is_a_simple_file() {
+        let mut source = pixels(16, 16, 4);
+        source.chunks_exact_mut(4).for_each(|p| p[3] = 255);
+        assert_eq!(&encode(&source, 16, 16, 4, 60).unwrap()[12..16], b"VP8 ");
+    }
+
+    #[test]
+    fn metadata_goes_around_the_image_chunks() {
+        let source = pixels(40, 24, 4);
+        let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_raw(40, 24, source.clone()).unwrap());
+        let file = crate::metadata::embed(encode(&source, 40, 24, 4, 60).unwrap(), crate::OutputFormat::WebP, Some(b"Exif\0\0"), None, None, &img);
+        let kinds: Vec<[u8; 4]> = crate::metadata::webp_chunks(&file).iter().map(|(kind, _)| *kind).collect();
+        assert_eq!(kinds, [*b"VP8X", *b"ALPH", *b"VP8 ", *b"EXIF"]);
+        assert_eq!(image::load_from_memory(&file).unwrap().to_rgba8().dimensions(), (40, 24));
+    }
+}